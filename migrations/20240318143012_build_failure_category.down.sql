@@ -0,0 +1,5 @@
+ALTER TABLE builds
+    DROP COLUMN failure_category,
+    DROP COLUMN error_excerpt;
+
+DROP TYPE build_failure_category;
@@ -0,0 +1,14 @@
+CREATE TYPE build_failure_category AS ENUM (
+    'compile_error',
+    'dependency_failed',
+    'out_of_memory',
+    'timeout',
+    'missing_native_library',
+    'network_needed',
+    'rustdoc_ice',
+    'other'
+);
+
+ALTER TABLE builds
+    ADD COLUMN failure_category build_failure_category,
+    ADD COLUMN error_excerpt TEXT;
@@ -0,0 +1,2 @@
+DROP TABLE build_target_results;
+ALTER TABLE builds DROP CONSTRAINT builds_pkey;
@@ -0,0 +1,12 @@
+-- the results reference their build, which needs a unique ID for that
+ALTER TABLE builds ADD CONSTRAINT builds_pkey PRIMARY KEY (id);
+
+CREATE TABLE build_target_results (
+    id SERIAL PRIMARY KEY,
+    build_id INTEGER NOT NULL REFERENCES builds(id) ON DELETE CASCADE,
+    target TEXT NOT NULL,
+    successful BOOLEAN NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    log_path TEXT NOT NULL,
+    UNIQUE (build_id, target)
+);
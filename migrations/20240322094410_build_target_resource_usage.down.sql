@@ -0,0 +1,3 @@
+ALTER TABLE build_target_results
+    DROP COLUMN peak_memory_bytes,
+    DROP COLUMN cpu_time_ms;
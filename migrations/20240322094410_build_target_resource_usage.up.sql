@@ -0,0 +1,3 @@
+ALTER TABLE build_target_results
+    ADD COLUMN peak_memory_bytes BIGINT,
+    ADD COLUMN cpu_time_ms INTEGER;
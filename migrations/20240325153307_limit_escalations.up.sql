@@ -0,0 +1,10 @@
+CREATE TABLE limit_escalations (
+    crate_name TEXT NOT NULL,
+    version TEXT NOT NULL,
+    failure_category build_failure_category NOT NULL,
+    max_memory_bytes BIGINT,
+    timeout_seconds INTEGER,
+    max_targets INTEGER,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (crate_name, version)
+);
@@ -0,0 +1,5 @@
+DROP TABLE build_workers;
+
+ALTER TABLE queue
+    DROP COLUMN leased_by,
+    DROP COLUMN lease_expires_at;
@@ -0,0 +1,11 @@
+ALTER TABLE queue
+    ADD COLUMN leased_by TEXT,
+    ADD COLUMN lease_expires_at TIMESTAMP WITH TIME ZONE;
+
+CREATE TABLE build_workers (
+    name TEXT PRIMARY KEY,
+    last_heartbeat TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    crate_name TEXT,
+    crate_version TEXT,
+    build_started_at TIMESTAMP WITH TIME ZONE
+);
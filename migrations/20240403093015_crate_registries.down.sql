@@ -0,0 +1,23 @@
+DROP INDEX queue_name_version_registry_idx;
+ALTER TABLE queue ADD CONSTRAINT queue_name_version_key UNIQUE (name, version);
+
+-- without the registry, the crates of alternative registries would clash with the crates.io ones.
+CREATE TEMPORARY TABLE alternative_releases AS
+    SELECT releases.id FROM releases
+    INNER JOIN crates ON crates.id = releases.crate_id
+    WHERE crates.registry IS NOT NULL;
+DELETE FROM builds WHERE rid IN (SELECT id FROM alternative_releases);
+DELETE FROM compression_rels WHERE release IN (SELECT id FROM alternative_releases);
+DELETE FROM doc_coverage WHERE release_id IN (SELECT id FROM alternative_releases);
+DELETE FROM keyword_rels WHERE rid IN (SELECT id FROM alternative_releases);
+DELETE FROM releases WHERE id IN (SELECT id FROM alternative_releases);
+DELETE FROM owner_rels WHERE cid IN (SELECT id FROM crates WHERE registry IS NOT NULL);
+DELETE FROM crates WHERE registry IS NOT NULL;
+DROP TABLE alternative_releases;
+
+DROP INDEX crates_normalized_name_idx;
+DROP INDEX crates_registry_name_idx;
+CREATE UNIQUE INDEX crates_normalized_name_idx ON crates USING btree (normalize_crate_name(name));
+ALTER TABLE crates ADD CONSTRAINT crates_name_key UNIQUE (name);
+
+ALTER TABLE crates DROP COLUMN registry;
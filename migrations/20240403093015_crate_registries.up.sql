@@ -0,0 +1,12 @@
+-- the alternative registry a crate comes from, NULL for crates.io.
+ALTER TABLE crates ADD COLUMN registry TEXT;
+
+-- crate names only have to be unique within a registry.
+-- The name comes first so lookups by name alone can still use the indexes.
+ALTER TABLE crates DROP CONSTRAINT crates_name_key;
+DROP INDEX crates_normalized_name_idx;
+CREATE UNIQUE INDEX crates_registry_name_idx ON crates (name, (COALESCE(registry, '')));
+CREATE UNIQUE INDEX crates_normalized_name_idx ON crates (normalize_crate_name(name), (COALESCE(registry, '')));
+
+ALTER TABLE queue DROP CONSTRAINT queue_name_version_key;
+CREATE UNIQUE INDEX queue_name_version_registry_idx ON queue (name, version, (COALESCE(registry, '')));
@@ -0,0 +1,8 @@
+-- SHA-256 checksums of the files of an archive, as we stored them.
+-- `archive_sha256` is NULL for deduplicated archives, which don't have a ZIP file.
+CREATE TABLE archive_checksums (
+    archive_path TEXT PRIMARY KEY,
+    archive_sha256 TEXT,
+    index_sha256 TEXT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,16 @@
+-- the items in the documentation of the latest release of each crate, for searches across
+-- all crates. `url` is relative to the documentation of the default target.
+CREATE TABLE crate_items (
+    crate_id INTEGER NOT NULL REFERENCES crates(id) ON DELETE CASCADE,
+    release_id INTEGER NOT NULL REFERENCES releases(id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    url TEXT NOT NULL,
+    description TEXT
+);
+
+CREATE INDEX crate_items_crate_id_idx ON crate_items (crate_id);
+CREATE INDEX crate_items_release_id_idx ON crate_items (release_id);
+-- `text_pattern_ops` so prefix searches with `LIKE` can use the index too.
+CREATE INDEX crate_items_name_idx ON crate_items (LOWER(name) text_pattern_ops);
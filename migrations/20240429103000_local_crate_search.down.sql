@@ -0,0 +1,3 @@
+DROP INDEX crates_name_trgm_idx;
+DROP INDEX releases_search_document_idx;
+DROP FUNCTION release_search_document;
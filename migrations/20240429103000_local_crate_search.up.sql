@@ -0,0 +1,13 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- the text of a release the local crate search matches, with the keywords weighted higher
+-- than the description. A function, so the index and the queries use the same expression.
+CREATE FUNCTION release_search_document(description TEXT, keywords JSON) RETURNS tsvector AS $$
+    SELECT
+        setweight(to_tsvector('english', COALESCE(keywords::TEXT, '')), 'A') ||
+        setweight(to_tsvector('english', COALESCE(description, '')), 'B')
+$$ LANGUAGE SQL IMMUTABLE;
+
+CREATE INDEX releases_search_document_idx ON releases
+    USING GIN (release_search_document(description, keywords));
+CREATE INDEX crates_name_trgm_idx ON crates USING GIN (name gin_trgm_ops);
@@ -0,0 +1,5 @@
+DELETE FROM limit_escalations WHERE registry IS NOT NULL;
+DROP INDEX limit_escalations_release_idx;
+ALTER TABLE limit_escalations ADD PRIMARY KEY (crate_name, version);
+
+ALTER TABLE limit_escalations DROP COLUMN registry;
@@ -0,0 +1,5 @@
+-- the alternative registry of the escalated release, NULL for crates.io.
+ALTER TABLE limit_escalations ADD COLUMN registry TEXT;
+
+ALTER TABLE limit_escalations DROP CONSTRAINT limit_escalations_pkey;
+CREATE UNIQUE INDEX limit_escalations_release_idx ON limit_escalations (crate_name, version, (COALESCE(registry, '')));
@@ -0,0 +1 @@
+DROP TABLE sparse_index_etags;
@@ -0,0 +1,6 @@
+-- `ETag`s of the crate files of the sparse registry index whose changes were processed,
+-- see `index::sparse`.
+CREATE TABLE sparse_index_etags (
+    crate_name TEXT PRIMARY KEY,
+    etag TEXT NOT NULL
+);
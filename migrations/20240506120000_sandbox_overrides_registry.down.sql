@@ -0,0 +1,5 @@
+DELETE FROM sandbox_overrides WHERE registry IS NOT NULL;
+DROP INDEX sandbox_overrides_crate_idx;
+ALTER TABLE sandbox_overrides ADD PRIMARY KEY (crate_name);
+
+ALTER TABLE sandbox_overrides DROP COLUMN registry;
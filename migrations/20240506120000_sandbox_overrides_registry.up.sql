@@ -0,0 +1,5 @@
+-- the alternative registry of the crate, NULL for crates.io.
+ALTER TABLE sandbox_overrides ADD COLUMN registry TEXT;
+
+ALTER TABLE sandbox_overrides DROP CONSTRAINT sandbox_overrides_pkey;
+CREATE UNIQUE INDEX sandbox_overrides_crate_idx ON sandbox_overrides (crate_name, (COALESCE(registry, '')));
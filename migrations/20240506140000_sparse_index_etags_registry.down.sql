@@ -0,0 +1,5 @@
+DELETE FROM sparse_index_etags WHERE registry IS NOT NULL;
+DROP INDEX sparse_index_etags_crate_idx;
+ALTER TABLE sparse_index_etags ADD PRIMARY KEY (crate_name);
+
+ALTER TABLE sparse_index_etags DROP COLUMN registry;
@@ -0,0 +1,5 @@
+-- the alternative registry of the crate, NULL for crates.io.
+ALTER TABLE sparse_index_etags ADD COLUMN registry TEXT;
+
+ALTER TABLE sparse_index_etags DROP CONSTRAINT sparse_index_etags_pkey;
+CREATE UNIQUE INDEX sparse_index_etags_crate_idx ON sparse_index_etags (crate_name, (COALESCE(registry, '')));
@@ -9,11 +9,11 @@ use anyhow::{anyhow, Context as _, Error, Result};
 use axum::async_trait;
 use clap::{Parser, Subcommand, ValueEnum};
 use docs_rs::cdn::CdnBackend;
-use docs_rs::db::{self, add_path_into_database, Overrides, Pool, PoolClient};
+use docs_rs::db::{self, add_path_into_database, LimitEscalation, Overrides, Pool, PoolClient};
 use docs_rs::repositories::RepositoryStatsUpdater;
 use docs_rs::utils::{
-    get_config, get_crate_pattern_and_priority, list_crate_priorities, queue_builder,
-    remove_crate_priority, set_config, set_crate_priority, ConfigName,
+    get_config, get_crate_pattern_and_priority, list_crate_priorities, remove_crate_priority,
+    set_config, set_crate_priority, start_build_workers, ConfigName,
 };
 use docs_rs::{
     start_background_metrics_webserver, start_web_server, AsyncStorage, BuildQueue, Config,
@@ -172,6 +172,18 @@ enum CommandLine {
         #[command(subcommand)]
         subcommand: QueueSubcommand,
     },
+
+    /// Interactions with the local cache of archive indexes
+    ArchiveIndexCache {
+        #[command(subcommand)]
+        subcommand: ArchiveIndexCacheSubcommand,
+    },
+
+    /// Interactions with the storage
+    Storage {
+        #[command(subcommand)]
+        subcommand: StorageSubcommand,
+    },
 }
 
 impl CommandLine {
@@ -201,10 +213,11 @@ impl CommandLine {
             } => {
                 start_background_metrics_webserver(Some(metric_server_socket_addr), &ctx)?;
 
-                let build_queue = ctx.build_queue()?;
-                let config = ctx.config()?;
-                let rustwide_builder = RustwideBuilder::init(&ctx)?;
-                queue_builder(&ctx, rustwide_builder, build_queue, config)?;
+                for handle in start_build_workers(Arc::new(ctx))? {
+                    handle
+                        .join()
+                        .map_err(|err| anyhow!("build worker panicked: {:?}", err))?;
+                }
             }
             Self::StartWebServer { socket_addr } => {
                 // Blocks indefinitely
@@ -215,8 +228,143 @@ impl CommandLine {
             }
             Self::Database { subcommand } => subcommand.handle_args(ctx)?,
             Self::Queue { subcommand } => subcommand.handle_args(ctx)?,
+            Self::ArchiveIndexCache { subcommand } => subcommand.handle_args(ctx)?,
+            Self::Storage { subcommand } => subcommand.handle_args(ctx)?,
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+enum ArchiveIndexCacheSubcommand {
+    /// Show the number and size of the cached indexes
+    Info,
+
+    /// Remove the least recently used indexes until the cache isn't bigger than the given size.
+    /// Indexes used in the last minute are kept.
+    Prune {
+        /// Size in bytes the cache is pruned to, defaults to its configured maximum size
+        #[arg(long)]
+        max_size: Option<u64>,
+    },
+}
+
+impl ArchiveIndexCacheSubcommand {
+    fn handle_args(self, ctx: BinContext) -> Result<()> {
+        match self {
+            Self::Info => {
+                let info = ctx
+                    .runtime()?
+                    .block_on(async {
+                        let storage = ctx.async_storage().await?;
+                        storage.archive_index_cache().info().await
+                    })
+                    .context("Failed to load the archive index cache")?;
+
+                println!("Indexes: {}", info.entries);
+                println!("Size: {} bytes", info.total_size);
+                println!("Maximum size: {} bytes", info.max_size);
+                if let Some(oldest_access) = info.oldest_access {
+                    println!(
+                        "Least recently used: {}",
+                        humantime::format_rfc3339_seconds(oldest_access)
+                    );
+                }
+            }
+
+            Self::Prune { max_size } => {
+                let pruned = ctx
+                    .runtime()?
+                    .block_on(async {
+                        let storage = ctx.async_storage().await?;
+                        let cache = storage.archive_index_cache();
+                        cache
+                            .prune(max_size.unwrap_or(cache.info().await?.max_size))
+                            .await
+                    })
+                    .context("Failed to prune the archive index cache")?;
+
+                println!("Removed {pruned} indexes");
+            }
         }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+enum StorageSubcommand {
+    /// Download the archives of releases again, and check that they have the checksums recorded
+    /// when they were stored and that all their files can be decompressed
+    Verify {
+        /// Only verify the archives of this crate
+        #[arg(long)]
+        crate_name: Option<String>,
+
+        /// Add the releases with corrupted archives to the build queue
+        #[arg(long)]
+        rebuild: bool,
+
+        /// Priority of the rebuilds
+        #[arg(long, default_value = "5", allow_negative_numbers = true)]
+        priority: i32,
+    },
+}
 
+impl StorageSubcommand {
+    fn handle_args(self, ctx: BinContext) -> Result<()> {
+        match self {
+            Self::Verify {
+                crate_name,
+                rebuild,
+                priority,
+            } => {
+                let corrupted = ctx
+                    .runtime()?
+                    .block_on(async {
+                        let storage = ctx.async_storage().await?;
+                        let mut conn = ctx.pool()?.get_async().await?;
+                        docs_rs::storage::verify_archives(
+                            &mut conn,
+                            &storage,
+                            crate_name.as_deref(),
+                        )
+                        .await
+                    })
+                    .context("Failed to verify archives")?;
+
+                for release in &corrupted {
+                    match &release.registry {
+                        Some(registry) => {
+                            println!("{} {} ({registry})", release.name, release.version)
+                        }
+                        None => println!("{} {}", release.name, release.version),
+                    }
+                    for (archive_path, problem) in &release.problems {
+                        println!("  {archive_path}: {problem}");
+                    }
+                }
+                println!("Releases with corrupted archives: {}", corrupted.len());
+
+                if rebuild {
+                    let config = ctx.config()?;
+                    let build_queue = ctx.build_queue()?;
+                    for release in &corrupted {
+                        let registry_url = release
+                            .registry
+                            .as_deref()
+                            .map(|registry| config.registry_index_url(registry))
+                            .transpose()?;
+                        build_queue
+                            .add_crate(&release.name, &release.version, priority, registry_url)
+                            .with_context(|| {
+                                format!("Failed to queue {} {}", release.name, release.version)
+                            })?;
+                    }
+                    println!("Queued rebuilds: {}", corrupted.len());
+                }
+            }
+        }
         Ok(())
     }
 }
@@ -240,6 +388,9 @@ enum QueueSubcommand {
             allow_negative_numbers = true
         )]
         build_priority: i32,
+        /// Name of the alternative registry the crate comes from
+        #[arg(long = "registry")]
+        registry: Option<String>,
     },
 
     /// Interactions with build queue priorities
@@ -271,12 +422,20 @@ impl QueueSubcommand {
                 crate_name,
                 crate_version,
                 build_priority,
-            } => ctx.build_queue()?.add_crate(
-                &crate_name,
-                &crate_version,
-                build_priority,
-                ctx.config()?.registry_url.as_deref(),
-            )?,
+                registry,
+            } => {
+                let config = ctx.config()?;
+                let registry_url = match &registry {
+                    Some(registry) => Some(config.registry_index_url(registry)?),
+                    None => config.registry_url.as_deref(),
+                };
+                ctx.build_queue()?.add_crate(
+                    &crate_name,
+                    &crate_version,
+                    build_priority,
+                    registry_url,
+                )?
+            }
 
             Self::GetLastSeenReference => {
                 if let Some(reference) = ctx.build_queue()?.last_seen_reference()? {
@@ -390,6 +549,10 @@ enum BuildSubcommand {
         /// Build a crate at a specific path
         #[arg(short = 'l', long = "local", conflicts_with_all(&["CRATE_NAME", "CRATE_VERSION"]))]
         local: Option<PathBuf>,
+
+        /// Name of the alternative registry the crate comes from
+        #[arg(long = "registry", conflicts_with("local"))]
+        registry: Option<String>,
     },
 
     /// update the currently installed rustup toolchain
@@ -424,6 +587,7 @@ impl BuildSubcommand {
                 crate_name,
                 crate_version,
                 local,
+                registry,
             } => {
                 let mut builder = rustwide_builder()?;
 
@@ -432,7 +596,11 @@ impl BuildSubcommand {
                         .build_local_package(&path)
                         .context("Building documentation failed")?;
                 } else {
-                    let registry_url = ctx.config()?.registry_url.clone();
+                    let config = ctx.config()?;
+                    let registry_url = match &registry {
+                        Some(registry) => Some(config.registry_index_url(registry)?.to_owned()),
+                        None => config.registry_url.clone(),
+                    };
                     builder
                         .build_package(
                             &crate_name
@@ -509,6 +677,14 @@ enum DatabaseSubcommand {
     /// temporary commant to update the `crates.latest_version_id` field
     UpdateLatestVersionId,
 
+    /// Replaces the items of crates in the index for searches across all crates with the items
+    /// of their latest release, read from its stored documentation
+    RebuildCrateItems {
+        /// Only rebuild the items of this crate
+        #[arg(name = "CRATE")]
+        name: Option<String>,
+    },
+
     /// Updates Github/Gitlab stats for crates.
     UpdateRepositoryFields,
 
@@ -527,6 +703,40 @@ enum DatabaseSubcommand {
         directory: PathBuf,
     },
 
+    /// Turn the ZIP archives of all releases into deduplicated archives, storing the files
+    /// they have in common only once
+    DeduplicateArchives,
+
+    /// Delete the blobs of deduplicated archives that no release references anymore, like the
+    /// ones of deleted releases. The build queue has to be locked.
+    DeleteOrphanedBlobs,
+
+    /// Repack the archives that still have bzip2 compressed files with zstd, while the
+    /// archives keep being served
+    RepackArchives,
+
+    /// Write a release with its builds, archives and build logs into a bundle that can be
+    /// imported into another instance
+    ExportRelease {
+        #[arg(name = "CRATE")]
+        name: String,
+        #[arg(name = "VERSION")]
+        version: String,
+        /// The alternative registry of the crate, crates.io by default
+        #[arg(long)]
+        registry: Option<String>,
+        /// Where to write the bundle, `<CRATE>-<VERSION>.tar.zst` by default
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+
+    /// Add the release in a bundle written by `export-release`, a release we have already has to
+    /// be deleted first
+    ImportRelease {
+        #[arg(name = "BUNDLE")]
+        path: PathBuf,
+    },
+
     /// Remove documentation from the database
     Delete {
         #[command(subcommand)]
@@ -552,6 +762,15 @@ enum DatabaseSubcommand {
         #[arg(long)]
         dry_run: bool,
     },
+
+    /// Compares the database with the archives and build logs in the storage and resolves
+    /// inconsistencies
+    #[cfg(feature = "consistency_check")]
+    SynchronizeStorage {
+        /// Don't actually resolve the inconsistencies, just log them
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 impl DatabaseSubcommand {
@@ -591,6 +810,39 @@ impl DatabaseSubcommand {
                     .context("Failed to update latest version id")?
             }
 
+            Self::RebuildCrateItems { name } => {
+                let pool = ctx.pool()?;
+                ctx.runtime()?
+                    .block_on(async {
+                        let storage = ctx.async_storage().await?;
+                        let mut list_conn = pool.get_async().await?;
+                        let mut update_conn = pool.get_async().await?;
+
+                        let mut result_stream = sqlx::query!(
+                            "SELECT id, name FROM crates
+                             WHERE $1::TEXT IS NULL OR name = $1
+                             ORDER BY name",
+                            name,
+                        )
+                        .fetch(&mut *list_conn);
+
+                        while let Some(row) = result_stream.next().await {
+                            let row = row?;
+
+                            println!("handling crate {}", row.name);
+
+                            if let Err(err) =
+                                db::rebuild_crate_items(&mut update_conn, &storage, row.id).await
+                            {
+                                eprintln!("{err:?}");
+                            }
+                        }
+
+                        Ok::<(), anyhow::Error>(())
+                    })
+                    .context("Failed to rebuild the crate items")?
+            }
+
             Self::UpdateRepositoryFields => {
                 ctx.runtime()?
                     .block_on(ctx.repository_stats_updater()?.update_all_crates())?;
@@ -604,7 +856,7 @@ impl DatabaseSubcommand {
             Self::UpdateCrateRegistryFields { name } => ctx.runtime()?.block_on(async move {
                 let mut conn = ctx.pool()?.get_async().await?;
                 let registry_data = ctx.registry_api()?.get_crate_data(&name).await?;
-                db::update_crate_data_in_database(&mut conn, &name, &registry_data).await
+                db::update_crate_data_in_database(&mut conn, None, &name, &registry_data).await
             })?,
 
             Self::AddDirectory { directory } => {
@@ -617,22 +869,107 @@ impl DatabaseSubcommand {
                     .context("Failed to add directory into database")?;
             }
 
+            Self::DeduplicateArchives => ctx
+                .runtime()?
+                .block_on(async {
+                    let storage = ctx.async_storage().await?;
+                    let mut conn = ctx.pool()?.get_async().await?;
+                    docs_rs::storage::deduplicate_archives(&mut conn, &storage).await
+                })
+                .context("Failed to deduplicate archives")?,
+
+            Self::DeleteOrphanedBlobs => {
+                // builds store the blobs of their archives before the archives referencing them
+                if !ctx.build_queue()?.is_locked()? {
+                    return Err(anyhow!(
+                        "the build queue has to be locked while orphaned blobs are deleted"
+                    ));
+                }
+                let deleted = ctx
+                    .runtime()?
+                    .block_on(async {
+                        let storage = ctx.async_storage().await?;
+                        let mut conn = ctx.pool()?.get_async().await?;
+                        docs_rs::storage::delete_orphaned_blobs(&mut conn, &storage).await
+                    })
+                    .context("Failed to delete orphaned blobs")?;
+                println!("deleted {deleted} orphaned blobs");
+            }
+
+            Self::RepackArchives => ctx
+                .runtime()?
+                .block_on(async {
+                    let storage = ctx.async_storage().await?;
+                    let mut conn = ctx.pool()?.get_async().await?;
+                    docs_rs::storage::repack_archives(&mut conn, &storage).await
+                })
+                .context("Failed to repack archives")?,
+
+            Self::ExportRelease {
+                name,
+                version,
+                registry,
+                output,
+            } => {
+                let output = output.unwrap_or_else(|| format!("{name}-{version}.tar.zst").into());
+                ctx.runtime()?
+                    .block_on(async {
+                        let storage = ctx.async_storage().await?;
+                        let mut conn = ctx.pool()?.get_async().await?;
+                        db::export_release(
+                            &mut conn,
+                            &storage,
+                            registry.as_deref(),
+                            &name,
+                            &version,
+                            &output,
+                        )
+                        .await
+                    })
+                    .context("Failed to export the release")?;
+                println!("wrote {name} {version} to {}", output.display());
+            }
+
+            Self::ImportRelease { path } => {
+                ctx.runtime()?
+                    .block_on(async {
+                        let storage = ctx.async_storage().await?;
+                        let config = ctx.config()?;
+                        let mut conn = ctx.pool()?.get_async().await?;
+                        db::import_release(&mut conn, &storage, &config, &path).await
+                    })
+                    .context("Failed to import the release")?;
+                println!("imported {}", path.display());
+            }
+
             Self::Delete {
-                command: DeleteSubcommand::Version { name, version },
-            } => db::delete_version(
-                &mut *ctx.pool()?.get()?,
-                &*ctx.storage()?,
-                &*ctx.config()?,
-                &name,
-                &version,
-            )
-            .context("failed to delete the version")?,
+                command:
+                    DeleteSubcommand::Version {
+                        name,
+                        version,
+                        registry,
+                    },
+            } => {
+                db::delete_version(
+                    &mut *ctx.pool()?.get()?,
+                    &*ctx.storage()?,
+                    &*ctx.config()?,
+                    registry.as_deref(),
+                    &name,
+                    &version,
+                )
+                .context("failed to delete the version")?;
+                ctx.build_queue()?
+                    .rebuild_crate_items(registry.as_deref(), &name)
+                    .context("failed to update the items of the crate")?;
+            }
             Self::Delete {
-                command: DeleteSubcommand::Crate { name },
+                command: DeleteSubcommand::Crate { name, registry },
             } => db::delete_crate(
                 &mut *ctx.pool()?.get()?,
                 &*ctx.storage()?,
                 &*ctx.config()?,
+                registry.as_deref(),
                 &name,
             )
             .context("failed to delete the crate")?,
@@ -644,6 +981,11 @@ impl DatabaseSubcommand {
             Self::Synchronize { dry_run } => {
                 docs_rs::utils::consistency::run_check(&ctx, dry_run)?;
             }
+
+            #[cfg(feature = "consistency_check")]
+            Self::SynchronizeStorage { dry_run } => {
+                docs_rs::utils::consistency::run_storage_check(&ctx, dry_run)?;
+            }
         }
         Ok(())
     }
@@ -652,7 +994,12 @@ impl DatabaseSubcommand {
 #[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
 enum LimitsSubcommand {
     /// Get sandbox limit overrides for a crate
-    Get { crate_name: String },
+    Get {
+        crate_name: String,
+        /// Name of the alternative registry the crate comes from
+        #[arg(long = "registry")]
+        registry: Option<String>,
+    },
 
     /// List sandbox limit overrides for all crates
     List,
@@ -666,10 +1013,30 @@ enum LimitsSubcommand {
         targets: Option<usize>,
         #[arg(long)]
         timeout: Option<Duration>,
+        /// Name of the alternative registry the crate comes from
+        #[arg(long = "registry")]
+        registry: Option<String>,
     },
 
     /// Remove sandbox limits overrides for a crate
-    Remove { crate_name: String },
+    Remove {
+        crate_name: String,
+        /// Name of the alternative registry the crate comes from
+        #[arg(long = "registry")]
+        registry: Option<String>,
+    },
+
+    /// List the limits the build queue raised after timeouts or running out of memory
+    Escalations,
+
+    /// Make the escalated limits of a release sandbox limit overrides for its crate
+    Promote {
+        crate_name: String,
+        version: String,
+        /// Name of the alternative registry the crate comes from
+        #[arg(long = "registry")]
+        registry: Option<String>,
+    },
 }
 
 impl LimitsSubcommand {
@@ -679,14 +1046,23 @@ impl LimitsSubcommand {
             let mut conn = pool.get_async().await?;
 
             match self {
-                Self::Get { crate_name } => {
-                    let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
+                Self::Get {
+                    crate_name,
+                    registry,
+                } => {
+                    let overrides =
+                        Overrides::for_crate(&mut conn, registry.as_deref(), &crate_name).await?;
                     println!("sandbox limit overrides for {crate_name} = {overrides:?}");
                 }
 
                 Self::List => {
-                    for (crate_name, overrides) in Overrides::all(&mut conn).await? {
-                        println!("sandbox limit overrides for {crate_name} = {overrides:?}");
+                    for (registry, crate_name, overrides) in Overrides::all(&mut conn).await? {
+                        println!(
+                            "sandbox limit overrides for {crate_name}{} = {overrides:?}",
+                            registry
+                                .map(|registry| format!(" ({registry})"))
+                                .unwrap_or_default(),
+                        );
                     }
                 }
 
@@ -695,23 +1071,68 @@ impl LimitsSubcommand {
                     memory,
                     targets,
                     timeout,
+                    registry,
                 } => {
-                    let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
+                    let registry = registry.as_deref();
+                    let overrides = Overrides::for_crate(&mut conn, registry, &crate_name).await?;
                     println!("previous sandbox limit overrides for {crate_name} = {overrides:?}");
                     let overrides = Overrides {
                         memory,
                         targets,
                         timeout: timeout.map(Into::into),
                     };
-                    Overrides::save(&mut conn, &crate_name, overrides).await?;
-                    let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
+                    Overrides::save(&mut conn, registry, &crate_name, overrides).await?;
+                    let overrides = Overrides::for_crate(&mut conn, registry, &crate_name).await?;
                     println!("new sandbox limit overrides for {crate_name} = {overrides:?}");
                 }
 
-                Self::Remove { crate_name } => {
-                    let overrides = Overrides::for_crate(&mut conn, &crate_name).await?;
+                Self::Remove {
+                    crate_name,
+                    registry,
+                } => {
+                    let registry = registry.as_deref();
+                    let overrides = Overrides::for_crate(&mut conn, registry, &crate_name).await?;
                     println!("previous overrides for {crate_name} = {overrides:?}");
-                    Overrides::remove(&mut conn, &crate_name).await?;
+                    Overrides::remove(&mut conn, registry, &crate_name).await?;
+                }
+
+                Self::Escalations => {
+                    for escalation in LimitEscalation::all(&mut conn).await? {
+                        println!(
+                            "escalated sandbox limits for {}-{}{} at {} = {:?}",
+                            escalation.crate_name,
+                            escalation.version,
+                            escalation
+                                .registry
+                                .map(|registry| format!(" ({registry})"))
+                                .unwrap_or_default(),
+                            escalation.created_at,
+                            escalation.overrides,
+                        );
+                    }
+                }
+
+                Self::Promote {
+                    crate_name,
+                    version,
+                    registry,
+                } => {
+                    let overrides =
+                        Overrides::for_crate(&mut conn, registry.as_deref(), &crate_name).await?;
+                    println!("previous sandbox limit overrides for {crate_name} = {overrides:?}");
+                    match LimitEscalation::promote(
+                        &mut conn,
+                        registry.as_deref(),
+                        &crate_name,
+                        &version,
+                    )
+                    .await?
+                    {
+                        Some(overrides) => {
+                            println!("new sandbox limit overrides for {crate_name} = {overrides:?}")
+                        }
+                        None => println!("no escalated limits for {crate_name}-{version}"),
+                    }
                 }
             }
             Ok(())
@@ -767,6 +1188,10 @@ enum DeleteSubcommand {
         /// Name of the crate to delete
         #[arg(name = "CRATE_NAME")]
         name: String,
+
+        /// Name of the alternative registry the crate comes from
+        #[arg(long = "registry")]
+        registry: Option<String>,
     },
     /// Delete a single version of a crate (which may include multiple builds)
     Version {
@@ -777,6 +1202,10 @@ enum DeleteSubcommand {
         /// The version of the crate to delete
         #[arg(name = "VERSION")]
         version: String,
+
+        /// Name of the alternative registry the crate comes from
+        #[arg(long = "registry")]
+        registry: Option<String>,
     },
 }
 
@@ -835,6 +1264,7 @@ impl Context for BinContext {
             self.instance_metrics()?,
             self.config()?,
             self.storage()?,
+            self.runtime()?.block_on(self.async_storage())?,
             self.runtime()?,
         );
         fn storage(self) -> Storage = {
@@ -859,10 +1289,14 @@ impl Context for BinContext {
         fn index(self) -> Index = {
             let config = self.config()?;
             let path = config.registry_index_path.clone();
-            if let Some(registry_url) = config.registry_url.clone() {
-                Index::from_url(path, registry_url)
-            } else {
-                Index::new(path)
+            match config.registry_url.clone() {
+                Some(registry_url) if Index::is_sparse_url(&registry_url) => Index::from_sparse_url(
+                    path,
+                    registry_url,
+                    config.registry_changelog_url.clone(),
+                ),
+                Some(registry_url) => Index::from_url(path, registry_url),
+                None => Index::new(path),
             }?
         };
         fn registry_api(self) -> RegistryApi = {
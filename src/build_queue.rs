@@ -1,17 +1,32 @@
 use crate::cdn;
-use crate::db::{delete_crate, delete_version, update_latest_version_id, Pool};
-use crate::docbuilder::PackageKind;
+use crate::db::{
+    delete_crate, delete_version, rebuild_crate_items, types::BuildFailureCategory,
+    update_latest_version_id, LimitEscalation, Pool,
+};
+use crate::docbuilder::{Limits, PackageKind};
 use crate::error::Result;
-use crate::storage::Storage;
+use crate::index::{
+    sparse::{CrateFile, SparseIndexState},
+    SparseIndex,
+};
+use crate::storage::{AsyncStorage, Storage};
 use crate::utils::{get_config, get_crate_priority, report_error, retry, set_config, ConfigName};
 use crate::Context;
 use crate::{Config, Index, InstanceMetrics, RustwideBuilder};
 use anyhow::Context as _;
+use chrono::{DateTime, Utc};
 use fn_error_context::context;
-use std::collections::HashMap;
+use postgres::GenericClient;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 use tokio::runtime::Runtime;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+/// Priority of the retries of builds that ran into their limits.
+const ESCALATED_BUILD_PRIORITY: i32 = 20;
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize)]
 pub(crate) struct QueuedCrate {
@@ -21,12 +36,30 @@ pub(crate) struct QueuedCrate {
     pub(crate) version: String,
     pub(crate) priority: i32,
     pub(crate) registry: Option<String>,
+    pub(crate) attempt: i32,
+    /// whether a worker is currently building this crate.
+    pub(crate) building: bool,
+    /// when the next attempt may start after a failed one, see `delay_between_build_attempts`.
+    #[serde(skip)]
+    pub(crate) next_attempt_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub(crate) struct WorkerStatus {
+    pub(crate) name: String,
+    pub(crate) last_heartbeat: DateTime<Utc>,
+    /// whether the worker sent a heartbeat within the lease duration.
+    pub(crate) alive: bool,
+    pub(crate) crate_name: Option<String>,
+    pub(crate) crate_version: Option<String>,
+    pub(crate) build_started_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug)]
 pub struct BuildQueue {
     config: Arc<Config>,
     storage: Arc<Storage>,
+    async_storage: Arc<AsyncStorage>,
     pub(crate) db: Pool,
     metrics: Arc<InstanceMetrics>,
     runtime: Arc<Runtime>,
@@ -39,6 +72,7 @@ impl BuildQueue {
         metrics: Arc<InstanceMetrics>,
         config: Arc<Config>,
         storage: Arc<Storage>,
+        async_storage: Arc<AsyncStorage>,
         runtime: Arc<Runtime>,
     ) -> Self {
         BuildQueue {
@@ -47,6 +81,7 @@ impl BuildQueue {
             db,
             metrics,
             storage,
+            async_storage,
             runtime,
         }
     }
@@ -71,6 +106,19 @@ impl BuildQueue {
         Ok(())
     }
 
+    pub(crate) fn last_seen_sparse_index_state(&self) -> Result<SparseIndexState> {
+        let mut conn = self.db.get()?;
+        Ok(
+            get_config::<SparseIndexState>(&mut conn, ConfigName::LastSeenSparseIndexState)?
+                .unwrap_or_default(),
+        )
+    }
+
+    pub(crate) fn set_last_seen_sparse_index_state(&self, state: &SparseIndexState) -> Result<()> {
+        let mut conn = self.db.get()?;
+        set_config(&mut conn, ConfigName::LastSeenSparseIndexState, state)
+    }
+
     #[context("error trying to add {name}-{version} to build queue")]
     pub fn add_crate(
         &self,
@@ -82,9 +130,8 @@ impl BuildQueue {
         self.db.get()?.execute(
             "INSERT INTO queue (name, version, priority, registry)
              VALUES ($1, $2, $3, $4)
-             ON CONFLICT (name, version) DO UPDATE
+             ON CONFLICT (name, version, (COALESCE(registry, ''))) DO UPDATE
                 SET priority = EXCLUDED.priority,
-                    registry = EXCLUDED.registry,
                     attempt = 0,
                     last_attempt = NULL
             ;",
@@ -122,6 +169,31 @@ impl BuildQueue {
             .collect())
     }
 
+    /// Like [`BuildQueue::pending_count_by_priority`], but only counts the releases a worker
+    /// could start building right now: ones that aren't being built, and aren't waiting for
+    /// their next attempt.
+    pub(crate) fn buildable_count_by_priority(&self) -> Result<HashMap<i32, usize>> {
+        let res = self.db.get()?.query(
+            "SELECT
+                priority,
+                COUNT(*)
+            FROM queue
+            WHERE
+                attempt < $1 AND
+                (last_attempt IS NULL OR last_attempt < NOW() - make_interval(secs => $2)) AND
+                leased_by IS NULL
+            GROUP BY priority",
+            &[
+                &self.max_attempts,
+                &self.config.delay_between_build_attempts.as_secs_f64(),
+            ],
+        )?;
+        Ok(res
+            .iter()
+            .map(|row| (row.get::<_, i32>(0), row.get::<_, i64>(1) as usize))
+            .collect())
+    }
+
     pub(crate) fn failed_count(&self) -> Result<usize> {
         let res = self.db.get()?.query(
             "SELECT COUNT(*) FROM queue WHERE attempt >= $1;",
@@ -132,11 +204,17 @@ impl BuildQueue {
 
     pub(crate) fn queued_crates(&self) -> Result<Vec<QueuedCrate>> {
         let query = self.db.get()?.query(
-            "SELECT id, name, version, priority, registry
+            "SELECT
+                id, name, version, priority, registry, attempt,
+                leased_by IS NOT NULL AS building,
+                last_attempt + make_interval(secs => $2) AS next_attempt_at
              FROM queue
              WHERE attempt < $1
              ORDER BY priority ASC, attempt ASC, id ASC",
-            &[&self.max_attempts],
+            &[
+                &self.max_attempts,
+                &self.config.delay_between_build_attempts.as_secs_f64(),
+            ],
         )?;
 
         Ok(query
@@ -147,6 +225,9 @@ impl BuildQueue {
                 version: row.get("version"),
                 priority: row.get("priority"),
                 registry: row.get("registry"),
+                attempt: row.get("attempt"),
+                building: row.get("building"),
+                next_attempt_at: row.get("next_attempt_at"),
             })
             .collect())
     }
@@ -168,29 +249,49 @@ impl BuildQueue {
             .is_some())
     }
 
-    fn process_next_crate(&self, f: impl FnOnce(&QueuedCrate) -> Result<()>) -> Result<()> {
+    /// Claims the next queued crate for `worker` and calls `f` with it.
+    ///
+    /// The claimed entry is leased to the worker, and the lease is extended in the
+    /// background while `f` runs. Other workers skip leased entries, so several workers
+    /// can build at the same time.
+    fn process_next_crate(
+        &self,
+        worker: &str,
+        f: impl FnOnce(&QueuedCrate) -> Result<()>,
+    ) -> Result<()> {
         let mut conn = self.db.get()?;
-        let mut transaction = conn.transaction()?;
 
-        // fetch the next available crate from the queue table.
-        // We are using `SELECT FOR UPDATE` inside a transaction so
-        // the QueuedCrate is locked until we are finished with it.
-        // `SKIP LOCKED` here will enable another build-server to just
-        // skip over taken (=locked) rows and start building the first
-        // available one.
-        let to_process = match transaction
+        self.reclaim_expired_leases(&mut *conn)?;
+
+        // Claim the next available crate from the queue table.
+        // `SKIP LOCKED` here will enable other workers to just skip over
+        // the row we are claiming and lease the next available one.
+        let to_process = match conn
             .query_opt(
-                "SELECT id, name, version, priority, registry
-                 FROM queue
-                 WHERE
-                    attempt < $1 AND
-                    (last_attempt IS NULL OR last_attempt < NOW() - make_interval(secs => $2))
-                 ORDER BY priority ASC, attempt ASC, id ASC
-                 LIMIT 1
-                 FOR UPDATE SKIP LOCKED",
+                "UPDATE queue
+                 SET
+                    leased_by = $3,
+                    lease_expires_at = NOW() + make_interval(secs => $4)
+                 WHERE id = (
+                    SELECT id
+                    FROM queue
+                    WHERE
+                        attempt < $1 AND
+                        (last_attempt IS NULL OR last_attempt < NOW() - make_interval(secs => $2)) AND
+                        leased_by IS NULL
+                    ORDER BY priority ASC, attempt ASC, id ASC
+                    LIMIT 1
+                    FOR UPDATE SKIP LOCKED
+                 )
+                 RETURNING
+                    id, name, version, priority, registry, attempt,
+                    leased_by IS NOT NULL AS building,
+                    last_attempt + make_interval(secs => $2) AS next_attempt_at",
                 &[
                     &self.max_attempts,
                     &self.config.delay_between_build_attempts.as_secs_f64(),
+                    &worker,
+                    &self.config.build_lease_duration.as_secs_f64(),
                 ],
             )?
             .map(|row| QueuedCrate {
@@ -199,11 +300,22 @@ impl BuildQueue {
                 version: row.get("version"),
                 priority: row.get("priority"),
                 registry: row.get("registry"),
+                attempt: row.get("attempt"),
+                building: row.get("building"),
+                next_attempt_at: row.get("next_attempt_at"),
             }) {
             Some(krate) => krate,
             None => return Ok(()),
         };
 
+        update_worker_status(&mut *conn, worker, Some(&to_process))?;
+
+        let heartbeat = LeaseHeartbeat::start(
+            self.db.clone(),
+            to_process.id,
+            worker,
+            self.config.build_lease_duration,
+        );
         let res = self.metrics.build_time.observe_closure_duration(|| {
             f(&to_process).with_context(|| {
                 format!(
@@ -212,43 +324,242 @@ impl BuildQueue {
                 )
             })
         });
+        drop(heartbeat);
         self.metrics.total_builds.inc();
+
+        let mut transaction = conn.transaction()?;
         if let Err(err) =
             cdn::queue_crate_invalidation(&mut transaction, &self.config, &to_process.name)
         {
             report_error(&err);
         }
 
-        match res {
+        // When the lease was lost in the meantime, the entry was already reclaimed
+        // and will be built again.
+        let lease_lost = match res {
             Ok(()) => {
-                transaction.execute("DELETE FROM queue WHERE id = $1;", &[&to_process.id])?;
+                transaction.execute(
+                    "DELETE FROM queue WHERE id = $1 AND leased_by = $2;",
+                    &[&to_process.id, &worker],
+                )? == 0
             }
             Err(e) => {
                 // Increase attempt count
-                let attempt: i32 = transaction
-                    .query_one(
+                let attempt: Option<i32> = transaction
+                    .query_opt(
                         "UPDATE queue
                          SET
                             attempt = attempt + 1,
-                            last_attempt = NOW()
-                         WHERE id = $1
+                            last_attempt = NOW(),
+                            leased_by = NULL,
+                            lease_expires_at = NULL
+                         WHERE id = $1 AND leased_by = $2
                          RETURNING attempt;",
-                        &[&to_process.id],
+                        &[&to_process.id, &worker],
                     )?
-                    .get(0);
+                    .map(|row| row.get(0));
 
-                if attempt >= self.max_attempts {
+                if attempt.is_some_and(|attempt| attempt >= self.max_attempts) {
                     self.metrics.failed_builds.inc();
                 }
 
                 report_error(&e);
+                attempt.is_none()
             }
+        };
+        if lease_lost {
+            warn!(
+                "{} lost its lease on {}-{} during the build",
+                worker, to_process.name, to_process.version
+            );
         }
 
+        update_worker_status(&mut transaction, worker, None)?;
         transaction.commit()?;
 
         Ok(())
     }
+
+    /// Releases the queue entries of workers that stopped extending their lease,
+    /// for example because the build server crashed.
+    ///
+    /// This counts as a failed attempt, so a release which keeps killing its
+    /// builder isn't retried forever.
+    fn reclaim_expired_leases(&self, conn: &mut impl GenericClient) -> Result<()> {
+        let reclaimed = conn.query(
+            "UPDATE queue
+             SET
+                attempt = attempt + 1,
+                last_attempt = NOW(),
+                leased_by = NULL,
+                lease_expires_at = NULL
+             WHERE lease_expires_at < NOW()
+             RETURNING name, version, attempt",
+            &[],
+        )?;
+
+        for row in reclaimed {
+            let name: String = row.get("name");
+            let version: String = row.get("version");
+            let attempt: i32 = row.get("attempt");
+            warn!("reclaimed expired lease on {}-{}", name, version);
+            if attempt >= self.max_attempts {
+                self.metrics.failed_builds.inc();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that `worker` is alive, and which crate it is building.
+    pub(crate) fn update_worker_status(
+        &self,
+        worker: &str,
+        building: Option<&QueuedCrate>,
+    ) -> Result<()> {
+        update_worker_status(&mut *self.db.get()?, worker, building)
+    }
+
+    /// The status of all build workers that were alive during the last day.
+    pub(crate) fn worker_statuses(&self) -> Result<Vec<WorkerStatus>> {
+        Ok(self
+            .db
+            .get()?
+            .query(
+                "SELECT
+                    name,
+                    last_heartbeat,
+                    last_heartbeat > NOW() - make_interval(secs => $1) AS alive,
+                    crate_name,
+                    crate_version,
+                    build_started_at
+                 FROM build_workers
+                 WHERE last_heartbeat > NOW() - INTERVAL '1 day'
+                 ORDER BY name",
+                &[&self.config.build_lease_duration.as_secs_f64()],
+            )?
+            .into_iter()
+            .map(|row| WorkerStatus {
+                name: row.get("name"),
+                last_heartbeat: row.get("last_heartbeat"),
+                alive: row.get("alive"),
+                crate_name: row.get("crate_name"),
+                crate_version: row.get("crate_version"),
+                build_started_at: row.get("build_started_at"),
+            })
+            .collect())
+    }
+}
+
+/// Whether the release of the crate in `registry` is already in the database, or in the build
+/// queue, where its builds are queued with the `index_url` of the registry.
+fn is_release_known(
+    conn: &mut postgres::Client,
+    registry: Option<&str>,
+    index_url: Option<&str>,
+    name: &str,
+    version: &str,
+) -> Result<bool> {
+    Ok(conn
+        .query_one(
+            "SELECT
+            EXISTS(
+                SELECT 1
+                FROM queue
+                WHERE name = $1 AND version = $2 AND COALESCE(registry, '') = COALESCE($4, '')
+            ) OR
+            EXISTS(
+                SELECT 1
+                FROM releases
+                INNER JOIN crates ON crates.id = releases.crate_id
+                WHERE
+                    crates.name = $1 AND
+                    releases.version = $2 AND
+                    crates.registry IS NOT DISTINCT FROM $3
+            )",
+            &[&name, &version, &registry, &index_url],
+        )
+        .with_context(|| format!("failed to check if {name}-{version} is already known"))?
+        .get(0))
+}
+
+fn update_worker_status(
+    conn: &mut impl GenericClient,
+    worker: &str,
+    building: Option<&QueuedCrate>,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO build_workers (name, last_heartbeat, crate_name, crate_version, build_started_at)
+         VALUES ($1, NOW(), $2, $3, CASE WHEN $2::TEXT IS NULL THEN NULL ELSE NOW() END)
+         ON CONFLICT (name) DO UPDATE
+            SET last_heartbeat = EXCLUDED.last_heartbeat,
+                crate_name = EXCLUDED.crate_name,
+                crate_version = EXCLUDED.crate_version,
+                build_started_at = EXCLUDED.build_started_at",
+        &[
+            &worker,
+            &building.map(|krate| &krate.name),
+            &building.map(|krate| &krate.version),
+        ],
+    )?;
+    Ok(())
+}
+
+/// Extends the lease on a queue entry in the background, until it is dropped.
+struct LeaseHeartbeat {
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl LeaseHeartbeat {
+    fn start(db: Pool, queue_id: i32, worker: &str, lease_duration: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let worker = worker.to_owned();
+        let handle = thread::Builder::new()
+            .name(format!("{worker} lease heartbeat"))
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(lease_duration / 3)
+                {
+                    if let Err(err) = extend_lease(&db, queue_id, &worker, lease_duration)
+                        .with_context(|| format!("failed to extend lease of {worker}"))
+                    {
+                        report_error(&err);
+                    }
+                }
+            })
+            .map_err(|err| error!("could not start lease heartbeat: {}", err))
+            .ok();
+
+        Self {
+            stop: Some(stop),
+            handle,
+        }
+    }
+}
+
+impl Drop for LeaseHeartbeat {
+    fn drop(&mut self) {
+        // closing the channel stops the thread
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn extend_lease(db: &Pool, queue_id: i32, worker: &str, lease_duration: Duration) -> Result<()> {
+    let mut conn = db.get()?;
+    conn.execute(
+        "UPDATE queue
+         SET lease_expires_at = NOW() + make_interval(secs => $3)
+         WHERE id = $1 AND leased_by = $2",
+        &[&queue_id, &worker, &lease_duration.as_secs_f64()],
+    )?;
+    conn.execute(
+        "UPDATE build_workers SET last_heartbeat = NOW() WHERE name = $1",
+        &[&worker],
+    )?;
+    Ok(())
 }
 
 /// Locking functions.
@@ -277,8 +588,39 @@ impl BuildQueue {
 impl BuildQueue {
     /// Updates registry index repository and adds new crates into build queue.
     ///
-    /// Returns the number of crates added
+    /// Only one update per machine runs at a time. When an update is already running, this
+    /// asks it to run once more after it's done instead of waiting for it, so any number of
+    /// concurrent requests results in at most one more update. Releases that are already
+    /// queued or built are skipped, so processing the same changes again doesn't queue them
+    /// twice.
+    ///
+    /// Returns the number of crates added by the updates that ran in this call.
     pub fn get_new_crates(&self, index: &Index) -> Result<usize> {
+        index.request_update()?;
+
+        let mut crates_added = 0;
+        // whoever holds the lock takes the requests made while it's running, we only have to
+        // check for requests again after we released the lock ourselves.
+        while index.update_requested() {
+            let Some(_lock) = index.try_lock()? else {
+                debug!("the build queue is already being updated from the index");
+                break;
+            };
+            while index.take_update_request()? {
+                crates_added += self.update_from_index(index)?;
+            }
+        }
+        Ok(crates_added)
+    }
+
+    /// Adds the changes of the index since the last update to the build queue.
+    ///
+    /// Has to be called while holding the [`Index::try_lock`] lock.
+    fn update_from_index(&self, index: &Index) -> Result<usize> {
+        if let Some(sparse) = index.sparse() {
+            return self.get_new_crates_from_sparse_index(sparse, index.repository_url());
+        }
+
         let mut conn = self.db.get()?;
         let diff = index.diff()?;
 
@@ -294,7 +636,7 @@ impl BuildQueue {
 
         for change in &changes {
             if let Some((ref krate, ..)) = change.crate_deleted() {
-                match delete_crate(&mut conn, &self.storage, &self.config, krate)
+                match delete_crate(&mut conn, &self.storage, &self.config, None, krate)
                     .with_context(|| format!("failed to delete crate {krate}"))
                 {
                     Ok(_) => info!(
@@ -314,6 +656,7 @@ impl BuildQueue {
                     &mut conn,
                     &self.storage,
                     &self.config,
+                    None,
                     &release.name,
                     &release.version,
                 )
@@ -323,10 +666,15 @@ impl BuildQueue {
                         release.name, release.version
                     )
                 }) {
-                    Ok(_) => info!(
-                        "release {}-{} was deleted from the index and the database",
-                        release.name, release.version
-                    ),
+                    Ok(_) => {
+                        info!(
+                            "release {}-{} was deleted from the index and the database",
+                            release.name, release.version
+                        );
+                        if let Err(err) = self.rebuild_crate_items(None, &release.name) {
+                            report_error(&err);
+                        }
+                    }
                     Err(err) => report_error(&err),
                 }
                 if let Err(err) =
@@ -337,7 +685,21 @@ impl BuildQueue {
                 continue;
             }
 
-            if let Some(release) = change.added() {
+            let added = match change.added() {
+                Some(release)
+                    if !is_release_known(
+                        &mut conn,
+                        None,
+                        index.repository_url(),
+                        &release.name,
+                        &release.version,
+                    )? =>
+                {
+                    Some(release)
+                }
+                _ => None,
+            };
+            if let Some(release) = added {
                 let priority = get_crate_priority(&mut conn, &release.name)?;
 
                 match self
@@ -372,6 +734,7 @@ impl BuildQueue {
                 // https://github.com/rust-lang/docs.rs/issues/1934
                 if let Err(err) = self.set_yanked(
                     &mut conn,
+                    None,
                     release.name.as_str(),
                     release.version.as_str(),
                     yanked.is_some(),
@@ -395,10 +758,169 @@ impl BuildQueue {
         Ok(crates_added)
     }
 
+    /// Adds new releases from a sparse index into the build queue, and updates yanked releases.
+    ///
+    /// Unlike with the git index, releases missing from the index aren't deleted, since
+    /// a missing file doesn't tell us whether the crate was really removed. Crates whose
+    /// changes couldn't be processed are tried again on the next call.
+    fn get_new_crates_from_sparse_index(
+        &self,
+        index: &SparseIndex,
+        index_url: Option<&str>,
+    ) -> Result<usize> {
+        let mut conn = self.db.get()?;
+        let mut state = self.last_seen_sparse_index_state()?;
+
+        // the index can be the one of a configured alternative registry
+        let registry = index_url.and_then(|url| self.config.registry_for_index(url));
+        let index_url = registry
+            .map(|registry| registry.index_url.as_str())
+            .or(index_url);
+        let registry = registry.map(|registry| registry.name.as_str());
+
+        let changed: BTreeSet<String> = match self.runtime.block_on(index.changelog())? {
+            Some(changelog) => {
+                let changed = state
+                    .new_changelog_lines(&changelog)
+                    .iter()
+                    .cloned()
+                    .collect();
+                state.set_processed_changelog(&changelog);
+                changed
+            }
+            // the files of unchanged crates aren't downloaded again thanks to their `ETag`
+            None => conn
+                .query(
+                    "SELECT name FROM crates WHERE registry IS NOT DISTINCT FROM $1
+                     UNION
+                     SELECT name FROM queue WHERE COALESCE(registry, '') = COALESCE($2, '')",
+                    &[&registry, &index_url],
+                )?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect(),
+        };
+        let names: BTreeSet<String> = changed
+            .into_iter()
+            .chain(std::mem::take(&mut state.failed))
+            .collect();
+
+        debug!("checking {} crates in the sparse index", names.len());
+
+        let mut crates_added = 0;
+        for name in names {
+            match self
+                .update_crate_from_sparse_index(&mut conn, index, registry, index_url, &name)
+                .with_context(|| format!("failed to process the index file of {name}"))
+            {
+                Ok(added) => crates_added += added,
+                Err(err) => {
+                    report_error(&err);
+                    state.failed.insert(name);
+                }
+            }
+        }
+
+        self.set_last_seen_sparse_index_state(&state)?;
+
+        Ok(crates_added)
+    }
+
+    /// Adds the new releases of a crate in a sparse index into the build queue, and updates
+    /// its yanked releases. Returns the number of releases added.
+    ///
+    /// The `ETag` of the crate file is only stored after all of its changes were processed,
+    /// so a file with changes that failed is downloaded again.
+    fn update_crate_from_sparse_index(
+        &self,
+        conn: &mut postgres::Client,
+        index: &SparseIndex,
+        registry: Option<&str>,
+        index_url: Option<&str>,
+        name: &str,
+    ) -> Result<usize> {
+        let etag: Option<String> = conn
+            .query_opt(
+                "SELECT etag
+                 FROM sparse_index_etags
+                 WHERE crate_name = $1 AND registry IS NOT DISTINCT FROM $2",
+                &[&name, &registry],
+            )?
+            .map(|row| row.get(0));
+
+        let (versions, etag) = match self
+            .runtime
+            .block_on(index.fetch_crate(name, etag.as_deref()))?
+        {
+            CrateFile::Unchanged => return Ok(0),
+            CrateFile::NotFound => {
+                warn!("crate {} is not in the sparse index", name);
+                conn.execute(
+                    "DELETE FROM sparse_index_etags
+                     WHERE crate_name = $1 AND registry IS NOT DISTINCT FROM $2",
+                    &[&name, &registry],
+                )?;
+                return Ok(0);
+            }
+            CrateFile::Changed { versions, etag } => (versions, etag),
+        };
+
+        let built_versions: HashMap<String, bool> = conn
+            .query(
+                "SELECT releases.version, releases.yanked
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE crates.name = $1 AND crates.registry IS NOT DISTINCT FROM $2",
+                &[&name, &registry],
+            )?
+            .into_iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+
+        let mut crates_added = 0;
+        for release in versions {
+            if let Some(&yanked) = built_versions.get(&release.vers) {
+                if yanked != release.yanked {
+                    self.set_yanked(conn, registry, &release.name, &release.vers, release.yanked)?;
+                    cdn::queue_crate_invalidation(&mut *conn, &self.config, &release.name)?;
+                }
+                continue;
+            }
+
+            if is_release_known(conn, registry, index_url, &release.name, &release.vers)? {
+                continue;
+            }
+
+            let priority = get_crate_priority(conn, &release.name)?;
+            self.add_crate(&release.name, &release.vers, priority, index_url)?;
+            debug!("{}-{} added into build queue", release.name, release.vers);
+            self.metrics.queued_builds.inc();
+            crates_added += 1;
+        }
+
+        match etag {
+            Some(etag) => conn.execute(
+                "INSERT INTO sparse_index_etags (crate_name, registry, etag)
+                 VALUES ($1, $2, $3)
+                 ON CONFLICT (crate_name, (COALESCE(registry, '')))
+                    DO UPDATE SET etag = EXCLUDED.etag",
+                &[&name, &registry, &etag],
+            )?,
+            None => conn.execute(
+                "DELETE FROM sparse_index_etags
+                 WHERE crate_name = $1 AND registry IS NOT DISTINCT FROM $2",
+                &[&name, &registry],
+            )?,
+        };
+
+        Ok(crates_added)
+    }
+
     #[context("error trying to set {name}-{version} to yanked: {yanked}")]
     pub fn set_yanked(
         &self,
         conn: &mut postgres::Client,
+        registry: Option<&str>,
         name: &str,
         version: &str,
         yanked: bool,
@@ -412,9 +934,10 @@ impl BuildQueue {
              WHERE crates.id = releases.crate_id
                  AND name = $1
                  AND version = $2
+                 AND crates.registry IS NOT DISTINCT FROM $4
             RETURNING crates.id
             ",
-            &[&name, &version, &yanked],
+            &[&name, &version, &yanked, &registry],
         )?;
         if result.len() != 1 {
             match self
@@ -442,16 +965,45 @@ impl BuildQueue {
         if let Some(row) = result.first() {
             let crate_id: i32 = row.get(0);
 
-            self.runtime.block_on(async {
+            let latest_changed = self.runtime.block_on(async {
                 let mut conn = self.db.get_async().await?;
 
                 update_latest_version_id(&mut conn, crate_id).await
             })?;
+
+            if latest_changed {
+                if let Err(err) = self.runtime.block_on(async {
+                    let mut conn = self.db.get_async().await?;
+                    rebuild_crate_items(&mut conn, &self.async_storage, crate_id).await
+                }) {
+                    report_error(&err);
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Replaces the items of the crate in the index for searches across all crates with the
+    /// items of its latest release, after releases of it were deleted.
+    pub fn rebuild_crate_items(&self, registry: Option<&str>, name: &str) -> Result<()> {
+        self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+            let Some(crate_id) = sqlx::query_scalar!(
+                "SELECT id FROM crates WHERE name = $1 AND registry IS NOT DISTINCT FROM $2",
+                name,
+                registry,
+            )
+            .fetch_optional(&mut *conn)
+            .await?
+            else {
+                return Ok(());
+            };
+
+            rebuild_crate_items(&mut conn, &self.async_storage, crate_id).await
+        })
+    }
+
     fn update_toolchain(&self, builder: &mut RustwideBuilder) -> Result<()> {
         let updated = retry(
             || {
@@ -488,9 +1040,11 @@ impl BuildQueue {
         &self,
         context: &dyn Context,
         builder: &mut RustwideBuilder,
+        worker: &str,
     ) -> Result<bool> {
         let mut processed = false;
-        self.process_next_crate(|krate| {
+        let mut built = None;
+        self.process_next_crate(worker, |krate| {
             processed = true;
 
             let kind = krate
@@ -522,18 +1076,200 @@ impl BuildQueue {
             }
 
             builder.build_package(&krate.name, &krate.version, kind)?;
+            built = Some(krate.clone());
             Ok(())
         })?;
 
+        // this has to happen after the finished build was removed from the queue.
+        if let Some(krate) = built {
+            if self.config.build_limit_escalation {
+                if let Err(err) = self
+                    .escalate_limits(&krate.name, &krate.version, krate.registry.as_deref())
+                    .with_context(|| {
+                        format!(
+                            "failed to escalate limits for {}-{}",
+                            krate.name, krate.version
+                        )
+                    })
+                {
+                    report_error(&err);
+                }
+            }
+        }
+
         Ok(processed)
     }
+
+    /// Re-queues a release whose last build failed because of a timeout or running out
+    /// of memory, with higher limits.
+    ///
+    /// Each release is only escalated once. Returns whether the release was queued again.
+    fn escalate_limits(&self, name: &str, version: &str, registry: Option<&str>) -> Result<bool> {
+        let registry_name = registry
+            .and_then(|index_url| self.config.registry_for_index(index_url))
+            .map(|registry| registry.name.as_str());
+
+        let escalated = self.runtime.block_on(async {
+            let mut conn = self.db.get_async().await?;
+
+            let failure_category = sqlx::query_scalar!(
+                r#"SELECT builds.failure_category as "failure_category: BuildFailureCategory"
+                 FROM builds
+                 INNER JOIN releases ON releases.id = builds.rid
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE
+                    crates.name = $1 AND
+                    releases.version = $2 AND
+                    crates.registry IS NOT DISTINCT FROM $3
+                 ORDER BY builds.id DESC
+                 LIMIT 1"#,
+                name,
+                version,
+                registry_name,
+            )
+            .fetch_optional(&mut *conn)
+            .await?
+            .flatten();
+
+            let Some(category) = failure_category else {
+                return Ok::<_, anyhow::Error>(false);
+            };
+
+            let limits =
+                Limits::for_release(&self.config, &mut conn, registry_name, name, version).await?;
+            let Some(overrides) = limits.escalate(&self.config, category) else {
+                debug!("can't escalate limits for {name}-{version} after {category}");
+                return Ok(false);
+            };
+
+            LimitEscalation::save(&mut conn, registry_name, name, version, category, overrides)
+                .await
+        })?;
+
+        if escalated {
+            info!("re-queueing {name}-{version} with escalated limits");
+            self.add_crate(name, version, ESCALATED_BUILD_PRIORITY, registry)?;
+        }
+        Ok(escalated)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::{DateTime, Utc};
-    use std::time::Duration;
+    use crate::{
+        db::update_crate_items,
+        test::FakeBuild,
+        utils::search_index::{desc_shard_path, TEST_DESC_SHARDS, TEST_SEARCH_INDEX},
+    };
+
+    const WORKER: &str = "test-worker";
+
+    #[test]
+    fn escalate_limits_after_timeout() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+
+            env.fake_release()
+                .name("slow")
+                .version("0.1.0")
+                .builds(vec![
+                    FakeBuild::default().failure(BuildFailureCategory::Timeout, None)
+                ])
+                .create()?;
+
+            assert!(queue.escalate_limits("slow", "0.1.0", None)?);
+
+            let queued_crates = queue.queued_crates()?;
+            assert_eq!(queued_crates.len(), 1);
+            assert_eq!(queued_crates[0].name, "slow");
+            assert_eq!(queued_crates[0].priority, ESCALATED_BUILD_PRIORITY);
+
+            let escalation = env.runtime().block_on(async {
+                let mut conn = env.async_db().await.async_conn().await;
+                LimitEscalation::for_release(&mut conn, None, "slow", "0.1.0").await
+            })?;
+            assert_eq!(
+                escalation.unwrap().failure_category,
+                BuildFailureCategory::Timeout
+            );
+
+            // the release is only retried once
+            queue.process_next_crate(WORKER, |_| Ok(()))?;
+            assert!(!queue.escalate_limits("slow", "0.1.0", None)?);
+            assert!(queue.queued_crates()?.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn dont_escalate_limits_after_compile_error() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+
+            env.fake_release()
+                .name("broken")
+                .version("0.1.0")
+                .builds(vec![
+                    FakeBuild::default().failure(BuildFailureCategory::CompileError, None)
+                ])
+                .create()?;
+            env.fake_release().name("fine").version("0.1.0").create()?;
+
+            assert!(!queue.escalate_limits("broken", "0.1.0", None)?);
+            assert!(!queue.escalate_limits("fine", "0.1.0", None)?);
+            assert!(queue.queued_crates()?.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn yanking_the_latest_release_rebuilds_crate_items() {
+        crate::test::wrapper(|env| {
+            let crate_page = br#"<html><head><meta name="rustdoc-vars" data-resource-suffix="-20240401-1.79.0-nightly-abcdef"></head></html>"#;
+            let mut fake_release = env
+                .fake_release()
+                .name("foo")
+                .version("1.0.0")
+                .archive_storage(true)
+                .rustdoc_file_with("foo/index.html", crate_page.to_vec())
+                .rustdoc_file_with(
+                    "search-index-20240401-1.79.0-nightly-abcdef.js",
+                    TEST_SEARCH_INDEX.as_bytes().to_vec(),
+                );
+            let shards: Vec<_> = (0..TEST_DESC_SHARDS.len())
+                .map(|shard| desc_shard_path("foo", shard, "-20240401-1.79.0-nightly-abcdef"))
+                .collect();
+            for (path, shard) in shards.iter().zip(TEST_DESC_SHARDS) {
+                fake_release = fake_release.rustdoc_file_with(path, shard.as_bytes().to_vec());
+            }
+            let old_release = fake_release.create()?;
+            let latest_release = env.fake_release().name("foo").version("1.1.0").create()?;
+            env.runtime().block_on(async {
+                let mut conn = env.async_db().await.async_conn().await;
+                update_crate_items(&mut conn, latest_release, &[]).await
+            })?;
+
+            let queue = env.build_queue();
+            let mut conn = env.db().conn();
+            queue.set_yanked(&mut conn, None, "foo", "1.1.0", true)?;
+
+            let items: Vec<(i32, String)> = conn
+                .query(
+                    "SELECT release_id, path FROM crate_items ORDER BY path",
+                    &[],
+                )?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            assert!(items.contains(&(old_release, "foo::bar::Baz".into())));
+            assert!(items.iter().all(|(release, _)| *release == old_release));
+
+            Ok(())
+        })
+    }
 
     #[test]
     fn test_add_duplicate_doesnt_fail_last_priority_wins() {
@@ -620,12 +1356,12 @@ mod tests {
             queue.add_crate("krate", "1.0.0", 0, None)?;
 
             // first let it fail
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!(krate.name, "krate");
                 anyhow::bail!("simulate a failure");
             })?;
 
-            queue.process_next_crate(|_| {
+            queue.process_next_crate(WORKER, |_| {
                 // this can't happen since we didn't wait between attempts
                 unreachable!();
             })?;
@@ -641,7 +1377,7 @@ mod tests {
 
             let mut handled = false;
             // now we can process it again
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!(krate.name, "krate");
                 handled = true;
                 Ok(())
@@ -678,14 +1414,14 @@ mod tests {
             }
 
             let assert_next = |name| -> Result<()> {
-                queue.process_next_crate(|krate| {
+                queue.process_next_crate(WORKER, |krate| {
                     assert_eq!(name, krate.name);
                     Ok(())
                 })?;
                 Ok(())
             };
             let assert_next_and_fail = |name| -> Result<()> {
-                queue.process_next_crate(|krate| {
+                queue.process_next_crate(WORKER, |krate| {
                     assert_eq!(name, krate.name);
                     anyhow::bail!("simulate a failure");
                 })?;
@@ -718,7 +1454,7 @@ mod tests {
             // Since low-priority failed many times it will be removed from the queue. Because of
             // that the queue should now be empty.
             let mut called = false;
-            queue.process_next_crate(|_| {
+            queue.process_next_crate(WORKER, |_| {
                 called = true;
                 Ok(())
             })?;
@@ -753,7 +1489,7 @@ mod tests {
             let mut conn = env.db().conn();
             cdn::queued_or_active_crate_invalidations(&mut *conn)?.is_empty();
 
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!("will_succeed", krate.name);
                 Ok(())
             })?;
@@ -764,7 +1500,7 @@ mod tests {
                 .iter()
                 .all(|i| i.krate == "will_succeed"));
 
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!("will_fail", krate.name);
                 anyhow::bail!("simulate a failure");
             })?;
@@ -791,7 +1527,7 @@ mod tests {
             queue.add_crate("bar", "1.0.0", 0, None)?;
             assert_eq!(queue.pending_count()?, 2);
 
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!("foo", krate.name);
                 Ok(())
             })?;
@@ -814,7 +1550,7 @@ mod tests {
             queue.add_crate("baz", "1.0.0", 100, None)?;
             assert_eq!(queue.prioritized_count()?, 2);
 
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!("bar", krate.name);
                 Ok(())
             })?;
@@ -841,7 +1577,7 @@ mod tests {
             );
 
             while queue.pending_count()? > 0 {
-                queue.process_next_crate(|_| Ok(()))?;
+                queue.process_next_crate(WORKER, |_| Ok(()))?;
             }
             assert!(queue.pending_count_by_priority()?.is_empty());
 
@@ -866,14 +1602,14 @@ mod tests {
 
             for _ in 0..MAX_ATTEMPTS {
                 assert_eq!(queue.failed_count()?, 0);
-                queue.process_next_crate(|krate| {
+                queue.process_next_crate(WORKER, |krate| {
                     assert_eq!("foo", krate.name);
                     anyhow::bail!("this failed");
                 })?;
             }
             assert_eq!(queue.failed_count()?, 1);
 
-            queue.process_next_crate(|krate| {
+            queue.process_next_crate(WORKER, |krate| {
                 assert_eq!("bar", krate.name);
                 Ok(())
             })?;
@@ -965,4 +1701,424 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn leased_crates_are_skipped_by_other_workers() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("first", "1.0.0", 0, None)?;
+            queue.add_crate("second", "1.0.0", 0, None)?;
+
+            let mut built = Vec::new();
+            queue.process_next_crate(WORKER, |krate| {
+                built.push(krate.name.clone());
+
+                queue.process_next_crate("other-worker", |krate| {
+                    built.push(krate.name.clone());
+
+                    let statuses = queue.worker_statuses()?;
+                    assert_eq!(statuses.len(), 2);
+                    assert!(statuses.iter().all(|status| status.alive));
+                    assert_eq!(statuses[0].name, "other-worker");
+                    assert_eq!(statuses[0].crate_name.as_deref(), Some("second"));
+                    assert_eq!(statuses[1].name, WORKER);
+                    assert_eq!(statuses[1].crate_name.as_deref(), Some("first"));
+                    Ok(())
+                })
+            })?;
+
+            assert_eq!(built, ["first", "second"]);
+            assert!(queue.queued_crates()?.is_empty());
+
+            // both workers are idle again
+            let statuses = queue.worker_statuses()?;
+            assert_eq!(statuses.len(), 2);
+            assert!(statuses
+                .iter()
+                .all(|status| status.crate_name.is_none() && status.build_started_at.is_none()));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn expired_leases_are_reclaimed() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.delay_between_build_attempts = Duration::ZERO;
+            });
+            let queue = env.build_queue();
+            queue.add_crate("krate", "1.0.0", 0, None)?;
+            queue.add_crate("other", "1.0.0", 0, None)?;
+
+            let mut conn = env.db().conn();
+            conn.execute(
+                "UPDATE queue
+                 SET leased_by = 'dead-worker', lease_expires_at = NOW() + INTERVAL '1 minute'
+                 WHERE name = 'krate'",
+                &[],
+            )?;
+
+            // the entry is still leased, so the worker skips it
+            queue.process_next_crate(WORKER, |krate| {
+                assert_eq!(krate.name, "other");
+                Ok(())
+            })?;
+            assert_eq!(queue.queued_crates()?.len(), 1);
+
+            conn.execute(
+                "UPDATE queue SET lease_expires_at = NOW() - INTERVAL '1 minute'",
+                &[],
+            )?;
+
+            let mut handled = false;
+            queue.process_next_crate(WORKER, |krate| {
+                assert_eq!(krate.name, "krate");
+                handled = true;
+
+                // the lost lease counts as failed attempt
+                let row = env.db().conn().query_one(
+                    "SELECT attempt, leased_by FROM queue WHERE name = 'krate'",
+                    &[],
+                )?;
+                assert_eq!(row.get::<_, i32>(0), 1);
+                assert_eq!(row.get::<_, Option<String>>(1).as_deref(), Some(WORKER));
+                Ok(())
+            })?;
+            assert!(handled);
+            assert!(queue.queued_crates()?.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn failed_build_releases_lease() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("krate", "1.0.0", 0, None)?;
+
+            queue.process_next_crate(WORKER, |_| anyhow::bail!("simulate a failure"))?;
+
+            let row = env.db().conn().query_one(
+                "SELECT attempt, leased_by, lease_expires_at IS NULL FROM queue",
+                &[],
+            )?;
+            assert_eq!(row.get::<_, i32>(0), 1);
+            assert_eq!(row.get::<_, Option<String>>(1), None);
+            assert!(row.get::<_, bool>(2));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn worker_status_without_build() {
+        crate::test::wrapper(|env| {
+            let queue = env.build_queue();
+            assert!(queue.worker_statuses()?.is_empty());
+
+            queue.update_worker_status(WORKER, None)?;
+
+            let statuses = queue.worker_statuses()?;
+            assert_eq!(statuses.len(), 1);
+            assert_eq!(statuses[0].name, WORKER);
+            assert!(statuses[0].alive);
+            assert_eq!(statuses[0].crate_name, None);
+
+            env.db().conn().execute(
+                "UPDATE build_workers SET last_heartbeat = NOW() - INTERVAL '1 hour'",
+                &[],
+            )?;
+            assert!(!queue.worker_statuses()?[0].alive);
+
+            Ok(())
+        })
+    }
+
+    fn sparse_index_file(versions: &[(&str, bool)]) -> String {
+        versions
+            .iter()
+            .map(|(version, yanked)| {
+                format!(
+                    "{}\n",
+                    serde_json::json!({
+                        "name": "foo",
+                        "vers": version,
+                        "deps": [],
+                        "cksum": "0".repeat(64),
+                        "features": {},
+                        "yanked": yanked,
+                    })
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn get_new_crates_from_sparse_index_changelog() {
+        crate::test::wrapper(|env| {
+            let mut registry = mockito::Server::new();
+            let registry_url = format!("sparse+{}/index", registry.url());
+            env.override_config(|config| {
+                config.registry_url = Some(registry_url.clone());
+                config.registry_changelog_url =
+                    Some(format!("{}/changelog", registry.url()).parse().unwrap());
+            });
+
+            let changelog = registry
+                .mock("GET", "/changelog")
+                .with_body("foo\nbar\nfoo\n")
+                .expect(2)
+                .create();
+            let foo = registry
+                .mock("GET", "/index/3/f/foo")
+                .with_header("etag", "\"foo-v1\"")
+                .with_body(sparse_index_file(&[("0.1.0", true), ("0.2.0", false)]))
+                .expect(1)
+                .create();
+            let bar = registry
+                .mock("GET", "/index/3/b/bar")
+                .with_status(404)
+                .expect(1)
+                .create();
+
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let queue = env.build_queue();
+            assert_eq!(queue.get_new_crates(&env.index())?, 1);
+
+            let queued = queue.queued_crates()?;
+            assert_eq!(queued.len(), 1);
+            assert_eq!(queued[0].name, "foo");
+            assert_eq!(queued[0].version, "0.2.0");
+            assert_eq!(queued[0].registry.as_deref(), Some(registry_url.as_str()));
+
+            let yanked: bool = env
+                .db()
+                .conn()
+                .query_one("SELECT yanked FROM releases", &[])?
+                .get(0);
+            assert!(yanked);
+
+            let state = queue.last_seen_sparse_index_state()?;
+            assert_eq!(state.changelog_lines, 3);
+            assert!(state.failed.is_empty());
+            let etag: String = env
+                .db()
+                .conn()
+                .query_one(
+                    "SELECT etag FROM sparse_index_etags WHERE crate_name = 'foo'",
+                    &[],
+                )?
+                .get(0);
+            assert_eq!(etag, "\"foo-v1\"");
+
+            // nothing new in the changelog, so no crate files are fetched
+            assert_eq!(queue.get_new_crates(&env.index())?, 0);
+
+            changelog.assert();
+            foo.assert();
+            bar.assert();
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn get_new_crates_from_sparse_index_retries_failed_crates() {
+        crate::test::wrapper(|env| {
+            let mut registry = mockito::Server::new();
+            env.override_config(|config| {
+                config.registry_url = Some(format!("sparse+{}", registry.url()));
+                config.registry_changelog_url =
+                    Some(format!("{}/changelog", registry.url()).parse().unwrap());
+            });
+
+            let _changelog = registry
+                .mock("GET", "/changelog")
+                .with_body("foo\n")
+                .create();
+            let failing = registry
+                .mock("GET", "/3/f/foo")
+                .with_status(500)
+                .expect(1)
+                .create();
+
+            let queue = env.build_queue();
+            assert_eq!(queue.get_new_crates(&env.index())?, 0);
+            failing.assert();
+            let state = queue.last_seen_sparse_index_state()?;
+            assert_eq!(state.changelog_lines, 1);
+            assert_eq!(state.failed, ["foo".to_owned()].into());
+
+            // the crate is fetched again even though it's not in the new part of the changelog
+            failing.remove();
+            let _foo = registry
+                .mock("GET", "/3/f/foo")
+                .with_header("etag", "\"foo-v1\"")
+                .with_body(sparse_index_file(&[("0.1.0", false)]))
+                .expect(1)
+                .create();
+
+            assert_eq!(queue.get_new_crates(&env.index())?, 1);
+            assert_eq!(queue.queued_crates()?[0].name, "foo");
+            assert!(queue.last_seen_sparse_index_state()?.failed.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn get_new_crates_from_sparse_index_without_changelog() {
+        crate::test::wrapper(|env| {
+            let mut registry = mockito::Server::new();
+            env.override_config(|config| {
+                config.registry_url = Some(format!("sparse+{}", registry.url()));
+                config.registry_changelog_url = None;
+            });
+
+            let foo = registry
+                .mock("GET", "/3/f/foo")
+                .match_header("if-none-match", mockito::Matcher::Missing)
+                .with_header("etag", "\"foo-v1\"")
+                .with_body(sparse_index_file(&[("0.1.0", false), ("0.2.0", false)]))
+                .expect(1)
+                .create();
+            let unchanged = registry
+                .mock("GET", "/3/f/foo")
+                .match_header("if-none-match", "\"foo-v1\"")
+                .with_status(304)
+                .expect(1)
+                .create();
+
+            // only the crates we know are polled
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let queue = env.build_queue();
+            assert_eq!(queue.get_new_crates(&env.index())?, 1);
+            assert_eq!(queue.queued_crates()?[0].version, "0.2.0");
+            assert_eq!(queue.get_new_crates(&env.index())?, 0);
+
+            foo.assert();
+            unchanged.assert();
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn get_new_crates_from_sparse_index_of_alternative_registry() {
+        crate::test::wrapper(|env| {
+            let mut registry = mockito::Server::new();
+            let index_url = format!("sparse+{}/", registry.url());
+            env.override_config(|config| {
+                config.registry_url = Some(index_url.clone());
+                config.registry_changelog_url =
+                    Some(format!("{}/changelog", registry.url()).parse().unwrap());
+                config.registries = vec![crate::registries::Registry {
+                    name: "internal".into(),
+                    index_url: index_url.clone(),
+                    api_host: registry.url().parse().unwrap(),
+                }];
+            });
+
+            let _changelog = registry
+                .mock("GET", "/changelog")
+                .with_body("foo\n")
+                .create();
+            let _foo = registry
+                .mock("GET", "/3/f/foo")
+                .with_header("etag", "\"foo-v1\"")
+                .with_body(sparse_index_file(&[("0.1.0", true), ("0.2.0", false)]))
+                .create();
+
+            // a crates.io crate with the same name isn't touched
+            env.fake_release().name("foo").version("0.1.0").create()?;
+            env.fake_release()
+                .registry("internal")
+                .name("foo")
+                .version("0.1.0")
+                .create()?;
+
+            let queue = env.build_queue();
+            assert_eq!(queue.get_new_crates(&env.index())?, 1);
+            let queued = queue.queued_crates()?;
+            assert_eq!(queued.len(), 1);
+            assert_eq!(queued[0].version, "0.2.0");
+            assert_eq!(queued[0].registry.as_deref(), Some(index_url.as_str()));
+
+            let mut conn = env.db().conn();
+            let yanked: Vec<(Option<String>, bool)> = conn
+                .query(
+                    "SELECT crates.registry, releases.yanked
+                     FROM releases
+                     INNER JOIN crates ON crates.id = releases.crate_id
+                     ORDER BY crates.registry NULLS FIRST",
+                    &[],
+                )?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            assert_eq!(yanked, vec![(None, false), (Some("internal".into()), true)]);
+
+            let etags: Vec<(Option<String>, String)> = conn
+                .query(
+                    "SELECT registry, etag FROM sparse_index_etags WHERE crate_name = 'foo'",
+                    &[],
+                )?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            assert_eq!(etags, vec![(Some("internal".into()), "\"foo-v1\"".into())]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn get_new_crates_while_already_updating() {
+        crate::test::wrapper(|env| {
+            let mut registry = mockito::Server::new();
+            env.override_config(|config| {
+                config.registry_url = Some(format!("sparse+{}", registry.url()));
+                config.registry_changelog_url =
+                    Some(format!("{}/changelog", registry.url()).parse().unwrap());
+            });
+            let _changelog = registry
+                .mock("GET", "/changelog")
+                .with_body("foo\n")
+                .create();
+            let foo = registry
+                .mock("GET", "/3/f/foo")
+                .with_body(sparse_index_file(&[("0.1.0", false), ("0.2.0", false)]))
+                .expect(1)
+                .create();
+
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let index = env.index();
+            let queue = env.build_queue();
+
+            // requests made while an update is running are left to it
+            let _lock = index.try_lock()?.unwrap();
+            assert!(index.try_lock()?.is_none());
+            assert_eq!(queue.get_new_crates(&index)?, 0);
+            assert_eq!(queue.get_new_crates(&index)?, 0);
+            assert!(index.update_requested());
+            assert!(queue.queued_crates()?.is_empty());
+
+            // which handles all of them with a single update
+            let mut updates = 0;
+            while index.take_update_request()? {
+                assert_eq!(queue.update_from_index(&index)?, 1);
+                updates += 1;
+            }
+            assert_eq!(updates, 1);
+            assert!(!index.update_requested());
+            foo.assert();
+
+            Ok(())
+        })
+    }
 }
@@ -1,4 +1,9 @@
-use crate::{cdn::CdnKind, storage::StorageKind};
+use crate::{
+    cdn::CdnKind,
+    registries::{load_registries, Registry},
+    storage::StorageKind,
+    web::CrateSearchBackend,
+};
 use anyhow::{anyhow, bail, Context, Result};
 use std::{env::VarError, error::Error, path::PathBuf, str::FromStr, time::Duration};
 use tracing::trace;
@@ -9,7 +14,11 @@ pub struct Config {
     pub prefix: PathBuf,
     pub registry_index_path: PathBuf,
     pub registry_url: Option<String>,
+    /// changelog of a sparse registry index, see `index::sparse`.
+    pub registry_changelog_url: Option<Url>,
     pub registry_api_host: Url,
+    /// alternative registries next to crates.io, see `registries`.
+    pub(crate) registries: Vec<Registry>,
 
     // Database connection params
     pub(crate) database_url: String,
@@ -20,6 +29,9 @@ pub struct Config {
     // Storage params
     pub(crate) storage_backend: StorageKind,
 
+    // Filesystem storage params
+    pub(crate) storage_filesystem_root: PathBuf,
+
     // AWS SDK configuration
     pub(crate) aws_sdk_max_retries: u32,
 
@@ -55,6 +67,9 @@ pub struct Config {
     pub(crate) max_parse_memory: usize,
     // Time between 'git gc --auto' calls in seconds
     pub(crate) registry_gc_interval: u64,
+    // Secret used to sign the requests to the index webhook.
+    // The webhook is disabled without it.
+    pub(crate) index_webhook_secret: Option<String>,
 
     /// amount of threads for CPU intensive rendering
     pub(crate) render_threads: usize,
@@ -68,10 +83,27 @@ pub struct Config {
     // For unit-tests the number has to be higher.
     pub(crate) random_crate_search_view_size: u32,
 
+    // where crate searches are answered: the crates.io search API, or our own database
+    // for mirrors that can't reach crates.io
+    pub(crate) crate_search_backend: CrateSearchBackend,
+
     // where do we want to store the locally cached index files
     // for the remote archives?
     pub(crate) local_archive_cache_path: PathBuf,
 
+    // the size the local archive index cache can grow to before the least recently used
+    // indexes are removed, in bytes
+    pub(crate) local_archive_cache_max_size: u64,
+
+    // in-memory cache of the files loaded from the storage, in bytes, 0 disables it
+    pub(crate) storage_memory_cache_size: usize,
+    // how long files stay in the in-memory cache, since other processes can change them
+    pub(crate) storage_memory_cache_ttl: Duration,
+
+    // store the files of new archives as content-addressed blobs, shared between releases,
+    // instead of ZIP files
+    pub(crate) deduplicate_archives: bool,
+
     // Content Security Policy
     pub(crate) csp_report_only: bool,
 
@@ -85,6 +117,12 @@ pub struct Config {
     // This only affects pages that depend on invalidations to work.
     pub(crate) cache_invalidatable_responses: bool,
 
+    // how many items of parsed search indexes the web server keeps in memory for searches
+    // within the documentation of releases, 0 disables the cache
+    pub(crate) search_index_cache_items: usize,
+    // same for the items of the parsed rustdoc JSON of releases, for API comparisons
+    pub(crate) api_diff_cache_items: usize,
+
     pub(crate) cdn_backend: CdnKind,
 
     // CloudFront distribution ID for the web server.
@@ -105,6 +143,22 @@ pub struct Config {
     pub(crate) build_default_memory_limit: Option<usize>,
     pub(crate) include_default_targets: bool,
     pub(crate) disable_memory_limit: bool,
+    // Run rustdoc a second time for the default target to store its JSON output,
+    // this roughly doubles the time spent documenting a release
+    pub(crate) build_rustdoc_json: bool,
+
+    // Retry builds that ran into a timeout or out of memory once, with higher limits
+    pub(crate) build_limit_escalation: bool,
+    pub(crate) build_limit_escalation_max_memory: usize,
+    pub(crate) build_limit_escalation_max_timeout: Duration,
+
+    // Build workers running on this server
+    pub(crate) build_workers: usize,
+    /// Name the workers of this server are registered with, has to be unique
+    /// between build servers.
+    pub(crate) build_worker_name: String,
+    /// How long a claimed queue entry stays leased to a worker without a heartbeat.
+    pub(crate) build_lease_duration: Duration,
 }
 
 impl Config {
@@ -142,10 +196,15 @@ impl Config {
 
             registry_index_path: env("REGISTRY_INDEX_PATH", prefix.join("crates.io-index"))?,
             registry_url: maybe_env("REGISTRY_URL")?,
+            registry_changelog_url: maybe_env("REGISTRY_CHANGELOG_URL")?,
             registry_api_host: env(
                 "DOCSRS_REGISTRY_API_HOST",
                 "https://crates.io".parse().unwrap(),
             )?,
+            registries: match maybe_env::<PathBuf>("DOCSRS_REGISTRIES")? {
+                Some(path) => load_registries(&path)?,
+                None => Vec::new(),
+            },
             prefix: prefix.clone(),
 
             database_url: require_env("DOCSRS_DATABASE_URL")?,
@@ -154,6 +213,7 @@ impl Config {
             min_pool_idle: env("DOCSRS_MIN_POOL_IDLE", 10)?,
 
             storage_backend: env("DOCSRS_STORAGE_BACKEND", StorageKind::Database)?,
+            storage_filesystem_root: env("DOCSRS_STORAGE_FILESYSTEM_ROOT", prefix.join("storage"))?,
 
             aws_sdk_max_retries: env("DOCSRS_AWS_SDK_MAX_RETRIES", 6)?,
 
@@ -180,13 +240,16 @@ impl Config {
             max_file_size_html: env("DOCSRS_MAX_FILE_SIZE_HTML", 50 * 1024 * 1024)?,
             // LOL HTML only uses as much memory as the size of the start tag!
             // https://github.com/rust-lang/docs.rs/pull/930#issuecomment-667729380
-            max_parse_memory: env("DOCSRS_MAX_PARSE_MEMORY", 5 * 1024 * 1024)?,
+            // The page itself is streamed through it, so this doesn't bound the page size.
+            max_parse_memory: env("DOCSRS_MAX_PARSE_MEMORY", 16 * 1024 * 1024)?,
             registry_gc_interval: env("DOCSRS_REGISTRY_GC_INTERVAL", 60 * 60)?,
+            index_webhook_secret: maybe_env("DOCSRS_INDEX_WEBHOOK_SECRET")?,
             render_threads: env("DOCSRS_RENDER_THREADS", num_cpus::get())?,
             request_timeout: maybe_env::<u64>("DOCSRS_REQUEST_TIMEOUT")?.map(Duration::from_secs),
             report_request_timeouts: env("DOCSRS_REPORT_REQUEST_TIMEOUTS", false)?,
 
             random_crate_search_view_size: env("DOCSRS_RANDOM_CRATE_SEARCH_VIEW_SIZE", 500)?,
+            crate_search_backend: env("DOCSRS_CRATE_SEARCH_BACKEND", CrateSearchBackend::CratesIo)?,
 
             csp_report_only: env("DOCSRS_CSP_REPORT_ONLY", false)?,
 
@@ -196,6 +259,9 @@ impl Config {
 
             cache_invalidatable_responses: env("DOCSRS_CACHE_INVALIDATEABLE_RESPONSES", true)?,
 
+            search_index_cache_items: env("DOCSRS_SEARCH_INDEX_CACHE_ITEMS", 500_000)?,
+            api_diff_cache_items: env("DOCSRS_API_DIFF_CACHE_ITEMS", 200_000)?,
+
             cdn_backend: env("DOCSRS_CDN_BACKEND", CdnKind::Dummy)?,
 
             cloudfront_distribution_id_web: maybe_env("CLOUDFRONT_DISTRIBUTION_ID_WEB")?,
@@ -205,6 +271,17 @@ impl Config {
                 "DOCSRS_ARCHIVE_INDEX_CACHE_PATH",
                 prefix.join("archive_cache"),
             )?,
+            local_archive_cache_max_size: env(
+                "DOCSRS_ARCHIVE_INDEX_CACHE_MAX_SIZE",
+                10 * 1024 * 1024 * 1024,
+            )?,
+            storage_memory_cache_size: env("DOCSRS_STORAGE_MEMORY_CACHE_SIZE", 256 * 1024 * 1024)?,
+            storage_memory_cache_ttl: Duration::from_secs(env(
+                "DOCSRS_STORAGE_MEMORY_CACHE_TTL",
+                300,
+            )?),
+
+            deduplicate_archives: env("DOCSRS_DEDUPLICATE_ARCHIVES", false)?,
 
             temp_dir,
 
@@ -216,12 +293,49 @@ impl Config {
             build_default_memory_limit: maybe_env("DOCSRS_BUILD_DEFAULT_MEMORY_LIMIT")?,
             include_default_targets: env("DOCSRS_INCLUDE_DEFAULT_TARGETS", true)?,
             disable_memory_limit: env("DOCSRS_DISABLE_MEMORY_LIMIT", false)?,
+            build_rustdoc_json: env("DOCSRS_BUILD_RUSTDOC_JSON", false)?,
+            build_limit_escalation: env("DOCSRS_BUILD_LIMIT_ESCALATION", false)?,
+            build_limit_escalation_max_memory: env(
+                "DOCSRS_BUILD_LIMIT_ESCALATION_MAX_MEMORY",
+                8 * 1024 * 1024 * 1024,
+            )?,
+            build_limit_escalation_max_timeout: Duration::from_secs(env(
+                "DOCSRS_BUILD_LIMIT_ESCALATION_MAX_TIMEOUT",
+                60 * 60,
+            )?),
+            build_workers: env("DOCSRS_BUILD_WORKERS", 1)?,
+            build_worker_name: env(
+                "DOCSRS_BUILD_WORKER_NAME",
+                hostname::get()?.to_string_lossy().into_owned(),
+            )?,
+            build_lease_duration: Duration::from_secs(env("DOCSRS_BUILD_LEASE_DURATION", 300)?),
             build_workspace_reinitialization_interval: Duration::from_secs(env(
                 "DOCSRS_BUILD_WORKSPACE_REINITIALIZATION_INTERVAL",
                 86400,
             )?),
         })
     }
+
+    /// Index URL of the alternative registry with the given name.
+    pub fn registry_index_url(&self, name: &str) -> Result<&str> {
+        self.registry(name)
+            .map(|registry| registry.index_url.as_str())
+            .with_context(|| format!("unknown registry {name}"))
+    }
+
+    /// The alternative registry with the given name.
+    pub(crate) fn registry(&self, name: &str) -> Option<&Registry> {
+        self.registries
+            .iter()
+            .find(|registry| registry.name == name)
+    }
+
+    /// The alternative registry with its index at `index_url`.
+    pub(crate) fn registry_for_index(&self, index_url: &str) -> Option<&Registry> {
+        self.registries
+            .iter()
+            .find(|registry| registry.has_index(index_url))
+    }
 }
 
 fn env<T>(var: &str, default: T) -> Result<T>
@@ -1,16 +1,21 @@
 use crate::{
-    db::types::{BuildStatus, Feature},
-    docbuilder::DocCoverage,
+    db::types::{BuildFailureCategory, BuildStatus, Feature},
+    docbuilder::{BuildFailure, DocCoverage, TargetBuildResult},
     error::Result,
     registry_api::{CrateData, CrateOwner, ReleaseData},
-    storage::CompressionAlgorithm,
-    utils::MetadataPackage,
+    storage::{crate_storage_name, CompressionAlgorithm},
+    utils::{
+        search_index::{fetch_search_index, SearchItem},
+        MetadataPackage,
+    },
     web::crate_details::{latest_release, releases_for_crate},
+    AsyncStorage,
 };
 use anyhow::Context;
 use futures_util::stream::TryStreamExt;
 use serde_json::Value;
 use slug::slugify;
+use sqlx::Acquire as _;
 use std::{
     collections::{HashMap, HashSet},
     fs,
@@ -25,10 +30,14 @@ use tracing::{debug, info, instrument};
 ///
 /// NOTE: `source_files` refers to the files originally in the crate,
 /// not the files generated by rustdoc.
+///
+/// `registry` is the name of the alternative registry the package comes from,
+/// `None` for crates.io.
 #[allow(clippy::too_many_arguments)]
 #[instrument(skip(conn))]
 pub(crate) async fn add_package_into_database(
     conn: &mut sqlx::PgConnection,
+    registry: Option<&str>,
     metadata_pkg: &MetadataPackage,
     source_dir: &Path,
     default_target: &str,
@@ -42,7 +51,7 @@ pub(crate) async fn add_package_into_database(
     archive_storage: bool,
 ) -> Result<i32> {
     debug!("Adding package into database");
-    let crate_id = initialize_crate(conn, &metadata_pkg.name).await?;
+    let crate_id = initialize_crate(conn, registry, &metadata_pkg.name).await?;
     let dependencies = convert_dependencies(metadata_pkg);
     let rustdoc = get_rustdoc(metadata_pkg, source_dir).unwrap_or(None);
     let readme = get_readme(metadata_pkg, source_dir).unwrap_or(None);
@@ -131,20 +140,24 @@ pub(crate) async fn add_package_into_database(
     Ok(release_id)
 }
 
-pub async fn update_latest_version_id(conn: &mut sqlx::PgConnection, crate_id: i32) -> Result<()> {
+/// Updates the latest release of the crate, returns whether it changed.
+pub async fn update_latest_version_id(
+    conn: &mut sqlx::PgConnection,
+    crate_id: i32,
+) -> Result<bool> {
     let releases = releases_for_crate(conn, crate_id).await?;
 
-    sqlx::query!(
+    let result = sqlx::query!(
         "UPDATE crates
          SET latest_version_id = $2
-         WHERE id = $1",
+         WHERE id = $1 AND latest_version_id IS DISTINCT FROM $2",
         crate_id,
         latest_release(&releases).map(|release| release.id),
     )
     .execute(&mut *conn)
     .await?;
 
-    Ok(())
+    Ok(result.rows_affected() > 0)
 }
 
 pub async fn update_build_status(conn: &mut sqlx::PgConnection, release_id: i32) -> Result<()> {
@@ -185,7 +198,10 @@ pub async fn update_build_status(conn: &mut sqlx::PgConnection, release_id: i32)
     Ok(())
 }
 
-async fn crate_id_from_release_id(conn: &mut sqlx::PgConnection, release_id: i32) -> Result<i32> {
+pub(crate) async fn crate_id_from_release_id(
+    conn: &mut sqlx::PgConnection,
+    release_id: i32,
+) -> Result<i32> {
     Ok(sqlx::query_scalar!(
         "SELECT crate_id
          FROM releases
@@ -226,6 +242,66 @@ pub(crate) async fn add_doc_coverage(
     .await?)
 }
 
+#[instrument(skip(conn))]
+pub(crate) async fn add_build_failure(
+    conn: &mut sqlx::PgConnection,
+    build_id: i32,
+    failure: BuildFailure,
+) -> Result<()> {
+    debug!("Adding build failure into database");
+    sqlx::query!(
+        "UPDATE builds
+         SET failure_category = $2, error_excerpt = $3
+         WHERE id = $1",
+        build_id,
+        failure.category as BuildFailureCategory,
+        failure.excerpt,
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip(conn))]
+pub(crate) async fn add_target_build_result(
+    conn: &mut sqlx::PgConnection,
+    build_id: i32,
+    result: &TargetBuildResult,
+    log_path: &str,
+) -> Result<()> {
+    debug!("Adding target build result into database");
+    sqlx::query!(
+        "INSERT INTO build_target_results (
+            build_id, target, successful, duration_ms, log_path,
+            peak_memory_bytes, cpu_time_ms
+        )
+         VALUES ($1, $2, $3, $4, $5, $6, $7)
+         ON CONFLICT (build_id, target) DO UPDATE
+            SET
+                successful = $3,
+                duration_ms = $4,
+                log_path = $5,
+                peak_memory_bytes = $6,
+                cpu_time_ms = $7",
+        build_id,
+        result.target,
+        result.successful,
+        i32::try_from(result.duration.as_millis()).unwrap_or(i32::MAX),
+        log_path,
+        result
+            .resource_usage
+            .peak_memory
+            .map(|bytes| i64::try_from(bytes).unwrap_or(i64::MAX)),
+        result
+            .resource_usage
+            .cpu_time
+            .map(|cpu_time| i32::try_from(cpu_time.as_millis()).unwrap_or(i32::MAX)),
+    )
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
 /// Adds a build into database
 #[instrument(skip(conn))]
 pub(crate) async fn add_build_into_database(
@@ -261,14 +337,140 @@ pub(crate) async fn add_build_into_database(
     Ok(build_id)
 }
 
-pub(crate) async fn initialize_crate(conn: &mut sqlx::PgConnection, name: &str) -> Result<i32> {
+/// Replaces the items of a crate in the index for searches across all crates with the items
+/// of the release, when it's the latest release of the crate.
+#[instrument(skip(conn, items))]
+pub(crate) async fn update_crate_items(
+    conn: &mut sqlx::PgConnection,
+    release_id: i32,
+    items: &[SearchItem],
+) -> Result<()> {
+    let Some(crate_id) = sqlx::query_scalar!(
+        "SELECT id FROM crates WHERE latest_version_id = $1",
+        release_id
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        debug!("not the latest release, keeping the items of the crate");
+        return Ok(());
+    };
+
+    let mut paths = Vec::with_capacity(items.len());
+    let mut names = Vec::with_capacity(items.len());
+    let mut kinds = Vec::with_capacity(items.len());
+    let mut urls = Vec::with_capacity(items.len());
+    let mut descriptions = Vec::with_capacity(items.len());
+    for item in items {
+        paths.push(item.path.clone());
+        names.push(item.name().to_owned());
+        kinds.push(item.kind.to_owned());
+        urls.push(item.url.clone());
+        descriptions.push(item.description.clone().unwrap_or_default());
+    }
+
+    let mut transaction = conn.begin().await?;
+    sqlx::query!("DELETE FROM crate_items WHERE crate_id = $1", crate_id)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO crate_items (crate_id, release_id, path, name, kind, url, description)
+         SELECT $1, $2, path, name, kind, url, NULLIF(description, '')
+         FROM UNNEST($3::TEXT[], $4::TEXT[], $5::TEXT[], $6::TEXT[], $7::TEXT[])
+            AS items(path, name, kind, url, description)",
+        crate_id,
+        release_id,
+        &paths[..],
+        &names[..],
+        &kinds[..],
+        &urls[..],
+        &descriptions[..],
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Replaces the items of a crate in the index for searches across all crates with the items
+/// in the stored documentation of its latest release. For when the latest release changes
+/// without being built, like when the previous one is yanked or deleted.
+#[instrument(skip(conn, storage))]
+pub async fn rebuild_crate_items(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+    crate_id: i32,
+) -> Result<()> {
+    let Some(release) = sqlx::query!(
+        r#"SELECT
+            releases.id,
+            crates.name,
+            crates.registry,
+            releases.version,
+            releases.rustdoc_status,
+            releases.archive_storage,
+            releases.target_name,
+            (
+                SELECT id
+                FROM builds
+                WHERE
+                    builds.rid = releases.id AND
+                    builds.build_status = 'success'
+                ORDER BY build_time DESC
+                LIMIT 1
+            ) AS latest_build_id
+         FROM crates
+         INNER JOIN releases ON releases.id = crates.latest_version_id
+         WHERE crates.id = $1"#,
+        crate_id,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    else {
+        sqlx::query!("DELETE FROM crate_items WHERE crate_id = $1", crate_id)
+            .execute(&mut *conn)
+            .await?;
+        return Ok(());
+    };
+
+    let items = if release.rustdoc_status {
+        fetch_search_index(
+            storage,
+            &crate_storage_name(release.registry.as_deref(), &release.name),
+            &release.version,
+            release.latest_build_id.unwrap_or(0),
+            release.archive_storage,
+            "",
+            &release.target_name,
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "could not read the search index of {} {}",
+                release.name, release.version
+            )
+        })?
+    } else {
+        None
+    };
+
+    update_crate_items(conn, release.id, &items.unwrap_or_default()).await
+}
+
+pub(crate) async fn initialize_crate(
+    conn: &mut sqlx::PgConnection,
+    registry: Option<&str>,
+    name: &str,
+) -> Result<i32> {
     sqlx::query_scalar!(
-        "INSERT INTO crates (name)
-         VALUES ($1)
-         ON CONFLICT (name) DO UPDATE
+        "INSERT INTO crates (registry, name)
+         VALUES ($1, $2)
+         ON CONFLICT (name, (COALESCE(registry, ''))) DO UPDATE
          SET -- this `SET` is needed so the id is always returned.
             name = EXCLUDED.name
          RETURNING id",
+        registry,
         name
     )
     .fetch_one(&mut *conn)
@@ -427,13 +629,20 @@ async fn add_keywords_into_database(
 #[instrument(skip(conn))]
 pub async fn update_crate_data_in_database(
     conn: &mut sqlx::PgConnection,
+    registry: Option<&str>,
     name: &str,
     registry_data: &CrateData,
 ) -> Result<()> {
     info!("Updating crate data for {}", name);
-    let crate_id = sqlx::query_scalar!("SELECT id FROM crates WHERE crates.name = $1", name)
-        .fetch_one(&mut *conn)
-        .await?;
+    let crate_id = sqlx::query_scalar!(
+        "SELECT id
+         FROM crates
+         WHERE crates.name = $1 AND crates.registry IS NOT DISTINCT FROM $2",
+        name,
+        registry,
+    )
+    .fetch_one(&mut *conn)
+    .await?;
 
     update_owners_in_database(conn, &registry_data.owners, crate_id).await?;
 
@@ -524,6 +733,51 @@ mod test {
     use crate::utils::CargoMetadata;
     use test_case::test_case;
 
+    #[test]
+    fn crate_items_of_latest_release() {
+        async_wrapper(|env| async move {
+            let old_release = env
+                .async_fake_release()
+                .await
+                .name("foo")
+                .version("1.0.0")
+                .create_async()
+                .await?;
+            let latest_release = env
+                .async_fake_release()
+                .await
+                .name("foo")
+                .version("1.1.0")
+                .create_async()
+                .await?;
+
+            let item = |path: &str| SearchItem {
+                path: path.into(),
+                kind: "struct",
+                description: None,
+                url: format!("foo/struct.{}.html", path.trim_start_matches("foo::")),
+            };
+            let mut conn = env.async_db().await.async_conn().await;
+            update_crate_items(&mut conn, latest_release, &[item("foo::New")]).await?;
+            // rebuilds of older releases keep the items of the latest one
+            update_crate_items(&mut conn, old_release, &[item("foo::Old")]).await?;
+
+            let items: Vec<(i32, String, String)> =
+                sqlx::query!("SELECT release_id, path, name FROM crate_items")
+                    .fetch_all(&mut *conn)
+                    .await?
+                    .into_iter()
+                    .map(|row| (row.release_id, row.path, row.name))
+                    .collect();
+            assert_eq!(
+                items,
+                vec![(latest_release, "foo::New".into(), "New".into())]
+            );
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn new_keywords() {
         wrapper(|env| {
@@ -637,12 +891,33 @@ mod test {
         })
     }
 
+    #[test]
+    fn crate_names_are_unique_per_registry() {
+        async_wrapper(|env| async move {
+            let mut conn = env.async_db().await.async_conn().await;
+
+            let crates_io = initialize_crate(&mut conn, None, "foo").await?;
+            let internal = initialize_crate(&mut conn, Some("internal"), "foo").await?;
+            let other = initialize_crate(&mut conn, Some("other"), "foo").await?;
+
+            assert_ne!(crates_io, internal);
+            assert_ne!(internal, other);
+            assert_eq!(initialize_crate(&mut conn, None, "foo").await?, crates_io);
+            assert_eq!(
+                initialize_crate(&mut conn, Some("internal"), "foo").await?,
+                internal
+            );
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn new_owners() {
         async_wrapper(|env| async move {
             let mut conn = env.async_db().await.async_conn().await;
 
-            let crate_id = initialize_crate(&mut conn, "").await?;
+            let crate_id = initialize_crate(&mut conn, None, "").await?;
 
             let owner1 = CrateOwner {
                 avatar: "avatar".into(),
@@ -680,7 +955,7 @@ mod test {
     fn update_owner_detais() {
         async_wrapper(|env| async move {
             let mut conn = env.async_db().await.async_conn().await;
-            let crate_id = initialize_crate(&mut conn, "").await?;
+            let crate_id = initialize_crate(&mut conn, None, "").await?;
 
             // set initial owner details
             update_owners_in_database(
@@ -725,7 +1000,7 @@ mod test {
     fn add_new_owners_and_delete_old() {
         async_wrapper(|env| async move {
             let mut conn = env.async_db().await.async_conn().await;
-            let crate_id = initialize_crate(&mut conn, "").await?;
+            let crate_id = initialize_crate(&mut conn, None, "").await?;
 
             // set initial owner details
             update_owners_in_database(
@@ -0,0 +1,873 @@
+//! Exporting a release into a self-contained bundle, and importing it into another instance.
+//!
+//! A bundle is a zstd compressed tarball with
+//!
+//! * `release.json`, the rows of the release, its builds and the owners of the crate,
+//! * `sources.zip` and `rustdoc.zip`, the files of the source and rustdoc archives,
+//! * `build-logs/<build>/<file>`, the build logs of every build, by its position in
+//!   `release.json`, since builds get new ids when they're imported.
+//!
+//! Importing a bundle stores the archives again, the way the importing instance stores archives,
+//! so both deduplicated and ZIP archives can be moved between instances. Releases we have already
+//! aren't imported, they have to be deleted first.
+
+use crate::{
+    db::{
+        add_build_into_database, add_package_into_database, add_path_into_remote_archive,
+        add_target_build_result, crate_id_from_release_id, rebuild_crate_items,
+        types::{BuildFailureCategory, BuildStatus, Feature},
+        update_crate_data_in_database,
+    },
+    docbuilder::{ResourceUsage, TargetBuildResult},
+    error::Result,
+    registry_api::{CrateData, CrateOwner, ReleaseData},
+    storage::{crate_storage_name, rustdoc_archive_path, source_archive_path},
+    utils::{report_error, spawn_blocking, MetadataPackage, Target},
+    AsyncStorage, Config,
+};
+use anyhow::{anyhow, bail, ensure, Context as _};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::Acquire as _;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Read as _, Write as _},
+    path::Path,
+    time::Duration,
+};
+use tracing::{info, instrument};
+
+/// Bumped on changes importing older bundles can't handle.
+const FORMAT_VERSION: u32 = 1;
+
+const MANIFEST_PATH: &str = "release.json";
+const SOURCES_ARCHIVE_PATH: &str = "sources.zip";
+const RUSTDOC_ARCHIVE_PATH: &str = "rustdoc.zip";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    format_version: u32,
+    /// The name of the alternative registry of the crate, `None` for crates.io.
+    registry: Option<String>,
+    /// Recreated from the release, with the metadata `add_package_into_database` reads.
+    package: MetadataPackage,
+    release: BundledRelease,
+    builds: Vec<BundledBuild>,
+    owners: Vec<BundledOwner>,
+}
+
+/// The columns of the release that aren't part of the package metadata.
+#[derive(Debug, Serialize, Deserialize)]
+struct BundledRelease {
+    release_time: DateTime<Utc>,
+    yanked: bool,
+    downloads: i32,
+    rustdoc_status: bool,
+    have_examples: bool,
+    default_target: String,
+    doc_targets: Vec<String>,
+    /// These are read from the sources when a release is built, so they're restored as they are.
+    dependencies: Option<Value>,
+    description_long: Option<String>,
+    readme: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundledBuild {
+    rustc_version: String,
+    docsrs_version: String,
+    build_status: BuildStatus,
+    build_time: DateTime<Utc>,
+    build_server: String,
+    output: Option<String>,
+    failure_category: Option<BuildFailureCategory>,
+    error_excerpt: Option<String>,
+    targets: Vec<BundledTargetResult>,
+    /// The names of the build logs in the storage.
+    logs: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundledTargetResult {
+    target: String,
+    successful: bool,
+    duration_ms: i32,
+    peak_memory_bytes: Option<i64>,
+    cpu_time_ms: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BundledOwner {
+    login: String,
+    avatar: String,
+}
+
+/// Writes a bundle with the release, its builds, the owners of the crate and the archives and
+/// build logs of the release to `destination`.
+///
+/// Only releases stored in archives can be exported.
+#[instrument(skip(conn, storage))]
+pub async fn export_release(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+    registry: Option<&str>,
+    name: &str,
+    version: &str,
+    destination: &Path,
+) -> Result<()> {
+    let release = sqlx::query!(
+        r#"SELECT
+            releases.id,
+            releases.crate_id,
+            releases.release_time,
+            releases.dependencies,
+            releases.target_name,
+            releases.yanked,
+            releases.is_library,
+            releases.rustdoc_status,
+            releases.license,
+            releases.repository_url,
+            releases.homepage_url,
+            releases.documentation_url,
+            releases.description,
+            releases.description_long,
+            releases.readme,
+            releases.keywords,
+            releases.have_examples,
+            releases.downloads,
+            releases.doc_targets,
+            releases.default_target,
+            releases.features as "features?: Vec<Feature>",
+            releases.archive_storage
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         WHERE
+            crates.name = $1 AND
+            releases.version = $2 AND
+            crates.registry IS NOT DISTINCT FROM $3"#,
+        name,
+        version,
+        registry,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| anyhow!("release {name} {version} not found"))?;
+
+    ensure!(
+        release.archive_storage,
+        "only releases stored in archives can be exported"
+    );
+
+    let storage_name = crate_storage_name(registry, name);
+    let sources = storage
+        .get_archive_zip(&source_archive_path(&storage_name, version))
+        .await?;
+
+    // we only store whether the release has a library, the manifest has the kind of library
+    let crate_types = if release.is_library {
+        let sources = sources.clone();
+        spawn_blocking(move || library_crate_types(&sources)).await?
+    } else {
+        vec!["bin".to_owned()]
+    };
+    let package = MetadataPackage {
+        id: format!("{name} {version}"),
+        name: name.to_owned(),
+        version: version.to_owned(),
+        license: release.license,
+        repository: release.repository_url,
+        homepage: release.homepage_url,
+        description: release.description,
+        documentation: release.documentation_url,
+        targets: vec![Target::new(release.target_name, crate_types, None)],
+        keywords: release
+            .keywords
+            .map(serde_json::from_value)
+            .transpose()?
+            .unwrap_or_default(),
+        features: release
+            .features
+            .unwrap_or_default()
+            .into_iter()
+            .map(|feature| (feature.name, feature.subfeatures))
+            .collect(),
+        ..Default::default()
+    };
+
+    let mut files = vec![(SOURCES_ARCHIVE_PATH.to_owned(), sources)];
+    if release.rustdoc_status {
+        files.push((
+            RUSTDOC_ARCHIVE_PATH.to_owned(),
+            storage
+                .get_archive_zip(&rustdoc_archive_path(&storage_name, version))
+                .await?,
+        ));
+    }
+
+    let builds = sqlx::query!(
+        r#"SELECT
+            id,
+            rustc_version,
+            docsrs_version,
+            build_status as "build_status: BuildStatus",
+            build_time,
+            build_server,
+            output,
+            failure_category as "failure_category: BuildFailureCategory",
+            error_excerpt
+         FROM builds
+         WHERE rid = $1
+         ORDER BY id"#,
+        release.id,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut bundled_builds = Vec::with_capacity(builds.len());
+    for (index, build) in builds.into_iter().enumerate() {
+        let targets = sqlx::query_as!(
+            BundledTargetResult,
+            "SELECT target, successful, duration_ms, peak_memory_bytes, cpu_time_ms
+             FROM build_target_results
+             WHERE build_id = $1
+             ORDER BY target",
+            build.id,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let prefix = format!("build-logs/{}/", build.id);
+        let log_paths: Vec<String> = storage.list_prefix(&prefix).await.try_collect().await?;
+        let mut logs = Vec::with_capacity(log_paths.len());
+        for log_path in log_paths {
+            let log_name = log_path[prefix.len()..].to_owned();
+            let content = storage.get(&log_path, std::usize::MAX).await?.content;
+            files.push((format!("build-logs/{index}/{log_name}"), content));
+            logs.push(log_name);
+        }
+
+        bundled_builds.push(BundledBuild {
+            rustc_version: build.rustc_version,
+            docsrs_version: build.docsrs_version,
+            build_status: build.build_status,
+            build_time: build.build_time,
+            build_server: build.build_server,
+            output: build.output,
+            failure_category: build.failure_category,
+            error_excerpt: build.error_excerpt,
+            targets,
+            logs,
+        });
+    }
+
+    let owners = sqlx::query_as!(
+        BundledOwner,
+        "SELECT owners.login, owners.avatar
+         FROM owners
+         INNER JOIN owner_rels ON owner_rels.oid = owners.id
+         WHERE owner_rels.cid = $1
+         ORDER BY owners.login",
+        release.crate_id,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let manifest = Manifest {
+        format_version: FORMAT_VERSION,
+        registry: registry.map(ToOwned::to_owned),
+        package,
+        release: BundledRelease {
+            release_time: release.release_time,
+            yanked: release.yanked,
+            downloads: release.downloads,
+            rustdoc_status: release.rustdoc_status,
+            have_examples: release.have_examples,
+            default_target: release.default_target,
+            doc_targets: serde_json::from_value(release.doc_targets)?,
+            dependencies: release.dependencies,
+            description_long: release.description_long,
+            readme: release.readme,
+        },
+        builds: bundled_builds,
+        owners,
+    };
+    files.insert(
+        0,
+        (
+            MANIFEST_PATH.to_owned(),
+            serde_json::to_vec_pretty(&manifest)?,
+        ),
+    );
+
+    let destination = destination.to_owned();
+    spawn_blocking(move || write_bundle(&destination, files)).await?;
+
+    info!(name, version, "exported release");
+    Ok(())
+}
+
+/// Adds the release in the bundle at `source` to this instance, and returns its id.
+///
+/// Fails when we have the release already. The rows are only added once the archives and build
+/// logs are stored, a failed import leaves no rows behind.
+#[instrument(skip(conn, storage, config))]
+pub async fn import_release(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+    config: &Config,
+    source: &Path,
+) -> Result<i32> {
+    let mut files = spawn_blocking({
+        let source = source.to_owned();
+        move || read_bundle(&source)
+    })
+    .await?;
+
+    let manifest: Manifest = serde_json::from_slice(
+        &files
+            .remove(MANIFEST_PATH)
+            .ok_or_else(|| anyhow!("bundle without {MANIFEST_PATH}"))?,
+    )
+    .with_context(|| format!("invalid {MANIFEST_PATH}"))?;
+    if manifest.format_version != FORMAT_VERSION {
+        bail!(
+            "unsupported bundle format version {}",
+            manifest.format_version
+        );
+    }
+
+    let package = &manifest.package;
+    let release = &manifest.release;
+    let registry = manifest.registry.as_deref();
+    let storage_name = crate_storage_name(registry, &package.name);
+
+    let mut transaction = conn.begin().await?;
+    let exists = sqlx::query_scalar!(
+        r#"SELECT EXISTS(
+            SELECT 1
+            FROM releases
+            INNER JOIN crates ON crates.id = releases.crate_id
+            WHERE
+                crates.name = $1 AND
+                releases.version = $2 AND
+                crates.registry IS NOT DISTINCT FROM $3
+         ) as "exists!""#,
+        package.name,
+        package.version,
+        registry,
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    ensure!(
+        !exists,
+        "release {} {} exists already, it has to be deleted before importing it",
+        package.name,
+        package.version
+    );
+
+    let sources = files
+        .remove(SOURCES_ARCHIVE_PATH)
+        .ok_or_else(|| anyhow!("bundle without {SOURCES_ARCHIVE_PATH}"))?;
+    let rustdoc = files.remove(RUSTDOC_ARCHIVE_PATH);
+    let temp_dir = spawn_blocking({
+        let temp_dir = config.temp_dir.clone();
+        move || {
+            fs::create_dir_all(&temp_dir)?;
+            let dir = tempfile::tempdir_in(&temp_dir)?;
+            extract_zip(sources, &dir.path().join("sources"))?;
+            if let Some(rustdoc) = rustdoc {
+                extract_zip(rustdoc, &dir.path().join("rustdoc"))?;
+            }
+            Ok(dir)
+        }
+    })
+    .await?;
+    let source_dir = temp_dir.path().join("sources");
+    let rustdoc_dir = temp_dir.path().join("rustdoc");
+
+    let mut algs = HashSet::new();
+    let (source_files, alg) = add_path_into_remote_archive(
+        &mut transaction,
+        storage,
+        &source_archive_path(&storage_name, &package.version),
+        &source_dir,
+        false,
+    )
+    .await?;
+    algs.insert(alg);
+    if rustdoc_dir.exists() {
+        let (_, alg) = add_path_into_remote_archive(
+            &mut transaction,
+            storage,
+            &rustdoc_archive_path(&storage_name, &package.version),
+            &rustdoc_dir,
+            true,
+        )
+        .await?;
+        algs.insert(alg);
+    }
+
+    let release_id = add_package_into_database(
+        &mut transaction,
+        registry,
+        package,
+        &source_dir,
+        &release.default_target,
+        source_files,
+        release.doc_targets.clone(),
+        &ReleaseData {
+            release_time: release.release_time,
+            yanked: release.yanked,
+            downloads: release.downloads,
+        },
+        release.rustdoc_status,
+        release.have_examples,
+        algs,
+        None,
+        true,
+    )
+    .await?;
+
+    sqlx::query!(
+        "UPDATE releases
+         SET dependencies = $2, description_long = $3, readme = $4
+         WHERE id = $1",
+        release_id,
+        release.dependencies,
+        release.description_long,
+        release.readme,
+    )
+    .execute(&mut *transaction)
+    .await?;
+
+    for (index, build) in manifest.builds.iter().enumerate() {
+        let build_id = add_build_into_database(
+            &mut transaction,
+            release_id,
+            &build.rustc_version,
+            &build.docsrs_version,
+            build.build_status,
+        )
+        .await?;
+        sqlx::query!(
+            "UPDATE builds
+             SET
+                build_time = $2,
+                build_server = $3,
+                output = $4,
+                failure_category = $5,
+                error_excerpt = $6
+             WHERE id = $1",
+            build_id,
+            build.build_time,
+            build.build_server,
+            build.output,
+            build.failure_category as Option<BuildFailureCategory>,
+            build.error_excerpt,
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+        for target in &build.targets {
+            let result = TargetBuildResult {
+                target: target.target.clone(),
+                successful: target.successful,
+                duration: Duration::from_millis(target.duration_ms.try_into().unwrap_or(0)),
+                resource_usage: ResourceUsage {
+                    peak_memory: target
+                        .peak_memory_bytes
+                        .and_then(|bytes| bytes.try_into().ok()),
+                    cpu_time: target
+                        .cpu_time_ms
+                        .and_then(|ms| ms.try_into().ok())
+                        .map(Duration::from_millis),
+                },
+            };
+            let log_path = format!("build-logs/{build_id}/{}.txt", target.target);
+            add_target_build_result(&mut transaction, build_id, &result, &log_path).await?;
+        }
+
+        for log_name in &build.logs {
+            let content = files
+                .remove(&format!("build-logs/{index}/{log_name}"))
+                .ok_or_else(|| anyhow!("bundle without build log {log_name} of build {index}"))?;
+            storage
+                .store_one(format!("build-logs/{build_id}/{log_name}"), content)
+                .await?;
+        }
+    }
+
+    update_crate_data_in_database(
+        &mut transaction,
+        registry,
+        &package.name,
+        &CrateData {
+            owners: manifest
+                .owners
+                .iter()
+                .map(|owner| CrateOwner {
+                    login: owner.login.clone(),
+                    avatar: owner.avatar.clone(),
+                })
+                .collect(),
+        },
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    // the imported release might be the latest one of the crate now
+    let crate_id = crate_id_from_release_id(&mut *conn, release_id).await?;
+    if let Err(err) = rebuild_crate_items(&mut *conn, storage, crate_id).await {
+        report_error(&err.context("could not rebuild the items of the imported release"));
+    }
+
+    info!(name = %package.name, version = %package.version, "imported release");
+    Ok(release_id)
+}
+
+fn write_bundle(destination: &Path, files: Vec<(String, Vec<u8>)>) -> Result<()> {
+    let file = fs::File::create(destination)
+        .with_context(|| format!("failed to create {}", destination.display()))?;
+    let mut builder = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+    let mtime = Utc::now().timestamp().try_into().unwrap_or(0);
+    for (path, content) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        builder.append_data(&mut header, path, content.as_slice())?;
+    }
+    builder.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+/// The content of all files in the bundle, by their path.
+fn read_bundle(source: &Path) -> Result<HashMap<String, Vec<u8>>> {
+    let file =
+        fs::File::open(source).with_context(|| format!("failed to open {}", source.display()))?;
+    let mut archive = tar::Archive::new(zstd::Decoder::new(file)?);
+
+    let mut files = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry
+            .path()?
+            .to_str()
+            .ok_or_else(|| anyhow!("invalid path in bundle"))?
+            .to_owned();
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        files.insert(path, content);
+    }
+    Ok(files)
+}
+
+/// The crate types of the library in the `Cargo.toml` in the source archive.
+fn library_crate_types(sources: &[u8]) -> Result<Vec<String>> {
+    let mut archive = zip::ZipArchive::new(io::Cursor::new(sources))?;
+    let mut manifest = String::new();
+    match archive.by_name("Cargo.toml") {
+        Ok(mut file) => file.read_to_string(&mut manifest)?,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(vec!["lib".to_owned()]),
+        Err(err) => return Err(err.into()),
+    };
+    let manifest: toml::Table = toml::from_str(&manifest).context("invalid Cargo.toml")?;
+
+    let Some(lib) = manifest.get("lib").and_then(|lib| lib.as_table()) else {
+        return Ok(vec!["lib".to_owned()]);
+    };
+    let proc_macro = ["proc-macro", "proc_macro"]
+        .iter()
+        .any(|key| lib.get(*key).and_then(|value| value.as_bool()) == Some(true));
+    if proc_macro {
+        return Ok(vec!["proc-macro".to_owned()]);
+    }
+    let crate_types = ["crate-type", "crate_type"]
+        .iter()
+        .find_map(|key| lib.get(*key).and_then(|value| value.as_array()))
+        .map(|types| {
+            types
+                .iter()
+                .filter_map(|kind| kind.as_str().map(ToOwned::to_owned))
+                .collect::<Vec<_>>()
+        })
+        .filter(|types| !types.is_empty());
+    Ok(crate_types.unwrap_or_else(|| vec!["lib".to_owned()]))
+}
+
+fn extract_zip(content: Vec<u8>, destination: &Path) -> Result<()> {
+    fs::create_dir_all(destination)?;
+    zip::ZipArchive::new(io::Cursor::new(content))?.extract(destination)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::delete_version,
+        test::{wrapper, TestEnvironment},
+        utils::search_index::{desc_shard_path, TEST_DESC_SHARDS, TEST_SEARCH_INDEX},
+    };
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn export_and_import_release() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("1.0.0")
+                .archive_storage(true)
+                .description("a crate")
+                .keywords(vec!["bundle".into()])
+                .add_owner(CrateOwner {
+                    login: "owner".into(),
+                    avatar: "https://example.org/avatar.png".into(),
+                })
+                .rustdoc_file("foo/struct.Foo.html")
+                .create()?;
+
+            let bundle = NamedTempFile::new()?;
+            env.runtime().block_on(async {
+                let mut conn = env.async_db().await.async_conn().await;
+                let storage = env.async_storage().await;
+                export_release(&mut conn, &storage, None, "foo", "1.0.0", bundle.path()).await
+            })?;
+
+            delete_version(
+                &mut env.db().conn(),
+                &env.storage(),
+                &env.config(),
+                None,
+                "foo",
+                "1.0.0",
+            )?;
+
+            let release_id = env.runtime().block_on(async {
+                let mut conn = env.async_db().await.async_conn().await;
+                let storage = env.async_storage().await;
+                import_release(&mut conn, &storage, &env.config(), bundle.path()).await
+            })?;
+
+            let mut conn = env.db().conn();
+            let row = conn.query_one(
+                "SELECT releases.description, releases.rustdoc_status, crates.name
+                 FROM releases
+                 INNER JOIN crates ON crates.id = releases.crate_id
+                 WHERE releases.id = $1",
+                &[&release_id],
+            )?;
+            assert_eq!(row.get::<_, Option<String>>(0).as_deref(), Some("a crate"));
+            assert!(row.get::<_, bool>(1));
+            assert_eq!(row.get::<_, String>(2), "foo");
+
+            let keywords: Vec<String> = conn
+                .query(
+                    "SELECT keywords.name
+                     FROM keywords
+                     INNER JOIN keyword_rels ON keyword_rels.kid = keywords.id
+                     WHERE keyword_rels.rid = $1",
+                    &[&release_id],
+                )?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+            assert_eq!(keywords, vec!["bundle"]);
+
+            let owners: Vec<String> = conn
+                .query(
+                    "SELECT owners.login
+                     FROM owners
+                     INNER JOIN owner_rels ON owner_rels.oid = owners.id
+                     INNER JOIN releases ON releases.crate_id = owner_rels.cid
+                     WHERE releases.id = $1",
+                    &[&release_id],
+                )?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+            assert_eq!(owners, vec!["owner"]);
+
+            let build_ids: Vec<i32> = conn
+                .query("SELECT id FROM builds WHERE rid = $1", &[&release_id])?
+                .into_iter()
+                .map(|row| row.get(0))
+                .collect();
+            assert_eq!(build_ids.len(), 1);
+            assert!(env.storage().exists(&format!(
+                "build-logs/{}/x86_64-unknown-linux-gnu.txt",
+                build_ids[0]
+            ))?);
+
+            assert!(env.storage().exists_in_archive(
+                "rustdoc/foo/1.0.0.zip",
+                build_ids[0],
+                "foo/struct.Foo.html"
+            )?);
+
+            Ok(())
+        })
+    }
+
+    fn export(env: &TestEnvironment, name: &str, version: &str) -> Result<NamedTempFile> {
+        let bundle = NamedTempFile::new()?;
+        env.runtime().block_on(async {
+            let mut conn = env.async_db().await.async_conn().await;
+            let storage = env.async_storage().await;
+            export_release(&mut conn, &storage, None, name, version, bundle.path()).await
+        })?;
+        Ok(bundle)
+    }
+
+    fn import(env: &TestEnvironment, bundle: &Path) -> Result<i32> {
+        env.runtime().block_on(async {
+            let mut conn = env.async_db().await.async_conn().await;
+            let storage = env.async_storage().await;
+            import_release(&mut conn, &storage, &env.config(), bundle).await
+        })
+    }
+
+    fn release_count(env: &TestEnvironment) -> Result<i64> {
+        Ok(env
+            .db()
+            .conn()
+            .query_one("SELECT COUNT(*) FROM releases", &[])?
+            .get(0))
+    }
+
+    #[test]
+    fn imports_the_items_of_the_latest_release() {
+        wrapper(|env| {
+            let suffix = "-20240401-1.79.0-nightly-abcdef";
+            let crate_page = format!(
+                r#"<html><head><meta name="rustdoc-vars" data-resource-suffix="{suffix}"></head></html>"#
+            );
+            let index_path = format!("search-index{suffix}.js");
+            let shard_paths: Vec<_> = (0..TEST_DESC_SHARDS.len())
+                .map(|shard| desc_shard_path("foo", shard, suffix))
+                .collect();
+            let mut release = env
+                .fake_release()
+                .name("foo")
+                .version("1.0.0")
+                .archive_storage(true)
+                .rustdoc_file_with("foo/index.html", crate_page.as_bytes().to_vec())
+                .rustdoc_file_with(&index_path, TEST_SEARCH_INDEX.as_bytes().to_vec());
+            for (path, shard) in shard_paths.iter().zip(TEST_DESC_SHARDS) {
+                release = release.rustdoc_file_with(path, shard.as_bytes().to_vec());
+            }
+            release.create()?;
+
+            let bundle = export(env, "foo", "1.0.0")?;
+            delete_version(
+                &mut env.db().conn(),
+                &env.storage(),
+                &env.config(),
+                None,
+                "foo",
+                "1.0.0",
+            )?;
+            let release_id = import(env, bundle.path())?;
+
+            let items: Vec<(i32, String)> = env
+                .db()
+                .conn()
+                .query("SELECT release_id, path FROM crate_items", &[])?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1)))
+                .collect();
+            assert!(items.contains(&(release_id, "foo::bar::Baz".into())));
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn keeps_the_crate_types() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("1.0.0")
+                .archive_storage(true)
+                .source_file(
+                    "Cargo.toml",
+                    b"[package]\nname = \"foo\"\nversion = \"1.0.0\"\n\n[lib]\nproc-macro = true\n",
+                )
+                .create()?;
+
+            let bundle = export(env, "foo", "1.0.0")?;
+            let manifest: Manifest =
+                serde_json::from_slice(&read_bundle(bundle.path())?[MANIFEST_PATH])?;
+            assert_eq!(manifest.package.targets[0].crate_types, ["proc-macro"]);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn refuses_to_import_existing_releases() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("1.0.0")
+                .archive_storage(true)
+                .create()?;
+
+            let bundle = export(env, "foo", "1.0.0")?;
+            let err = import(env, bundle.path()).unwrap_err();
+            assert!(err.to_string().contains("exists already"), "{err:?}");
+
+            let builds: i64 = env
+                .db()
+                .conn()
+                .query_one("SELECT COUNT(*) FROM builds", &[])?
+                .get(0);
+            assert_eq!(builds, 1);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn failed_import_adds_nothing() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("1.0.0")
+                .archive_storage(true)
+                .create()?;
+
+            let bundle = export(env, "foo", "1.0.0")?;
+            delete_version(
+                &mut env.db().conn(),
+                &env.storage(),
+                &env.config(),
+                None,
+                "foo",
+                "1.0.0",
+            )?;
+            assert_eq!(release_count(env)?, 0);
+
+            // the build log is only missing once the archives are stored and the release added
+            let broken = NamedTempFile::new()?;
+            let files = read_bundle(bundle.path())?
+                .into_iter()
+                .filter(|(path, _)| !path.starts_with("build-logs/"))
+                .collect();
+            write_bundle(broken.path(), files)?;
+
+            let err = import(env, broken.path()).unwrap_err();
+            assert!(err.to_string().contains("without build log"), "{err:?}");
+            assert_eq!(release_count(env)?, 0);
+
+            import(env, bundle.path())?;
+            assert_eq!(release_count(env)?, 1);
+
+            Ok(())
+        })
+    }
+}
@@ -1,6 +1,6 @@
 use crate::{
     error::Result,
-    storage::{rustdoc_archive_path, source_archive_path, Storage},
+    storage::{crate_storage_name, rustdoc_archive_path, source_archive_path, Storage},
     Config,
 };
 use anyhow::Context as _;
@@ -19,15 +19,21 @@ enum CrateDeletionError {
     MissingCrate(String),
 }
 
+/// Deletes the crate with all its releases.
+///
+/// The blobs of deduplicated archives might be shared with other crates, so they're kept.
+/// `cratesfyi database delete-orphaned-blobs` removes the ones no other release references.
 #[context("error trying to delete crate {name} from database")]
 pub fn delete_crate(
     conn: &mut Client,
     storage: &Storage,
     config: &Config,
+    registry: Option<&str>,
     name: &str,
 ) -> Result<()> {
-    let crate_id = get_id(conn, name)?;
-    let is_library = delete_crate_from_database(conn, name, crate_id)?;
+    let crate_id = get_id(conn, registry, name)?;
+    let is_library = delete_crate_from_database(conn, registry, name, crate_id)?;
+    let storage_name = crate_storage_name(registry, name);
     // #899
     let paths = if is_library {
         LIBRARY_STORAGE_PATHS_TO_DELETE
@@ -38,7 +44,7 @@ pub fn delete_crate(
     for prefix in paths {
         // delete the whole rustdoc/source folder for this crate.
         // it will include existing archives.
-        let remote_folder = format!("{prefix}/{name}/");
+        let remote_folder = format!("{prefix}/{storage_name}/");
         storage.delete_prefix(&remote_folder)?;
 
         // remove existing local archive index files.
@@ -56,15 +62,20 @@ pub fn delete_crate(
     Ok(())
 }
 
+/// Deletes a single release of the crate.
+///
+/// Like with [`delete_crate`], the blobs of its deduplicated archives are kept.
 #[context("error trying to delete release {name}-{version} from database")]
 pub fn delete_version(
     conn: &mut Client,
     storage: &Storage,
     config: &Config,
+    registry: Option<&str>,
     name: &str,
     version: &str,
 ) -> Result<()> {
-    let is_library = delete_version_from_database(conn, name, version)?;
+    let is_library = delete_version_from_database(conn, registry, name, version)?;
+    let storage_name = crate_storage_name(registry, name);
     let paths = if is_library {
         LIBRARY_STORAGE_PATHS_TO_DELETE
     } else {
@@ -72,13 +83,13 @@ pub fn delete_version(
     };
 
     for prefix in paths {
-        storage.delete_prefix(&format!("{prefix}/{name}/{version}/"))?;
+        storage.delete_prefix(&format!("{prefix}/{storage_name}/{version}/"))?;
     }
 
     let local_archive_cache = &config.local_archive_cache_path;
-    let mut paths = vec![source_archive_path(name, version)];
+    let mut paths = vec![source_archive_path(&storage_name, version)];
     if is_library {
-        paths.push(rustdoc_archive_path(name, version));
+        paths.push(rustdoc_archive_path(&storage_name, version));
     }
 
     for archive_filename in paths {
@@ -97,8 +108,11 @@ pub fn delete_version(
     Ok(())
 }
 
-fn get_id(conn: &mut Client, name: &str) -> Result<i32> {
-    let crate_id_res = conn.query("SELECT id FROM crates WHERE name = $1", &[&name])?;
+fn get_id(conn: &mut Client, registry: Option<&str>, name: &str) -> Result<i32> {
+    let crate_id_res = conn.query(
+        "SELECT id FROM crates WHERE name = $1 AND registry IS NOT DISTINCT FROM $2",
+        &[&name, &registry],
+    )?;
     if let Some(row) = crate_id_res.into_iter().next() {
         Ok(row.get("id"))
     } else {
@@ -116,8 +130,14 @@ const METADATA: &[(&str, &str)] = &[
 ];
 
 /// Returns whether this release was a library
-fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) -> Result<bool> {
-    let crate_id = get_id(conn, name)?;
+fn delete_version_from_database(
+    conn: &mut Client,
+    registry: Option<&str>,
+    name: &str,
+    version: &str,
+) -> Result<bool> {
+    let crate_id = get_id(conn, registry, name)?;
+    let storage_name = crate_storage_name(registry, name);
     let mut transaction = conn.transaction()?;
     for &(table, column) in METADATA {
         transaction.execute(
@@ -148,7 +168,11 @@ fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) ->
     for prefix in paths {
         transaction.execute(
             "DELETE FROM files WHERE path LIKE $1;",
-            &[&format!("{prefix}/{name}/{version}/%")],
+            &[&format!("{prefix}/{storage_name}/{version}/%")],
+        )?;
+        transaction.execute(
+            "DELETE FROM archive_checksums WHERE archive_path = $1;",
+            &[&format!("{prefix}/{storage_name}/{version}.zip")],
         )?;
     }
 
@@ -157,12 +181,17 @@ fn delete_version_from_database(conn: &mut Client, name: &str, version: &str) ->
 }
 
 /// Returns whether any release in this crate was a library
-fn delete_crate_from_database(conn: &mut Client, name: &str, crate_id: i32) -> Result<bool> {
+fn delete_crate_from_database(
+    conn: &mut Client,
+    registry: Option<&str>,
+    name: &str,
+    crate_id: i32,
+) -> Result<bool> {
     let mut transaction = conn.transaction()?;
 
     transaction.execute(
-        "DELETE FROM sandbox_overrides WHERE crate_name = $1",
-        &[&name],
+        "DELETE FROM sandbox_overrides WHERE crate_name = $1 AND registry IS NOT DISTINCT FROM $2",
+        &[&name, &registry],
     )?;
     for &(table, column) in METADATA {
         transaction.execute(
@@ -174,6 +203,12 @@ fn delete_crate_from_database(conn: &mut Client, name: &str, crate_id: i32) -> R
         )?;
     }
     transaction.execute("DELETE FROM owner_rels WHERE cid = $1;", &[&crate_id])?;
+    for prefix in LIBRARY_STORAGE_PATHS_TO_DELETE {
+        transaction.execute(
+            "DELETE FROM archive_checksums WHERE starts_with(archive_path, $1);",
+            &[&format!("{prefix}/{}/", crate_storage_name(registry, name))],
+        )?;
+    }
     let has_library = transaction
         .query_one(
             "SELECT
@@ -256,7 +291,13 @@ mod tests {
                 )?);
             }
 
-            delete_crate(&mut db.conn(), &env.storage(), &env.config(), "package-1")?;
+            delete_crate(
+                &mut db.conn(),
+                &env.storage(),
+                &env.config(),
+                None,
+                "package-1",
+            )?;
 
             assert!(!crate_exists(&mut db.conn(), "package-1")?);
             assert!(crate_exists(&mut db.conn(), "package-2")?);
@@ -373,7 +414,14 @@ mod tests {
                 vec!["Peter Rabbit".to_string()]
             );
 
-            delete_version(&mut db.conn(), &env.storage(), &env.config(), "a", "1.0.0")?;
+            delete_version(
+                &mut db.conn(),
+                &env.storage(),
+                &env.config(),
+                None,
+                "a",
+                "1.0.0",
+            )?;
             assert!(!release_exists(&mut db.conn(), v1)?);
             if archive_storage {
                 // for archive storage the archive and index files
@@ -418,4 +466,65 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn test_delete_alternative_registry_crate() {
+        wrapper(|env| {
+            let db = env.db();
+
+            let crates_io_id = env
+                .fake_release()
+                .name("package")
+                .version("1.0.0")
+                .archive_storage(true)
+                .create()?;
+            let internal_id = env
+                .fake_release()
+                .name("package")
+                .version("1.0.0")
+                .registry("internal")
+                .archive_storage(true)
+                .create()?;
+            env.fake_release()
+                .name("package")
+                .version("2.0.0")
+                .registry("internal")
+                .archive_storage(true)
+                .create()?;
+
+            delete_version(
+                &mut db.conn(),
+                &env.storage(),
+                &env.config(),
+                Some("internal"),
+                "package",
+                "1.0.0",
+            )?;
+            assert!(!release_exists(&mut db.conn(), internal_id)?);
+            assert!(release_exists(&mut db.conn(), crates_io_id)?);
+            assert!(!env
+                .storage()
+                .exists(&rustdoc_archive_path("@internal/package", "1.0.0"))?);
+            assert!(env
+                .storage()
+                .exists(&rustdoc_archive_path("package", "1.0.0"))?);
+
+            delete_crate(
+                &mut db.conn(),
+                &env.storage(),
+                &env.config(),
+                Some("internal"),
+                "package",
+            )?;
+            assert_eq!(
+                db.conn()
+                    .query_one("SELECT COUNT(*) FROM crates WHERE name = 'package'", &[])?
+                    .get::<_, i64>(0),
+                1
+            );
+            assert!(release_exists(&mut db.conn(), crates_io_id)?);
+
+            Ok(())
+        })
+    }
 }
@@ -8,7 +8,9 @@
 //! However, postgres is still available for testing and backwards compatibility.
 
 use crate::error::Result;
-use crate::storage::{AsyncStorage, CompressionAlgorithm, CompressionAlgorithms};
+use crate::storage::{
+    record_archive_checksums, AsyncStorage, CompressionAlgorithm, CompressionAlgorithms,
+};
 use serde_json::Value;
 use std::path::{Path, PathBuf};
 use tracing::instrument;
@@ -34,17 +36,23 @@ pub async fn add_path_into_database<P: AsRef<Path>>(
     ))
 }
 
-#[instrument(skip(storage))]
+/// Store all files in a directory as archive at `archive_path`, and record the checksums of the
+/// archive so `cratesfyi storage verify` can check it later.
+#[instrument(skip(conn, storage))]
 pub async fn add_path_into_remote_archive<P: AsRef<Path> + std::fmt::Debug>(
+    conn: &mut sqlx::PgConnection,
     storage: &AsyncStorage,
     archive_path: &str,
     path: P,
     public_access: bool,
 ) -> Result<(Value, CompressionAlgorithm)> {
-    let (file_list, algorithm) = storage
+    let (file_list, algorithm, checksums) = storage
         .store_all_in_archive(archive_path, path.as_ref())
         .await?;
-    if public_access {
+    record_archive_checksums(conn, archive_path, &checksums).await?;
+    // deduplicated archives don't have a ZIP file that could be public,
+    // it's only created when someone downloads the archive.
+    if public_access && storage.exists(archive_path).await? {
         storage.set_public_access(archive_path, true).await?;
     }
     Ok((
@@ -0,0 +1,253 @@
+use crate::{
+    db::{types::BuildFailureCategory, Overrides},
+    error::Result,
+};
+use chrono::{DateTime, Utc};
+use futures_util::stream::TryStreamExt;
+use std::time::Duration;
+
+/// Higher sandbox limits the build queue gave a single release, after its build
+/// ran into a timeout or out of memory.
+///
+/// Unlike [`Overrides`] these only apply to the release they were created for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitEscalation {
+    pub crate_name: String,
+    pub version: String,
+    /// the alternative registry of the release, `None` for crates.io
+    pub registry: Option<String>,
+    pub(crate) failure_category: BuildFailureCategory,
+    pub overrides: Overrides,
+    pub created_at: DateTime<Utc>,
+}
+
+macro_rules! row_to_escalation {
+    ($row:expr) => {{
+        LimitEscalation {
+            crate_name: $row.crate_name,
+            version: $row.version,
+            registry: $row.registry,
+            failure_category: $row.failure_category,
+            overrides: Overrides {
+                memory: $row.max_memory_bytes.map(|i| i as usize),
+                targets: $row.max_targets.map(|i| i as usize),
+                timeout: $row.timeout_seconds.map(|i| Duration::from_secs(i as u64)),
+            },
+            created_at: $row.created_at,
+        }
+    }};
+}
+
+impl LimitEscalation {
+    pub async fn all(conn: &mut sqlx::PgConnection) -> Result<Vec<Self>> {
+        Ok(sqlx::query!(
+            r#"SELECT
+                crate_name,
+                version,
+                registry,
+                failure_category as "failure_category: BuildFailureCategory",
+                max_memory_bytes,
+                timeout_seconds,
+                max_targets,
+                created_at
+            FROM limit_escalations
+            ORDER BY created_at DESC"#
+        )
+        .fetch(conn)
+        .map_ok(|row| row_to_escalation!(row))
+        .try_collect()
+        .await?)
+    }
+
+    pub async fn for_release(
+        conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
+        krate: &str,
+        version: &str,
+    ) -> Result<Option<Self>> {
+        Ok(sqlx::query!(
+            r#"SELECT
+                crate_name,
+                version,
+                registry,
+                failure_category as "failure_category: BuildFailureCategory",
+                max_memory_bytes,
+                timeout_seconds,
+                max_targets,
+                created_at
+            FROM limit_escalations
+            WHERE
+                crate_name = $1 AND
+                version = $2 AND
+                registry IS NOT DISTINCT FROM $3"#,
+            krate,
+            version,
+            registry,
+        )
+        .fetch_optional(conn)
+        .await?
+        .map(|row| row_to_escalation!(row)))
+    }
+
+    /// Records an escalation, returns `false` if the release already had one.
+    ///
+    /// `overrides` should only contain the limits that were raised.
+    pub(crate) async fn save(
+        conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
+        krate: &str,
+        version: &str,
+        failure_category: BuildFailureCategory,
+        overrides: Overrides,
+    ) -> Result<bool> {
+        Ok(sqlx::query!(
+            "INSERT INTO limit_escalations (
+                crate_name, version, registry, failure_category,
+                max_memory_bytes, max_targets, timeout_seconds
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (crate_name, version, (COALESCE(registry, ''))) DO NOTHING",
+            krate,
+            version,
+            registry,
+            failure_category as BuildFailureCategory,
+            overrides.memory.map(|i| i as i64),
+            overrides.targets.map(|i| i as i32),
+            overrides.timeout.map(|d| d.as_secs() as i32),
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected()
+            > 0)
+    }
+
+    /// Makes the escalated limits of a release permanent overrides for its crate.
+    ///
+    /// Only the limits that were raised are changed, the other overrides of the crate are kept.
+    /// Returns the new overrides of the crate.
+    pub async fn promote(
+        conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
+        krate: &str,
+        version: &str,
+    ) -> Result<Option<Overrides>> {
+        let Some(escalation) = Self::for_release(&mut *conn, registry, krate, version).await?
+        else {
+            return Ok(None);
+        };
+        let existing = Overrides::for_crate(&mut *conn, registry, krate)
+            .await?
+            .unwrap_or_default();
+        let overrides = Overrides {
+            memory: escalation.overrides.memory.or(existing.memory),
+            targets: escalation.overrides.targets.or(existing.targets),
+            timeout: escalation.overrides.timeout.or(existing.timeout),
+        };
+        Overrides::save(&mut *conn, registry, krate, overrides).await?;
+        Ok(Some(overrides))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::*;
+
+    #[test]
+    fn save_and_promote() {
+        async_wrapper(|env| async move {
+            let db = env.async_db().await;
+            let mut conn = db.async_conn().await;
+
+            let krate = "hexponent";
+            assert_eq!(
+                LimitEscalation::for_release(&mut conn, None, krate, "0.1.0").await?,
+                None
+            );
+
+            let escalated = Overrides {
+                memory: Some(6 * 1024 * 1024 * 1024),
+                ..Overrides::default()
+            };
+            assert!(
+                LimitEscalation::save(
+                    &mut conn,
+                    None,
+                    krate,
+                    "0.1.0",
+                    BuildFailureCategory::OutOfMemory,
+                    escalated
+                )
+                .await?
+            );
+            // releases are only escalated once
+            assert!(
+                !LimitEscalation::save(
+                    &mut conn,
+                    None,
+                    krate,
+                    "0.1.0",
+                    BuildFailureCategory::Timeout,
+                    Overrides::default()
+                )
+                .await?
+            );
+
+            let escalation = LimitEscalation::for_release(&mut conn, None, krate, "0.1.0")
+                .await?
+                .unwrap();
+            assert_eq!(
+                escalation.failure_category,
+                BuildFailureCategory::OutOfMemory
+            );
+            assert_eq!(escalation.registry, None);
+            assert_eq!(escalation.overrides, escalated);
+            assert_eq!(LimitEscalation::all(&mut conn).await?, vec![escalation]);
+
+            // releases of other registries are escalated separately
+            assert_eq!(
+                LimitEscalation::for_release(&mut conn, Some("internal"), krate, "0.1.0").await?,
+                None
+            );
+            assert!(
+                LimitEscalation::save(
+                    &mut conn,
+                    Some("internal"),
+                    krate,
+                    "0.1.0",
+                    BuildFailureCategory::Timeout,
+                    Overrides::default()
+                )
+                .await?
+            );
+
+            // escalations only apply to their release until they are promoted, which only
+            // changes the escalated limits of the crate
+            let existing = Overrides {
+                targets: Some(2),
+                timeout: Some(Duration::from_secs(20 * 60)),
+                ..Overrides::default()
+            };
+            Overrides::save(&mut conn, None, krate, existing).await?;
+            let promoted = Overrides {
+                memory: escalated.memory,
+                ..existing
+            };
+            assert_eq!(
+                LimitEscalation::promote(&mut conn, None, krate, "0.1.0").await?,
+                Some(promoted)
+            );
+            assert_eq!(
+                Overrides::for_crate(&mut conn, None, krate).await?,
+                Some(promoted)
+            );
+
+            assert_eq!(
+                LimitEscalation::promote(&mut conn, None, krate, "0.2.0").await?,
+                None
+            );
+
+            Ok(())
+        })
+    }
+}
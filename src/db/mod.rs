@@ -4,20 +4,25 @@ use sqlx::migrate::{Migrate, Migrator};
 
 pub use self::add_package::update_latest_version_id;
 pub(crate) use self::add_package::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
+    add_build_failure, add_build_into_database, add_doc_coverage, add_package_into_database,
+    add_target_build_result, crate_id_from_release_id, update_crate_items,
 };
 pub use self::{
-    add_package::{update_build_status, update_crate_data_in_database},
+    add_package::{rebuild_crate_items, update_build_status, update_crate_data_in_database},
+    bundle::{export_release, import_release},
     delete::{delete_crate, delete_version},
     file::{add_path_into_database, add_path_into_remote_archive},
+    limit_escalations::LimitEscalation,
     overrides::Overrides,
     pool::{AsyncPoolClient, Pool, PoolClient, PoolError},
 };
 
 mod add_package;
 pub mod blacklist;
+mod bundle;
 pub mod delete;
 pub(crate) mod file;
+mod limit_escalations;
 mod overrides;
 mod pool;
 pub(crate) mod types;
@@ -20,33 +20,52 @@ macro_rules! row_to_overrides {
 }
 
 impl Overrides {
-    pub async fn all(conn: &mut sqlx::PgConnection) -> Result<Vec<(String, Self)>> {
+    /// All overrides, with the alternative registry and the name of their crate.
+    pub async fn all(conn: &mut sqlx::PgConnection) -> Result<Vec<(Option<String>, String, Self)>> {
         Ok(sqlx::query!("SELECT * FROM sandbox_overrides")
             .fetch(conn)
-            .map_ok(|row| (row.crate_name, row_to_overrides!(row)))
+            .map_ok(|row| {
+                let overrides = row_to_overrides!(row);
+                (row.registry, row.crate_name, overrides)
+            })
             .try_collect()
             .await?)
     }
 
-    pub async fn for_crate(conn: &mut sqlx::PgConnection, krate: &str) -> Result<Option<Self>> {
+    pub async fn for_crate(
+        conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
+        krate: &str,
+    ) -> Result<Option<Self>> {
         Ok(sqlx::query!(
-            "SELECT * FROM sandbox_overrides WHERE crate_name = $1",
-            krate
+            "SELECT * FROM sandbox_overrides
+             WHERE crate_name = $1 AND registry IS NOT DISTINCT FROM $2",
+            krate,
+            registry,
         )
         .fetch_optional(conn)
         .await?
         .map(|row| row_to_overrides!(row)))
     }
 
-    pub async fn save(conn: &mut sqlx::PgConnection, krate: &str, overrides: Self) -> Result<()> {
+    pub async fn save(
+        conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
+        krate: &str,
+        overrides: Self,
+    ) -> Result<()> {
         if overrides.timeout.is_some() && overrides.targets.is_none() {
             tracing::warn!("setting `Overrides::timeout` implies a default `Overrides::targets = 1`, prefer setting this explicitly");
         }
 
-        if sqlx::query_scalar!("SELECT id FROM crates WHERE crates.name = $1", krate)
-            .fetch_optional(&mut *conn)
-            .await?
-            .is_none()
+        if sqlx::query_scalar!(
+            "SELECT id FROM crates WHERE crates.name = $1 AND crates.registry IS NOT DISTINCT FROM $2",
+            krate,
+            registry,
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .is_none()
         {
             tracing::warn!("setting overrides for unknown crate `{krate}`");
         }
@@ -54,10 +73,10 @@ impl Overrides {
         sqlx::query!(
             "
             INSERT INTO sandbox_overrides (
-                crate_name, max_memory_bytes, max_targets, timeout_seconds
+                crate_name, max_memory_bytes, max_targets, timeout_seconds, registry
             )
-            VALUES ($1, $2, $3, $4)
-            ON CONFLICT (crate_name) DO UPDATE
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (crate_name, (COALESCE(registry, ''))) DO UPDATE
                 SET
                     max_memory_bytes = $2,
                     max_targets = $3,
@@ -67,16 +86,26 @@ impl Overrides {
             overrides.memory.map(|i| i as i64),
             overrides.targets.map(|i| i as i32),
             overrides.timeout.map(|d| d.as_secs() as i32),
+            registry,
         )
         .execute(&mut *conn)
         .await?;
         Ok(())
     }
 
-    pub async fn remove(conn: &mut sqlx::PgConnection, krate: &str) -> Result<()> {
-        sqlx::query!("DELETE FROM sandbox_overrides WHERE crate_name = $1", krate)
-            .execute(conn)
-            .await?;
+    pub async fn remove(
+        conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
+        krate: &str,
+    ) -> Result<()> {
+        sqlx::query!(
+            "DELETE FROM sandbox_overrides
+             WHERE crate_name = $1 AND registry IS NOT DISTINCT FROM $2",
+            krate,
+            registry,
+        )
+        .execute(conn)
+        .await?;
         Ok(())
     }
 }
@@ -95,7 +124,7 @@ mod test {
             let krate = "hexponent";
 
             // no overrides
-            let actual = Overrides::for_crate(&mut conn, krate).await?;
+            let actual = Overrides::for_crate(&mut conn, None, krate).await?;
             assert_eq!(actual, None);
 
             // add partial overrides
@@ -103,8 +132,8 @@ mod test {
                 targets: Some(1),
                 ..Overrides::default()
             };
-            Overrides::save(&mut conn, krate, expected).await?;
-            let actual = Overrides::for_crate(&mut conn, krate).await?;
+            Overrides::save(&mut conn, None, krate, expected).await?;
+            let actual = Overrides::for_crate(&mut conn, None, krate).await?;
             assert_eq!(actual, Some(expected));
 
             // overwrite with full overrides
@@ -113,8 +142,8 @@ mod test {
                 targets: Some(1),
                 timeout: Some(Duration::from_secs(300)),
             };
-            Overrides::save(&mut conn, krate, expected).await?;
-            let actual = Overrides::for_crate(&mut conn, krate).await?;
+            Overrides::save(&mut conn, None, krate, expected).await?;
+            let actual = Overrides::for_crate(&mut conn, None, krate).await?;
             assert_eq!(actual, Some(expected));
 
             // overwrite with partial overrides
@@ -122,13 +151,20 @@ mod test {
                 memory: Some(1),
                 ..Overrides::default()
             };
-            Overrides::save(&mut conn, krate, expected).await?;
-            let actual = Overrides::for_crate(&mut conn, krate).await?;
+            Overrides::save(&mut conn, None, krate, expected).await?;
+            let actual = Overrides::for_crate(&mut conn, None, krate).await?;
             assert_eq!(actual, Some(expected));
 
             // remove overrides
-            Overrides::remove(&mut conn, krate).await?;
-            let actual = Overrides::for_crate(&mut conn, krate).await?;
+            Overrides::remove(&mut conn, None, krate).await?;
+            let actual = Overrides::for_crate(&mut conn, None, krate).await?;
+            assert_eq!(actual, None);
+
+            // crates of alternative registries have their own overrides
+            Overrides::save(&mut conn, Some("internal"), krate, expected).await?;
+            let actual = Overrides::for_crate(&mut conn, Some("internal"), krate).await?;
+            assert_eq!(actual, Some(expected));
+            let actual = Overrides::for_crate(&mut conn, None, krate).await?;
             assert_eq!(actual, None);
 
             Ok(())
@@ -45,6 +45,38 @@ impl sqlx::postgres::PgHasArrayType for BuildStatus {
     }
 }
 
+/// Why a build failed, derived from its result and build log.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    strum::EnumString,
+    strum::Display,
+)]
+#[sqlx(type_name = "build_failure_category", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub(crate) enum BuildFailureCategory {
+    /// the crate itself didn't compile
+    CompileError,
+    /// one of the dependencies didn't compile, or couldn't be resolved
+    DependencyFailed,
+    OutOfMemory,
+    Timeout,
+    /// a system library needed by a build script wasn't found
+    MissingNativeLibrary,
+    /// the build tried to access the network, which is blocked in the sandbox
+    NetworkNeeded,
+    /// an internal compiler error in rustc or rustdoc
+    RustdocIce,
+    Other,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,4 +93,14 @@ mod tests {
             status
         );
     }
+
+    #[test_case(BuildFailureCategory::CompileError, "compile_error")]
+    #[test_case(BuildFailureCategory::MissingNativeLibrary, "missing_native_library")]
+    #[test_case(BuildFailureCategory::RustdocIce, "rustdoc_ice")]
+    fn test_build_failure_category_serialization(category: BuildFailureCategory, expected: &str) {
+        let serialized = serde_json::to_string(&category).unwrap();
+        assert_eq!(serialized, format!("\"{}\"", expected));
+        assert_eq!(category.to_string(), expected);
+        assert_eq!(expected.parse::<BuildFailureCategory>().unwrap(), category);
+    }
 }
@@ -0,0 +1,262 @@
+//! Classification of failed builds, based on the error of the build command and its log.
+
+use crate::db::types::BuildFailureCategory;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rustwide::cmd::CommandError;
+use std::borrow::Cow;
+
+/// Maximum number of log lines stored as error excerpt.
+const EXCERPT_MAX_LINES: usize = 15;
+/// Maximum length of the stored error excerpt, in bytes.
+const EXCERPT_MAX_LEN: usize = 2000;
+
+const ICE_PATTERNS: &[&str] = &[
+    "internal compiler error",
+    "query stack during panic",
+    "thread 'rustc' panicked",
+    "thread 'rustdoc' panicked",
+];
+
+const OUT_OF_MEMORY_PATTERNS: &[&str] = &["memory allocation of", "out of memory", "SIGKILL"];
+
+const MISSING_NATIVE_LIBRARY_PATTERNS: &[&str] = &[
+    "Could not find system library",
+    "was not found in the pkg-config search path",
+    "pkg-config has not been configured",
+    "could not find native static library",
+    "unable to find library -l",
+    "cannot find -l",
+    ".h: No such file or directory",
+    "file not found for module",
+];
+
+const NETWORK_NEEDED_PATTERNS: &[&str] = &[
+    "Could not resolve host",
+    "Temporary failure in name resolution",
+    "failed to lookup address information",
+    "Network is unreachable",
+    "Connection refused",
+    "dns error",
+];
+
+const DEPENDENCY_RESOLUTION_PATTERNS: &[&str] = &[
+    "failed to select a version for",
+    "no matching package named",
+    "failed to load source for dependency",
+    "failed to get `",
+];
+
+/// Matches the level and stream prefixes rustwide adds to each captured log line,
+/// like `[INFO] [stderr] `.
+static LOG_PREFIX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(\[[a-zA-Z]+\] )+").unwrap());
+static COULD_NOT_COMPILE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"error: could not compile `([^`\s]+)").unwrap());
+static BUILD_SCRIPT_FAILED: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"error: failed to run custom build command for `([^`\s]+)").unwrap());
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct BuildFailure {
+    pub(crate) category: BuildFailureCategory,
+    /// The part of the build log explaining the failure.
+    pub(crate) excerpt: Option<String>,
+}
+
+/// Classifies a failed build of `crate_name`.
+///
+/// `error` is the error returned when running the build command, which tells us
+/// about timeouts and the sandbox running out of memory.
+pub(crate) fn classify_build_failure(
+    crate_name: &str,
+    error: Option<&anyhow::Error>,
+    log: &str,
+) -> BuildFailure {
+    let lines: Vec<Cow<'_, str>> = log
+        .lines()
+        .map(|line| LOG_PREFIX.replace(line, ""))
+        .collect();
+
+    let find = |patterns: &[&str]| {
+        lines
+            .iter()
+            .position(|line| patterns.iter().any(|pattern| line.contains(pattern)))
+    };
+    let first_error = lines.iter().position(|line| line.starts_with("error"));
+    let failed_crate = |re: &Regex| {
+        lines.iter().enumerate().find_map(|(i, line)| {
+            let name = re.captures(line)?.get(1)?.as_str();
+            Some((i, name == crate_name))
+        })
+    };
+
+    let command_category = match error.and_then(|err| err.downcast_ref::<CommandError>()) {
+        Some(CommandError::SandboxOOM) => Some(BuildFailureCategory::OutOfMemory),
+        Some(CommandError::Timeout(_) | CommandError::NoOutputFor(_)) => {
+            Some(BuildFailureCategory::Timeout)
+        }
+        _ => None,
+    };
+
+    let (category, excerpt_start) = if let Some(category) = command_category {
+        // the interesting part is where the build stopped
+        (
+            category,
+            Some(lines.len().saturating_sub(EXCERPT_MAX_LINES)),
+        )
+    } else if let Some(i) = find(ICE_PATTERNS) {
+        (BuildFailureCategory::RustdocIce, Some(i))
+    } else if let Some(i) = find(OUT_OF_MEMORY_PATTERNS) {
+        (BuildFailureCategory::OutOfMemory, Some(i))
+    } else if let Some(i) = find(MISSING_NATIVE_LIBRARY_PATTERNS) {
+        (BuildFailureCategory::MissingNativeLibrary, Some(i))
+    } else if let Some(i) = find(NETWORK_NEEDED_PATTERNS) {
+        (BuildFailureCategory::NetworkNeeded, Some(i))
+    } else if let Some(i) = find(DEPENDENCY_RESOLUTION_PATTERNS) {
+        (BuildFailureCategory::DependencyFailed, Some(i))
+    } else if let Some((i, false)) =
+        failed_crate(&BUILD_SCRIPT_FAILED).or_else(|| failed_crate(&COULD_NOT_COMPILE))
+    {
+        (
+            BuildFailureCategory::DependencyFailed,
+            first_error.or(Some(i)),
+        )
+    } else if first_error.is_some() {
+        (BuildFailureCategory::CompileError, first_error)
+    } else {
+        (BuildFailureCategory::Other, None)
+    };
+
+    let excerpt = excerpt_start
+        .map(|start| {
+            let mut excerpt = lines
+                .iter()
+                .skip(start)
+                .take(EXCERPT_MAX_LINES)
+                .map(|line| line.trim_end())
+                .collect::<Vec<_>>()
+                .join("\n");
+            if excerpt.len() > EXCERPT_MAX_LEN {
+                let mut end = EXCERPT_MAX_LEN;
+                while !excerpt.is_char_boundary(end) {
+                    end -= 1;
+                }
+                excerpt.truncate(end);
+            }
+            excerpt
+        })
+        .filter(|excerpt| !excerpt.trim().is_empty());
+
+    BuildFailure { category, excerpt }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case(
+        "[INFO] [stderr]    Compiling krate v0.1.0\n\
+         [INFO] [stderr] error[E0425]: cannot find value `x` in this scope\n\
+         [INFO] [stderr] error: could not compile `krate` (lib) due to 1 previous error",
+        BuildFailureCategory::CompileError;
+        "compile error"
+    )]
+    #[test_case(
+        "[INFO] [stderr] error[E0599]: no method named `foo` found\n\
+         [INFO] [stderr] error: could not compile `some-dependency` (lib) due to 1 previous error",
+        BuildFailureCategory::DependencyFailed;
+        "dependency compile error"
+    )]
+    #[test_case(
+        "[INFO] [stderr] error: failed to run custom build command for `openssl-sys v0.9.0`\n\
+         [INFO] [stderr]   process didn't exit successfully: `build-script-build`",
+        BuildFailureCategory::DependencyFailed;
+        "dependency build script"
+    )]
+    #[test_case(
+        "[INFO] [stderr] error: failed to select a version for `foo`.",
+        BuildFailureCategory::DependencyFailed;
+        "dependency resolution"
+    )]
+    #[test_case(
+        "[INFO] [stderr] error: failed to run custom build command for `krate v0.1.0`\n\
+         [INFO] [stderr]   The system library `gtk+-3.0` required by crate `krate` was not found.\n\
+         [INFO] [stderr]   Package gtk+-3.0 was not found in the pkg-config search path.",
+        BuildFailureCategory::MissingNativeLibrary;
+        "missing native library"
+    )]
+    #[test_case(
+        "[INFO] [stderr] error: failed to run custom build command for `krate v0.1.0`\n\
+         [INFO] [stderr]   curl: (6) Could not resolve host: example.com",
+        BuildFailureCategory::NetworkNeeded;
+        "network needed"
+    )]
+    #[test_case(
+        "[INFO] [stderr] error: internal compiler error: unexpected panic\n\
+         [INFO] [stderr] query stack during panic:",
+        BuildFailureCategory::RustdocIce;
+        "ice"
+    )]
+    #[test_case(
+        "[INFO] [stderr] memory allocation of 1073741824 bytes failed",
+        BuildFailureCategory::OutOfMemory;
+        "oom from log"
+    )]
+    #[test_case(
+        "[INFO] [stderr]    Documenting krate v0.1.0",
+        BuildFailureCategory::Other;
+        "other"
+    )]
+    fn classify_from_log(log: &str, expected: BuildFailureCategory) {
+        assert_eq!(
+            classify_build_failure("krate", None, log).category,
+            expected
+        );
+    }
+
+    #[test]
+    fn classify_from_command_error() {
+        let log = "[INFO] [stderr]    Compiling krate v0.1.0";
+
+        let timeout = anyhow::Error::from(CommandError::Timeout(900));
+        let failure = classify_build_failure("krate", Some(&timeout), log);
+        assert_eq!(failure.category, BuildFailureCategory::Timeout);
+        assert_eq!(
+            failure.excerpt.as_deref(),
+            Some("   Compiling krate v0.1.0")
+        );
+
+        let oom = anyhow::Error::from(CommandError::SandboxOOM);
+        assert_eq!(
+            classify_build_failure("krate", Some(&oom), log).category,
+            BuildFailureCategory::OutOfMemory
+        );
+    }
+
+    #[test]
+    fn excerpt_starts_at_first_error() {
+        let log = "[INFO] [stderr]    Compiling krate v0.1.0\n\
+                   [INFO] [stderr] error[E0425]: cannot find value `x` in this scope\n\
+                   [INFO] [stderr]  --> src/lib.rs:1:1\n\
+                   [INFO] [stderr] error: could not compile `krate` (lib) due to 1 previous error";
+
+        assert_eq!(
+            classify_build_failure("krate", None, log)
+                .excerpt
+                .as_deref(),
+            Some(
+                "error[E0425]: cannot find value `x` in this scope\n \
+                 --> src/lib.rs:1:1\n\
+                 error: could not compile `krate` (lib) due to 1 previous error"
+            )
+        );
+    }
+
+    #[test]
+    fn excerpt_is_truncated() {
+        let log = format!("error: {}", "ä".repeat(EXCERPT_MAX_LEN));
+        let excerpt = classify_build_failure("krate", None, &log).excerpt.unwrap();
+        assert!(excerpt.len() <= EXCERPT_MAX_LEN);
+        assert!(excerpt.starts_with("error: ä"));
+    }
+}
@@ -1,4 +1,8 @@
-use crate::{db::Overrides, error::Result, Config};
+use crate::{
+    db::{types::BuildFailureCategory, LimitEscalation, Overrides},
+    error::Result,
+    Config,
+};
 use serde::Serialize;
 use std::time::Duration;
 
@@ -28,10 +32,13 @@ impl Limits {
     pub(crate) async fn for_crate(
         config: &Config,
         conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
         name: &str,
     ) -> Result<Self> {
         let default = Self::new(config);
-        let overrides = Overrides::for_crate(conn, name).await?.unwrap_or_default();
+        let overrides = Overrides::for_crate(conn, registry, name)
+            .await?
+            .unwrap_or_default();
         Ok(Self {
             memory: overrides
                 .memory
@@ -47,6 +54,66 @@ impl Limits {
         })
     }
 
+    /// Limits for a single release, including the escalated limits it
+    /// might have been given after a failed build.
+    pub(crate) async fn for_release(
+        config: &Config,
+        conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
+        name: &str,
+        version: &str,
+    ) -> Result<Self> {
+        let limits = Self::for_crate(config, &mut *conn, registry, name).await?;
+        let Some(escalation) =
+            LimitEscalation::for_release(&mut *conn, registry, name, version).await?
+        else {
+            return Ok(limits);
+        };
+        let overrides = escalation.overrides;
+        Ok(Self {
+            memory: overrides.memory.unwrap_or(limits.memory).max(limits.memory),
+            targets: overrides.targets.unwrap_or(limits.targets),
+            timeout: overrides
+                .timeout
+                .unwrap_or(limits.timeout)
+                .max(limits.timeout),
+            ..limits
+        })
+    }
+
+    /// Raises the limit a build failing with `category` ran into, up to the
+    /// ceiling in the config.
+    ///
+    /// The returned overrides only contain the raised limits. Returns `None` if
+    /// the failure isn't caused by the limits, or they can't be raised any further.
+    pub(crate) fn escalate(
+        &self,
+        config: &Config,
+        category: BuildFailureCategory,
+    ) -> Option<Overrides> {
+        let mut overrides = Overrides::default();
+        match category {
+            BuildFailureCategory::OutOfMemory => {
+                let memory = (self.memory * 2).min(config.build_limit_escalation_max_memory);
+                if memory <= self.memory {
+                    return None;
+                }
+                overrides.memory = Some(memory);
+            }
+            BuildFailureCategory::Timeout => {
+                let timeout = (self.timeout * 2).min(config.build_limit_escalation_max_timeout);
+                if timeout <= self.timeout {
+                    return None;
+                }
+                overrides.timeout = Some(timeout);
+                // like with manual timeout overrides, only build the default target
+                overrides.targets = Some(1);
+            }
+            _ => return None,
+        }
+        Some(overrides)
+    }
+
     pub(crate) fn memory(&self) -> usize {
         self.memory
     }
@@ -83,11 +150,12 @@ mod test {
 
             let krate = "hexponent";
             // limits work if no crate has limits set
-            let hexponent = Limits::for_crate(&env.config(), &mut conn, krate).await?;
+            let hexponent = Limits::for_crate(&env.config(), &mut conn, None, krate).await?;
             assert_eq!(hexponent, defaults);
 
             Overrides::save(
                 &mut conn,
+                None,
                 krate,
                 Overrides {
                     targets: Some(15),
@@ -96,7 +164,7 @@ mod test {
             )
             .await?;
             // limits work if crate has limits set
-            let hexponent = Limits::for_crate(&env.config(), &mut conn, krate).await?;
+            let hexponent = Limits::for_crate(&env.config(), &mut conn, None, krate).await?;
             assert_eq!(
                 hexponent,
                 Limits {
@@ -115,6 +183,7 @@ mod test {
             };
             Overrides::save(
                 &mut conn,
+                None,
                 krate,
                 Overrides {
                     memory: Some(limits.memory),
@@ -125,7 +194,7 @@ mod test {
             .await?;
             assert_eq!(
                 limits,
-                Limits::for_crate(&env.config(), &mut conn, krate).await?
+                Limits::for_crate(&env.config(), &mut conn, None, krate).await?
             );
             Ok(())
         })
@@ -139,6 +208,7 @@ mod test {
             let krate = "hexponent";
             Overrides::save(
                 &mut conn,
+                None,
                 krate,
                 Overrides {
                     timeout: Some(Duration::from_secs(20 * 60)),
@@ -146,7 +216,7 @@ mod test {
                 },
             )
             .await?;
-            let limits = Limits::for_crate(&env.config(), &mut conn, krate).await?;
+            let limits = Limits::for_crate(&env.config(), &mut conn, None, krate).await?;
             assert_eq!(limits.targets, 1);
 
             Ok(())
@@ -163,13 +233,107 @@ mod test {
             let db = env.async_db().await;
             let mut conn = db.async_conn().await;
 
-            let limits = Limits::for_crate(&env.config(), &mut conn, "krate").await?;
+            let limits = Limits::for_crate(&env.config(), &mut conn, None, "krate").await?;
             assert_eq!(limits.memory, 6 * GB);
 
             Ok(())
         })
     }
 
+    #[test]
+    fn escalate_limits() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.build_default_memory_limit = Some(3 * GB);
+                config.build_limit_escalation_max_memory = 5 * GB;
+                config.build_limit_escalation_max_timeout = Duration::from_secs(60 * 60);
+            });
+            let config = env.config();
+            let defaults = Limits::new(&config);
+
+            let escalated = defaults
+                .escalate(&config, BuildFailureCategory::OutOfMemory)
+                .unwrap();
+            assert_eq!(
+                escalated,
+                Overrides {
+                    memory: Some(5 * GB),
+                    ..Overrides::default()
+                }
+            );
+
+            let escalated = defaults
+                .escalate(&config, BuildFailureCategory::Timeout)
+                .unwrap();
+            assert_eq!(
+                escalated,
+                Overrides {
+                    timeout: Some(Duration::from_secs(30 * 60)),
+                    targets: Some(1),
+                    ..Overrides::default()
+                }
+            );
+
+            // other failures aren't caused by the limits
+            assert_eq!(
+                defaults.escalate(&config, BuildFailureCategory::CompileError),
+                None
+            );
+
+            // the ceiling is already reached
+            let limits = Limits {
+                memory: 5 * GB,
+                ..defaults
+            };
+            assert_eq!(
+                limits.escalate(&config, BuildFailureCategory::OutOfMemory),
+                None
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn escalated_limits_apply_to_their_release() {
+        async_wrapper(|env| async move {
+            let db = env.async_db().await;
+            let mut conn = db.async_conn().await;
+            let defaults = Limits::new(&env.config());
+
+            LimitEscalation::save(
+                &mut conn,
+                None,
+                "krate",
+                "0.1.0",
+                BuildFailureCategory::Timeout,
+                Overrides {
+                    timeout: Some(defaults.timeout * 2),
+                    targets: Some(1),
+                    ..Overrides::default()
+                },
+            )
+            .await?;
+
+            let limits =
+                Limits::for_release(&env.config(), &mut conn, None, "krate", "0.1.0").await?;
+            assert_eq!(
+                limits,
+                Limits {
+                    timeout: defaults.timeout * 2,
+                    targets: 1,
+                    ..defaults.clone()
+                }
+            );
+
+            let limits =
+                Limits::for_release(&env.config(), &mut conn, None, "krate", "0.2.0").await?;
+            assert_eq!(limits, defaults);
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn overrides_dont_lower_memory_limit() {
         async_wrapper(|env| async move {
@@ -180,6 +344,7 @@ mod test {
 
             Overrides::save(
                 &mut conn,
+                None,
                 "krate",
                 Overrides {
                     memory: Some(defaults.memory / 2),
@@ -188,7 +353,7 @@ mod test {
             )
             .await?;
 
-            let limits = Limits::for_crate(&env.config(), &mut conn, "krate").await?;
+            let limits = Limits::for_crate(&env.config(), &mut conn, None, "krate").await?;
             assert_eq!(limits, defaults);
 
             Ok(())
@@ -1,6 +1,10 @@
+mod failure;
 mod limits;
+mod resource_usage;
 mod rustwide_builder;
 
+pub(crate) use self::failure::BuildFailure;
 pub(crate) use self::limits::Limits;
-pub(crate) use self::rustwide_builder::DocCoverage;
+pub(crate) use self::resource_usage::ResourceUsage;
+pub(crate) use self::rustwide_builder::{DocCoverage, TargetBuildResult};
 pub use self::rustwide_builder::{PackageKind, RustwideBuilder};
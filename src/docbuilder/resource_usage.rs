@@ -0,0 +1,310 @@
+//! Measuring the resources used by the sandboxed build commands.
+//!
+//! rustwide runs every command in a new docker container, so there is no process
+//! we could ask about its resource usage. Instead we sample the cgroups docker creates
+//! for its containers while a build is running. rustwide doesn't tell us the ids of the
+//! containers, so the commands of a build get the [`BUILD_ENV_VAR`] environment variable, and we
+//! only sample the containers that have it set to the value of our build.
+
+use std::time::Duration;
+
+/// Environment variable of the sandboxed commands, telling the resource monitor which build they
+/// belong to.
+pub(crate) const BUILD_ENV_VAR: &str = "DOCSRS_BUILD";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ResourceUsage {
+    /// highest memory usage of any of the containers, in bytes.
+    pub(crate) peak_memory: Option<u64>,
+    /// CPU time used by all containers together.
+    pub(crate) cpu_time: Option<Duration>,
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) use self::linux::ResourceMonitor;
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) struct ResourceMonitor;
+
+#[cfg(not(target_os = "linux"))]
+impl ResourceMonitor {
+    pub(crate) fn start(_build: String) -> Self {
+        Self
+    }
+
+    pub(crate) fn finish(self) -> ResourceUsage {
+        ResourceUsage::default()
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{ResourceUsage, BUILD_ENV_VAR};
+    use std::{
+        collections::HashMap,
+        fs,
+        path::{Path, PathBuf},
+        process::Command,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+        time::Duration,
+    };
+    use tracing::warn;
+
+    const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Where docker puts the cgroups of its containers, with the systemd
+    /// and with the cgroupfs cgroup driver.
+    const CGROUP_ROOTS: &[&str] = &["/sys/fs/cgroup/system.slice", "/sys/fs/cgroup/docker"];
+
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    struct ContainerUsage {
+        peak_memory: u64,
+        cpu_usage_usec: u64,
+    }
+
+    /// Samples the resource usage of the docker containers of a build until it is finished.
+    pub(crate) struct ResourceMonitor {
+        stop: Arc<AtomicBool>,
+        handle: Option<JoinHandle<HashMap<PathBuf, ContainerUsage>>>,
+    }
+
+    impl ResourceMonitor {
+        /// Starts sampling the containers whose [`BUILD_ENV_VAR`] is set to `build`.
+        pub(crate) fn start(build: String) -> Self {
+            let stop = Arc::new(AtomicBool::new(false));
+            let handle = thread::Builder::new()
+                .name("resource monitor".into())
+                .spawn({
+                    let stop = stop.clone();
+                    move || {
+                        let mut containers = HashMap::new();
+                        // whether the containers we have seen belong to the build
+                        let mut of_build = HashMap::new();
+                        let mut is_of_build = |id: &str| {
+                            *of_build
+                                .entry(id.to_owned())
+                                .or_insert_with(|| container_runs_build(id, &build))
+                        };
+                        loop {
+                            // sample once more after being stopped, to get the final numbers
+                            // of containers that are still around.
+                            let stopped = stop.load(Ordering::Relaxed);
+                            for root in CGROUP_ROOTS {
+                                sample(Path::new(root), &mut is_of_build, &mut containers);
+                            }
+                            if stopped {
+                                break containers;
+                            }
+                            thread::park_timeout(SAMPLE_INTERVAL);
+                        }
+                    }
+                })
+                .map_err(|err| warn!("could not start resource monitor: {}", err))
+                .ok();
+
+            Self { stop, handle }
+        }
+
+        pub(crate) fn finish(mut self) -> ResourceUsage {
+            self.stop()
+                .map(|containers| summarize(containers.values()))
+                .unwrap_or_default()
+        }
+
+        fn stop(&mut self) -> Option<HashMap<PathBuf, ContainerUsage>> {
+            let handle = self.handle.take()?;
+            self.stop.store(true, Ordering::Relaxed);
+            handle.thread().unpark();
+            handle.join().ok()
+        }
+    }
+
+    impl Drop for ResourceMonitor {
+        fn drop(&mut self) {
+            self.stop();
+        }
+    }
+
+    /// The id of the container whose cgroup is called `name`, if it is the cgroup of a container.
+    fn container_id(name: &str) -> Option<&str> {
+        // `docker-<id>.scope` with the systemd driver, just the id with cgroupfs.
+        let id = name
+            .strip_prefix("docker-")
+            .and_then(|name| name.strip_suffix(".scope"))
+            .unwrap_or(name);
+        (id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())).then_some(id)
+    }
+
+    /// Whether the container runs a command of `build`, which we can only tell while it exists.
+    fn container_runs_build(id: &str, build: &str) -> bool {
+        let output = match Command::new("docker")
+            .args(["inspect", "--format", "{{join .Config.Env \"\\n\"}}", id])
+            .output()
+        {
+            Ok(output) if output.status.success() => output,
+            Ok(_) => return false,
+            Err(err) => {
+                warn!("could not inspect container {}: {}", id, err);
+                return false;
+            }
+        };
+        let expected = format!("{BUILD_ENV_VAR}={build}");
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line == expected)
+    }
+
+    fn read_u64(path: &Path) -> Option<u64> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    fn read_cpu_usage(path: &Path) -> Option<u64> {
+        fs::read_to_string(path.join("cpu.stat"))
+            .ok()?
+            .lines()
+            .find_map(|line| line.strip_prefix("usage_usec "))?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Updates `containers` with the current usage of the containers of the build under `root`.
+    ///
+    /// Containers are removed once their command finishes, so we have to remember
+    /// the highest values we have seen for each of them.
+    fn sample(
+        root: &Path,
+        is_of_build: &mut impl FnMut(&str) -> bool,
+        containers: &mut HashMap<PathBuf, ContainerUsage>,
+    ) {
+        let Ok(entries) = fs::read_dir(root) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            if !file_name
+                .to_str()
+                .and_then(container_id)
+                .is_some_and(&mut *is_of_build)
+            {
+                continue;
+            }
+            let path = entry.path();
+            // `memory.peak` only exists on newer kernels
+            let memory = read_u64(&path.join("memory.peak"))
+                .or_else(|| read_u64(&path.join("memory.current")));
+            let cpu_usage = read_cpu_usage(&path);
+
+            let usage = containers.entry(path).or_default();
+            usage.peak_memory = usage.peak_memory.max(memory.unwrap_or(0));
+            usage.cpu_usage_usec = usage.cpu_usage_usec.max(cpu_usage.unwrap_or(0));
+        }
+    }
+
+    fn summarize<'a>(containers: impl Iterator<Item = &'a ContainerUsage>) -> ResourceUsage {
+        let mut result = ResourceUsage::default();
+        for usage in containers {
+            if usage.peak_memory > 0 {
+                result.peak_memory = Some(result.peak_memory.unwrap_or(0).max(usage.peak_memory));
+            }
+            if usage.cpu_usage_usec > 0 {
+                result.cpu_time = Some(
+                    result.cpu_time.unwrap_or_default()
+                        + Duration::from_micros(usage.cpu_usage_usec),
+                );
+            }
+        }
+        result
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const CONTAINER_ID: &str =
+            "4c0f2e3b1a5d6e7f8091a2b3c4d5e6f708192a3b4c5d6e7f8091a2b3c4d5e6f7";
+        const OTHER_CONTAINER_ID: &str =
+            "9a8b7c6d5e4f30211f2e3d4c5b6a79880a1b2c3d4e5f60718293a4b5c6d7e8f9";
+
+        fn write_container(root: &Path, name: &str, memory: &str, cpu_stat: &str) -> PathBuf {
+            let path = root.join(name);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("memory.current"), memory).unwrap();
+            fs::write(path.join("cpu.stat"), cpu_stat).unwrap();
+            path
+        }
+
+        #[test]
+        fn container_cgroup_names() {
+            assert_eq!(
+                container_id(&format!("docker-{CONTAINER_ID}.scope")),
+                Some(CONTAINER_ID)
+            );
+            assert_eq!(container_id(CONTAINER_ID), Some(CONTAINER_ID));
+            assert_eq!(container_id("docker.service"), None);
+            assert_eq!(container_id("docker-short.scope"), None);
+        }
+
+        #[test]
+        fn sample_keeps_highest_values() {
+            let root = tempfile::tempdir().unwrap();
+            let mut containers = HashMap::new();
+
+            let container = write_container(
+                root.path(),
+                &format!("docker-{CONTAINER_ID}.scope"),
+                "2048\n",
+                "usage_usec 1500000\nuser_usec 1000000\nsystem_usec 500000\n",
+            );
+            write_container(root.path(), "docker.service", "999999", "usage_usec 999999");
+            // the container of another build
+            write_container(root.path(), OTHER_CONTAINER_ID, "4096", "usage_usec 999999");
+            let mut is_of_build = |id: &str| id == CONTAINER_ID;
+            sample(root.path(), &mut is_of_build, &mut containers);
+
+            fs::write(container.join("memory.current"), "1024\n").unwrap();
+            fs::write(container.join("cpu.stat"), "usage_usec 2500000\n").unwrap();
+            sample(root.path(), &mut is_of_build, &mut containers);
+
+            assert_eq!(containers.len(), 1);
+            assert_eq!(
+                summarize(containers.values()),
+                ResourceUsage {
+                    peak_memory: Some(2048),
+                    cpu_time: Some(Duration::from_millis(2500)),
+                }
+            );
+        }
+
+        #[test]
+        fn summarize_without_containers() {
+            assert_eq!(summarize([].iter()), ResourceUsage::default());
+        }
+
+        #[test]
+        fn summarize_multiple_containers() {
+            let containers = [
+                ContainerUsage {
+                    peak_memory: 100,
+                    cpu_usage_usec: 1_000_000,
+                },
+                ContainerUsage {
+                    peak_memory: 300,
+                    cpu_usage_usec: 2_000_000,
+                },
+            ];
+            assert_eq!(
+                summarize(containers.iter()),
+                ResourceUsage {
+                    peak_memory: Some(300),
+                    cpu_time: Some(Duration::from_secs(3)),
+                }
+            );
+        }
+    }
+}
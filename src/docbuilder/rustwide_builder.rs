@@ -1,19 +1,25 @@
 use crate::db::file::add_path_into_database;
 use crate::db::{
-    add_build_into_database, add_doc_coverage, add_package_into_database,
-    add_path_into_remote_archive, types::BuildStatus, update_crate_data_in_database, Pool,
+    add_build_failure, add_build_into_database, add_doc_coverage, add_package_into_database,
+    add_path_into_remote_archive, add_target_build_result, types::BuildStatus,
+    update_crate_data_in_database, update_crate_items, Pool,
+};
+use crate::docbuilder::{
+    failure::classify_build_failure,
+    resource_usage::{ResourceMonitor, BUILD_ENV_VAR},
+    BuildFailure, Limits, ResourceUsage,
 };
-use crate::docbuilder::Limits;
 use crate::error::Result;
+use crate::metrics::duration_to_seconds;
 use crate::repositories::RepositoryStatsUpdater;
-use crate::storage::{rustdoc_archive_path, source_archive_path};
+use crate::storage::{crate_storage_name, rustdoc_archive_path, source_archive_path};
 use crate::utils::{
-    copy_dir_all, get_config, parse_rustc_version, report_error, set_config, CargoMetadata,
-    ConfigName,
+    copy_dir_all, get_config, parse_rustc_version, report_error, search_index::read_search_index,
+    set_config, BuildWorker, CargoMetadata, ConfigName,
 };
-use crate::RUSTDOC_STATIC_STORAGE_PREFIX;
 use crate::{db::blacklist::is_blacklisted, utils::MetadataPackage};
 use crate::{AsyncStorage, Config, Context, InstanceMetrics, RegistryApi, Storage};
+use crate::{RUSTDOC_JSON_PATH, RUSTDOC_STATIC_STORAGE_PREFIX};
 use anyhow::{anyhow, bail, Context as _, Error};
 use docsrs_metadata::{BuildTargets, Metadata, DEFAULT_TARGETS, HOST_TARGET};
 use failure::Error as FailureError;
@@ -25,9 +31,9 @@ use rustwide::toolchain::ToolchainError;
 use rustwide::{AlternativeRegistry, Build, Crate, Toolchain, Workspace, WorkspaceBuilder};
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
 use tracing::{debug, info, info_span, instrument, warn};
 
@@ -51,11 +57,11 @@ fn get_configured_toolchain(conn: &mut Client) -> Result<Toolchain> {
     }
 }
 
-fn build_workspace(context: &dyn Context) -> Result<Workspace> {
+fn build_workspace(context: &dyn Context, path: &Path) -> Result<Workspace> {
     let config = context.config()?;
 
-    let mut builder = WorkspaceBuilder::new(&config.rustwide_workspace, USER_AGENT)
-        .running_inside_docker(config.inside_docker);
+    let mut builder =
+        WorkspaceBuilder::new(path, USER_AGENT).running_inside_docker(config.inside_docker);
     if let Some(custom_image) = &config.docker_image {
         let image = match SandboxImage::local(custom_image) {
             Ok(i) => i,
@@ -84,6 +90,8 @@ pub enum PackageKind<'a> {
 
 pub struct RustwideBuilder {
     workspace: Workspace,
+    workspace_path: PathBuf,
+    temp_dir: PathBuf,
     toolchain: Toolchain,
     runtime: Arc<Runtime>,
     config: Arc<Config>,
@@ -92,18 +100,41 @@ pub struct RustwideBuilder {
     async_storage: Arc<AsyncStorage>,
     metrics: Arc<InstanceMetrics>,
     registry_api: Arc<RegistryApi>,
+    /// APIs of the configured alternative registries, by registry name.
+    registry_apis: HashMap<String, Arc<RegistryApi>>,
     repository_stats_updater: Arc<RepositoryStatsUpdater>,
     workspace_initialize_time: Instant,
 }
 
 impl RustwideBuilder {
     pub fn init(context: &dyn Context) -> Result<Self> {
+        let config = context.config()?;
+        Self::init_in(context, &config.rustwide_workspace, &config.temp_dir)
+    }
+
+    /// Creates the builder of one of the build workers, using its own workspace.
+    pub(crate) fn init_for_worker(context: &dyn Context, worker: &BuildWorker) -> Result<Self> {
+        Self::init_in(context, &worker.rustwide_workspace, &worker.temp_dir)
+    }
+
+    fn init_in(context: &dyn Context, workspace_path: &Path, temp_dir: &Path) -> Result<Self> {
         let config = context.config()?;
         let pool = context.pool()?;
         let runtime = context.runtime()?;
+        let registry_apis = config
+            .registries
+            .iter()
+            .map(|registry| {
+                let api =
+                    RegistryApi::new(registry.api_host.clone(), config.crates_io_api_call_retries)?;
+                Ok((registry.name.clone(), Arc::new(api)))
+            })
+            .collect::<Result<_>>()?;
 
         Ok(RustwideBuilder {
-            workspace: build_workspace(context)?,
+            workspace: build_workspace(context, workspace_path)?,
+            workspace_path: workspace_path.to_owned(),
+            temp_dir: temp_dir.to_owned(),
             toolchain: get_configured_toolchain(&mut *pool.get()?)?,
             config,
             db: pool,
@@ -112,6 +143,7 @@ impl RustwideBuilder {
             async_storage: runtime.block_on(context.async_storage())?,
             metrics: context.instance_metrics()?,
             registry_api: context.registry_api()?,
+            registry_apis,
             repository_stats_updater: context.repository_stats_updater()?,
             workspace_initialize_time: Instant::now(),
         })
@@ -124,7 +156,7 @@ impl RustwideBuilder {
         let interval = context.config()?.build_workspace_reinitialization_interval;
         if self.workspace_initialize_time.elapsed() >= interval {
             info!("start reinitialize workspace again");
-            self.workspace = build_workspace(context)?;
+            self.workspace = build_workspace(context, &self.workspace_path)?;
             self.workspace_initialize_time = Instant::now();
         }
 
@@ -260,13 +292,13 @@ impl RustwideBuilder {
     }
 
     #[instrument(skip(self))]
-    fn get_limits(&self, krate: &str) -> Result<Limits> {
+    fn get_limits(&self, registry: Option<&str>, krate: &str, version: &str) -> Result<Limits> {
         self.runtime.block_on({
             let db = self.db.clone();
             let config = self.config.clone();
             async move {
                 let mut conn = db.get_async().await?;
-                Limits::for_crate(&config, &mut conn, krate).await
+                Limits::for_release(&config, &mut conn, registry, krate, version).await
             }
         })
     }
@@ -278,7 +310,7 @@ impl RustwideBuilder {
         info!("building a dummy crate to get essential files");
 
         let mut conn = self.db.get()?;
-        let limits = self.get_limits(DUMMY_CRATE_NAME)?;
+        let limits = self.get_limits(None, DUMMY_CRATE_NAME, DUMMY_CRATE_VERSION)?;
 
         // FIXME: for now, purge all build dirs before each build.
         // Currently we have some error situations where the build directory wouldn't be deleted
@@ -288,7 +320,7 @@ impl RustwideBuilder {
         // but for now we chose this simple way to prevent that the build directory remains can
         // fill up disk space.
         // This also prevents having multiple builders using the same rustwide workspace,
-        // which we don't do. Currently our separate builders use a separate rustwide workspace.
+        // which we don't do. Separate builders and build workers use separate rustwide workspaces.
         self.workspace
             .purge_all_build_dirs()
             .map_err(FailureError::compat)?;
@@ -377,7 +409,23 @@ impl RustwideBuilder {
             return Ok(false);
         }
 
-        let limits = self.get_limits(name)?;
+        // crates of the configured alternative registries are kept apart from crates.io crates,
+        // other registries are treated like crates.io.
+        let registry = match kind {
+            PackageKind::Registry(index_url) => self.config.registry_for_index(index_url).cloned(),
+            _ => None,
+        };
+        let registry_name = registry.as_ref().map(|registry| registry.name.as_str());
+        let registry_api = match registry_name {
+            Some(registry_name) => self
+                .registry_apis
+                .get(registry_name)
+                .with_context(|| format!("no API client for registry {registry_name}"))?
+                .clone(),
+            None => self.registry_api.clone(),
+        };
+
+        let limits = self.get_limits(registry_name, name, version)?;
         #[cfg(target_os = "linux")]
         if !self.config.disable_memory_limit {
             use anyhow::Context;
@@ -406,7 +454,7 @@ impl RustwideBuilder {
         // but for now we chose this simple way to prevent that the build directory remains can
         // fill up disk space.
         // This also prevents having multiple builders using the same rustwide workspace,
-        // which we don't do. Currently our separate builders use a separate rustwide workspace.
+        // which we don't do. Separate builders and build workers use separate rustwide workspaces.
         info_span!("purge_all_build_dirs").in_scope(|| {
             self.workspace
                 .purge_all_build_dirs()
@@ -416,6 +464,7 @@ impl RustwideBuilder {
         let mut build_dir = self.workspace.build_dir(&format!("{name}-{version}"));
 
         let is_local = matches!(kind, PackageKind::Local(_));
+        let storage_name = crate_storage_name(registry_name, name);
         let krate = {
             let _span = info_span!("krate.fetch").entered();
 
@@ -430,8 +479,8 @@ impl RustwideBuilder {
             krate
         };
 
-        fs::create_dir_all(&self.config.temp_dir)?;
-        let local_storage = tempfile::tempdir_in(&self.config.temp_dir)?;
+        fs::create_dir_all(&self.temp_dir)?;
+        let local_storage = tempfile::tempdir_in(&self.temp_dir)?;
 
         let successful = build_dir
             .build(&self.toolchain, &krate, self.prepare_sandbox(&limits))
@@ -498,8 +547,11 @@ impl RustwideBuilder {
                         }
                     }
 
+                    let mut target_results = vec![TargetBuildResult::from(&res)];
+
                     let mut algs = HashSet::new();
                     let mut target_build_logs = HashMap::new();
+                    let mut items = None;
                     if has_docs {
                         debug!("adding documentation for the default target to the database");
                         self.copy_docs(
@@ -509,6 +561,11 @@ impl RustwideBuilder {
                             true,
                         )?;
 
+                        if let Some(rustdoc_json) = &res.rustdoc_json {
+                            debug!("adding rustdoc JSON output for the default target");
+                            fs::copy(rustdoc_json, local_storage.path().join(RUSTDOC_JSON_PATH))?;
+                        }
+
                         successful_targets.push(res.target.clone());
 
                         // Then build the documentation for all the targets
@@ -523,27 +580,50 @@ impl RustwideBuilder {
                                 &mut successful_targets,
                                 &metadata,
                             )?;
+                            target_results.push(TargetBuildResult::from(&target_res));
                             target_build_logs.insert(target, target_res.build_log);
                         }
-                        let (_, new_alg) = self.runtime.block_on(add_path_into_remote_archive(
-                            &self.async_storage,
-                            &rustdoc_archive_path(name, version),
-                            local_storage.path(),
-                            true,
-                        ))?;
+                        let (_, new_alg) = self.runtime.block_on(async {
+                            let mut conn = self.db.get_async().await?;
+                            add_path_into_remote_archive(
+                                &mut conn,
+                                &self.async_storage,
+                                &rustdoc_archive_path(&storage_name, version),
+                                local_storage.path(),
+                                true,
+                            )
+                            .await
+                        })?;
                         algs.insert(new_alg);
+
+                        // the items of the crate for searches across all crates. The build
+                        // doesn't depend on them, so problems reading them are only reported.
+                        if let Some(name) = res.cargo_metadata.root().library_name() {
+                            match read_search_index(local_storage.path(), &name) {
+                                Ok(crate_items) => items = crate_items,
+                                Err(err) => {
+                                    report_error(&err.context(format!(
+                                        "could not read the search index of {name}"
+                                    )))
+                                }
+                            }
+                        }
                     };
 
                     // Store the sources even if the build fails
                     debug!("adding sources into database");
                     let files_list = {
-                        let (files_list, new_alg) =
-                            self.runtime.block_on(add_path_into_remote_archive(
+                        let (files_list, new_alg) = self.runtime.block_on(async {
+                            let mut conn = self.db.get_async().await?;
+                            add_path_into_remote_archive(
+                                &mut conn,
                                 &self.async_storage,
-                                &source_archive_path(name, version),
+                                &source_archive_path(&storage_name, version),
                                 build.host_source_dir(),
                                 false,
-                            ))?;
+                            )
+                            .await
+                        })?;
                         algs.insert(new_alg);
                         files_list
                     };
@@ -560,7 +640,7 @@ impl RustwideBuilder {
                     let release_data = if !is_local {
                         match self
                             .runtime
-                            .block_on(self.registry_api.get_release_data(name, version))
+                            .block_on(registry_api.get_release_data(name, version))
                             .with_context(|| {
                                 format!("could not fetch releases-data for {name}-{version}")
                             }) {
@@ -582,6 +662,7 @@ impl RustwideBuilder {
 
                     let release_id = self.runtime.block_on(add_package_into_database(
                         &mut async_conn,
+                        registry_name,
                         cargo_metadata,
                         &build.host_source_dir(),
                         &res.target,
@@ -616,6 +697,24 @@ impl RustwideBuilder {
                         build_status,
                     ))?;
 
+                    if let Some(failure) = res.failure {
+                        self.runtime.block_on(add_build_failure(
+                            &mut async_conn,
+                            build_id,
+                            failure,
+                        ))?;
+                    }
+
+                    // only now the release can be the latest one of the crate, as releases
+                    // without a finished build are skipped
+                    if let Some(items) = &items {
+                        self.runtime.block_on(update_crate_items(
+                            &mut async_conn,
+                            release_id,
+                            items,
+                        ))?;
+                    }
+
                     {
                         let _span = info_span!("store_build_logs").entered();
                         let build_log_path = format!("build-logs/{build_id}/{default_target}.txt");
@@ -626,15 +725,28 @@ impl RustwideBuilder {
                         }
                     }
 
+                    for target_result in &target_results {
+                        let log_path =
+                            format!("build-logs/{build_id}/{}.txt", target_result.target);
+                        self.runtime.block_on(add_target_build_result(
+                            &mut async_conn,
+                            build_id,
+                            target_result,
+                            &log_path,
+                        ))?;
+                    }
+
                     // Some crates.io crate data is mutable, so we proactively update it during a release
                     if !is_local {
-                        match self
-                            .runtime
-                            .block_on(self.registry_api.get_crate_data(name))
-                        {
-                            Ok(crate_data) => self.runtime.block_on(
-                                update_crate_data_in_database(&mut async_conn, name, &crate_data),
-                            )?,
+                        match self.runtime.block_on(registry_api.get_crate_data(name)) {
+                            Ok(crate_data) => {
+                                self.runtime.block_on(update_crate_data_in_database(
+                                    &mut async_conn,
+                                    registry_name,
+                                    name,
+                                    &crate_data,
+                                ))?
+                            }
                             Err(err) => warn!("{:#?}", err),
                         }
                     }
@@ -644,7 +756,7 @@ impl RustwideBuilder {
                         // we're doing this in the end so eventual problems in the build
                         // won't lead to non-existing docs.
                         for prefix in &["rustdoc", "sources"] {
-                            let prefix = format!("{prefix}/{name}/{version}/");
+                            let prefix = format!("{prefix}/{storage_name}/{version}/");
                             debug!("cleaning old storage folder {}", prefix);
                             self.storage.delete_prefix(&prefix)?;
                         }
@@ -752,6 +864,48 @@ impl RustwideBuilder {
         )
     }
 
+    /// Runs rustdoc with `--output-format json` and moves the generated file
+    /// out of the doc-directory, so the following HTML build doesn't remove it.
+    ///
+    /// Returns the path of the JSON file, or `None` when the crate has no library.
+    #[instrument(skip(self, build, cargo_metadata))]
+    fn get_rustdoc_json(
+        &self,
+        target: &str,
+        build: &Build,
+        metadata: &Metadata,
+        limits: &Limits,
+        cargo_metadata: &CargoMetadata,
+    ) -> Result<Option<PathBuf>> {
+        let Some(library_name) = cargo_metadata.root().library_name() else {
+            return Ok(None);
+        };
+
+        let rustdoc_flags = vec!["--output-format".to_string(), "json".to_string()];
+
+        self.prepare_command(build, target, metadata, limits, rustdoc_flags)?
+            .log_output(false)
+            .run()?;
+
+        // proc-macros are built without `--target`, see `prepare_command`.
+        let target_dir = build.host_target_dir();
+        let doc_dir = if metadata.proc_macro {
+            target_dir.join("doc")
+        } else {
+            target_dir.join(target).join("doc")
+        };
+
+        let source = doc_dir.join(format!("{library_name}.json"));
+        if !source.is_file() {
+            return Ok(None);
+        }
+
+        let dest = target_dir.join(format!("{target}-{RUSTDOC_JSON_PATH}"));
+        debug!("move {} to {}", source.display(), dest.display());
+        fs::rename(source, &dest)?;
+        Ok(Some(dest))
+    }
+
     #[instrument(skip(self, build))]
     fn execute_build(
         &self,
@@ -762,6 +916,8 @@ impl RustwideBuilder {
         metadata: &Metadata,
         create_essential_files: bool,
     ) -> Result<FullBuildResult> {
+        let start = Instant::now();
+        let resource_monitor = ResourceMonitor::start(resource_monitor_build(build));
         let cargo_metadata = CargoMetadata::load_from_rustwide(
             &self.workspace,
             &self.toolchain,
@@ -794,14 +950,29 @@ impl RustwideBuilder {
             }
         };
 
-        let successful = {
+        // the JSON output is only stored for the default target, and like the
+        // coverage it has to be generated before the doc-build.
+        let rustdoc_json = if is_default_target && self.config.build_rustdoc_json {
+            match self.get_rustdoc_json(target, build, metadata, limits, &cargo_metadata) {
+                Ok(path) => path,
+                Err(err) => {
+                    info!("error when trying to generate rustdoc JSON: {}", err);
+                    info!("continuing anyways.");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let build_result = {
             let _span = info_span!("cargo_build", target = %target, is_default_target).entered();
             logging::capture(&storage, || {
                 self.prepare_command(build, target, metadata, limits, rustdoc_flags)
                     .and_then(|command| command.run().map_err(Error::from))
-                    .is_ok()
             })
         };
+        let successful = build_result.is_ok();
 
         // For proc-macros, cargo will put the output in `target/doc`.
         // Move it to the target-specific directory for consistency with other builds.
@@ -820,6 +991,27 @@ impl RustwideBuilder {
             std::fs::rename(old_dir, new_dir)?;
         }
 
+        let duration = start.elapsed();
+        let resource_usage = resource_monitor.finish();
+        self.metrics
+            .build_target_time
+            .observe(duration_to_seconds(duration));
+        if let Some(cpu_time) = resource_usage.cpu_time {
+            self.metrics
+                .build_target_cpu_time
+                .observe(duration_to_seconds(cpu_time));
+        }
+        if let Some(peak_memory) = resource_usage.peak_memory {
+            self.metrics
+                .build_target_peak_memory
+                .observe(peak_memory as f64);
+        }
+
+        let build_log = storage.to_string();
+        let failure = build_result
+            .err()
+            .map(|err| classify_build_failure(&cargo_metadata.root().name, Some(&err), &build_log));
+
         Ok(FullBuildResult {
             result: BuildResult {
                 rustc_version: self.rustc_version()?,
@@ -827,9 +1019,13 @@ impl RustwideBuilder {
                 successful,
             },
             doc_coverage,
+            rustdoc_json,
+            failure,
             cargo_metadata,
-            build_log: storage.to_string(),
+            build_log,
             target: target.to_string(),
+            duration,
+            resource_usage,
         })
     }
 
@@ -861,6 +1057,19 @@ impl RustwideBuilder {
             // docs.rs, but once it's stable we can remove this flag.
             "-Zrustdoc-scrape-examples".into(),
         ];
+        // Link dependencies from the configured alternative registries to their docs here.
+        // Cargo only knows registries by name, so they have to be declared as well.
+        for registry in &self.config.registries {
+            cargo_args.push(format!(
+                r#"--config=registries.{}.index="{}""#,
+                registry.name, registry.index_url
+            ));
+            cargo_args.push(format!(
+                r#"--config=doc.extern-map.registries.{}="{}/{{pkg_name}}/{{version}}/{target}""#,
+                registry.name,
+                registry.url_prefix(),
+            ));
+        }
         if let Some(cpu_limit) = self.config.build_cpu_limit {
             cargo_args.push(format!("-j{cpu_limit}"));
         }
@@ -908,6 +1117,7 @@ impl RustwideBuilder {
         for (key, val) in metadata.environment_variables() {
             command = command.env(key, val);
         }
+        command = command.env(BUILD_ENV_VAR, resource_monitor_build(build));
 
         Ok(command.args(&cargo_args))
     }
@@ -943,12 +1153,45 @@ impl RustwideBuilder {
     }
 }
 
+/// Tells the resource monitor which containers are running the commands of `build`. The build
+/// directories of concurrent builds differ, so we use the path of the target directory.
+fn resource_monitor_build(build: &Build) -> String {
+    build.host_target_dir().to_string_lossy().into_owned()
+}
+
 struct FullBuildResult {
     result: BuildResult,
     target: String,
     cargo_metadata: CargoMetadata,
     doc_coverage: Option<DocCoverage>,
+    /// location of the rustdoc JSON output, only generated for the default target.
+    rustdoc_json: Option<PathBuf>,
+    /// why the build failed, `None` for successful builds.
+    failure: Option<BuildFailure>,
     build_log: String,
+    /// how long building the documentation for this target took.
+    duration: Duration,
+    resource_usage: ResourceUsage,
+}
+
+/// The outcome of building the documentation for a single target.
+#[derive(Debug, Clone)]
+pub(crate) struct TargetBuildResult {
+    pub(crate) target: String,
+    pub(crate) successful: bool,
+    pub(crate) duration: Duration,
+    pub(crate) resource_usage: ResourceUsage,
+}
+
+impl From<&FullBuildResult> for TargetBuildResult {
+    fn from(res: &FullBuildResult) -> Self {
+        Self {
+            target: res.target.clone(),
+            successful: res.result.successful,
+            duration: res.duration,
+            resource_usage: res.resource_usage,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -1012,6 +1255,7 @@ mod tests {
     #[ignore]
     fn test_build_crate() {
         wrapper(|env| {
+            env.override_config(|config| config.build_rustdoc_json = true);
             let crate_ = DUMMY_CRATE_NAME;
             let crate_path = crate_.replace('-', "_");
             let version = DUMMY_CRATE_VERSION;
@@ -1064,6 +1308,27 @@ mod tests {
                 .collect();
             targets.sort();
 
+            // every built target has a result, with the location of its build log
+            let target_results: Vec<(String, bool, String)> = conn
+                .query(
+                    "SELECT target, successful, log_path
+                     FROM build_target_results
+                     WHERE build_id = $1
+                     ORDER BY target",
+                    &[&row.get::<_, i32>("build_id")],
+                )?
+                .into_iter()
+                .map(|row| (row.get(0), row.get(1), row.get(2)))
+                .collect();
+            assert_eq!(
+                target_results.iter().map(|r| &r.0).collect::<Vec<_>>(),
+                targets.iter().collect::<Vec<_>>(),
+            );
+            for (_, successful, log_path) in &target_results {
+                assert!(successful);
+                assert!(storage.exists(log_path)?);
+            }
+
             let web = env.frontend();
 
             // old rustdoc & source files are gone
@@ -1086,6 +1351,10 @@ mod tests {
             )?);
             assert_success(&format!("/{crate_}/{version}/{crate_path}"), web)?;
 
+            // rustdoc JSON for the default target is stored & served
+            assert!(storage.exists_in_archive(&doc_archive, 0, RUSTDOC_JSON_PATH)?);
+            assert_success(&format!("/crate/{crate_}/{version}/json"), web)?;
+
             // source is also packaged
             assert!(storage.exists_in_archive(&source_archive, 0, "src/lib.rs",)?);
             assert_success(&format!("/crate/{crate_}/{version}/source/src/lib.rs"), web)?;
@@ -1229,6 +1498,27 @@ mod tests {
         });
     }
 
+    #[test]
+    #[ignore]
+    fn test_items_of_new_crate_are_searchable() {
+        wrapper(|env| {
+            let crate_ = "lazy_static";
+            let version = "1.4.0";
+            let mut builder = RustwideBuilder::init(env).unwrap();
+            builder.update_toolchain()?;
+            assert!(builder.build_package(crate_, version, PackageKind::CratesIo)?);
+
+            let web = env.frontend();
+            let response = web.get("/releases/items?q=LazyStatic").send()?;
+            assert!(response.status().is_success());
+            let page = response.text()?;
+            assert!(page.contains("lazy_static::LazyStatic"));
+            assert!(page.contains("/lazy_static/1.4.0/lazy_static/trait.LazyStatic.html"));
+
+            Ok(())
+        });
+    }
+
     #[test]
     #[ignore]
     fn test_cross_compile_non_host_default() {
@@ -0,0 +1,199 @@
+use std::fs::{self, File, OpenOptions};
+use std::sync::atomic::AtomicBool;
+use std::{path::PathBuf, process::Command};
+
+use anyhow::{bail, Context};
+use crates_index_diff::gix;
+use fs2::FileExt;
+use url::Url;
+
+use crate::error::Result;
+use crate::utils::report_error;
+
+pub(crate) use self::sparse::SparseIndex;
+
+pub(crate) mod sparse;
+
+/// Name of the lock file guarding the updates of the build queue from the index.
+const UPDATE_LOCK_FILE: &str = ".docsrs-update.lock";
+
+/// Name of the file marking that the build queue should be updated from the index again,
+/// see [`Index::request_update`].
+const UPDATE_REQUEST_FILE: &str = ".docsrs-update.requested";
+
+pub struct Index {
+    path: PathBuf,
+    repository_url: Option<String>,
+    sparse: Option<SparseIndex>,
+}
+
+/// Guard for [`Index::lock`], releases the lock when dropped.
+pub(crate) struct IndexLock {
+    _file: File,
+}
+
+impl Index {
+    pub fn from_url(path: PathBuf, url: String) -> Result<Self> {
+        crates_index_diff::Index::from_path_or_cloned_with_options(
+            &path,
+            gix::progress::Discard,
+            &AtomicBool::default(),
+            crates_index_diff::index::CloneOptions { url: url.clone() },
+        )
+        .map(|_| ())
+        .context("initialising registry index repository")?;
+
+        Ok(Self {
+            path,
+            repository_url: Some(url),
+            sparse: None,
+        })
+    }
+
+    /// Whether `url` points to an index using the sparse protocol, like `sparse+https://…`.
+    pub fn is_sparse_url(url: &str) -> bool {
+        url.starts_with(sparse::SPARSE_PREFIX)
+    }
+
+    /// Opens an index using the sparse protocol.
+    ///
+    /// There is no local copy of sparse indexes, `path` is only used for the lock
+    /// used while updating the build queue. Without a changelog, we only learn about
+    /// new releases of the crates we already know, see [`sparse`].
+    pub fn from_sparse_url(path: PathBuf, url: String, changelog_url: Option<Url>) -> Result<Self> {
+        fs::create_dir_all(&path)
+            .with_context(|| format!("failed to create index directory {}", path.display()))?;
+
+        Ok(Self {
+            sparse: Some(SparseIndex::new(&url, changelog_url)?),
+            path,
+            repository_url: Some(url),
+        })
+    }
+
+    pub fn new(path: PathBuf) -> Result<Self> {
+        // This initializes the repository, then closes it afterwards to avoid leaking file descriptors.
+        // See https://github.com/rust-lang/docs.rs/pull/847
+        crates_index_diff::Index::from_path_or_cloned(&path)
+            .map(|_| ())
+            .context("initialising registry index repository")?;
+        Ok(Self {
+            path,
+            repository_url: None,
+            sparse: None,
+        })
+    }
+
+    /// The sparse index, if this isn't a git index.
+    pub(crate) fn sparse(&self) -> Option<&SparseIndex> {
+        self.sparse.as_ref()
+    }
+
+    pub fn diff(&self) -> Result<crates_index_diff::Index> {
+        if self.sparse.is_some() {
+            bail!("sparse indexes can't be diffed");
+        }
+        let options = self
+            .repository_url
+            .clone()
+            .map(|url| crates_index_diff::index::CloneOptions { url })
+            .unwrap_or_default();
+        let diff = crates_index_diff::Index::from_path_or_cloned_with_options(
+            &self.path,
+            gix::progress::Discard,
+            &AtomicBool::default(),
+            options,
+        )
+        .context("re-opening registry index for diff")?;
+        Ok(diff)
+    }
+
+    /// Locks updating the build queue from this index, unless someone else on this machine
+    /// is already updating it, in which case `None` is returned.
+    ///
+    /// This is a file lock, so it also works between processes, and it's
+    /// released by the OS when a process dies while holding it.
+    pub(crate) fn try_lock(&self) -> Result<Option<IndexLock>> {
+        let path = self.path.join(UPDATE_LOCK_FILE);
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("failed to open index lock file {}", path.display()))?;
+        match file.try_lock_exclusive() {
+            Ok(()) => Ok(Some(IndexLock { _file: file })),
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => Ok(None),
+            Err(err) => Err(anyhow::Error::from(err)
+                .context(format!("failed to lock index lock file {}", path.display()))),
+        }
+    }
+
+    /// Marks that the build queue should be updated from this index.
+    ///
+    /// Like the lock, this is a file so requests from all processes on this machine are
+    /// merged into a single pending update.
+    pub(crate) fn request_update(&self) -> Result<()> {
+        let path = self.path.join(UPDATE_REQUEST_FILE);
+        File::create(&path)
+            .with_context(|| format!("failed to create update request file {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Whether an update of the build queue was requested and not taken yet.
+    pub(crate) fn update_requested(&self) -> bool {
+        self.path.join(UPDATE_REQUEST_FILE).exists()
+    }
+
+    /// Takes the pending update request, returns `false` if there is none.
+    ///
+    /// Should only be called while holding the [`Index::try_lock`] lock.
+    pub(crate) fn take_update_request(&self) -> Result<bool> {
+        let path = self.path.join(UPDATE_REQUEST_FILE);
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(anyhow::Error::from(err).context(format!(
+                "failed to remove update request file {}",
+                path.display()
+            ))),
+        }
+    }
+
+    #[cfg(feature = "consistency_check")]
+    pub(crate) fn crates(&self) -> Result<crates_index::GitIndex> {
+        if self.sparse.is_some() {
+            bail!("the consistency check only supports git indexes");
+        }
+        tracing::debug!("Opening with `crates_index`");
+        // crates_index requires the repo url to match the existing origin or it tries to reinitialize the repo
+        let repo_url = self
+            .repository_url
+            .as_deref()
+            .unwrap_or("https://github.com/rust-lang/crates.io-index");
+        let mut index = crates_index::GitIndex::with_path(&self.path, repo_url)?;
+        index.update()?;
+        Ok(index)
+    }
+
+    pub fn run_git_gc(&self) {
+        if self.sparse.is_some() {
+            return;
+        }
+
+        let gc = Command::new("git")
+            .arg("-C")
+            .arg(&self.path)
+            .args(["gc", "--auto"])
+            .output()
+            .with_context(|| format!("failed to run `git gc --auto`\npath: {:#?}", &self.path));
+
+        if let Err(err) = gc {
+            report_error(&err);
+        }
+    }
+
+    pub fn repository_url(&self) -> Option<&str> {
+        self.repository_url.as_deref()
+    }
+}
@@ -0,0 +1,304 @@
+//! Reading registry indexes over the [sparse protocol][sparse].
+//!
+//! Sparse indexes don't have a history we could diff like the git index, and they can't
+//! list their crates. Registries can offer a changelog instead: a text file listing the
+//! name of each changed crate on a new line, appended on every publish or yank. The files
+//! of the crates listed since the last poll are downloaded, using their `ETag` to skip the
+//! ones that didn't change since we last processed them.
+//!
+//! Without a changelog, the files of all the crates we already know are polled, so new
+//! crates have to be added to the build queue by hand once.
+//!
+//! [sparse]: https://doc.rust-lang.org/cargo/reference/registry-index.html#sparse-protocol
+
+use crate::error::Result;
+use anyhow::Context as _;
+use reqwest::{
+    header::{HeaderValue, ETAG, IF_NONE_MATCH, USER_AGENT},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use url::Url;
+
+/// Prefix cargo uses to mark sparse registry URLs.
+pub(crate) const SPARSE_PREFIX: &str = "sparse+";
+
+const APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), " sparse index reader");
+
+/// What we remember between polls of a sparse index.
+///
+/// The `ETag`s of the crate files are kept in their own table.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SparseIndexState {
+    /// Number of changelog lines that were already processed.
+    #[serde(default)]
+    pub(crate) changelog_lines: usize,
+    /// Hash of the processed changelog lines, which tells us when the changelog was replaced.
+    #[serde(default)]
+    pub(crate) changelog_hash: Option<String>,
+    /// Crates whose changes couldn't be processed, they are tried again on the next poll.
+    #[serde(default)]
+    pub(crate) failed: BTreeSet<String>,
+}
+
+impl SparseIndexState {
+    /// The lines of `changelog` that weren't processed yet.
+    ///
+    /// A changelog that doesn't start with the lines we processed was started anew, all of
+    /// its lines are new then.
+    pub(crate) fn new_changelog_lines<'a>(&self, changelog: &'a [String]) -> &'a [String] {
+        let Some(processed) = changelog.get(..self.changelog_lines) else {
+            return changelog;
+        };
+        match &self.changelog_hash {
+            Some(hash) if *hash != changelog_hash(processed) => changelog,
+            _ => &changelog[processed.len()..],
+        }
+    }
+
+    /// Remembers that all lines of `changelog` were processed.
+    pub(crate) fn set_processed_changelog(&mut self, changelog: &[String]) {
+        self.changelog_lines = changelog.len();
+        self.changelog_hash = Some(changelog_hash(changelog));
+    }
+}
+
+fn changelog_hash(lines: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for line in lines {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// One line of a crate file in the index.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct IndexVersion {
+    pub(crate) name: String,
+    pub(crate) vers: String,
+    #[serde(default)]
+    pub(crate) yanked: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum CrateFile {
+    /// The file didn't change since the given `ETag`.
+    Unchanged,
+    /// The crate was removed from the index.
+    NotFound,
+    Changed {
+        versions: Vec<IndexVersion>,
+        etag: Option<String>,
+    },
+}
+
+#[derive(Debug)]
+pub(crate) struct SparseIndex {
+    url: Url,
+    changelog_url: Option<Url>,
+    client: reqwest::Client,
+}
+
+impl SparseIndex {
+    /// `url` is the index URL, with or without the `sparse+` prefix.
+    pub(crate) fn new(url: &str, changelog_url: Option<Url>) -> Result<Self> {
+        let url = url.strip_prefix(SPARSE_PREFIX).unwrap_or(url);
+        let mut url = Url::parse(url).with_context(|| format!("invalid index URL {url}"))?;
+        // crate files are resolved relative to the index root
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+
+        let client = reqwest::Client::builder()
+            .default_headers(
+                [(USER_AGENT, HeaderValue::from_static(APP_USER_AGENT))]
+                    .into_iter()
+                    .collect(),
+            )
+            .build()?;
+
+        Ok(Self {
+            url,
+            changelog_url,
+            client,
+        })
+    }
+
+    /// Fetches all crate names in the changelog, in order, or `None` if the registry
+    /// doesn't have a changelog.
+    pub(crate) async fn changelog(&self) -> Result<Option<Vec<String>>> {
+        let Some(changelog_url) = &self.changelog_url else {
+            return Ok(None);
+        };
+        Ok(Some(
+            self.client
+                .get(changelog_url.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned)
+                .collect(),
+        ))
+    }
+
+    /// Fetches the index file of a crate, unless it still has the given `ETag`.
+    pub(crate) async fn fetch_crate(&self, name: &str, etag: Option<&str>) -> Result<CrateFile> {
+        let url = self.url.join(&crate_path(name))?;
+        let mut request = self.client.get(url);
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let response = request.send().await?;
+
+        match response.status() {
+            StatusCode::NOT_MODIFIED => return Ok(CrateFile::Unchanged),
+            // some registries answer with 403 for crates they don't know
+            StatusCode::NOT_FOUND | StatusCode::GONE | StatusCode::FORBIDDEN => {
+                return Ok(CrateFile::NotFound)
+            }
+            _ => {}
+        }
+
+        let response = response.error_for_status()?;
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_owned);
+
+        let versions = response
+            .text()
+            .await?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("invalid line in index file of {name}: {line}"))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(CrateFile::Changed { versions, etag })
+    }
+}
+
+/// Path of the index file of a crate, relative to the index root.
+fn crate_path(name: &str) -> String {
+    let name = name.to_lowercase();
+    match name.len() {
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test_case("a", "1/a")]
+    #[test_case("ab", "2/ab")]
+    #[test_case("abc", "3/a/abc")]
+    #[test_case("Serde", "se/rd/serde")]
+    #[test_case("cargo-deny", "ca/rg/cargo-deny")]
+    fn crate_paths(name: &str, path: &str) {
+        assert_eq!(crate_path(name), path);
+    }
+
+    #[test]
+    fn new_changelog_lines() {
+        let lines = |names: &[&str]| {
+            names
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect::<Vec<_>>()
+        };
+        let mut state = SparseIndexState::default();
+
+        let changelog = lines(&["foo", "bar"]);
+        assert_eq!(state.new_changelog_lines(&changelog), changelog);
+        state.set_processed_changelog(&changelog);
+
+        let changelog = lines(&["foo", "bar", "baz"]);
+        assert_eq!(state.new_changelog_lines(&changelog), lines(&["baz"]));
+        state.set_processed_changelog(&changelog);
+
+        // started anew, and already longer than the old changelog
+        let changelog = lines(&["qux", "foo", "bar", "baz"]);
+        assert_eq!(state.new_changelog_lines(&changelog), changelog);
+
+        // started anew, and shorter than the old changelog
+        let changelog = lines(&["qux"]);
+        assert_eq!(state.new_changelog_lines(&changelog), changelog);
+    }
+
+    #[test]
+    fn index_url() -> Result<()> {
+        let index = SparseIndex::new("sparse+http://localhost:1234/index", None)?;
+        assert_eq!(index.url.as_str(), "http://localhost:1234/index/");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_crate_files() -> Result<()> {
+        let mut server = mockito::Server::new_async().await;
+        let _changed = server
+            .mock("GET", "/3/f/foo")
+            .match_header("if-none-match", mockito::Matcher::Missing)
+            .with_header("etag", "\"v1\"")
+            .with_body(
+                "{\"name\":\"foo\",\"vers\":\"0.1.0\",\"deps\":[],\"yanked\":false}\n\
+                 {\"name\":\"foo\",\"vers\":\"0.2.0\",\"deps\":[],\"yanked\":true}\n",
+            )
+            .create_async()
+            .await;
+        let _unchanged = server
+            .mock("GET", "/3/f/foo")
+            .match_header("if-none-match", "\"v1\"")
+            .with_status(304)
+            .create_async()
+            .await;
+        let _missing = server
+            .mock("GET", "/3/b/bar")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let index = SparseIndex::new(&format!("sparse+{}", server.url()), None)?;
+
+        assert_eq!(
+            index.fetch_crate("foo", None).await?,
+            CrateFile::Changed {
+                versions: vec![
+                    IndexVersion {
+                        name: "foo".into(),
+                        vers: "0.1.0".into(),
+                        yanked: false,
+                    },
+                    IndexVersion {
+                        name: "foo".into(),
+                        vers: "0.2.0".into(),
+                        yanked: true,
+                    },
+                ],
+                etag: Some("\"v1\"".into()),
+            }
+        );
+        assert_eq!(
+            index.fetch_crate("foo", Some("\"v1\"")).await?,
+            CrateFile::Unchanged
+        );
+        assert_eq!(index.fetch_crate("bar", None).await?, CrateFile::NotFound);
+
+        Ok(())
+    }
+}
@@ -22,6 +22,7 @@ mod docbuilder;
 mod error;
 pub mod index;
 pub mod metrics;
+mod registries;
 mod registry_api;
 pub mod repositories;
 pub mod storage;
@@ -65,5 +66,9 @@ pub const BUILD_VERSION: &str = concat!(
 /// `s3://rust-docs-rs//rustdoc-static/something.css`
 pub const RUSTDOC_STATIC_STORAGE_PREFIX: &str = "/rustdoc-static/";
 
+/// Where the rustdoc JSON output for the default target is stored inside
+/// the rustdoc archive of a release.
+pub const RUSTDOC_JSON_PATH: &str = "rustdoc.json";
+
 /// Maximum number of targets allowed for a crate to be documented on.
 pub const DEFAULT_MAX_TARGETS: usize = 10;
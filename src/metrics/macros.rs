@@ -23,6 +23,9 @@ macro_rules! metrics {
             pub(crate) cdn_invalidation_time: prometheus::HistogramVec,
             pub(crate) cdn_queue_time: prometheus::HistogramVec,
             pub(crate) build_time: prometheus::Histogram,
+            pub(crate) build_target_time: prometheus::Histogram,
+            pub(crate) build_target_cpu_time: prometheus::Histogram,
+            pub(crate) build_target_peak_memory: prometheus::Histogram,
         }
         impl $name {
             $vis fn new() -> Result<Self, prometheus::Error> {
@@ -72,12 +75,45 @@ macro_rules! metrics {
                 )?;
                 registry.register(Box::new(build_time.clone()))?;
 
+                let build_target_time = prometheus::Histogram::with_opts(
+                    prometheus::HistogramOpts::new(
+                        "build_target_time",
+                        "wall-clock time spent building a single target",
+                    )
+                    .namespace($namespace)
+                    .buckets($crate::metrics::build_time_histogram_buckets()),
+                )?;
+                registry.register(Box::new(build_target_time.clone()))?;
+
+                let build_target_cpu_time = prometheus::Histogram::with_opts(
+                    prometheus::HistogramOpts::new(
+                        "build_target_cpu_time",
+                        "CPU time used by the sandbox while building a single target",
+                    )
+                    .namespace($namespace)
+                    .buckets($crate::metrics::build_time_histogram_buckets()),
+                )?;
+                registry.register(Box::new(build_target_cpu_time.clone()))?;
+
+                let build_target_peak_memory = prometheus::Histogram::with_opts(
+                    prometheus::HistogramOpts::new(
+                        "build_target_peak_memory",
+                        "peak memory usage of the sandbox while building a single target",
+                    )
+                    .namespace($namespace)
+                    .buckets($crate::metrics::BUILD_MEMORY_HISTOGRAM_BUCKETS.to_vec()),
+                )?;
+                registry.register(Box::new(build_target_peak_memory.clone()))?;
+
                 Ok(Self {
                     registry,
                     recently_accessed_releases: RecentlyAccessedReleases::new(),
                     cdn_invalidation_time,
                     cdn_queue_time,
                     build_time,
+                    build_target_time,
+                    build_target_cpu_time,
+                    build_target_peak_memory,
                     $(
                         $(#[$meta])*
                         $metric,
@@ -65,6 +65,20 @@ pub fn build_time_histogram_buckets() -> Vec<f64> {
     ]
 }
 
+/// the measured peak memory usage of builds will be put into these buckets (bytes)
+pub const BUILD_MEMORY_HISTOGRAM_BUCKETS: &[f64; 10] = &[
+    268_435_456.0,    // 256 MiB
+    536_870_912.0,    // 512 MiB
+    1_073_741_824.0,  // 1 GiB
+    1_610_612_736.0,  // 1.5 GiB
+    2_147_483_648.0,  // 2 GiB
+    3_221_225_472.0,  // 3 GiB
+    4_294_967_296.0,  // 4 GiB
+    6_442_450_944.0,  // 6 GiB
+    8_589_934_592.0,  // 8 GiB
+    17_179_869_184.0, // 16 GiB
+];
+
 metrics! {
     pub struct InstanceMetrics {
         /// The number of idle database connections
@@ -108,6 +122,29 @@ metrics! {
 
         /// Number of files uploaded to the storage backend
         pub(crate) uploaded_files_total: IntCounter,
+        /// Number of archive files that weren't uploaded, because the storage already had a blob
+        /// with their content
+        pub(crate) deduplicated_files_total: IntCounter,
+        /// Uncompressed size of the archive files that weren't uploaded thanks to deduplication
+        pub(crate) deduplicated_bytes_total: IntCounter,
+        /// Compressed size of the stored files sent to clients without decompressing them,
+        /// because the clients accept their compression
+        pub(crate) compressed_bytes_passed_through_total: IntCounter,
+        /// Compressed size of the stored files we decompressed before sending them to clients
+        pub(crate) compressed_bytes_decompressed_total: IntCounter,
+        /// Number of archive index lookups that found the index in the local cache
+        pub(crate) archive_index_cache_hits_total: IntCounter,
+        /// Number of archive indexes downloaded into the local cache
+        pub(crate) archive_index_cache_misses_total: IntCounter,
+        /// Number of archive indexes removed from the local cache to keep it below its size limit
+        pub(crate) archive_index_cache_evictions_total: IntCounter,
+        /// Number of storage reads served from the in-memory cache
+        pub(crate) storage_memory_cache_hits_total: IntCounter,
+        /// Number of storage reads that weren't in the in-memory cache
+        pub(crate) storage_memory_cache_misses_total: IntCounter,
+        /// Number of storage reads that waited for a concurrent read of the same file, instead of
+        /// loading it themselves
+        pub(crate) storage_memory_cache_coalesced_total: IntCounter,
 
         /// The number of attempted files that failed due to a memory limit
         pub(crate) html_rewrite_ooms: IntCounter,
@@ -0,0 +1,173 @@
+//! Alternative registries we build documentation for, next to crates.io.
+//!
+//! Crates of an alternative registry are served under their own URL namespace,
+//! `/r/<registry>/...`, and are kept apart from crates.io crates with the same name
+//! in the database and in the storage.
+//!
+//! Unlike the crates.io index, their indexes aren't watched for new releases. Releases are
+//! added to the build queue with `cratesfyi queue add --registry <name>`, and removed with
+//! `cratesfyi database delete --registry <name>`.
+
+use anyhow::{bail, Context as _, Result};
+use serde::Deserialize;
+use std::{collections::HashSet, fs, path::Path};
+use url::Url;
+
+/// An alternative registry, as configured in the file `DOCSRS_REGISTRIES` points to:
+///
+/// ```toml
+/// [[registry]]
+/// name = "internal"
+/// index = "sparse+https://registry.example.com/index/"
+/// api = "https://registry.example.com/"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub(crate) struct Registry {
+    /// Used in URLs, and as the name of the registry in the cargo configuration of builds.
+    pub(crate) name: String,
+    /// URL of the index builds fetch the crates from, starting with `sparse+` for sparse
+    /// indexes.
+    #[serde(rename = "index")]
+    pub(crate) index_url: String,
+    /// Host of the web API of the registry, which has to offer the same endpoints
+    /// we use from the crates.io API.
+    #[serde(rename = "api")]
+    pub(crate) api_host: Url,
+}
+
+impl Registry {
+    /// Whether the registry has its index at `url`.
+    pub(crate) fn has_index(&self, url: &str) -> bool {
+        self.index_url.trim_end_matches('/') == url.trim_end_matches('/')
+    }
+
+    /// Prefix of the URLs of the crates in this registry.
+    pub(crate) fn url_prefix(&self) -> String {
+        format!("/r/{}", self.name)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RegistriesFile {
+    #[serde(default, rename = "registry")]
+    registries: Vec<Registry>,
+}
+
+/// Loads the configured alternative registries.
+pub(crate) fn load_registries(path: &Path) -> Result<Vec<Registry>> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read registries from {}", path.display()))?;
+    parse_registries(&content).with_context(|| format!("invalid registries in {}", path.display()))
+}
+
+fn parse_registries(content: &str) -> Result<Vec<Registry>> {
+    let file: RegistriesFile = toml::from_str(content)?;
+
+    let mut names = HashSet::new();
+    for registry in &file.registries {
+        validate_name(&registry.name)?;
+        if !names.insert(registry.name.as_str()) {
+            bail!("registry {} is configured more than once", registry.name);
+        }
+    }
+
+    Ok(file.registries)
+}
+
+/// `/r/<registry>/` can also be the start of the documentation of the `r` crate.
+/// Names starting with a letter can't be confused with a version, except for the ones
+/// we accept instead of a version.
+fn validate_name(name: &str) -> Result<()> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+    if !valid {
+        bail!(
+            "invalid registry name {name:?}: only lowercase letters, digits, `-` and `_` \
+             are allowed, and the name has to start with a letter"
+        );
+    }
+
+    if ["latest", "newest", "crates-io"].contains(&name) {
+        bail!("registry name {name:?} is reserved");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_case::test_case;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let registries = parse_registries(
+            r#"
+            [[registry]]
+            name = "internal"
+            index = "sparse+https://registry.example.com/index/"
+            api = "https://registry.example.com/"
+
+            [[registry]]
+            name = "other-registry"
+            index = "https://github.com/example/index"
+            api = "https://other.example.com/"
+            "#,
+        )?;
+
+        assert_eq!(
+            registries,
+            vec![
+                Registry {
+                    name: "internal".into(),
+                    index_url: "sparse+https://registry.example.com/index/".into(),
+                    api_host: "https://registry.example.com/".parse()?,
+                },
+                Registry {
+                    name: "other-registry".into(),
+                    index_url: "https://github.com/example/index".into(),
+                    api_host: "https://other.example.com/".parse()?,
+                },
+            ]
+        );
+        assert!(registries[0].has_index("sparse+https://registry.example.com/index"));
+        assert!(!registries[0].has_index("https://registry.example.com/index/"));
+        assert_eq!(registries[1].url_prefix(), "/r/other-registry");
+
+        assert!(parse_registries("")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_names() {
+        assert!(parse_registries(
+            r#"
+            [[registry]]
+            name = "internal"
+            index = "https://example.com/a"
+            api = "https://example.com/"
+
+            [[registry]]
+            name = "internal"
+            index = "https://example.com/b"
+            api = "https://example.com/"
+            "#,
+        )
+        .is_err());
+    }
+
+    #[test_case("internal", true)]
+    #[test_case("my_registry-2", true)]
+    #[test_case("", false)]
+    #[test_case("1registry", false)]
+    #[test_case("Internal", false)]
+    #[test_case("in/ternal", false)]
+    #[test_case("latest", false)]
+    #[test_case("newest", false)]
+    #[test_case("crates-io", false)]
+    fn names(name: &str, valid: bool) {
+        assert_eq!(validate_name(name).is_ok(), valid);
+    }
+}
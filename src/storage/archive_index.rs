@@ -2,7 +2,10 @@ use crate::error::Result;
 use crate::storage::{compression::CompressionAlgorithm, FileRange};
 use anyhow::{bail, Context as _};
 use rusqlite::{Connection, OpenFlags, OptionalExtension};
-use std::{fs, io, path::Path};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 use tracing::instrument;
 
 #[derive(PartialEq, Eq, Debug)]
@@ -20,6 +23,39 @@ impl FileInfo {
     }
 }
 
+/// How the files of an archive are stored, each kind has its own index format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArchiveKind {
+    /// In a ZIP file, the index has the ranges of the files in it.
+    Zip,
+    /// As content-addressed blobs, the index has the hashes of the files.
+    Deduplicated,
+}
+
+impl ArchiveKind {
+    pub(crate) fn remote_index_path(self, archive_path: &str) -> String {
+        match self {
+            Self::Zip => format!("{archive_path}.index"),
+            Self::Deduplicated => format!("{archive_path}.blobs.index"),
+        }
+    }
+
+    /// Cached indexes are specific to a build, so we don't keep using them after a rebuild.
+    pub(crate) fn local_index_path(
+        self,
+        cache_path: &Path,
+        archive_path: &str,
+        latest_build_id: i32,
+    ) -> PathBuf {
+        match self {
+            Self::Zip => cache_path.join(format!("{archive_path}.{latest_build_id}.index")),
+            Self::Deduplicated => {
+                cache_path.join(format!("{archive_path}.{latest_build_id}.blobs.index"))
+            }
+        }
+    }
+}
+
 /// create an archive index based on a zipfile.
 ///
 /// Will delete the destination file if it already exists.
@@ -50,7 +86,6 @@ pub(crate) fn create<R: io::Read + io::Seek, P: AsRef<Path> + std::fmt::Debug>(
     )?;
 
     let mut archive = zip::ZipArchive::new(zipfile)?;
-    let compression_bzip = CompressionAlgorithm::Bzip2 as i32;
 
     for i in 0..archive.len() {
         let zf = archive.by_index(i)?;
@@ -62,7 +97,8 @@ pub(crate) fn create<R: io::Read + io::Seek, P: AsRef<Path> + std::fmt::Debug>(
                 zf.data_start(),
                 zf.data_start() + zf.compressed_size() - 1,
                 match zf.compression() {
-                    zip::CompressionMethod::Bzip2 => compression_bzip,
+                    zip::CompressionMethod::Bzip2 => CompressionAlgorithm::Bzip2 as i32,
+                    zip::CompressionMethod::Zstd => CompressionAlgorithm::Zstd as i32,
                     c => bail!("unsupported compression algorithm {} in zip-file", c),
                 },
             ),
@@ -74,6 +110,59 @@ pub(crate) fn create<R: io::Read + io::Seek, P: AsRef<Path> + std::fmt::Debug>(
     Ok(())
 }
 
+/// create an archive index for an archive whose files are stored as separate, content-addressed
+/// blobs. `files` are the paths inside the archive, with the hash of their content.
+///
+/// Will delete the destination file if it already exists.
+#[instrument(skip(files))]
+pub(crate) fn create_for_blobs<'a, P: AsRef<Path> + std::fmt::Debug>(
+    files: impl IntoIterator<Item = (&'a str, &'a str)>,
+    destination: P,
+) -> Result<()> {
+    let destination = destination.as_ref();
+    if destination.exists() {
+        fs::remove_file(destination)?;
+    }
+
+    let conn = rusqlite::Connection::open(destination)?;
+    conn.execute("PRAGMA synchronous = FULL", ())?;
+    conn.execute("BEGIN", ())?;
+    conn.execute(
+        "
+            CREATE TABLE files (
+                id INTEGER PRIMARY KEY,
+                path TEXT UNIQUE,
+                blob TEXT
+            );
+            ",
+        (),
+    )?;
+
+    for (path, blob) in files {
+        conn.execute("INSERT INTO files (path, blob) VALUES (?, ?)", (path, blob))?;
+    }
+    conn.execute("CREATE INDEX idx_files_path ON files (path);", ())?;
+    conn.execute("END", ())?;
+    conn.execute("VACUUM", ())?;
+    Ok(())
+}
+
+/// Reads the `start`, `end` and `compression` columns, which have to be the first ones.
+fn file_info_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<FileInfo> {
+    let compression: i32 = row.get(2)?;
+
+    Ok(FileInfo {
+        range: row.get(0)?..=row.get(1)?,
+        compression: compression.try_into().map_err(|value| {
+            rusqlite::Error::FromSqlConversionFailure(
+                2,
+                rusqlite::types::Type::Integer,
+                format!("invalid compression algorithm '{}' in database", value).into(),
+            )
+        })?,
+    })
+}
+
 fn find_in_sqlite_index(conn: &Connection, search_for: &str) -> Result<Option<FileInfo>> {
     let mut stmt = conn.prepare(
         "
@@ -83,22 +172,9 @@ fn find_in_sqlite_index(conn: &Connection, search_for: &str) -> Result<Option<Fi
         ",
     )?;
 
-    stmt.query_row((search_for,), |row| {
-        let compression: i32 = row.get(2)?;
-
-        Ok(FileInfo {
-            range: row.get(0)?..=row.get(1)?,
-            compression: compression.try_into().map_err(|value| {
-                rusqlite::Error::FromSqlConversionFailure(
-                    2,
-                    rusqlite::types::Type::Integer,
-                    format!("invalid compression algorithm '{}' in database", value).into(),
-                )
-            })?,
-        })
-    })
-    .optional()
-    .context("error fetching SQLite data")
+    stmt.query_row((search_for,), file_info_from_row)
+        .optional()
+        .context("error fetching SQLite data")
 }
 
 #[instrument]
@@ -113,13 +189,69 @@ pub(crate) fn find_in_file<P: AsRef<Path> + std::fmt::Debug>(
     find_in_sqlite_index(&connection, search_for)
 }
 
+/// All files in an index created by [`create`], with their range in the archive.
+#[instrument]
+pub(crate) fn list_in_file<P: AsRef<Path> + std::fmt::Debug>(
+    archive_index_path: P,
+) -> Result<Vec<(String, FileInfo)>> {
+    let connection = Connection::open_with_flags(
+        archive_index_path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    let mut stmt =
+        connection.prepare("SELECT start, end, compression, path FROM files ORDER BY path")?;
+    let files = stmt
+        .query_map((), |row| Ok((row.get(3)?, file_info_from_row(row)?)))?
+        .collect::<Result<Vec<_>, _>>()
+        .context("error fetching SQLite data")?;
+    Ok(files)
+}
+
+/// Find the hash of the blob with the content of a file, in an index created by
+/// [`create_for_blobs`].
+#[instrument]
+pub(crate) fn find_blob_in_file<P: AsRef<Path> + std::fmt::Debug>(
+    archive_index_path: P,
+    search_for: &str,
+) -> Result<Option<String>> {
+    let connection = Connection::open_with_flags(
+        archive_index_path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    connection
+        .query_row(
+            "SELECT blob FROM files WHERE path = ?",
+            (search_for,),
+            |row| row.get(0),
+        )
+        .optional()
+        .context("error fetching SQLite data")
+}
+
+/// All files in an index created by [`create_for_blobs`], with the hash of their content.
+#[instrument]
+pub(crate) fn list_blobs_in_file<P: AsRef<Path> + std::fmt::Debug>(
+    archive_index_path: P,
+) -> Result<Vec<(String, String)>> {
+    let connection = Connection::open_with_flags(
+        archive_index_path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )?;
+    let mut stmt = connection.prepare("SELECT path, blob FROM files ORDER BY path")?;
+    let files = stmt
+        .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<_>, _>>()
+        .context("error fetching SQLite data")?;
+    Ok(files)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
+    use std::io::{Read, Seek, Write};
     use zip::write::FileOptions;
 
-    fn create_test_archive() -> fs::File {
+    fn create_test_archive(compression: zip::CompressionMethod) -> fs::File {
         let mut tf = tempfile::tempfile().unwrap();
 
         let objectcontent: Vec<u8> = (0..255).collect();
@@ -128,7 +260,7 @@ mod tests {
         archive
             .start_file(
                 "testfile1",
-                FileOptions::default().compression_method(zip::CompressionMethod::Bzip2),
+                FileOptions::default().compression_method(compression),
             )
             .unwrap();
         archive.write_all(&objectcontent).unwrap();
@@ -138,7 +270,7 @@ mod tests {
 
     #[test]
     fn index_create_save_load_sqlite() {
-        let mut tf = create_test_archive();
+        let mut tf = create_test_archive(zip::CompressionMethod::Bzip2);
 
         let tempfile = tempfile::NamedTempFile::new().unwrap().into_temp_path();
         create(&mut tf, &tempfile).unwrap();
@@ -151,5 +283,61 @@ mod tests {
         assert!(find_in_file(&tempfile, "some_other_file",)
             .unwrap()
             .is_none());
+
+        assert_eq!(
+            list_in_file(&tempfile).unwrap(),
+            vec![("testfile1".to_owned(), fi)]
+        );
+    }
+
+    #[test]
+    fn index_of_zstd_archive() {
+        let mut tf = create_test_archive(zip::CompressionMethod::Zstd);
+
+        let tempfile = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        create(&mut tf, &tempfile).unwrap();
+
+        let fi = find_in_file(&tempfile, "testfile1").unwrap().unwrap();
+        assert_eq!(fi.compression, CompressionAlgorithm::Zstd);
+
+        let mut compressed = vec![0; (fi.range.end() - fi.range.start() + 1) as usize];
+        tf.seek(io::SeekFrom::Start(*fi.range.start())).unwrap();
+        tf.read_exact(&mut compressed).unwrap();
+        assert_eq!(
+            crate::storage::decompress(compressed.as_slice(), fi.compression, std::usize::MAX)
+                .unwrap(),
+            (0..255).collect::<Vec<u8>>()
+        );
+    }
+
+    #[test]
+    fn blob_index_create_save_load() {
+        let tempfile = tempfile::NamedTempFile::new().unwrap().into_temp_path();
+        create_for_blobs(
+            [
+                ("src/lib.rs", "aaaa"),
+                ("index.html", "bbbb"),
+                ("all.html", "aaaa"),
+            ],
+            &tempfile,
+        )
+        .unwrap();
+
+        assert_eq!(
+            find_blob_in_file(&tempfile, "all.html").unwrap().as_deref(),
+            Some("aaaa")
+        );
+        assert!(find_blob_in_file(&tempfile, "some_other_file")
+            .unwrap()
+            .is_none());
+
+        assert_eq!(
+            list_blobs_in_file(&tempfile).unwrap(),
+            vec![
+                ("all.html".to_owned(), "aaaa".to_owned()),
+                ("index.html".to_owned(), "bbbb".to_owned()),
+                ("src/lib.rs".to_owned(), "aaaa".to_owned()),
+            ]
+        );
     }
 }
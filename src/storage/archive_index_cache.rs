@@ -0,0 +1,498 @@
+//! The local cache of the archive indexes we downloaded, see
+//! [`AsyncStorage::download_archive_index`](super::AsyncStorage::download_archive_index).
+//!
+//! The cache is capped at a maximum size, the least recently used indexes are removed when it
+//! grows bigger than that.
+
+use crate::{error::Result, utils::spawn_blocking, InstanceMetrics};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::OnceCell;
+use tracing::{debug, instrument};
+
+/// Indexes accessed more recently than this aren't evicted, since a request might be about to
+/// open them.
+const EVICTION_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// The access times are kept in the modification times of the files, so we know them after a
+/// restart. To not write to the disk on every request, they're only updated when they're older
+/// than this.
+///
+/// This is also all we know about the accesses of other processes: an index might have been
+/// opened up to this long after its modification time, so it's only evicted once the
+/// modification time is older than this plus the [`EVICTION_GRACE_PERIOD`].
+const ACCESS_TIME_PRECISION: Duration = Duration::from_secs(10 * 60);
+
+/// When the cache is full, we evict indexes until it's this much of its maximum size again, so we
+/// don't have to evict on every download.
+const EVICTION_TARGET_PERCENT: u64 = 90;
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    size: u64,
+    last_access: SystemTime,
+    /// The access time we last wrote to the disk.
+    stored_access: SystemTime,
+}
+
+impl Entry {
+    fn from_metadata(metadata: &fs::Metadata) -> io::Result<Self> {
+        let modified = metadata.modified()?;
+        Ok(Self {
+            size: metadata.len(),
+            last_access: modified,
+            stored_access: modified,
+        })
+    }
+}
+
+/// If another process might still be about to open an index last stored with this access time.
+fn within_stored_grace_period(stored_access: SystemTime, now: SystemTime) -> bool {
+    now.duration_since(stored_access).map_or(true, |age| {
+        age < EVICTION_GRACE_PERIOD + ACCESS_TIME_PRECISION
+    })
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<PathBuf, Entry>,
+    total_size: u64,
+}
+
+impl State {
+    fn insert(&mut self, path: PathBuf, entry: Entry) {
+        self.total_size += entry.size;
+        if let Some(previous) = self.entries.insert(path, entry) {
+            self.total_size -= previous.size;
+        }
+    }
+
+    /// Removes the least recently used entries until the cache is at most `max_size` big, skipping
+    /// the ones we might still need.
+    fn evict(&mut self, max_size: u64, now: SystemTime) -> Vec<PathBuf> {
+        if self.total_size <= max_size {
+            return Vec::new();
+        }
+
+        let mut candidates: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                now.duration_since(entry.last_access)
+                    .is_ok_and(|age| age >= EVICTION_GRACE_PERIOD)
+                    && !within_stored_grace_period(entry.stored_access, now)
+            })
+            .map(|(path, entry)| (entry.last_access, path.clone()))
+            .collect();
+        candidates.sort_unstable();
+
+        let mut evicted = Vec::new();
+        for (_, path) in candidates {
+            if self.total_size <= max_size {
+                break;
+            }
+            if let Some(entry) = self.entries.remove(&path) {
+                self.total_size -= entry.size;
+                evicted.push(path);
+            }
+        }
+        evicted
+    }
+}
+
+/// The number and size of the cached indexes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveIndexCacheInfo {
+    pub entries: usize,
+    pub total_size: u64,
+    pub max_size: u64,
+    /// When the least recently used index was accessed.
+    pub oldest_access: Option<SystemTime>,
+}
+
+/// Keeps track of the size and the access times of the cached indexes.
+///
+/// Each process has its own view of the cache. Indexes removed by other processes are forgotten
+/// once we'd evict them, or when we download them again.
+///
+/// The indexes that are already in the cache are only loaded when we first need to know its size,
+/// so processes that never download an index don't have to scan the whole directory.
+pub struct ArchiveIndexCache {
+    root: PathBuf,
+    max_size: u64,
+    metrics: Arc<InstanceMetrics>,
+    state: Mutex<State>,
+    scanned: OnceCell<()>,
+}
+
+impl ArchiveIndexCache {
+    pub(super) fn new(root: PathBuf, max_size: u64, metrics: Arc<InstanceMetrics>) -> Self {
+        Self {
+            root,
+            max_size,
+            metrics,
+            state: Mutex::new(State::default()),
+            scanned: OnceCell::new(),
+        }
+    }
+
+    /// Loads the indexes that are already in the cache, once.
+    async fn scan(&self) -> Result<()> {
+        self.scanned
+            .get_or_try_init(|| async {
+                let scanned = spawn_blocking({
+                    let root = self.root.clone();
+                    move || scan(&root)
+                })
+                .await?;
+
+                // the entries we touched in the meantime are more recent
+                let mut state = self.state.lock().unwrap();
+                for (path, entry) in scanned.entries {
+                    if !state.entries.contains_key(&path) {
+                        state.insert(path, entry);
+                    }
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Checks if the index at `path` is in the cache, and records the access when it is.
+    pub(super) async fn touch(&self, path: &Path) -> Result<bool> {
+        let metadata = match tokio::fs::metadata(path).await {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+
+        let now = SystemTime::now();
+        let store_access = {
+            let mut state = self.state.lock().unwrap();
+            let entry = state.entries.get(path).copied().unwrap_or_else(|| {
+                Entry::from_metadata(&metadata).unwrap_or(Entry {
+                    size: metadata.len(),
+                    last_access: now,
+                    stored_access: now,
+                })
+            });
+            let store_access = now
+                .duration_since(entry.stored_access)
+                .is_ok_and(|age| age >= ACCESS_TIME_PRECISION);
+            state.insert(
+                path.to_owned(),
+                Entry {
+                    last_access: now,
+                    stored_access: if store_access {
+                        now
+                    } else {
+                        entry.stored_access
+                    },
+                    ..entry
+                },
+            );
+            store_access
+        };
+
+        if store_access {
+            let path = path.to_owned();
+            spawn_blocking(move || {
+                match fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .and_then(|file| file.set_modified(now))
+                {
+                    // evicted in the meantime
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                    result => Ok(result?),
+                }
+            })
+            .await?;
+        }
+
+        self.metrics.archive_index_cache_hits_total.inc();
+        Ok(true)
+    }
+
+    /// Adds an index we just downloaded to the cache, and evicts the least recently used ones
+    /// when the cache got too big.
+    pub(super) async fn insert(&self, path: &Path) -> Result<()> {
+        self.metrics.archive_index_cache_misses_total.inc();
+
+        self.scan().await?;
+
+        let size = tokio::fs::metadata(path).await?.len();
+        let now = SystemTime::now();
+        let evicted = {
+            let mut state = self.state.lock().unwrap();
+            state.insert(
+                path.to_owned(),
+                Entry {
+                    size,
+                    last_access: now,
+                    stored_access: now,
+                },
+            );
+            if state.total_size <= self.max_size {
+                return Ok(());
+            }
+            state.evict(self.max_size * EVICTION_TARGET_PERCENT / 100, now)
+        };
+
+        self.remove_files(evicted).await?;
+        Ok(())
+    }
+
+    /// Forgets an index that was removed from the cache.
+    pub(super) fn remove(&self, path: &Path) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.entries.remove(path) {
+            state.total_size -= entry.size;
+        }
+    }
+
+    pub async fn info(&self) -> Result<ArchiveIndexCacheInfo> {
+        self.scan().await?;
+
+        let state = self.state.lock().unwrap();
+        Ok(ArchiveIndexCacheInfo {
+            entries: state.entries.len(),
+            total_size: state.total_size,
+            max_size: self.max_size,
+            oldest_access: state.entries.values().map(|entry| entry.last_access).min(),
+        })
+    }
+
+    /// Evicts the least recently used indexes until the cache is at most `max_size` big.
+    ///
+    /// Returns the number of evicted indexes.
+    #[instrument(skip(self))]
+    pub async fn prune(&self, max_size: u64) -> Result<usize> {
+        self.scan().await?;
+
+        let evicted = self
+            .state
+            .lock()
+            .unwrap()
+            .evict(max_size, SystemTime::now());
+        self.remove_files(evicted).await
+    }
+
+    async fn remove_files(&self, paths: Vec<PathBuf>) -> Result<usize> {
+        let root = self.root.clone();
+        let (removed, kept) = spawn_blocking(move || {
+            let now = SystemTime::now();
+            let mut removed = 0;
+            let mut kept = Vec::new();
+            for path in paths {
+                let entry = match fs::metadata(&path).and_then(|m| Entry::from_metadata(&m)) {
+                    Ok(entry) => entry,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                // another process accessed the index since we last looked at it
+                if within_stored_grace_period(entry.stored_access, now) {
+                    kept.push((path, entry));
+                    continue;
+                }
+
+                debug!(path = %path.strip_prefix(&root).unwrap_or(&path).display(), "evicting archive index");
+                // Requests that opened the index already can keep reading it after it's removed.
+                match fs::remove_file(&path) {
+                    Ok(()) => removed += 1,
+                    Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Ok((removed, kept))
+        })
+        .await?;
+
+        if !kept.is_empty() {
+            let mut state = self.state.lock().unwrap();
+            for (path, entry) in kept {
+                state.insert(path, entry);
+            }
+        }
+
+        self.metrics
+            .archive_index_cache_evictions_total
+            .inc_by(removed as u64);
+        Ok(removed)
+    }
+}
+
+/// Finds the cached indexes, with their modification time as the last access.
+fn scan(root: &Path) -> Result<State> {
+    let mut state = State::default();
+    if !root.exists() {
+        return Ok(state);
+    }
+
+    for entry in walkdir::WalkDir::new(root) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // evicted while we were scanning the directory
+            Err(err) if err.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound) => {
+                continue
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // there are also temporary files of indexes we're downloading
+        if !entry.file_type().is_file()
+            || entry.path().extension().and_then(|ext| ext.to_str()) != Some("index")
+        {
+            continue;
+        }
+
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) if err.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound) => {
+                continue
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let cached = Entry::from_metadata(&metadata)?;
+        state.insert(entry.into_path(), cached);
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_index(path: &Path, size: usize, seconds_ago: u64) -> Result<()> {
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, vec![0; size])?;
+        fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(SystemTime::now() - Duration::from_secs(seconds_ago))?;
+        Ok(())
+    }
+
+    fn entry(size: u64, seconds_ago: u64, now: SystemTime) -> Entry {
+        let last_access = now - Duration::from_secs(seconds_ago);
+        Entry {
+            size,
+            last_access,
+            stored_access: last_access,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries() {
+        let now = SystemTime::now();
+        let mut state = State::default();
+        state.insert("a.index".into(), entry(10, 3000, now));
+        state.insert("b.index".into(), entry(10, 1000, now));
+        state.insert("c.index".into(), entry(10, 2000, now));
+        state.insert("d.index".into(), entry(10, 4000, now));
+        assert_eq!(state.total_size, 40);
+
+        assert!(state.evict(40, now).is_empty());
+        assert_eq!(
+            state.evict(15, now),
+            vec![
+                PathBuf::from("d.index"),
+                PathBuf::from("a.index"),
+                PathBuf::from("c.index")
+            ]
+        );
+        assert_eq!(state.total_size, 10);
+        assert!(state.entries.contains_key(Path::new("b.index")));
+    }
+
+    #[test]
+    fn keeps_recently_accessed_entries() {
+        let now = SystemTime::now();
+        let mut state = State::default();
+        state.insert("old.index".into(), entry(10, 3000, now));
+        state.insert("new.index".into(), entry(10, 1, now));
+        // other processes might have opened it without updating the modification time
+        state.insert("stored.index".into(), entry(10, 300, now));
+
+        assert_eq!(state.evict(0, now), vec![PathBuf::from("old.index")]);
+        assert_eq!(state.total_size, 20);
+    }
+
+    #[test]
+    fn replacing_an_entry_keeps_the_size() {
+        let now = SystemTime::now();
+        let mut state = State::default();
+        state.insert("a.index".into(), entry(10, 300, now));
+        state.insert("a.index".into(), entry(20, 0, now));
+        assert_eq!(state.total_size, 20);
+        assert_eq!(state.entries.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn loads_and_evicts_cached_indexes() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let old = root.path().join("rustdoc/foo/1.0.0.zip.1.index");
+        let recent = root.path().join("rustdoc/bar/1.0.0.zip.2.blobs.index");
+        write_index(&old, 100, 3600)?;
+        write_index(&recent, 100, 1800)?;
+        // a download that didn't finish
+        fs::write(root.path().join(".tmp1234"), vec![0; 100])?;
+
+        let metrics = Arc::new(InstanceMetrics::new()?);
+        let cache = ArchiveIndexCache::new(root.path().to_owned(), 250, metrics.clone());
+        let info = cache.info().await?;
+        assert_eq!(info.entries, 2);
+        assert_eq!(info.total_size, 200);
+
+        assert!(cache.touch(&old).await?);
+        assert!(!cache.touch(&root.path().join("missing.index")).await?);
+        assert_eq!(metrics.archive_index_cache_hits_total.get(), 1);
+
+        // the recently used index stays, `recent` is the least recently used one now
+        let new = root.path().join("rustdoc/baz/1.0.0.zip.3.index");
+        write_index(&new, 100, 0)?;
+        cache.insert(&new).await?;
+        assert_eq!(metrics.archive_index_cache_misses_total.get(), 1);
+        assert_eq!(metrics.archive_index_cache_evictions_total.get(), 1);
+        assert!(old.exists());
+        assert!(!recent.exists());
+        assert!(new.exists());
+        assert_eq!(cache.info().await?.total_size, 200);
+
+        // both are within the grace period
+        assert_eq!(cache.prune(0).await?, 0);
+        assert_eq!(cache.info().await?.entries, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keeps_indexes_accessed_by_other_processes() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let index = root.path().join("rustdoc/foo/1.0.0.zip.1.index");
+        write_index(&index, 100, 3600)?;
+
+        let metrics = Arc::new(InstanceMetrics::new()?);
+        let cache = ArchiveIndexCache::new(root.path().to_owned(), 250, metrics);
+        // nothing is loaded before we need it
+        assert_eq!(cache.state.lock().unwrap().entries.len(), 0);
+        assert_eq!(cache.info().await?.entries, 1);
+
+        // another process updated the access time after we loaded the cache
+        fs::File::options()
+            .write(true)
+            .open(&index)?
+            .set_modified(SystemTime::now() - Duration::from_secs(300))?;
+
+        assert_eq!(cache.prune(0).await?, 0);
+        assert!(index.exists());
+        assert_eq!(cache.info().await?.total_size, 100);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Blob, FileRange};
+use super::{Blob, FileRange, StreamingBlob};
 use crate::{db::Pool, error::Result, InstanceMetrics};
 use chrono::{DateTime, Utc};
 use futures_util::stream::{Stream, TryStreamExt};
@@ -143,6 +143,16 @@ impl DatabaseBackend {
         })
     }
 
+    /// The content is stored in a single column, so we always load all of it.
+    pub(super) async fn get_stream(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<FileRange>,
+    ) -> Result<StreamingBlob> {
+        Ok(self.get(path, max_size, range).await?.into())
+    }
+
     pub(super) async fn store_batch(&self, batch: Vec<Blob>) -> Result<()> {
         let mut conn = self.pool.get_async().await?;
         let mut trans = conn.begin().await?;
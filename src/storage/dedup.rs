@@ -0,0 +1,656 @@
+//! Deduplicated archives.
+//!
+//! Instead of being put into a ZIP file, the files of a deduplicated archive are stored as blobs
+//! named after the SHA-256 hash of their content. Many files don't change between releases and
+//! targets, like most source files, search assets and pages of unchanged modules, and each of them
+//! is only stored once. The index of the archive, `<archive>.blobs.index`, has the hash of every
+//! file in the archive.
+//!
+//! Blobs are shared between releases, so they aren't removed when a release is deleted.
+//! [`delete_orphaned_blobs`] removes the ones no archive references anymore.
+
+use super::{
+    archive_index::{self, ArchiveKind},
+    compress, crate_storage_name, detect_mime, get_file_list, record_archive_checksums,
+    rustdoc_archive_path, source_archive_path, ArchiveChecksums, AsyncStorage, Blob,
+    CompressionAlgorithm, PathNotFoundError, RELEASES_PER_BATCH,
+};
+use crate::{error::Result, utils::spawn_blocking};
+use anyhow::{anyhow, Context as _};
+use chrono::Utc;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs,
+    io::{self, Read as _, Seek as _, Write as _},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::{info, instrument};
+
+/// Compression of the blobs with the content of the files.
+pub(super) const BLOB_COMPRESSION: CompressionAlgorithm = CompressionAlgorithm::Zstd;
+
+/// Number of blobs we check for, or fetch, at the same time.
+pub(super) const CONCURRENT_REQUESTS: usize = 32;
+
+pub(super) fn content_blob_path(hash: &str) -> String {
+    format!("blobs/{}/{hash}", &hash[..2])
+}
+
+struct ArchiveFile {
+    path: String,
+    content: Vec<u8>,
+}
+
+impl AsyncStorage {
+    /// Stores the files in `root_dir` as deduplicated archive, replacing the archive that might
+    /// have been stored at `archive_path` before.
+    ///
+    /// Returns the mime types of the files, the number of bytes we didn't have to store and the
+    /// checksums of the archive.
+    #[instrument(skip(self))]
+    pub(super) async fn store_deduplicated_archive(
+        &self,
+        archive_path: &str,
+        root_dir: &Path,
+    ) -> Result<(HashMap<PathBuf, String>, u64, ArchiveChecksums)> {
+        let (files, index_entries, file_paths) = spawn_blocking({
+            let root_dir = root_dir.to_owned();
+            move || {
+                let mut file_paths = HashMap::new();
+                let mut index_entries = Vec::new();
+                let mut files = Vec::new();
+
+                for file_path in get_file_list(&root_dir)? {
+                    let content = fs::read(root_dir.join(&file_path))?;
+                    let path = file_path
+                        .to_str()
+                        .ok_or_else(|| anyhow!("invalid file name {}", file_path.display()))?
+                        .to_owned();
+                    let hash = hex::encode(Sha256::digest(&content));
+
+                    index_entries.push((path.clone(), hash.clone()));
+                    files.push((hash, ArchiveFile { path, content }));
+                    let mime = detect_mime(&file_path);
+                    file_paths.insert(file_path, mime.to_string());
+                }
+
+                Ok((files, index_entries, file_paths))
+            }
+        })
+        .await?;
+
+        let mut deduplicated_files = 0;
+        let mut deduplicated_bytes = 0;
+
+        let mut unique_files = HashMap::new();
+        for (hash, file) in files {
+            match unique_files.entry(hash) {
+                Entry::Occupied(_) => {
+                    deduplicated_files += 1;
+                    deduplicated_bytes += file.content.len() as u64;
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(file);
+                }
+            }
+        }
+
+        let existing: HashSet<String> = stream::iter(unique_files.keys())
+            .map(|hash| async move {
+                let exists = self.exists(&content_blob_path(hash)).await?;
+                Ok::<_, anyhow::Error>(exists.then(|| hash.clone()))
+            })
+            .buffer_unordered(CONCURRENT_REQUESTS)
+            .try_filter_map(|hash| async move { Ok(hash) })
+            .try_collect()
+            .await?;
+
+        unique_files.retain(|hash, file| {
+            if existing.contains(hash) {
+                deduplicated_files += 1;
+                deduplicated_bytes += file.content.len() as u64;
+                false
+            } else {
+                true
+            }
+        });
+
+        let blobs = spawn_blocking(move || {
+            unique_files
+                .into_iter()
+                .map(|(hash, file)| {
+                    Ok(Blob {
+                        path: content_blob_path(&hash),
+                        mime: detect_mime(&file.path).to_owned(),
+                        content: compress(file.content.as_slice(), BLOB_COMPRESSION)?,
+                        compression: Some(BLOB_COMPRESSION),
+                        // this field is ignored by the backend
+                        date_updated: Utc::now(),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })
+        .await?;
+        self.store_inner(blobs).await?;
+
+        let index_content = spawn_blocking({
+            let temp_dir = self.config.temp_dir.clone();
+            move || {
+                fs::create_dir_all(&temp_dir)?;
+                let local_index_path = tempfile::NamedTempFile::new_in(&temp_dir)?.into_temp_path();
+                archive_index::create_for_blobs(
+                    index_entries
+                        .iter()
+                        .map(|(path, hash)| (path.as_str(), hash.as_str())),
+                    &local_index_path,
+                )?;
+                compress(
+                    io::BufReader::new(fs::File::open(&local_index_path)?),
+                    BLOB_COMPRESSION,
+                )
+            }
+        })
+        .await?;
+        let checksums = ArchiveChecksums::new(None, &index_content);
+
+        // Removes the ZIP file and index of an archive stored before, otherwise we'd keep
+        // serving its files.
+        self.delete_prefix(archive_path).await?;
+        self.store_inner(vec![Blob {
+            path: ArchiveKind::Deduplicated.remote_index_path(archive_path),
+            mime: "application/octet-stream".to_owned(),
+            content: index_content,
+            compression: Some(BLOB_COMPRESSION),
+            date_updated: Utc::now(),
+        }])
+        .await?;
+
+        self.metrics
+            .deduplicated_files_total
+            .inc_by(deduplicated_files);
+        self.metrics
+            .deduplicated_bytes_total
+            .inc_by(deduplicated_bytes);
+
+        Ok((file_paths, deduplicated_bytes, checksums))
+    }
+
+    /// Turns an archive stored as ZIP file into a deduplicated archive.
+    ///
+    /// Returns the number of bytes the deduplication saved and the checksums of the deduplicated
+    /// archive, or `None` if there's no ZIP archive at `archive_path`.
+    #[instrument(skip(self))]
+    pub(crate) async fn deduplicate_archive(
+        &self,
+        archive_path: &str,
+    ) -> Result<Option<(u64, ArchiveChecksums)>> {
+        // ZIP files recreated for downloads don't have an index
+        if !self
+            .exists(&ArchiveKind::Zip.remote_index_path(archive_path))
+            .await?
+        {
+            return Ok(None);
+        }
+
+        let zip_content = self.get(archive_path, std::usize::MAX).await?.content;
+        let dir = spawn_blocking({
+            let temp_dir = self.config.temp_dir.clone();
+            move || {
+                fs::create_dir_all(&temp_dir)?;
+                let dir = tempfile::tempdir_in(&temp_dir)?;
+                zip::ZipArchive::new(io::Cursor::new(zip_content))?.extract(dir.path())?;
+                Ok(dir)
+            }
+        })
+        .await?;
+
+        let (_, deduplicated_bytes, checksums) = self
+            .store_deduplicated_archive(archive_path, dir.path())
+            .await?;
+        Ok(Some((deduplicated_bytes, checksums)))
+    }
+
+    /// Recreates the ZIP file of a deduplicated archive, so it can be downloaded.
+    ///
+    /// Concurrent requests for the same archive share a single restore.
+    #[instrument(skip(self))]
+    pub(crate) async fn restore_archive_zip(&self, archive_path: &str) -> Result<()> {
+        let cell = self
+            .restoring_archives
+            .lock()
+            .unwrap()
+            .entry(archive_path.to_owned())
+            .or_default()
+            .clone();
+
+        let result = cell
+            .get_or_try_init(|| async {
+                let zip_content = self.zip_deduplicated_archive(archive_path).await?;
+                self.store_inner(vec![Blob {
+                    path: archive_path.to_owned(),
+                    mime: "application/zip".to_owned(),
+                    content: zip_content,
+                    compression: None,
+                    date_updated: Utc::now(),
+                }])
+                .await
+            })
+            .await
+            .map(|_| ());
+
+        // later requests find the restored ZIP file, or try again after a failure
+        let mut restoring_archives = self.restoring_archives.lock().unwrap();
+        if restoring_archives
+            .get(archive_path)
+            .is_some_and(|running| Arc::ptr_eq(running, &cell))
+        {
+            restoring_archives.remove(archive_path);
+        }
+
+        result
+    }
+
+    /// The content of the ZIP file of the archive at `archive_path`, which is created from the
+    /// blobs of deduplicated archives.
+    #[instrument(skip(self))]
+    pub(crate) async fn get_archive_zip(&self, archive_path: &str) -> Result<Vec<u8>> {
+        if self
+            .exists(&ArchiveKind::Zip.remote_index_path(archive_path))
+            .await?
+        {
+            Ok(self.get(archive_path, std::usize::MAX).await?.content)
+        } else {
+            self.zip_deduplicated_archive(archive_path).await
+        }
+    }
+
+    /// The paths of the files in the deduplicated archive at `archive_path`, with the hashes of
+    /// their blobs.
+    async fn list_deduplicated_archive(&self, archive_path: &str) -> Result<Vec<(String, String)>> {
+        let index_content = self
+            .get(
+                &ArchiveKind::Deduplicated.remote_index_path(archive_path),
+                std::usize::MAX,
+            )
+            .await?
+            .content;
+
+        spawn_blocking({
+            let temp_dir = self.config.temp_dir.clone();
+            move || {
+                fs::create_dir_all(&temp_dir)?;
+                let local_index_path = tempfile::NamedTempFile::new_in(&temp_dir)?.into_temp_path();
+                fs::write(&local_index_path, index_content)?;
+                archive_index::list_blobs_in_file(&local_index_path)
+            }
+        })
+        .await
+    }
+
+    async fn zip_deduplicated_archive(&self, archive_path: &str) -> Result<Vec<u8>> {
+        let files = self.list_deduplicated_archive(archive_path).await?;
+
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Zstd);
+        let mut zip = spawn_blocking({
+            let temp_dir = self.config.temp_dir.clone();
+            move || {
+                fs::create_dir_all(&temp_dir)?;
+                Ok(zip::ZipWriter::new(tempfile::tempfile_in(&temp_dir)?))
+            }
+        })
+        .await?;
+
+        // the ZIP file is written to the disk as the blobs arrive, so we only keep a few of
+        // them in memory
+        let mut blobs = stream::iter(files)
+            .map(|(path, hash)| async move {
+                let blob = self.get(&content_blob_path(&hash), std::usize::MAX).await?;
+                Ok::<_, anyhow::Error>((path, blob.content))
+            })
+            .buffered(CONCURRENT_REQUESTS)
+            .try_chunks(CONCURRENT_REQUESTS)
+            .map_err(|err| err.1);
+
+        while let Some(files) = blobs.try_next().await? {
+            zip = spawn_blocking(move || {
+                for (path, content) in files {
+                    zip.start_file(path, options)?;
+                    zip.write_all(&content)?;
+                }
+                Ok(zip)
+            })
+            .await?;
+        }
+
+        spawn_blocking(move || {
+            let mut file = zip.finish()?;
+            file.rewind()?;
+            let mut content = Vec::new();
+            file.read_to_end(&mut content)?;
+            Ok(content)
+        })
+        .await
+    }
+}
+
+/// The paths of the archives of the releases after `last_release_id`, with the id of the last
+/// release in the batch, or `None` if there are no more releases.
+async fn next_archive_paths(
+    conn: &mut sqlx::PgConnection,
+    last_release_id: i32,
+) -> Result<Option<(i32, Vec<String>)>> {
+    let releases = sqlx::query!(
+        "SELECT releases.id, crates.name, crates.registry, releases.version,
+                    releases.rustdoc_status
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE releases.archive_storage AND releases.id > $1
+             ORDER BY releases.id
+             LIMIT $2",
+        last_release_id,
+        RELEASES_PER_BATCH,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let Some(last_release) = releases.last() else {
+        return Ok(None);
+    };
+    let last_release_id = last_release.id;
+
+    let mut archive_paths = Vec::new();
+    for release in releases {
+        let storage_name = crate_storage_name(release.registry.as_deref(), &release.name);
+        archive_paths.push(source_archive_path(&storage_name, &release.version));
+        if release.rustdoc_status {
+            archive_paths.push(rustdoc_archive_path(&storage_name, &release.version));
+        }
+    }
+
+    Ok(Some((last_release_id, archive_paths)))
+}
+
+/// Turns the ZIP archives of all releases into deduplicated archives.
+pub async fn deduplicate_archives(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+) -> Result<()> {
+    let mut last_release_id = 0;
+    let mut deduplicated_bytes = 0;
+
+    while let Some((last_id, archive_paths)) =
+        next_archive_paths(&mut *conn, last_release_id).await?
+    {
+        last_release_id = last_id;
+
+        for archive_path in archive_paths {
+            if let Some((bytes, checksums)) = storage
+                .deduplicate_archive(&archive_path)
+                .await
+                .with_context(|| format!("failed to deduplicate {archive_path}"))?
+            {
+                record_archive_checksums(&mut *conn, &archive_path, &checksums).await?;
+                info!(%archive_path, bytes, "deduplicated archive");
+                deduplicated_bytes += bytes;
+            }
+        }
+    }
+
+    info!(deduplicated_bytes, "deduplicated all archives");
+    Ok(())
+}
+
+/// Deletes the blobs no deduplicated archive of a release references, like the ones only
+/// deleted releases had.
+///
+/// Archives are stored after their blobs, so the blobs of an archive being stored look like
+/// orphans. This must only run when no archives are stored, e.g. while the build queue is locked.
+///
+/// Returns the number of deleted blobs.
+pub async fn delete_orphaned_blobs(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+) -> Result<u64> {
+    let mut referenced = HashSet::new();
+    let mut last_release_id = 0;
+    while let Some((last_id, archive_paths)) =
+        next_archive_paths(&mut *conn, last_release_id).await?
+    {
+        last_release_id = last_id;
+
+        let mut archives = stream::iter(&archive_paths)
+            .map(|archive_path| async move {
+                match storage.list_deduplicated_archive(archive_path).await {
+                    Ok(files) => Ok(files),
+                    // a ZIP archive, which has no blobs
+                    Err(err) if err.is::<PathNotFoundError>() => Ok(Vec::new()),
+                    Err(err) => Err(err.context(format!("failed to list {archive_path}"))),
+                }
+            })
+            .buffer_unordered(CONCURRENT_REQUESTS);
+        while let Some(files) = archives.try_next().await? {
+            referenced.extend(files.into_iter().map(|(_, hash)| content_blob_path(&hash)));
+        }
+    }
+
+    let orphans: Vec<String> = storage
+        .list_prefix("blobs/")
+        .await
+        .try_filter(|path| std::future::ready(!referenced.contains(path)))
+        .try_collect()
+        .await?;
+
+    let mut deleted_blobs = 0;
+    for path in orphans {
+        storage.delete_prefix(&path).await?;
+        info!(%path, "deleted orphaned blob");
+        deleted_blobs += 1;
+    }
+
+    info!(deleted_blobs, "deleted all orphaned blobs");
+    Ok(deleted_blobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+
+    fn write_files(dir: &Path, files: &[(&str, &str)]) -> Result<()> {
+        for (name, content) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, content)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn store_and_read_deduplicated_archives() {
+        wrapper(|env| {
+            env.override_config(|config| config.deduplicate_archives = true);
+            let storage = env.storage();
+            let metrics = env.instance_metrics();
+
+            let first = tempfile::tempdir()?;
+            write_files(
+                first.path(),
+                &[
+                    ("src/lib.rs", "unchanged"),
+                    ("src/other.rs", "unchanged"),
+                    ("Cargo.toml", "version 1"),
+                ],
+            )?;
+            let (files, alg, checksums) =
+                storage.store_all_in_archive("sources/foo/1.0.0.zip", first.path())?;
+            assert_eq!(files.len(), 3);
+            assert_eq!(alg, BLOB_COMPRESSION);
+            // there's no ZIP file
+            assert_eq!(checksums.archive, None);
+            assert!(!storage.exists("sources/foo/1.0.0.zip")?);
+            assert!(storage.exists("sources/foo/1.0.0.zip.blobs.index")?);
+
+            // two blobs, the index, `src/other.rs` was deduplicated
+            assert_eq!(metrics.uploaded_files_total.get(), 3);
+            assert_eq!(metrics.deduplicated_files_total.get(), 1);
+            assert_eq!(metrics.deduplicated_bytes_total.get(), 9);
+
+            let second = tempfile::tempdir()?;
+            write_files(
+                second.path(),
+                &[("src/lib.rs", "unchanged"), ("Cargo.toml", "version 2")],
+            )?;
+            storage.store_all_in_archive("sources/foo/2.0.0.zip", second.path())?;
+
+            // one new blob and the index
+            assert_eq!(metrics.uploaded_files_total.get(), 5);
+            assert_eq!(metrics.deduplicated_files_total.get(), 2);
+            assert_eq!(metrics.deduplicated_bytes_total.get(), 18);
+
+            for (version, path, content) in [
+                ("1.0.0", "src/other.rs", "unchanged"),
+                ("1.0.0", "Cargo.toml", "version 1"),
+                ("2.0.0", "src/lib.rs", "unchanged"),
+                ("2.0.0", "Cargo.toml", "version 2"),
+            ] {
+                let archive_path = format!("sources/foo/{version}.zip");
+                assert!(storage.exists_in_archive(&archive_path, 0, path)?);
+                let blob = storage.get_from_archive(&archive_path, 0, path, std::usize::MAX)?;
+                assert_eq!(blob.content, content.as_bytes());
+                assert_eq!(blob.path, format!("{archive_path}/{path}"));
+            }
+            assert!(!storage.exists_in_archive("sources/foo/2.0.0.zip", 0, "src/other.rs")?);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn deduplicate_zip_archive() {
+        wrapper(|env| {
+            let storage = env.storage();
+            let dir = tempfile::tempdir()?;
+            write_files(
+                dir.path(),
+                &[("src/lib.rs", "content"), ("src/main.rs", "content")],
+            )?;
+            storage.store_all_in_archive("sources/foo/1.0.0.zip", dir.path())?;
+
+            // caches the index of the ZIP archive
+            assert_eq!(
+                storage
+                    .get_from_archive("sources/foo/1.0.0.zip", 0, "src/lib.rs", std::usize::MAX)?
+                    .content,
+                b"content"
+            );
+
+            let deduplicate = || {
+                storage
+                    .runtime
+                    .block_on(storage.inner.deduplicate_archive("sources/foo/1.0.0.zip"))
+            };
+            assert_eq!(deduplicate()?.map(|(bytes, _)| bytes), Some(7));
+            assert!(!storage.exists("sources/foo/1.0.0.zip")?);
+            assert!(!storage.exists("sources/foo/1.0.0.zip.index")?);
+            // already deduplicated
+            assert!(deduplicate()?.is_none());
+
+            // the cached index of the ZIP file is replaced
+            assert_eq!(
+                storage
+                    .get_from_archive("sources/foo/1.0.0.zip", 0, "src/main.rs", std::usize::MAX)?
+                    .content,
+                b"content"
+            );
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn restore_zip_of_deduplicated_archive() {
+        wrapper(|env| {
+            env.override_config(|config| config.deduplicate_archives = true);
+            let storage = env.storage();
+            let dir = tempfile::tempdir()?;
+            write_files(
+                dir.path(),
+                &[("foo/index.html", "<html>"), ("foo/all.html", "<html>")],
+            )?;
+            storage.store_all_in_archive("rustdoc/foo/1.0.0.zip", dir.path())?;
+
+            // concurrent downloads share the restore
+            storage.runtime.block_on(async {
+                futures_util::try_join!(
+                    storage.inner.restore_archive_zip("rustdoc/foo/1.0.0.zip"),
+                    storage.inner.restore_archive_zip("rustdoc/foo/1.0.0.zip"),
+                )
+            })?;
+            assert!(storage.inner.restoring_archives.lock().unwrap().is_empty());
+
+            let zip_content = storage
+                .get("rustdoc/foo/1.0.0.zip", std::usize::MAX)?
+                .content;
+            let mut zip = zip::ZipArchive::new(io::Cursor::new(zip_content))?;
+            assert_eq!(zip.len(), 2);
+            let mut content = String::new();
+            io::Read::read_to_string(&mut zip.by_name("foo/all.html")?, &mut content)?;
+            assert_eq!(content, "<html>");
+
+            // the files are still read from the blobs
+            assert!(storage.exists_in_archive("rustdoc/foo/1.0.0.zip", 0, "foo/index.html")?);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn delete_blobs_of_deleted_releases() {
+        wrapper(|env| {
+            env.override_config(|config| config.deduplicate_archives = true);
+            for version in ["1.0.0", "2.0.0"] {
+                env.fake_release()
+                    .name("foo")
+                    .version(version)
+                    .archive_storage(true)
+                    .source_file("src/shared.rs", b"shared")
+                    .source_file("src/version.rs", version.as_bytes())
+                    .create()?;
+            }
+            let blob_of = |content: &str| content_blob_path(&hex::encode(Sha256::digest(content)));
+
+            crate::db::delete_version(
+                &mut env.db().conn(),
+                &env.storage(),
+                &env.config(),
+                None,
+                "foo",
+                "1.0.0",
+            )?;
+            // the blob is only referenced by the deleted release
+            assert!(env.storage().exists(&blob_of("1.0.0"))?);
+
+            let deleted = env.runtime().block_on(async {
+                let mut conn = env.async_db().await.async_conn().await;
+                delete_orphaned_blobs(&mut conn, &*env.async_storage().await).await
+            })?;
+            assert!(deleted >= 1);
+            assert!(!env.storage().exists(&blob_of("1.0.0"))?);
+            assert!(env.storage().exists(&blob_of("shared"))?);
+
+            let archive_path = source_archive_path("foo", "2.0.0");
+            for (path, content) in [("src/shared.rs", "shared"), ("src/version.rs", "2.0.0")] {
+                let blob =
+                    env.storage()
+                        .get_from_archive(&archive_path, 0, path, std::usize::MAX)?;
+                assert_eq!(blob.content, content.as_bytes());
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,351 @@
+use super::{Blob, CompressionAlgorithm, FileRange, PathNotFoundError, StreamingBlob};
+use crate::{error::Result, utils::spawn_blocking, Config, InstanceMetrics};
+use anyhow::{anyhow, Context as _};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, Stream};
+use path_slash::PathExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tempfile::NamedTempFile;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Most filesystems don't allow longer file names, longer path components can't be stored.
+const MAX_FILE_NAME_LENGTH: usize = 255;
+
+/// Everything about a blob except its content, stored next to it.
+#[derive(Debug, Serialize, Deserialize)]
+struct Metadata {
+    mime: String,
+    compression: Option<CompressionAlgorithm>,
+    #[serde(default)]
+    public: bool,
+}
+
+/// Stores the blobs as files below a root directory:
+///
+/// * `files/<path>` has the content of the blob,
+/// * `metadata/<path>` has its mime type, compression and public access flag as JSON,
+/// * `tmp/` has the files being written, which are moved in place when they're complete.
+pub(super) struct FilesystemBackend {
+    root: PathBuf,
+    metrics: Arc<InstanceMetrics>,
+}
+
+impl FilesystemBackend {
+    pub(super) async fn new(metrics: Arc<InstanceMetrics>, config: &Config) -> Result<Self> {
+        let root = config.storage_filesystem_root.clone();
+        let backend = Self { root, metrics };
+
+        for dir in [
+            backend.files_dir(),
+            backend.metadata_dir(),
+            backend.tmp_dir(),
+        ] {
+            spawn_blocking(move || {
+                fs::create_dir_all(&dir)
+                    .with_context(|| format!("failed to create {}", dir.display()))
+            })
+            .await?;
+        }
+
+        Ok(backend)
+    }
+
+    fn files_dir(&self) -> PathBuf {
+        self.root.join("files")
+    }
+
+    fn metadata_dir(&self) -> PathBuf {
+        self.root.join("metadata")
+    }
+
+    fn tmp_dir(&self) -> PathBuf {
+        self.root.join("tmp")
+    }
+
+    pub(super) async fn exists(&self, path: &str) -> Result<bool> {
+        let Some(path) = relative_path(path) else {
+            return Ok(false);
+        };
+        let file = self.files_dir().join(path);
+        spawn_blocking(move || Ok(file.is_file())).await
+    }
+
+    pub(super) async fn get_public_access(&self, path: &str) -> Result<bool> {
+        let path = relative_path(path).ok_or(PathNotFoundError)?;
+        let metadata_file = self.metadata_dir().join(path);
+        spawn_blocking(move || Ok(read_metadata(&metadata_file)?.public)).await
+    }
+
+    pub(super) async fn set_public_access(&self, path: &str, public: bool) -> Result<()> {
+        let path = relative_path(path).ok_or(PathNotFoundError)?;
+        let metadata_file = self.metadata_dir().join(path);
+        let tmp_dir = self.tmp_dir();
+        spawn_blocking(move || {
+            let mut metadata = read_metadata(&metadata_file)?;
+            metadata.public = public;
+            write_atomically(&tmp_dir, &metadata_file, &serde_json::to_vec(&metadata)?)
+        })
+        .await
+    }
+
+    pub(super) async fn get(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<FileRange>,
+    ) -> Result<Blob> {
+        self.get_stream(path, max_size, range)
+            .await?
+            .materialize(max_size)
+            .await
+    }
+
+    pub(super) async fn get_stream(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<FileRange>,
+    ) -> Result<StreamingBlob> {
+        let relative = relative_path(path).ok_or(PathNotFoundError)?;
+        let metadata_file = self.metadata_dir().join(&relative);
+        let content_file = self.files_dir().join(&relative);
+
+        let metadata = spawn_blocking(move || read_metadata(&metadata_file)).await?;
+        let mut file = tokio::fs::File::open(&content_file)
+            .await
+            .map_err(not_found)?;
+        let file_metadata = file.metadata().await?;
+
+        let len = match &range {
+            Some(range) => range.end() - range.start() + 1,
+            None => file_metadata.len(),
+        };
+        if len > max_size as u64 {
+            return Err(
+                io::Error::new(io::ErrorKind::Other, crate::error::SizeLimitReached).into(),
+            );
+        }
+
+        if let Some(range) = &range {
+            file.seek(SeekFrom::Start(*range.start())).await?;
+        }
+
+        Ok(StreamingBlob {
+            path: path.to_owned(),
+            mime: metadata.mime,
+            date_updated: DateTime::<Utc>::from(file_metadata.modified()?),
+            content: Box::pin(tokio::io::BufReader::new(file.take(len))),
+            compression: metadata.compression,
+        })
+    }
+
+    pub(super) async fn store_batch(&self, batch: Vec<Blob>) -> Result<()> {
+        let files_dir = self.files_dir();
+        let metadata_dir = self.metadata_dir();
+        let tmp_dir = self.tmp_dir();
+        let metrics = self.metrics.clone();
+
+        spawn_blocking(move || {
+            for blob in batch {
+                let relative = relative_path(&blob.path)
+                    .ok_or_else(|| anyhow!("invalid storage path {:?}", blob.path))?;
+                let metadata_file = metadata_dir.join(&relative);
+
+                // like in the other backends, storing a blob again keeps its public access flag
+                let public = match read_metadata(&metadata_file) {
+                    Ok(metadata) => metadata.public,
+                    Err(err) if err.is::<PathNotFoundError>() => false,
+                    Err(err) => return Err(err),
+                };
+                let metadata = Metadata {
+                    mime: blob.mime,
+                    compression: blob.compression,
+                    public,
+                };
+
+                write_atomically(&tmp_dir, &metadata_file, &serde_json::to_vec(&metadata)?)?;
+                write_atomically(&tmp_dir, &files_dir.join(&relative), &blob.content)?;
+                metrics.uploaded_files_total.inc();
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    pub(super) async fn list_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Stream<Item = Result<String>> + 'a {
+        let files_dir = self.files_dir();
+        let owned_prefix = prefix.to_owned();
+
+        let items = match spawn_blocking(move || list_files(&files_dir, &owned_prefix)).await {
+            Ok(paths) => paths.into_iter().map(Ok).collect(),
+            Err(err) => vec![Err(err)],
+        };
+        stream::iter(items)
+    }
+
+    pub(super) async fn delete_prefix(&self, prefix: &str) -> Result<()> {
+        let files_dir = self.files_dir();
+        let metadata_dir = self.metadata_dir();
+        let prefix = prefix.to_owned();
+
+        spawn_blocking(move || {
+            for path in list_files(&files_dir, &prefix)? {
+                let relative =
+                    relative_path(&path).ok_or_else(|| anyhow!("invalid storage path {path:?}"))?;
+                for dir in [&files_dir, &metadata_dir] {
+                    let file = dir.join(&relative);
+                    match fs::remove_file(&file) {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                        Err(err) => {
+                            return Err(anyhow::Error::from(err)
+                                .context(format!("failed to delete {}", file.display())))
+                        }
+                    }
+                    remove_empty_parents(dir, &file);
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    #[cfg(test)]
+    pub(super) async fn cleanup_after_test(&self) -> Result<()> {
+        let root = self.root.clone();
+        spawn_blocking(move || {
+            if root.exists() {
+                fs::remove_dir_all(&root)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// The path of a blob relative to the directories of the backend, or `None` if we can't store a
+/// file for it, and thus can't have a blob with that path.
+///
+/// Blob paths can have components we can't use as file names, like the empty one before the
+/// leading slash of `/rustdoc-static/`. These are escaped with a `%` prefix, which is also added
+/// to components already starting with one, so [`blob_path`] can reverse it.
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.split('/') {
+        let component = if component.is_empty()
+            || component == "."
+            || component == ".."
+            || component.starts_with('%')
+        {
+            format!("%{component}")
+        } else {
+            component.to_owned()
+        };
+        if component.contains('\0') || component.len() > MAX_FILE_NAME_LENGTH {
+            return None;
+        }
+        relative.push(component);
+    }
+    Some(relative)
+}
+
+/// The path of the blob stored in `relative`, reversing the escaping of [`relative_path`].
+fn blob_path(relative: &Path) -> Option<String> {
+    let path = relative.to_slash()?;
+    Some(
+        path.split('/')
+            .map(|component| component.strip_prefix('%').unwrap_or(component))
+            .collect::<Vec<_>>()
+            .join("/"),
+    )
+}
+
+fn not_found(err: io::Error) -> anyhow::Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        PathNotFoundError.into()
+    } else {
+        err.into()
+    }
+}
+
+fn read_metadata(metadata_file: &Path) -> Result<Metadata> {
+    let content = fs::read(metadata_file).map_err(not_found)?;
+    serde_json::from_slice(&content)
+        .with_context(|| format!("invalid blob metadata in {}", metadata_file.display()))
+}
+
+/// Writes the file in the temporary directory first, so readers never see a partial file.
+fn write_atomically(tmp_dir: &Path, target: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+
+    let mut file = NamedTempFile::new_in(tmp_dir)?;
+    file.write_all(content)?;
+    file.persist(target)
+        .with_context(|| format!("failed to write {}", target.display()))?;
+    Ok(())
+}
+
+/// Paths of all the stored blobs starting with `prefix`, sorted like the other backends sort them.
+fn list_files(files_dir: &Path, prefix: &str) -> Result<Vec<String>> {
+    // only the directory the prefix ends in has to be searched
+    let dir = match prefix.rsplit_once('/') {
+        Some((dir, _)) => match relative_path(dir) {
+            Some(dir) => files_dir.join(dir),
+            None => return Ok(Vec::new()),
+        },
+        None => files_dir.to_path_buf(),
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths = Vec::new();
+    for entry in walkdir::WalkDir::new(&dir) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // deleted while we were listing the directory
+            Err(err) if err.io_error().map(io::Error::kind) == Some(io::ErrorKind::NotFound) => {
+                continue
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let path = blob_path(entry.path().strip_prefix(files_dir)?);
+        if let Some(path) = path.filter(|path| path.starts_with(prefix)) {
+            paths.push(path);
+        }
+    }
+
+    paths.sort_unstable();
+    Ok(paths)
+}
+
+/// Removes the directories left empty after deleting `file`, up to `root`.
+fn remove_empty_parents(root: &Path, file: &Path) {
+    let mut dir = file.parent();
+    while let Some(current) = dir.filter(|&dir| dir != root && dir.starts_with(root)) {
+        // fails when the directory isn't empty, which is where we stop
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+// The tests for this module are in src/storage/mod.rs, as part of the backend tests. Please add
+// any test checking the public interface there.
@@ -0,0 +1,475 @@
+//! An in-memory cache of the files we load from the storage, in front of the backends.
+//!
+//! The files are cached as they are stored, still compressed. Concurrent requests for a file
+//! that isn't cached yet share a single load from the backend. Streamed files are only cached
+//! when they're small enough, bigger ones are streamed from the backend without keeping them in
+//! memory.
+//!
+//! Other processes change the stored files without telling us, so the cached files expire after
+//! a while. Changes made through the same [`AsyncStorage`](super::AsyncStorage) remove the
+//! changed files from the cache right away.
+
+use super::{Blob, FileRange};
+use crate::{error::Result, InstanceMetrics};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::OnceCell;
+
+/// A single file can take at most this fraction of the cache, so a few big files don't push out
+/// all the others.
+const MAX_ENTRY_SIZE_DIVISOR: usize = 8;
+
+/// A range of an archive, from the build that stored the archive.
+///
+/// Rebuilds replace the archive, with its files at other positions, and other processes don't
+/// tell us when they do that.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) struct BuildRange {
+    pub(super) build_id: i32,
+    pub(super) range: FileRange,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    path: String,
+    range: Option<BuildRange>,
+}
+
+struct Entry {
+    blob: Blob,
+    inserted: Instant,
+    /// Position of the entry in [`State::lru`].
+    tick: u64,
+}
+
+#[derive(Default)]
+struct State {
+    /// The entries by path, so we find all the ranges of a file we cached when it changes.
+    entries: HashMap<String, HashMap<Option<BuildRange>, Entry>>,
+    /// The keys of the entries, least recently used first.
+    lru: BTreeMap<u64, Key>,
+    next_tick: u64,
+    total_size: usize,
+}
+
+impl State {
+    fn get(&mut self, key: &Key, ttl: Duration, now: Instant) -> Option<Blob> {
+        let ranges = self.entries.get_mut(&key.path)?;
+        let entry = ranges.get_mut(&key.range)?;
+
+        if now.duration_since(entry.inserted) >= ttl {
+            self.remove(key);
+            return None;
+        }
+
+        self.lru.remove(&entry.tick);
+        entry.tick = self.next_tick;
+        self.next_tick += 1;
+        self.lru.insert(entry.tick, key.clone());
+        Some(entry.blob.clone())
+    }
+
+    fn insert(&mut self, key: Key, blob: Blob, max_size: usize, now: Instant) {
+        self.remove(&key);
+
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.total_size += blob.content.len();
+        self.lru.insert(tick, key.clone());
+        self.entries.entry(key.path).or_default().insert(
+            key.range,
+            Entry {
+                blob,
+                inserted: now,
+                tick,
+            },
+        );
+
+        while self.total_size > max_size {
+            let Some((_, key)) = self.lru.pop_first() else {
+                break;
+            };
+            self.remove(&key);
+        }
+    }
+
+    fn remove(&mut self, key: &Key) {
+        let Some(ranges) = self.entries.get_mut(&key.path) else {
+            return;
+        };
+        if let Some(entry) = ranges.remove(&key.range) {
+            self.lru.remove(&entry.tick);
+            self.total_size -= entry.blob.content.len();
+        }
+        if ranges.is_empty() {
+            self.entries.remove(&key.path);
+        }
+    }
+
+    /// Removes all the cached ranges of the file at `path`.
+    fn remove_path(&mut self, path: &str) {
+        if let Some(ranges) = self.entries.remove(path) {
+            for entry in ranges.into_values() {
+                self.lru.remove(&entry.tick);
+                self.total_size -= entry.blob.content.len();
+            }
+        }
+    }
+}
+
+pub(super) struct MemoryCache {
+    max_size: usize,
+    ttl: Duration,
+    metrics: Arc<InstanceMetrics>,
+    state: Mutex<State>,
+    /// The loads from the backend that are running right now.
+    in_flight: Mutex<HashMap<Key, Arc<OnceCell<Blob>>>>,
+}
+
+impl MemoryCache {
+    /// A `max_size` of zero disables the cache. Concurrent loads are still shared then.
+    pub(super) fn new(max_size: usize, ttl: Duration, metrics: Arc<InstanceMetrics>) -> Self {
+        Self {
+            max_size,
+            ttl,
+            metrics,
+            state: Mutex::default(),
+            in_flight: Mutex::default(),
+        }
+    }
+
+    /// The size of the biggest file we cache, zero when the cache is disabled.
+    pub(super) fn max_entry_size(&self) -> usize {
+        self.max_size / MAX_ENTRY_SIZE_DIVISOR
+    }
+
+    /// Returns the cached file at `path`, or only the `range` of it, or loads it with `load`
+    /// and caches it.
+    ///
+    /// `load` has to fail when the file is bigger than `max_size`.
+    pub(super) async fn get_or_load<F, Fut>(
+        &self,
+        path: &str,
+        range: Option<BuildRange>,
+        max_size: usize,
+        load: F,
+    ) -> Result<Blob>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Blob>>,
+    {
+        if let Some(blob) = self.get(path, range.clone(), max_size)? {
+            return Ok(blob);
+        }
+
+        let key = Key {
+            path: path.to_owned(),
+            range,
+        };
+        let (blob, loaded) = self.load_once(&key, max_size, load).await?;
+        if loaded {
+            self.insert(key, blob.clone());
+        }
+        Ok(blob)
+    }
+
+    /// Returns the cached file at `path`, or only the `range` of it, without loading it when
+    /// it isn't cached.
+    pub(super) fn get(
+        &self,
+        path: &str,
+        range: Option<BuildRange>,
+        max_size: usize,
+    ) -> Result<Option<Blob>> {
+        if self.max_size > 0 {
+            let key = Key {
+                path: path.to_owned(),
+                range,
+            };
+            let cached = self
+                .state
+                .lock()
+                .unwrap()
+                .get(&key, self.ttl, Instant::now());
+            if let Some(blob) = cached {
+                self.metrics.storage_memory_cache_hits_total.inc();
+                return check_size(blob, max_size).map(Some);
+            }
+        }
+        self.metrics.storage_memory_cache_misses_total.inc();
+        Ok(None)
+    }
+
+    /// Caches a file we loaded without [`MemoryCache::get_or_load`], unless it's too big.
+    pub(super) fn insert_loaded(&self, path: &str, range: Option<BuildRange>, blob: Blob) {
+        self.insert(
+            Key {
+                path: path.to_owned(),
+                range,
+            },
+            blob,
+        );
+    }
+
+    fn insert(&self, key: Key, blob: Blob) {
+        if self.max_size > 0 && blob.content.len() <= self.max_entry_size() {
+            self.state
+                .lock()
+                .unwrap()
+                .insert(key, blob, self.max_size, Instant::now());
+        }
+    }
+
+    /// Loads the file at `path` with `load`, sharing the load with concurrent requests for it,
+    /// without caching it.
+    pub(super) async fn load_uncached<F, Fut>(
+        &self,
+        path: &str,
+        max_size: usize,
+        load: F,
+    ) -> Result<Blob>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Blob>>,
+    {
+        let key = Key {
+            path: path.to_owned(),
+            range: None,
+        };
+        Ok(self.load_once(&key, max_size, load).await?.0)
+    }
+
+    /// Waits for a running load of the same file, or loads it with `load` when there's none.
+    ///
+    /// Failed loads aren't shared, the next waiting request tries again with its own `load`.
+    /// Returns whether the file was loaded by this request.
+    async fn load_once<F, Fut>(&self, key: &Key, max_size: usize, load: F) -> Result<(Blob, bool)>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Blob>>,
+    {
+        let cell = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let _guard = InFlightGuard {
+            in_flight: &self.in_flight,
+            key,
+            cell: &cell,
+        };
+
+        let mut loaded = false;
+        let blob = cell
+            .get_or_try_init(|| {
+                loaded = true;
+                load()
+            })
+            .await?
+            .clone();
+
+        if loaded {
+            Ok((blob, true))
+        } else {
+            self.metrics.storage_memory_cache_coalesced_total.inc();
+            // the request that loaded it might allow bigger files
+            Ok((check_size(blob, max_size)?, false))
+        }
+    }
+
+    /// Removes the cached ranges of the files at `paths`, after they were stored again.
+    pub(super) fn invalidate<'a>(&self, paths: impl IntoIterator<Item = &'a str>) {
+        let mut state = self.state.lock().unwrap();
+        for path in paths {
+            state.remove_path(path);
+        }
+    }
+
+    /// Removes all the cached files starting with `prefix`, after they were deleted.
+    pub(super) fn invalidate_prefix(&self, prefix: &str) {
+        let mut state = self.state.lock().unwrap();
+        let paths: Vec<_> = state
+            .entries
+            .keys()
+            .filter(|path| path.starts_with(prefix))
+            .cloned()
+            .collect();
+        for path in paths {
+            state.remove_path(&path);
+        }
+    }
+}
+
+/// Removes a finished (or cancelled) load from the running loads, so the next request for the
+/// file uses the cache or starts a new load.
+struct InFlightGuard<'a> {
+    in_flight: &'a Mutex<HashMap<Key, Arc<OnceCell<Blob>>>>,
+    key: &'a Key,
+    cell: &'a Arc<OnceCell<Blob>>,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        // a later load might have replaced ours already
+        if in_flight
+            .get(self.key)
+            .is_some_and(|cell| Arc::ptr_eq(cell, self.cell))
+        {
+            in_flight.remove(self.key);
+        }
+    }
+}
+
+fn check_size(blob: Blob, max_size: usize) -> Result<Blob> {
+    if blob.content.len() > max_size {
+        return Err(io::Error::new(io::ErrorKind::Other, crate::error::SizeLimitReached).into());
+    }
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn blob(path: &str, content: &[u8]) -> Blob {
+        Blob {
+            path: path.into(),
+            mime: "text/plain".into(),
+            date_updated: Utc::now(),
+            content: content.to_vec(),
+            compression: None,
+        }
+    }
+
+    fn cache(max_size: usize) -> MemoryCache {
+        MemoryCache::new(
+            max_size,
+            Duration::from_secs(60),
+            Arc::new(InstanceMetrics::new().unwrap()),
+        )
+    }
+
+    #[test]
+    fn evicts_least_recently_used_entries() {
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+        let key = |path: &str| Key {
+            path: path.into(),
+            range: None,
+        };
+
+        let mut state = State::default();
+        state.insert(key("a"), blob("a", b"aaaa"), 10, now);
+        state.insert(key("b"), blob("b", b"bbbb"), 10, now);
+        assert!(state.get(&key("a"), ttl, now).is_some());
+
+        state.insert(key("c"), blob("c", b"cccc"), 10, now);
+        assert!(state.get(&key("b"), ttl, now).is_none());
+        assert!(state.get(&key("a"), ttl, now).is_some());
+        assert!(state.get(&key("c"), ttl, now).is_some());
+        assert_eq!(state.total_size, 8);
+
+        assert!(state.get(&key("a"), ttl, now + ttl).is_none());
+        assert_eq!(state.total_size, 4);
+    }
+
+    #[test]
+    fn invalidates_all_ranges_of_a_file() {
+        let cache = cache(1024);
+        let mut state = cache.state.lock().unwrap();
+        for range in [None, Some(0..=1), Some(2..=3)] {
+            let key = Key {
+                path: "archive.zip".into(),
+                range: range.map(|range| BuildRange { build_id: 1, range }),
+            };
+            state.insert(key, blob("archive.zip", b"ab"), 1024, Instant::now());
+        }
+        state.insert(
+            Key {
+                path: "other.zip".into(),
+                range: None,
+            },
+            blob("other.zip", b"ab"),
+            1024,
+            Instant::now(),
+        );
+        drop(state);
+
+        cache.invalidate(["archive.zip"]);
+        let state = cache.state.lock().unwrap();
+        assert_eq!(state.entries.len(), 1);
+        assert_eq!(state.lru.len(), 1);
+        assert_eq!(state.total_size, 2);
+    }
+
+    #[tokio::test]
+    async fn caches_loaded_files() -> Result<()> {
+        let cache = cache(1024);
+        let loads = &AtomicUsize::new(0);
+        let load = move || async move {
+            loads.fetch_add(1, Ordering::SeqCst);
+            Ok(blob("a.txt", b"content"))
+        };
+
+        for _ in 0..3 {
+            let blob = cache.get_or_load("a.txt", None, 1024, load).await?;
+            assert_eq!(blob.content, b"content");
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.metrics.storage_memory_cache_hits_total.get(), 2);
+        assert_eq!(cache.metrics.storage_memory_cache_misses_total.get(), 1);
+
+        // the cached file is bigger than this request allows
+        assert!(cache.get_or_load("a.txt", None, 3, load).await.is_err());
+
+        cache.invalidate(["a.txt"]);
+        cache.get_or_load("a.txt", None, 1024, load).await?;
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn coalesces_concurrent_loads() -> Result<()> {
+        let cache = cache(0);
+        let loads = &AtomicUsize::new(0);
+        let (sender, receiver) = tokio::sync::watch::channel(false);
+        let receiver = &receiver;
+        let load = move || {
+            let mut receiver = receiver.clone();
+            async move {
+                loads.fetch_add(1, Ordering::SeqCst);
+                receiver.wait_for(|done| *done).await?;
+                Ok(blob("a.txt", b"content"))
+            }
+        };
+
+        let requests = futures_util::future::join_all(
+            (0..5).map(|_| cache.get_or_load("a.txt", None, 1024, load)),
+        );
+        let release = async {
+            tokio::task::yield_now().await;
+            sender.send(true).unwrap();
+        };
+        let (results, ()) = tokio::join!(requests, release);
+
+        for result in results {
+            assert_eq!(result?.content, b"content");
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.metrics.storage_memory_cache_coalesced_total.get(), 4);
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+
+        Ok(())
+    }
+}
@@ -1,35 +1,64 @@
 mod archive_index;
+mod archive_index_cache;
 mod compression;
 mod database;
+mod dedup;
+mod filesystem;
+mod memory_cache;
+mod repack;
 mod s3;
+mod verify;
 
+use self::archive_index::ArchiveKind;
+pub use self::archive_index_cache::{ArchiveIndexCache, ArchiveIndexCacheInfo};
 pub use self::compression::{compress, decompress, CompressionAlgorithm, CompressionAlgorithms};
 use self::database::DatabaseBackend;
+pub use self::dedup::{deduplicate_archives, delete_orphaned_blobs};
+use self::filesystem::FilesystemBackend;
+use self::memory_cache::{BuildRange, MemoryCache};
+pub use self::repack::repack_archives;
 use self::s3::S3Backend;
+pub(crate) use self::verify::{record_archive_checksums, ArchiveChecksums};
+pub use self::verify::{verify_archives, ArchiveProblem, CorruptedRelease};
 use crate::{db::Pool, error::Result, utils::spawn_blocking, Config, InstanceMetrics};
 use anyhow::{anyhow, ensure};
+use async_compression::tokio::bufread::{BzDecoder, ZstdDecoder};
 use chrono::{DateTime, Utc};
 use fn_error_context::context;
 use futures_util::stream::BoxStream;
 use path_slash::PathExt;
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
     ffi::OsStr,
     fmt, fs,
     io::{self, BufReader},
     ops::RangeInclusive,
     path::{Path, PathBuf},
-    sync::Arc,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncReadExt, AsyncWriteExt},
+    runtime::Runtime,
+    sync::OnceCell,
 };
-use tokio::{io::AsyncWriteExt, runtime::Runtime};
 use tracing::{error, info_span, instrument, trace};
 
 type FileRange = RangeInclusive<u64>;
 
+/// Number of releases we load from the database at once, when we go through all of them.
+const RELEASES_PER_BATCH: i64 = 100;
+
 #[derive(Debug, thiserror::Error)]
 #[error("path not found")]
 pub(crate) struct PathNotFoundError;
 
+/// The archive changed since we cached its index.
+#[derive(Debug, thiserror::Error)]
+#[error("stale archive index")]
+struct StaleArchiveIndexError;
+
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub(crate) struct Blob {
     pub(crate) path: String,
@@ -45,6 +74,79 @@ impl Blob {
     }
 }
 
+/// A [`Blob`] whose content is read from the storage while it's used.
+pub(crate) struct StreamingBlob {
+    pub(crate) path: String,
+    pub(crate) mime: String,
+    pub(crate) date_updated: DateTime<Utc>,
+    pub(crate) content: Pin<Box<dyn AsyncBufRead + Send>>,
+    pub(crate) compression: Option<CompressionAlgorithm>,
+}
+
+impl fmt::Debug for StreamingBlob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamingBlob")
+            .field("path", &self.path)
+            .field("mime", &self.mime)
+            .field("date_updated", &self.date_updated)
+            .field("compression", &self.compression)
+            .finish()
+    }
+}
+
+impl StreamingBlob {
+    /// Decompresses the content while it's read.
+    fn decompress(mut self) -> Self {
+        let Some(alg) = self.compression else {
+            return self;
+        };
+        self.content = match alg {
+            CompressionAlgorithm::Zstd => {
+                Box::pin(tokio::io::BufReader::new(ZstdDecoder::new(self.content)))
+            }
+            CompressionAlgorithm::Bzip2 => {
+                Box::pin(tokio::io::BufReader::new(BzDecoder::new(self.content)))
+            }
+        };
+        self.compression = None;
+        self
+    }
+
+    /// Reads the whole content into memory, failing when it's bigger than `max_size`.
+    pub(crate) async fn materialize(mut self, max_size: usize) -> Result<Blob> {
+        let mut content = Vec::new();
+        (&mut self.content)
+            .take((max_size as u64).saturating_add(1))
+            .read_to_end(&mut content)
+            .await?;
+        if content.len() > max_size {
+            return Err(
+                io::Error::new(io::ErrorKind::Other, crate::error::SizeLimitReached).into(),
+            );
+        }
+
+        Ok(Blob {
+            path: self.path,
+            mime: self.mime,
+            date_updated: self.date_updated,
+            content,
+            compression: self.compression,
+        })
+    }
+}
+
+impl From<Blob> for StreamingBlob {
+    fn from(blob: Blob) -> Self {
+        Self {
+            path: blob.path,
+            mime: blob.mime,
+            date_updated: blob.date_updated,
+            content: Box::pin(io::Cursor::new(blob.content)),
+            compression: blob.compression,
+        }
+    }
+}
+
 fn get_file_list_from_dir<P: AsRef<Path>>(path: P, files: &mut Vec<PathBuf>) -> Result<()> {
     let path = path.as_ref();
 
@@ -88,6 +190,7 @@ pub(crate) struct InvalidStorageBackendError;
 #[derive(Debug)]
 pub(crate) enum StorageKind {
     Database,
+    Filesystem,
     S3,
 }
 
@@ -97,6 +200,7 @@ impl std::str::FromStr for StorageKind {
     fn from_str(input: &str) -> Result<Self, Self::Err> {
         match input {
             "database" => Ok(StorageKind::Database),
+            "filesystem" => Ok(StorageKind::Filesystem),
             "s3" => Ok(StorageKind::S3),
             _ => Err(InvalidStorageBackendError),
         }
@@ -105,12 +209,19 @@ impl std::str::FromStr for StorageKind {
 
 enum StorageBackend {
     Database(DatabaseBackend),
+    Filesystem(FilesystemBackend),
     S3(Box<S3Backend>),
 }
 
 pub struct AsyncStorage {
     backend: StorageBackend,
     config: Arc<Config>,
+    metrics: Arc<InstanceMetrics>,
+    archive_index_cache: ArchiveIndexCache,
+    memory_cache: MemoryCache,
+    /// The ZIP files of deduplicated archives that are being restored right now, see
+    /// [`AsyncStorage::restore_archive_zip`].
+    restoring_archives: Mutex<HashMap<String, Arc<OnceCell<()>>>>,
 }
 
 impl AsyncStorage {
@@ -123,19 +234,40 @@ impl AsyncStorage {
             config: config.clone(),
             backend: match config.storage_backend {
                 StorageKind::Database => {
-                    StorageBackend::Database(DatabaseBackend::new(pool, metrics))
+                    StorageBackend::Database(DatabaseBackend::new(pool, metrics.clone()))
                 }
+                StorageKind::Filesystem => StorageBackend::Filesystem(
+                    FilesystemBackend::new(metrics.clone(), &config).await?,
+                ),
                 StorageKind::S3 => {
-                    StorageBackend::S3(Box::new(S3Backend::new(metrics, &config).await?))
+                    StorageBackend::S3(Box::new(S3Backend::new(metrics.clone(), &config).await?))
                 }
             },
+            archive_index_cache: ArchiveIndexCache::new(
+                config.local_archive_cache_path.clone(),
+                config.local_archive_cache_max_size,
+                metrics.clone(),
+            ),
+            memory_cache: MemoryCache::new(
+                config.storage_memory_cache_size,
+                config.storage_memory_cache_ttl,
+                metrics.clone(),
+            ),
+            restoring_archives: Mutex::default(),
+            metrics,
         })
     }
 
+    /// The local cache of the archive indexes, see [`AsyncStorage::download_archive_index`].
+    pub fn archive_index_cache(&self) -> &ArchiveIndexCache {
+        &self.archive_index_cache
+    }
+
     #[instrument]
     pub(crate) async fn exists(&self, path: &str) -> Result<bool> {
         match &self.backend {
             StorageBackend::Database(db) => db.exists(path).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.exists(path).await,
             StorageBackend::S3(s3) => s3.exists(path).await,
         }
     }
@@ -144,6 +276,7 @@ impl AsyncStorage {
     pub(crate) async fn get_public_access(&self, path: &str) -> Result<bool> {
         match &self.backend {
             StorageBackend::Database(db) => db.get_public_access(path).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.get_public_access(path).await,
             StorageBackend::S3(s3) => s3.get_public_access(path).await,
         }
     }
@@ -152,6 +285,9 @@ impl AsyncStorage {
     pub(crate) async fn set_public_access(&self, path: &str, public: bool) -> Result<()> {
         match &self.backend {
             StorageBackend::Database(db) => db.set_public_access(path, public).await,
+            StorageBackend::Filesystem(filesystem) => {
+                filesystem.set_public_access(path, public).await
+            }
             StorageBackend::S3(s3) => s3.set_public_access(path, public).await,
         }
     }
@@ -173,6 +309,8 @@ impl AsyncStorage {
     /// * `path` - the wanted path inside the documentation.
     /// * `archive_storage` - if `true`, we will assume we have a remove ZIP archive and an index
     ///    where we can fetch the requested path from inside the ZIP file.
+    /// * `accepted` - the compression algorithms the client accepts, when the file is sent to a
+    ///   client as it is, see [`AsyncStorage::get_for_client`].
     #[instrument]
     pub(crate) async fn fetch_rustdoc_file(
         &self,
@@ -181,20 +319,50 @@ impl AsyncStorage {
         latest_build_id: i32,
         path: &str,
         archive_storage: bool,
+        accepted: Option<&CompressionAlgorithms>,
     ) -> Result<Blob> {
         trace!("fetch rustdoc file");
         Ok(if archive_storage {
-            self.get_from_archive(
+            self.get_from_archive_inner(
                 &rustdoc_archive_path(name, version),
                 latest_build_id,
                 path,
                 self.max_file_size_for(path),
+                accepted,
             )
             .await?
         } else {
             // Add rustdoc prefix, name and version to the path for accessing the file stored in the database
             let remote_path = format!("rustdoc/{name}/{version}/{path}");
-            self.get(&remote_path, self.max_file_size_for(path)).await?
+            self.get_inner(&remote_path, self.max_file_size_for(path), accepted)
+                .await?
+        })
+    }
+
+    /// Like [`AsyncStorage::fetch_rustdoc_file`], but the decompressed content is read while it's
+    /// used instead of being loaded into memory first.
+    #[instrument]
+    pub(crate) async fn stream_rustdoc_file(
+        &self,
+        name: &str,
+        version: &str,
+        latest_build_id: i32,
+        path: &str,
+        archive_storage: bool,
+    ) -> Result<StreamingBlob> {
+        trace!("stream rustdoc file");
+        Ok(if archive_storage {
+            self.stream_from_archive(
+                &rustdoc_archive_path(name, version),
+                latest_build_id,
+                path,
+                self.max_file_size_for(path),
+            )
+            .await?
+        } else {
+            let remote_path = format!("rustdoc/{name}/{version}/{path}");
+            self.get_stream(&remote_path, self.max_file_size_for(path))
+                .await?
         })
     }
 
@@ -251,10 +419,17 @@ impl AsyncStorage {
             .download_archive_index(archive_path, latest_build_id)
             .await
         {
-            Ok(index_filename) => Ok({
+            Ok((index_filename, kind)) => Ok({
                 let path = path.to_owned();
                 spawn_blocking(move || {
-                    Ok(archive_index::find_in_file(index_filename, &path)?.is_some())
+                    Ok(match kind {
+                        ArchiveKind::Zip => {
+                            archive_index::find_in_file(index_filename, &path)?.is_some()
+                        }
+                        ArchiveKind::Deduplicated => {
+                            archive_index::find_blob_in_file(index_filename, &path)?.is_some()
+                        }
+                    })
                 })
                 .await?
             }),
@@ -270,29 +445,164 @@ impl AsyncStorage {
 
     #[instrument]
     pub(crate) async fn get(&self, path: &str, max_size: usize) -> Result<Blob> {
-        let mut blob = match &self.backend {
-            StorageBackend::Database(db) => db.get(path, max_size, None).await,
-            StorageBackend::S3(s3) => s3.get(path, max_size, None).await,
-        }?;
-        if let Some(alg) = blob.compression {
-            blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
-            blob.compression = None;
+        self.get_inner(path, max_size, None).await
+    }
+
+    /// Like [`AsyncStorage::get`], for files we send to a client as they are. When the file is
+    /// compressed with one of the `accepted` algorithms, it stays compressed and
+    /// `blob.compression` tells the client how to decompress it.
+    #[instrument]
+    pub(crate) async fn get_for_client(
+        &self,
+        path: &str,
+        max_size: usize,
+        accepted: &CompressionAlgorithms,
+    ) -> Result<Blob> {
+        self.get_inner(path, max_size, Some(accepted)).await
+    }
+
+    async fn get_inner(
+        &self,
+        path: &str,
+        max_size: usize,
+        accepted: Option<&CompressionAlgorithms>,
+    ) -> Result<Blob> {
+        let blob = self.get_stored(path, max_size, None).await?;
+        self.decompress_unless_accepted(blob, max_size, accepted)
+    }
+
+    /// Loads the file, or a range of it, as it's stored, through the in-memory cache.
+    async fn get_stored(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<BuildRange>,
+    ) -> Result<Blob> {
+        self.memory_cache
+            .get_or_load(path, range.clone(), max_size, || {
+                self.get_from_backend(path, max_size, range.map(|range| range.range))
+            })
+            .await
+    }
+
+    /// Streams the file, or a range of it, as it's stored, through the in-memory cache.
+    ///
+    /// Files that aren't cached are only loaded into memory when they're small enough to be
+    /// cached, bigger ones are streamed from the backend. Concurrent loads aren't shared here.
+    async fn get_stored_stream(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<BuildRange>,
+    ) -> Result<StreamingBlob> {
+        if let Some(blob) = self.memory_cache.get(path, range.clone(), max_size)? {
+            return Ok(blob.into());
+        }
+
+        let mut blob = self
+            .get_stream_from_backend(path, max_size, range.clone().map(|range| range.range))
+            .await?;
+
+        let max_entry_size = self.memory_cache.max_entry_size();
+        if max_entry_size == 0 {
+            return Ok(blob);
+        }
+        let mut start = Vec::new();
+        (&mut blob.content)
+            .take(max_entry_size as u64 + 1)
+            .read_to_end(&mut start)
+            .await?;
+        if start.len() > max_entry_size {
+            blob.content = Box::pin(io::Cursor::new(start).chain(blob.content));
+            return Ok(blob);
+        }
+
+        let blob = Blob {
+            path: blob.path,
+            mime: blob.mime,
+            date_updated: blob.date_updated,
+            content: start,
+            compression: blob.compression,
+        };
+        self.memory_cache.insert_loaded(path, range, blob.clone());
+        Ok(blob.into())
+    }
+
+    async fn get_from_backend(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<FileRange>,
+    ) -> Result<Blob> {
+        match &self.backend {
+            StorageBackend::Database(db) => db.get(path, max_size, range).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.get(path, max_size, range).await,
+            StorageBackend::S3(s3) => s3.get(path, max_size, range).await,
+        }
+    }
+
+    async fn get_stream_from_backend(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<FileRange>,
+    ) -> Result<StreamingBlob> {
+        match &self.backend {
+            StorageBackend::Database(db) => db.get_stream(path, max_size, range).await,
+            StorageBackend::Filesystem(filesystem) => {
+                filesystem.get_stream(path, max_size, range).await
+            }
+            StorageBackend::S3(s3) => s3.get_stream(path, max_size, range).await,
+        }
+    }
+
+    /// Decompresses the content of the blob, unless the client accepts its compression.
+    ///
+    /// `accepted` is `None` when the content isn't sent to a client as it is, we only count the
+    /// bytes sent to clients in the metrics.
+    fn decompress_unless_accepted(
+        &self,
+        mut blob: Blob,
+        max_size: usize,
+        accepted: Option<&CompressionAlgorithms>,
+    ) -> Result<Blob> {
+        let Some(alg) = blob.compression else {
+            return Ok(blob);
+        };
+        let compressed_len = blob.content.len() as u64;
+
+        if accepted.is_some_and(|accepted| accepted.contains(&alg)) {
+            self.metrics
+                .compressed_bytes_passed_through_total
+                .inc_by(compressed_len);
+            return Ok(blob);
+        }
+
+        blob.content = decompress(blob.content.as_slice(), alg, max_size)?;
+        blob.compression = None;
+        if accepted.is_some() {
+            self.metrics
+                .compressed_bytes_decompressed_total
+                .inc_by(compressed_len);
         }
         Ok(blob)
     }
 
+    /// Loads a range of the archive at `path`, stored by the build `latest_build_id`.
     #[instrument]
     pub(super) async fn get_range(
         &self,
         path: &str,
+        latest_build_id: i32,
         max_size: usize,
         range: FileRange,
         compression: Option<CompressionAlgorithm>,
     ) -> Result<Blob> {
-        let mut blob = match &self.backend {
-            StorageBackend::Database(db) => db.get(path, max_size, Some(range)).await,
-            StorageBackend::S3(s3) => s3.get(path, max_size, Some(range)).await,
-        }?;
+        let range = BuildRange {
+            build_id: latest_build_id,
+            range,
+        };
+        let mut blob = self.get_stored(path, max_size, Some(range)).await?;
         // `compression` represents the compression of the file-stream inside the archive.
         // We don't compress the whole archive, so the encoding of the archive's blob is irrelevant
         // here.
@@ -303,41 +613,139 @@ impl AsyncStorage {
         Ok(blob)
     }
 
+    /// Like [`AsyncStorage::get`], but the decompressed content is read while it's used.
+    ///
+    /// `max_size` only limits the size of the stored file, since the decompressed content
+    /// doesn't have to fit in memory.
+    ///
+    /// Files that are too big for the in-memory cache are streamed from the backend.
+    #[instrument]
+    pub(crate) async fn get_stream(&self, path: &str, max_size: usize) -> Result<StreamingBlob> {
+        Ok(self
+            .get_stored_stream(path, max_size, None)
+            .await?
+            .decompress())
+    }
+
+    /// Streams a range of the archive at `path`, stored by the build `latest_build_id`, without
+    /// decompressing it.
+    async fn get_range_stream(
+        &self,
+        path: &str,
+        latest_build_id: i32,
+        max_size: usize,
+        range: FileRange,
+    ) -> Result<StreamingBlob> {
+        let range = BuildRange {
+            build_id: latest_build_id,
+            range,
+        };
+        self.get_stored_stream(path, max_size, Some(range)).await
+    }
+
+    /// Downloads the index of an archive into the local cache, unless it's cached already.
+    ///
+    /// Archives stored before we started deduplicating them only have a ZIP index, and
+    /// deduplicated archives only have their own index, so the kind of the index we find
+    /// tells us how the archive is stored.
+    ///
+    /// Downloading an index can evict the least recently used indexes from the cache.
     #[instrument]
     pub(super) async fn download_archive_index(
         &self,
         archive_path: &str,
         latest_build_id: i32,
-    ) -> Result<PathBuf> {
-        // remote/folder/and/x.zip.index
-        let remote_index_path = format!("{archive_path}.index");
-        let local_index_path = self
-            .config
-            .local_archive_cache_path
-            .join(format!("{archive_path}.{latest_build_id}.index"));
+    ) -> Result<(PathBuf, ArchiveKind)> {
+        // New archives are stored as the configured kind, so we look for its index first, to
+        // not send a request for the other kind for most archives we don't have cached.
+        let kinds = if self.config.deduplicate_archives {
+            [ArchiveKind::Deduplicated, ArchiveKind::Zip]
+        } else {
+            [ArchiveKind::Zip, ArchiveKind::Deduplicated]
+        };
+        let cache_path = &self.config.local_archive_cache_path;
+        let local_index_paths =
+            kinds.map(|kind| kind.local_index_path(cache_path, archive_path, latest_build_id));
 
-        if !local_index_path.exists() {
-            let index_content = self.get(&remote_index_path, std::usize::MAX).await?.content;
+        for (kind, local_index_path) in kinds.into_iter().zip(&local_index_paths) {
+            if self.archive_index_cache.touch(local_index_path).await? {
+                return Ok((local_index_path.clone(), kind));
+            }
+        }
 
-            tokio::fs::create_dir_all(
-                local_index_path
-                    .parent()
-                    .ok_or_else(|| anyhow!("index path without parent"))?,
+        let [first_kind, second_kind] = kinds;
+        let [first_index_path, second_index_path] = local_index_paths;
+        let (index_path, kind) = match self
+            .download_index_file(
+                &first_kind.remote_index_path(archive_path),
+                &first_index_path,
             )
+            .await
+        {
+            Ok(()) => (first_index_path, first_kind),
+            Err(err) if err.is::<PathNotFoundError>() => {
+                self.download_index_file(
+                    &second_kind.remote_index_path(archive_path),
+                    &second_index_path,
+                )
+                .await?;
+                (second_index_path, second_kind)
+            }
+            Err(err) => return Err(err),
+        };
+
+        self.archive_index_cache.insert(&index_path).await?;
+        Ok((index_path, kind))
+    }
+
+    async fn download_index_file(
+        &self,
+        remote_index_path: &str,
+        local_index_path: &Path,
+    ) -> Result<()> {
+        // The remote index is replaced after a rebuild, while the local index is specific to
+        // a build, so it can't come from the in-memory cache.
+        let index_content = self
+            .memory_cache
+            .load_uncached(remote_index_path, std::usize::MAX, || {
+                self.get_from_backend(remote_index_path, std::usize::MAX, None)
+            })
             .await?;
+        let index_content = self
+            .decompress_unless_accepted(index_content, std::usize::MAX, None)?
+            .content;
+
+        tokio::fs::create_dir_all(
+            local_index_path
+                .parent()
+                .ok_or_else(|| anyhow!("index path without parent"))?,
+        )
+        .await?;
 
-            // when we don't have a locally cached index and many parallel request
-            // we might download the same archive index multiple times here.
-            // So we're storing the content into a temporary file before renaming it
-            // into the final location.
-            let temp_path = tempfile::NamedTempFile::new_in(&self.config.local_archive_cache_path)?
-                .into_temp_path();
-            let mut file = tokio::fs::File::create(&temp_path).await?;
-            file.write_all(&index_content).await?;
-            tokio::fs::rename(temp_path, &local_index_path).await?;
-        }
+        // when we don't have a locally cached index and many parallel request
+        // we might download the same archive index multiple times here.
+        // So we're storing the content into a temporary file before renaming it
+        // into the final location.
+        let temp_path = tempfile::NamedTempFile::new_in(&self.config.local_archive_cache_path)?
+            .into_temp_path();
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        file.write_all(&index_content).await?;
+        tokio::fs::rename(temp_path, local_index_path).await?;
+
+        Ok(())
+    }
 
-        Ok(local_index_path)
+    /// Removes the cached index of an archive that doesn't match the archive anymore, see
+    /// [`AsyncStorage::get_from_archive`].
+    async fn remove_stale_archive_index(
+        &self,
+        index_filename: &Path,
+        err: anyhow::Error,
+    ) -> anyhow::Error {
+        // parallel requests might have removed it already
+        let _ = tokio::fs::remove_file(index_filename).await;
+        self.archive_index_cache.remove(index_filename);
+        err.context(StaleArchiveIndexError)
     }
 
     #[instrument]
@@ -348,41 +756,208 @@ impl AsyncStorage {
         path: &str,
         max_size: usize,
     ) -> Result<Blob> {
-        let index_filename = self
+        self.get_from_archive_inner(archive_path, latest_build_id, path, max_size, None)
+            .await
+    }
+
+    async fn get_from_archive_inner(
+        &self,
+        archive_path: &str,
+        latest_build_id: i32,
+        path: &str,
+        max_size: usize,
+        accepted: Option<&CompressionAlgorithms>,
+    ) -> Result<Blob> {
+        match self
+            .get_from_archive_index(archive_path, latest_build_id, path, max_size, accepted)
+            .await
+        {
+            // The ZIP file is gone when the archive was deduplicated after we cached its index, and
+            // the files are somewhere else when it was repacked. The cached index was removed, so
+            // this downloads the new one.
+            Err(err) if err.is::<StaleArchiveIndexError>() => {
+                self.get_from_archive_index(archive_path, latest_build_id, path, max_size, accepted)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    async fn get_from_archive_index(
+        &self,
+        archive_path: &str,
+        latest_build_id: i32,
+        path: &str,
+        max_size: usize,
+        accepted: Option<&CompressionAlgorithms>,
+    ) -> Result<Blob> {
+        let (index_filename, kind) = self
             .download_archive_index(archive_path, latest_build_id)
             .await?;
 
-        let info = {
-            let path = path.to_owned();
-            spawn_blocking(move || archive_index::find_in_file(index_filename, &path)).await
-        }?
-        .ok_or(PathNotFoundError)?;
-
-        let blob = self
-            .get_range(
-                archive_path,
-                max_size,
-                info.range(),
-                Some(info.compression()),
-            )
-            .await?;
-        assert_eq!(blob.compression, None);
+        let blob = match kind {
+            ArchiveKind::Zip => {
+                let info = {
+                    let index_filename = index_filename.clone();
+                    let path = path.to_owned();
+                    spawn_blocking(move || archive_index::find_in_file(index_filename, &path)).await
+                }?
+                .ok_or(PathNotFoundError)?;
+
+                let blob = match self
+                    .get_range(archive_path, latest_build_id, max_size, info.range(), None)
+                    .await
+                {
+                    Err(err) if err.is::<PathNotFoundError>() => {
+                        return Err(self.remove_stale_archive_index(&index_filename, err).await);
+                    }
+                    result => result?,
+                };
+
+                // `info.compression()` is the compression of the file-stream inside the archive.
+                // The archive isn't repacked when all its files are compressed with zstd already,
+                // so a client accepting zstd gets the file-stream from the current archive.
+                let blob = Blob {
+                    compression: Some(info.compression()),
+                    ..blob
+                };
+                match self.decompress_unless_accepted(blob, max_size, accepted) {
+                    Ok(blob) => blob,
+                    Err(err) if is_size_limit_error(&err) => return Err(err),
+                    Err(err) => {
+                        return Err(self.remove_stale_archive_index(&index_filename, err).await)
+                    }
+                }
+            }
+            ArchiveKind::Deduplicated => {
+                let hash = {
+                    let path = path.to_owned();
+                    spawn_blocking(move || archive_index::find_blob_in_file(index_filename, &path))
+                        .await
+                }?
+                .ok_or(PathNotFoundError)?;
+
+                self.get_inner(&dedup::content_blob_path(&hash), max_size, accepted)
+                    .await?
+            }
+        };
 
         Ok(Blob {
             path: format!("{archive_path}/{path}"),
             mime: detect_mime(path).into(),
             date_updated: blob.date_updated,
             content: blob.content,
-            compression: None,
+            compression: blob.compression,
         })
     }
 
+    /// Like [`AsyncStorage::get_from_archive`], but the decompressed content is read while it's
+    /// used, see [`AsyncStorage::get_stream`].
+    #[instrument]
+    pub(crate) async fn stream_from_archive(
+        &self,
+        archive_path: &str,
+        latest_build_id: i32,
+        path: &str,
+        max_size: usize,
+    ) -> Result<StreamingBlob> {
+        match self
+            .stream_from_archive_index(archive_path, latest_build_id, path, max_size)
+            .await
+        {
+            Err(err) if err.is::<StaleArchiveIndexError>() => {
+                self.stream_from_archive_index(archive_path, latest_build_id, path, max_size)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    async fn stream_from_archive_index(
+        &self,
+        archive_path: &str,
+        latest_build_id: i32,
+        path: &str,
+        max_size: usize,
+    ) -> Result<StreamingBlob> {
+        let (index_filename, kind) = self
+            .download_archive_index(archive_path, latest_build_id)
+            .await?;
+
+        let blob = match kind {
+            ArchiveKind::Zip => {
+                let info = {
+                    let index_filename = index_filename.clone();
+                    let path = path.to_owned();
+                    spawn_blocking(move || archive_index::find_in_file(index_filename, &path)).await
+                }?
+                .ok_or(PathNotFoundError)?;
+
+                // Archives with bzip2 compressed files are repacked, which moves the files in the
+                // archive. We'd only notice that a cached index is outdated after we started
+                // sending the file, so we load those files first.
+                if info.compression() == CompressionAlgorithm::Bzip2 {
+                    return Ok(self
+                        .get_from_archive_index(archive_path, latest_build_id, path, max_size, None)
+                        .await?
+                        .into());
+                }
+
+                let blob = match self
+                    .get_range_stream(archive_path, latest_build_id, max_size, info.range())
+                    .await
+                {
+                    Err(err) if err.is::<PathNotFoundError>() => {
+                        return Err(self.remove_stale_archive_index(&index_filename, err).await);
+                    }
+                    result => result?,
+                };
+                // `info.compression()` is the compression of the file-stream inside the archive.
+                StreamingBlob {
+                    compression: Some(info.compression()),
+                    ..blob
+                }
+                .decompress()
+            }
+            ArchiveKind::Deduplicated => {
+                let hash = {
+                    let path = path.to_owned();
+                    spawn_blocking(move || archive_index::find_blob_in_file(index_filename, &path))
+                        .await
+                }?
+                .ok_or(PathNotFoundError)?;
+
+                self.get_stream(&dedup::content_blob_path(&hash), max_size)
+                    .await?
+            }
+        };
+
+        Ok(StreamingBlob {
+            path: format!("{archive_path}/{path}"),
+            mime: detect_mime(path).into(),
+            ..blob
+        })
+    }
+
+    /// Stores the files in `root_dir` as archive, returns their mime types, the compression of
+    /// the files in the archive and the checksums of what we stored.
     #[instrument(skip(self))]
     pub(crate) async fn store_all_in_archive(
         &self,
         archive_path: &str,
         root_dir: &Path,
-    ) -> Result<(HashMap<PathBuf, String>, CompressionAlgorithm)> {
+    ) -> Result<(
+        HashMap<PathBuf, String>,
+        CompressionAlgorithm,
+        ArchiveChecksums,
+    )> {
+        if self.config.deduplicate_archives {
+            let (file_paths, _, checksums) = self
+                .store_deduplicated_archive(archive_path, root_dir)
+                .await?;
+            return Ok((file_paths, dedup::BLOB_COMPRESSION, checksums));
+        }
+
         let (zip_content, compressed_index_content, alg, remote_index_path, file_paths) =
             spawn_blocking({
                 let archive_path = archive_path.to_owned();
@@ -408,7 +983,7 @@ impl AsyncStorage {
                             info_span!("create_zip_archive", %archive_path, root_dir=%root_dir.display()).entered();
 
                         let options = zip::write::FileOptions::default()
-                            .compression_method(zip::CompressionMethod::Bzip2);
+                            .compression_method(zip::CompressionMethod::Zstd);
 
                         let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
                         for file_path in get_file_list(&root_dir)? {
@@ -428,16 +1003,7 @@ impl AsyncStorage {
                     let alg = CompressionAlgorithm::default();
                     let compressed_index_content = {
                         let _span = info_span!("create_archive_index", %remote_index_path).entered();
-
-                        fs::create_dir_all(&temp_dir)?;
-                        let local_index_path =
-                            tempfile::NamedTempFile::new_in(&temp_dir)?.into_temp_path();
-                        archive_index::create(
-                            &mut io::Cursor::new(&mut zip_content),
-                            &local_index_path,
-                        )?;
-
-                        compress(BufReader::new(fs::File::open(&local_index_path)?), alg)?
+                        create_compressed_archive_index(&temp_dir, &mut zip_content, alg)?
                     };
                     Ok((
                         zip_content,
@@ -450,6 +1016,7 @@ impl AsyncStorage {
             })
             .await?;
 
+        let checksums = ArchiveChecksums::new(Some(&zip_content), &compressed_index_content);
         self.store_inner(vec![
             Blob {
                 path: archive_path.to_string(),
@@ -468,8 +1035,8 @@ impl AsyncStorage {
         ])
         .await?;
 
-        let file_alg = CompressionAlgorithm::Bzip2;
-        Ok((file_paths, file_alg))
+        let file_alg = CompressionAlgorithm::Zstd;
+        Ok((file_paths, file_alg, checksums))
     }
 
     // Store all files in `root_dir` into the backend under `prefix`.
@@ -558,27 +1125,39 @@ impl AsyncStorage {
     }
 
     async fn store_inner(&self, batch: Vec<Blob>) -> Result<()> {
-        match &self.backend {
+        let paths: Vec<_> = batch.iter().map(|blob| blob.path.clone()).collect();
+        let result = match &self.backend {
             StorageBackend::Database(db) => db.store_batch(batch).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.store_batch(batch).await,
             StorageBackend::S3(s3) => s3.store_batch(batch).await,
-        }
+        };
+        // a failed batch might have been stored partially
+        self.memory_cache
+            .invalidate(paths.iter().map(String::as_str));
+        result
     }
 
-    pub(super) async fn list_prefix<'a>(
+    pub(crate) async fn list_prefix<'a>(
         &'a self,
         prefix: &'a str,
     ) -> BoxStream<'a, Result<String>> {
         match &self.backend {
             StorageBackend::Database(db) => Box::pin(db.list_prefix(prefix).await),
+            StorageBackend::Filesystem(filesystem) => {
+                Box::pin(filesystem.list_prefix(prefix).await)
+            }
             StorageBackend::S3(s3) => Box::pin(s3.list_prefix(prefix).await),
         }
     }
 
     pub(crate) async fn delete_prefix(&self, prefix: &str) -> Result<()> {
-        match &self.backend {
+        let result = match &self.backend {
             StorageBackend::Database(db) => db.delete_prefix(prefix).await,
+            StorageBackend::Filesystem(filesystem) => filesystem.delete_prefix(prefix).await,
             StorageBackend::S3(s3) => s3.delete_prefix(prefix).await,
-        }
+        };
+        self.memory_cache.invalidate_prefix(prefix);
+        result
     }
 
     // We're using `&self` instead of consuming `self` or creating a Drop impl because during tests
@@ -586,8 +1165,10 @@ impl AsyncStorage {
     // still holds a reference to the storage).
     #[cfg(test)]
     pub(crate) async fn cleanup_after_test(&self) -> Result<()> {
-        if let StorageBackend::S3(s3) = &self.backend {
-            s3.cleanup_after_test().await?;
+        match &self.backend {
+            StorageBackend::Database(_) => {}
+            StorageBackend::Filesystem(filesystem) => filesystem.cleanup_after_test().await?,
+            StorageBackend::S3(s3) => s3.cleanup_after_test().await?,
         }
         Ok(())
     }
@@ -597,6 +1178,7 @@ impl std::fmt::Debug for AsyncStorage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match &self.backend {
             StorageBackend::Database(_) => write!(f, "database-backed storage"),
+            StorageBackend::Filesystem(_) => write!(f, "filesystem-backed storage"),
             StorageBackend::S3(_) => write!(f, "S3-backed storage"),
         }
     }
@@ -641,6 +1223,7 @@ impl Storage {
             latest_build_id,
             path,
             archive_storage,
+            None,
         ))
     }
 
@@ -697,19 +1280,25 @@ impl Storage {
     pub(super) fn get_range(
         &self,
         path: &str,
+        latest_build_id: i32,
         max_size: usize,
         range: FileRange,
         compression: Option<CompressionAlgorithm>,
     ) -> Result<Blob> {
-        self.runtime
-            .block_on(self.inner.get_range(path, max_size, range, compression))
+        self.runtime.block_on(self.inner.get_range(
+            path,
+            latest_build_id,
+            max_size,
+            range,
+            compression,
+        ))
     }
 
     pub(super) fn download_index(
         &self,
         archive_path: &str,
         latest_build_id: i32,
-    ) -> Result<PathBuf> {
+    ) -> Result<(PathBuf, ArchiveKind)> {
         self.runtime.block_on(
             self.inner
                 .download_archive_index(archive_path, latest_build_id),
@@ -735,7 +1324,11 @@ impl Storage {
         &self,
         archive_path: &str,
         root_dir: &Path,
-    ) -> Result<(HashMap<PathBuf, String>, CompressionAlgorithm)> {
+    ) -> Result<(
+        HashMap<PathBuf, String>,
+        CompressionAlgorithm,
+        ArchiveChecksums,
+    )> {
         self.runtime
             .block_on(self.inner.store_all_in_archive(archive_path, root_dir))
     }
@@ -801,6 +1394,26 @@ impl std::fmt::Debug for Storage {
     }
 }
 
+/// Creates the index of a ZIP archive, compressed with `alg`.
+fn create_compressed_archive_index(
+    temp_dir: &Path,
+    zip_content: &mut Vec<u8>,
+    alg: CompressionAlgorithm,
+) -> Result<Vec<u8>> {
+    fs::create_dir_all(temp_dir)?;
+    let local_index_path = tempfile::NamedTempFile::new_in(temp_dir)?.into_temp_path();
+    archive_index::create(&mut io::Cursor::new(zip_content), &local_index_path)?;
+
+    compress(BufReader::new(fs::File::open(&local_index_path)?), alg)
+}
+
+pub(crate) fn is_size_limit_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>()
+        .and_then(|io| io.get_ref())
+        .and_then(|err| err.downcast_ref::<crate::error::SizeLimitReached>())
+        .is_some()
+}
+
 fn detect_mime(file_path: impl AsRef<Path>) -> &'static str {
     let mime = mime_guess::from_path(file_path.as_ref())
         .first_raw()
@@ -831,6 +1444,18 @@ pub(crate) fn source_archive_path(name: &str, version: &str) -> String {
     format!("sources/{name}/{version}.zip")
 }
 
+/// The name the files of a crate are stored under, to be used instead of the crate name
+/// in the storage paths.
+///
+/// Crates of alternative registries are stored under `@<registry>/<name>`. Crate names can't
+/// contain `@`, so they don't collide with crates.io crates of the same name.
+pub(crate) fn crate_storage_name<'a>(registry: Option<&str>, name: &'a str) -> Cow<'a, str> {
+    match registry {
+        Some(registry) => Cow::Owned(format!("@{registry}/{name}")),
+        None => Cow::Borrowed(name),
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -866,6 +1491,57 @@ mod test {
         let detected_mime = detect_mime(Path::new(&path));
         assert_eq!(detected_mime, expected_mime);
     }
+
+    #[test]
+    fn test_crate_storage_name() {
+        assert_eq!(crate_storage_name(None, "foo"), "foo");
+        assert_eq!(
+            rustdoc_archive_path(&crate_storage_name(Some("internal"), "foo"), "1.0.0"),
+            "rustdoc/@internal/foo/1.0.0.zip"
+        );
+    }
+
+    #[test]
+    fn streams_big_files_without_caching_them() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| config.storage_memory_cache_size = 8 * 1024);
+            let storage = env.storage();
+            let blob = |path: &str, content: Vec<u8>| Blob {
+                path: path.into(),
+                mime: "text/plain".into(),
+                date_updated: chrono::Utc::now(),
+                content,
+                compression: None,
+            };
+            // a single file can take at most an eighth of the cache
+            let small = b"small".to_vec();
+            let big = vec![b'x'; 2 * 1024];
+            storage.store_blobs(vec![
+                blob("small.txt", small.clone()),
+                blob("big.txt", big.clone()),
+            ])?;
+
+            for _ in 0..2 {
+                for (path, content) in [("small.txt", &small), ("big.txt", &big)] {
+                    let streamed = env.runtime().block_on(async {
+                        storage
+                            .inner
+                            .get_stream(path, usize::MAX)
+                            .await?
+                            .materialize(usize::MAX)
+                            .await
+                    })?;
+                    assert_eq!(&streamed.content, content);
+                }
+            }
+
+            let metrics = env.instance_metrics();
+            assert_eq!(metrics.storage_memory_cache_hits_total.get(), 1);
+            assert_eq!(metrics.storage_memory_cache_misses_total.get(), 3);
+
+            Ok(())
+        });
+    }
 }
 
 /// Backend tests are a set of tests executed on all the supported storage backends. They ensure
@@ -972,19 +1648,19 @@ mod backend_tests {
         assert_eq!(
             blob.content[0..=4],
             storage
-                .get_range("foo/bar.txt", std::usize::MAX, 0..=4, None)?
+                .get_range("foo/bar.txt", 1, std::usize::MAX, 0..=4, None)?
                 .content
         );
         assert_eq!(
             blob.content[5..=12],
             storage
-                .get_range("foo/bar.txt", std::usize::MAX, 5..=12, None)?
+                .get_range("foo/bar.txt", 1, std::usize::MAX, 5..=12, None)?
                 .content
         );
 
         for path in &["bar.txt", "baz.txt", "foo/baz.txt"] {
             assert!(storage
-                .get_range(path, std::usize::MAX, 0..=4, None)
+                .get_range(path, 1, std::usize::MAX, 0..=4, None)
                 .unwrap_err()
                 .downcast_ref::<PathNotFoundError>()
                 .is_some());
@@ -993,6 +1669,67 @@ mod backend_tests {
         Ok(())
     }
 
+    fn test_get_stream(storage: &Storage) -> Result<()> {
+        let content = b"test content\n".repeat(1000);
+        storage.store_blobs(vec![Blob {
+            path: "foo/bar.txt".into(),
+            mime: "text/plain".into(),
+            date_updated: Utc::now(),
+            compression: Some(CompressionAlgorithm::Zstd),
+            content: compress(content.as_slice(), CompressionAlgorithm::Zstd)?,
+        }])?;
+
+        let blob = storage.runtime.block_on(async {
+            storage
+                .inner
+                .get_stream("foo/bar.txt", std::usize::MAX)
+                .await?
+                .materialize(std::usize::MAX)
+                .await
+        })?;
+        assert_eq!(blob.mime, "text/plain");
+        assert_eq!(blob.compression, None);
+        assert_eq!(blob.content, content);
+
+        let dir = tempfile::Builder::new()
+            .prefix("docs.rs-stream-archive-test")
+            .tempdir()?;
+        fs::write(dir.path().join("index.html"), &content)?;
+        storage.store_all_in_archive("folder/test.zip", dir.path())?;
+
+        let blob = storage.runtime.block_on(async {
+            storage
+                .inner
+                .stream_from_archive("folder/test.zip", 0, "index.html", std::usize::MAX)
+                .await?
+                .materialize(std::usize::MAX)
+                .await
+        })?;
+        assert_eq!(blob.path, "folder/test.zip/index.html");
+        assert_eq!(blob.mime, "text/html");
+        assert_eq!(blob.content, content);
+
+        for path in ["baz.txt", "src/main.rs"] {
+            assert!(storage
+                .runtime
+                .block_on(storage.inner.get_stream(path, std::usize::MAX))
+                .unwrap_err()
+                .is::<PathNotFoundError>());
+            assert!(storage
+                .runtime
+                .block_on(storage.inner.stream_from_archive(
+                    "folder/test.zip",
+                    0,
+                    path,
+                    std::usize::MAX
+                ))
+                .unwrap_err()
+                .is::<PathNotFoundError>());
+        }
+
+        Ok(())
+    }
+
     fn test_list_prefix(storage: &Storage) -> Result<()> {
         static FILENAMES: &[&str] = &["baz.txt", "some/bar.txt"];
 
@@ -1024,6 +1761,51 @@ mod backend_tests {
         Ok(())
     }
 
+    fn test_leading_slash(storage: &Storage) -> Result<()> {
+        // the static rustdoc files are stored below `/rustdoc-static/`
+        static FILENAMES: &[&str] = &[
+            "/rustdoc-static/%foo.css",
+            "/rustdoc-static/..",
+            "/rustdoc-static//theme.css",
+            "/rustdoc-static/main.js",
+        ];
+
+        storage.store_blobs(
+            FILENAMES
+                .iter()
+                .map(|&filename| Blob {
+                    path: filename.into(),
+                    mime: "text/plain".into(),
+                    date_updated: Utc::now(),
+                    compression: None,
+                    content: filename.as_bytes().to_vec(),
+                })
+                .collect(),
+        )?;
+
+        for &filename in FILENAMES {
+            assert!(storage.exists(filename)?);
+            assert_eq!(
+                storage.get(filename, std::usize::MAX)?.content,
+                filename.as_bytes()
+            );
+        }
+        assert!(!storage.exists("rustdoc-static/main.js")?);
+        assert!(!storage.exists("/rustdoc-static/%main.js")?);
+
+        assert_eq!(
+            storage
+                .list_prefix("/rustdoc-static/")
+                .collect::<Result<Vec<String>>>()?,
+            FILENAMES
+        );
+
+        storage.delete_prefix("/rustdoc-static/")?;
+        assert!(!storage.exists("/rustdoc-static/main.js")?);
+
+        Ok(())
+    }
+
     fn test_too_long_filename(storage: &Storage) -> Result<()> {
         // minio returns ErrKeyTooLongError when the key is over 1024 bytes long.
         // When testing, minio just gave me `XMinioInvalidObjectName`, so I'll check that too.
@@ -1130,12 +1912,13 @@ mod backend_tests {
             .local_archive_cache_path
             .join("folder/test.zip.0.index");
 
-        let (stored_files, compression_alg) =
+        let (stored_files, compression_alg, checksums) =
             storage.store_all_in_archive("folder/test.zip", dir.path())?;
 
         assert!(storage.exists("folder/test.zip.index")?);
+        assert!(checksums.archive.is_some());
 
-        assert_eq!(compression_alg, CompressionAlgorithm::Bzip2);
+        assert_eq!(compression_alg, CompressionAlgorithm::Zstd);
         assert_eq!(stored_files.len(), files.len());
         for name in &files {
             let name = Path::new(name);
@@ -1371,6 +2154,7 @@ mod backend_tests {
         backends {
             s3 => StorageKind::S3,
             database => StorageKind::Database,
+            filesystem => StorageKind::Filesystem,
         }
 
         tests {
@@ -1378,8 +2162,10 @@ mod backend_tests {
             test_exists,
             test_get_object,
             test_get_range,
+            test_get_stream,
             test_get_too_big,
             test_too_long_filename,
+            test_leading_slash,
             test_list_prefix,
             test_delete_prefix,
             test_delete_prefix_without_matches,
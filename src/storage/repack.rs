@@ -0,0 +1,357 @@
+//! Repacking ZIP archives with bzip2 compressed files, which we created before we used zstd for
+//! the files in archives. Zstd files are much faster to decompress.
+
+use super::{
+    archive_index::ArchiveKind, crate_storage_name, create_compressed_archive_index,
+    record_archive_checksums, rustdoc_archive_path, source_archive_path, ArchiveChecksums,
+    AsyncStorage, Blob, CompressionAlgorithm, RELEASES_PER_BATCH,
+};
+use crate::{
+    error::Result,
+    utils::{report_error, spawn_blocking},
+};
+use anyhow::Context as _;
+use chrono::Utc;
+use sqlx::Acquire as _;
+use std::io;
+use tracing::{info, instrument};
+
+impl AsyncStorage {
+    /// Rewrites the ZIP archive at `archive_path` with zstd compressed files.
+    ///
+    /// Returns the checksums of the repacked archive, or `None` if there's no ZIP archive with
+    /// bzip2 compressed files at the path.
+    #[instrument(skip(self))]
+    pub(crate) async fn repack_archive(
+        &self,
+        archive_path: &str,
+    ) -> Result<Option<ArchiveChecksums>> {
+        let remote_index_path = ArchiveKind::Zip.remote_index_path(archive_path);
+        // deduplicated archives don't have a ZIP index
+        if !self.exists(&remote_index_path).await? {
+            return Ok(None);
+        }
+
+        let public = self.get_public_access(archive_path).await?;
+        let zip_content = self.get(archive_path, std::usize::MAX).await?.content;
+
+        let repacked = spawn_blocking({
+            let temp_dir = self.config.temp_dir.clone();
+            move || {
+                let mut archive = zip::ZipArchive::new(io::Cursor::new(zip_content))?;
+
+                let mut has_bzip2_files = false;
+                for i in 0..archive.len() {
+                    if archive.by_index_raw(i)?.compression() == zip::CompressionMethod::Bzip2 {
+                        has_bzip2_files = true;
+                        break;
+                    }
+                }
+                if !has_bzip2_files {
+                    return Ok(None);
+                }
+
+                let options = zip::write::FileOptions::default()
+                    .compression_method(zip::CompressionMethod::Zstd);
+                let mut repacked = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+                for i in 0..archive.len() {
+                    let mut file = archive.by_index(i)?;
+                    repacked.start_file(file.name(), options)?;
+                    io::copy(&mut file, &mut repacked)?;
+                }
+
+                let mut zip_content = repacked.finish()?.into_inner();
+                let index_content = create_compressed_archive_index(
+                    &temp_dir,
+                    &mut zip_content,
+                    CompressionAlgorithm::default(),
+                )?;
+                Ok(Some((zip_content, index_content)))
+            }
+        })
+        .await?;
+
+        let Some((zip_content, index_content)) = repacked else {
+            return Ok(None);
+        };
+        let checksums = ArchiveChecksums::new(Some(&zip_content), &index_content);
+
+        // Web servers that cached the old index notice that it doesn't match the archive anymore
+        // and download the new one, see `get_from_archive`.
+        self.store_inner(vec![
+            Blob {
+                path: archive_path.to_owned(),
+                mime: "application/zip".to_owned(),
+                content: zip_content,
+                compression: None,
+                date_updated: Utc::now(),
+            },
+            Blob {
+                path: remote_index_path,
+                mime: "application/octet-stream".to_owned(),
+                content: index_content,
+                compression: Some(CompressionAlgorithm::default()),
+                date_updated: Utc::now(),
+            },
+        ])
+        .await?;
+
+        // storing the archive again doesn't keep the public access in every backend
+        if public {
+            self.set_public_access(archive_path, true).await?;
+        }
+
+        Ok(Some(checksums))
+    }
+}
+
+/// Repacks the archives of all releases that still have bzip2 compressed files to zstd.
+///
+/// Meant to run next to the other services, which keep serving the archives while they're
+/// repacked. Releases that fail to repack are reported and skipped.
+pub async fn repack_archives(conn: &mut sqlx::PgConnection, storage: &AsyncStorage) -> Result<()> {
+    let mut last_release_id = 0;
+    let mut repacked = 0;
+
+    loop {
+        let releases = sqlx::query!(
+            "SELECT releases.id, crates.name, crates.registry, releases.version,
+                    releases.rustdoc_status
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             INNER JOIN compression_rels ON compression_rels.release = releases.id
+             WHERE
+                releases.archive_storage AND
+                compression_rels.algorithm = $1 AND
+                releases.id > $2
+             ORDER BY releases.id
+             LIMIT $3",
+            CompressionAlgorithm::Bzip2 as i32,
+            last_release_id,
+            RELEASES_PER_BATCH,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let Some(last_release) = releases.last() else {
+            break;
+        };
+        last_release_id = last_release.id;
+
+        for release in releases {
+            let storage_name = crate_storage_name(release.registry.as_deref(), &release.name);
+            let mut archive_paths = vec![source_archive_path(&storage_name, &release.version)];
+            if release.rustdoc_status {
+                archive_paths.push(rustdoc_archive_path(&storage_name, &release.version));
+            }
+
+            let result = async {
+                for archive_path in &archive_paths {
+                    if let Some(checksums) = storage
+                        .repack_archive(archive_path)
+                        .await
+                        .with_context(|| format!("failed to repack {archive_path}"))?
+                    {
+                        record_archive_checksums(&mut *conn, archive_path, &checksums).await?;
+                        info!(%archive_path, "repacked archive");
+                    }
+                }
+                update_compression_rels(&mut *conn, release.id).await
+            }
+            .await;
+
+            match result {
+                Ok(()) => repacked += 1,
+                Err(err) => report_error(&err.context(format!(
+                    "failed to repack the archives of {} {}",
+                    release.name, release.version
+                ))),
+            }
+        }
+    }
+
+    info!(repacked, "repacked the archives of all releases");
+    Ok(())
+}
+
+/// None of the files of the release are compressed with bzip2 anymore.
+async fn update_compression_rels(conn: &mut sqlx::PgConnection, release_id: i32) -> Result<()> {
+    let mut transaction = conn.begin().await?;
+    sqlx::query!(
+        "DELETE FROM compression_rels WHERE release = $1 AND algorithm = $2",
+        release_id,
+        CompressionAlgorithm::Bzip2 as i32,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO compression_rels (release, algorithm)
+         VALUES ($1, $2)
+         ON CONFLICT DO NOTHING",
+        release_id,
+        CompressionAlgorithm::Zstd as i32,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::async_wrapper;
+    use std::io::{Read, Write};
+
+    async fn store_bzip2_archive(
+        storage: &AsyncStorage,
+        archive_path: &str,
+        files: &[(&str, &str)],
+    ) -> Result<()> {
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Bzip2);
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        for (name, content) in files {
+            zip.start_file(*name, options)?;
+            zip.write_all(content.as_bytes())?;
+        }
+        let mut zip_content = zip.finish()?.into_inner();
+        let index_content = create_compressed_archive_index(
+            &storage.config.temp_dir,
+            &mut zip_content,
+            CompressionAlgorithm::default(),
+        )?;
+
+        storage
+            .store_blobs(vec![
+                Blob {
+                    path: archive_path.to_owned(),
+                    mime: "application/zip".to_owned(),
+                    content: zip_content,
+                    compression: None,
+                    date_updated: Utc::now(),
+                },
+                Blob {
+                    path: format!("{archive_path}.index"),
+                    mime: "application/octet-stream".to_owned(),
+                    content: index_content,
+                    compression: Some(CompressionAlgorithm::default()),
+                    date_updated: Utc::now(),
+                },
+            ])
+            .await
+    }
+
+    async fn archive_compressions(
+        storage: &AsyncStorage,
+        archive_path: &str,
+    ) -> Result<Vec<zip::CompressionMethod>> {
+        let content = storage.get(archive_path, std::usize::MAX).await?.content;
+        let mut archive = zip::ZipArchive::new(io::Cursor::new(content))?;
+        (0..archive.len())
+            .map(|i| Ok(archive.by_index_raw(i)?.compression()))
+            .collect()
+    }
+
+    #[test]
+    fn repack_bzip2_archive() {
+        async_wrapper(|env| async move {
+            let storage = env.async_storage().await;
+            store_bzip2_archive(
+                &storage,
+                "rustdoc/foo/1.0.0.zip",
+                &[("foo/index.html", "<html>"), ("foo/all.html", "all items")],
+            )
+            .await?;
+            storage
+                .set_public_access("rustdoc/foo/1.0.0.zip", true)
+                .await?;
+
+            // caches the index of the bzip2 archive
+            let file = storage
+                .get_from_archive("rustdoc/foo/1.0.0.zip", 0, "foo/all.html", std::usize::MAX)
+                .await?;
+            assert_eq!(file.content, b"all items");
+
+            assert!(storage
+                .repack_archive("rustdoc/foo/1.0.0.zip")
+                .await?
+                .is_some());
+            assert_eq!(
+                archive_compressions(&storage, "rustdoc/foo/1.0.0.zip").await?,
+                vec![zip::CompressionMethod::Zstd; 2]
+            );
+            assert!(storage.get_public_access("rustdoc/foo/1.0.0.zip").await?);
+
+            // the cached index doesn't match the new archive
+            let file = storage
+                .get_from_archive("rustdoc/foo/1.0.0.zip", 0, "foo/all.html", std::usize::MAX)
+                .await?;
+            assert_eq!(file.content, b"all items");
+
+            // nothing left to repack
+            assert!(storage
+                .repack_archive("rustdoc/foo/1.0.0.zip")
+                .await?
+                .is_none());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn repack_archives_of_releases() {
+        async_wrapper(|env| async move {
+            let release_id = env
+                .async_fake_release()
+                .await
+                .name("foo")
+                .version("1.0.0")
+                .archive_storage(true)
+                .create_async()
+                .await?;
+
+            let storage = env.async_storage().await;
+            store_bzip2_archive(&storage, "sources/foo/1.0.0.zip", &[("src/lib.rs", "")]).await?;
+
+            let mut conn = env.async_db().await.async_conn().await;
+            sqlx::query!(
+                "UPDATE compression_rels SET algorithm = $2 WHERE release = $1",
+                release_id,
+                CompressionAlgorithm::Bzip2 as i32,
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            repack_archives(&mut conn, &storage).await?;
+
+            assert_eq!(
+                archive_compressions(&storage, "sources/foo/1.0.0.zip").await?,
+                vec![zip::CompressionMethod::Zstd]
+            );
+            let algorithms: Vec<i32> = sqlx::query_scalar!(
+                "SELECT algorithm FROM compression_rels WHERE release = $1",
+                release_id
+            )
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+            assert_eq!(algorithms, vec![CompressionAlgorithm::Zstd as i32]);
+
+            let mut content = String::new();
+            zip::ZipArchive::new(io::Cursor::new(
+                storage
+                    .get("sources/foo/1.0.0.zip", std::usize::MAX)
+                    .await?
+                    .content,
+            ))?
+            .by_name("src/lib.rs")?
+            .read_to_string(&mut content)?;
+            assert_eq!(content, "");
+
+            Ok(())
+        })
+    }
+}
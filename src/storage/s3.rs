@@ -1,4 +1,4 @@
-use super::{Blob, FileRange};
+use super::{Blob, FileRange, StreamingBlob};
 use crate::{Config, InstanceMetrics};
 use anyhow::{Context as _, Error};
 use async_stream::try_stream;
@@ -221,6 +221,49 @@ impl S3Backend {
         })
     }
 
+    pub(super) async fn get_stream(
+        &self,
+        path: &str,
+        max_size: usize,
+        range: Option<FileRange>,
+    ) -> Result<StreamingBlob, Error> {
+        let res = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(path)
+            .set_range(range.map(|r| format!("bytes={}-{}", r.start(), r.end())))
+            .send()
+            .await
+            .convert_errors()?;
+
+        if res
+            .content_length
+            .and_then(|length| u64::try_from(length).ok())
+            .is_some_and(|length| length > max_size as u64)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                crate::error::SizeLimitReached,
+            )
+            .into());
+        }
+
+        let date_updated = res
+            .last_modified
+            // see `get` for why we need a fallback here
+            .and_then(|dt| dt.to_chrono_utc().ok())
+            .unwrap_or_else(Utc::now);
+
+        Ok(StreamingBlob {
+            path: path.into(),
+            mime: res.content_type.unwrap(),
+            date_updated,
+            compression: res.content_encoding.and_then(|s| s.parse().ok()),
+            content: Box::pin(res.body.into_async_read()),
+        })
+    }
+
     pub(super) async fn store_batch(&self, mut batch: Vec<Blob>) -> Result<(), Error> {
         // Attempt to upload the batch 3 times
         for _ in 0..3 {
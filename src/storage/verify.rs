@@ -0,0 +1,517 @@
+//! Verifying that the archives of releases are intact.
+//!
+//! When we store an archive, we record the SHA-256 checksums of its ZIP file and its index in the
+//! `archive_checksums` table. Verifying the archive downloads both again, compares their
+//! checksums, and checks that every file in the index can be decompressed. For deduplicated
+//! archives, the content of every blob has to match the hash it's named after.
+
+use super::{
+    archive_index::{self, ArchiveKind},
+    crate_storage_name, decompress,
+    dedup::{content_blob_path, CONCURRENT_REQUESTS},
+    rustdoc_archive_path, source_archive_path, AsyncStorage, Blob, PathNotFoundError,
+    RELEASES_PER_BATCH,
+};
+use crate::{
+    error::Result,
+    utils::{report_error, spawn_blocking},
+};
+use anyhow::Context as _;
+use futures_util::stream::{self, StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt, fs};
+use tracing::{info, instrument};
+
+/// The SHA-256 checksums of the files of an archive, as they're stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ArchiveChecksums {
+    /// Of the ZIP file, `None` for deduplicated archives.
+    pub(crate) archive: Option<String>,
+    /// Of the compressed index.
+    pub(crate) index: String,
+}
+
+impl ArchiveChecksums {
+    pub(super) fn new(archive: Option<&[u8]>, index: &[u8]) -> Self {
+        Self {
+            archive: archive.map(sha256),
+            index: sha256(index),
+        }
+    }
+}
+
+fn sha256(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+/// Records the checksums of the archive we just stored at `archive_path`, replacing the ones of
+/// an archive stored there before.
+pub(crate) async fn record_archive_checksums(
+    conn: &mut sqlx::PgConnection,
+    archive_path: &str,
+    checksums: &ArchiveChecksums,
+) -> Result<()> {
+    sqlx::query!(
+        "INSERT INTO archive_checksums (archive_path, archive_sha256, index_sha256)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (archive_path) DO UPDATE
+         SET
+            archive_sha256 = EXCLUDED.archive_sha256,
+            index_sha256 = EXCLUDED.index_sha256,
+            updated_at = NOW()",
+        archive_path,
+        checksums.archive,
+        checksums.index,
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+async fn load_archive_checksums(
+    conn: &mut sqlx::PgConnection,
+    archive_path: &str,
+) -> Result<Option<ArchiveChecksums>> {
+    Ok(sqlx::query!(
+        "SELECT archive_sha256, index_sha256
+         FROM archive_checksums
+         WHERE archive_path = $1",
+        archive_path,
+    )
+    .fetch_optional(conn)
+    .await?
+    .map(|row| ArchiveChecksums {
+        archive: row.archive_sha256,
+        index: row.index_sha256,
+    }))
+}
+
+/// What's wrong with an archive that isn't intact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveProblem {
+    /// Neither the index of a ZIP archive nor the one of a deduplicated archive are in the
+    /// storage.
+    NotInStorage,
+    /// The index of the archive is in the storage, but this file of the archive isn't.
+    MissingFile(String),
+    /// This file of the archive doesn't have the checksum we recorded when we stored it.
+    ChecksumMismatch(String),
+    /// The index of the archive can't be read.
+    InvalidIndex(String),
+    /// A file in the archive can't be decompressed, or doesn't have the content it should have.
+    BrokenFile { path: String, reason: String },
+}
+
+impl fmt::Display for ArchiveProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInStorage => write!(f, "archive not in storage"),
+            Self::MissingFile(path) => write!(f, "{path} not in storage"),
+            Self::ChecksumMismatch(path) => write!(f, "checksum of {path} doesn't match"),
+            Self::InvalidIndex(reason) => write!(f, "invalid index: {reason}"),
+            Self::BrokenFile { path, reason } => write!(f, "broken file {path}: {reason}"),
+        }
+    }
+}
+
+impl AsyncStorage {
+    /// Downloads the archive at `archive_path` again and returns everything that's wrong with it.
+    ///
+    /// The checksums are only compared when we have them, archives stored before we recorded
+    /// them are only checked for files that can't be decompressed. The files are loaded from the
+    /// backend, the in-memory cache might still have them from before they were corrupted.
+    #[instrument(skip(self))]
+    pub(crate) async fn verify_archive(
+        &self,
+        archive_path: &str,
+        checksums: Option<&ArchiveChecksums>,
+    ) -> Result<Vec<ArchiveProblem>> {
+        let mut index = None;
+        for kind in [ArchiveKind::Zip, ArchiveKind::Deduplicated] {
+            let index_path = kind.remote_index_path(archive_path);
+            if let Some(blob) = self.get_from_backend_if_exists(&index_path).await? {
+                index = Some((kind, index_path, blob));
+                break;
+            }
+        }
+        let Some((kind, index_path, index)) = index else {
+            return Ok(vec![ArchiveProblem::NotInStorage]);
+        };
+
+        let mut problems = Vec::new();
+        if checksums.is_some_and(|checksums| sha256(&index.content) != checksums.index) {
+            problems.push(ArchiveProblem::ChecksumMismatch(index_path));
+        }
+
+        let index_content = match index.compression {
+            Some(alg) => match decompress(index.content.as_slice(), alg, std::usize::MAX) {
+                Ok(content) => content,
+                Err(err) => {
+                    problems.push(ArchiveProblem::InvalidIndex(format!("{err:#}")));
+                    return Ok(problems);
+                }
+            },
+            None => index.content,
+        };
+        let local_index_path = spawn_blocking({
+            let temp_dir = self.config.temp_dir.clone();
+            move || {
+                fs::create_dir_all(&temp_dir)?;
+                let local_index_path = tempfile::NamedTempFile::new_in(&temp_dir)?.into_temp_path();
+                fs::write(&local_index_path, index_content)?;
+                Ok(local_index_path)
+            }
+        })
+        .await?;
+
+        match kind {
+            ArchiveKind::Zip => {
+                match spawn_blocking(move || archive_index::list_in_file(&local_index_path)).await {
+                    Ok(files) => {
+                        let expected = checksums.and_then(|checksums| checksums.archive.as_ref());
+                        problems.extend(self.verify_zip(archive_path, expected, files).await?);
+                    }
+                    Err(err) => problems.push(ArchiveProblem::InvalidIndex(format!("{err:#}"))),
+                }
+            }
+            ArchiveKind::Deduplicated => {
+                match spawn_blocking(move || archive_index::list_blobs_in_file(&local_index_path))
+                    .await
+                {
+                    Ok(files) => problems.extend(self.verify_blobs(files).await?),
+                    Err(err) => problems.push(ArchiveProblem::InvalidIndex(format!("{err:#}"))),
+                }
+            }
+        }
+
+        Ok(problems)
+    }
+
+    /// Checks the ZIP file of an archive, and that the range of every file in its index can be
+    /// decompressed.
+    async fn verify_zip(
+        &self,
+        archive_path: &str,
+        expected_checksum: Option<&String>,
+        files: Vec<(String, archive_index::FileInfo)>,
+    ) -> Result<Vec<ArchiveProblem>> {
+        let Some(zip) = self.get_from_backend_if_exists(archive_path).await? else {
+            return Ok(vec![ArchiveProblem::MissingFile(archive_path.to_owned())]);
+        };
+
+        let mut problems = Vec::new();
+        if expected_checksum.is_some_and(|expected| sha256(&zip.content) != *expected) {
+            problems.push(ArchiveProblem::ChecksumMismatch(archive_path.to_owned()));
+        }
+
+        let zip_content = zip.content;
+        problems.extend(
+            spawn_blocking(move || {
+                Ok(files
+                    .into_iter()
+                    .filter_map(|(path, file)| {
+                        let range = file.range();
+                        let reason = match zip_content
+                            .get(*range.start() as usize..=*range.end() as usize)
+                        {
+                            Some(compressed) => {
+                                match decompress(compressed, file.compression(), std::usize::MAX) {
+                                    Ok(_) => return None,
+                                    Err(err) => format!("can't be decompressed: {err:#}"),
+                                }
+                            }
+                            None => "range outside of the archive".to_owned(),
+                        };
+                        Some(ArchiveProblem::BrokenFile { path, reason })
+                    })
+                    .collect::<Vec<_>>())
+            })
+            .await?,
+        );
+        Ok(problems)
+    }
+
+    /// Checks that the blob of every file in a deduplicated archive can be decompressed, and
+    /// has the content its hash says it has.
+    async fn verify_blobs(&self, files: Vec<(String, String)>) -> Result<Vec<ArchiveProblem>> {
+        // every blob is only checked once, its problem is reported for the first file using it
+        let mut blobs = HashMap::new();
+        for (path, hash) in files {
+            blobs.entry(hash).or_insert(path);
+        }
+
+        let mut problems: Vec<ArchiveProblem> = stream::iter(blobs)
+            .map(|(hash, path)| async move {
+                let reason = match self
+                    .get_from_backend_if_exists(&content_blob_path(&hash))
+                    .await?
+                {
+                    None => format!("blob {hash} not in storage"),
+                    Some(blob) => {
+                        let content = match blob.compression {
+                            Some(alg) => decompress(blob.content.as_slice(), alg, std::usize::MAX),
+                            None => Ok(blob.content),
+                        };
+                        match content {
+                            Ok(content) if sha256(&content) == hash => return Ok(None),
+                            Ok(_) => format!("content of blob {hash} doesn't match its hash"),
+                            Err(err) => format!("blob {hash} can't be decompressed: {err:#}"),
+                        }
+                    }
+                };
+                Ok::<_, anyhow::Error>(Some(ArchiveProblem::BrokenFile { path, reason }))
+            })
+            .buffer_unordered(CONCURRENT_REQUESTS)
+            .try_filter_map(|problem| async move { Ok(problem) })
+            .try_collect()
+            .await?;
+
+        problems.sort_unstable_by_key(ToString::to_string);
+        Ok(problems)
+    }
+
+    async fn get_from_backend_if_exists(&self, path: &str) -> Result<Option<Blob>> {
+        match self.get_from_backend(path, std::usize::MAX, None).await {
+            Ok(blob) => Ok(Some(blob)),
+            Err(err) if err.is::<PathNotFoundError>() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// A release with archives that aren't intact.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorruptedRelease {
+    pub name: String,
+    pub registry: Option<String>,
+    pub version: String,
+    /// The paths of the broken archives, with what's wrong with them.
+    pub problems: Vec<(String, ArchiveProblem)>,
+}
+
+/// Verifies the archives of all releases, or only the ones of the crate named `crate_name`, and
+/// returns the releases with archives that aren't intact.
+///
+/// Archives that fail to download are reported and skipped, since that doesn't mean they're
+/// broken.
+pub async fn verify_archives(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+    crate_name: Option<&str>,
+) -> Result<Vec<CorruptedRelease>> {
+    let mut last_release_id = 0;
+    let mut verified = 0;
+    let mut corrupted = Vec::new();
+
+    loop {
+        let releases = sqlx::query!(
+            "SELECT releases.id, crates.name, crates.registry, releases.version,
+                    releases.rustdoc_status
+             FROM releases
+             INNER JOIN crates ON crates.id = releases.crate_id
+             WHERE
+                releases.archive_storage AND
+                releases.id > $1 AND
+                ($2::TEXT IS NULL OR crates.name = $2)
+             ORDER BY releases.id
+             LIMIT $3",
+            last_release_id,
+            crate_name,
+            RELEASES_PER_BATCH,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let Some(last_release) = releases.last() else {
+            break;
+        };
+        last_release_id = last_release.id;
+
+        for release in releases {
+            let storage_name = crate_storage_name(release.registry.as_deref(), &release.name);
+            let mut archive_paths = vec![source_archive_path(&storage_name, &release.version)];
+            if release.rustdoc_status {
+                archive_paths.push(rustdoc_archive_path(&storage_name, &release.version));
+            }
+
+            let mut problems = Vec::new();
+            for archive_path in archive_paths {
+                let checksums = load_archive_checksums(&mut *conn, &archive_path).await?;
+                match storage
+                    .verify_archive(&archive_path, checksums.as_ref())
+                    .await
+                    .with_context(|| format!("failed to verify {archive_path}"))
+                {
+                    Ok(archive_problems) => problems.extend(
+                        archive_problems
+                            .into_iter()
+                            .map(|problem| (archive_path.clone(), problem)),
+                    ),
+                    Err(err) => report_error(&err),
+                }
+            }
+
+            verified += 1;
+            if !problems.is_empty() {
+                info!(name = %release.name, version = %release.version, "corrupted release");
+                corrupted.push(CorruptedRelease {
+                    name: release.name,
+                    registry: release.registry,
+                    version: release.version,
+                    problems,
+                });
+            }
+        }
+    }
+
+    info!(
+        verified,
+        corrupted = corrupted.len(),
+        "verified the archives of releases"
+    );
+    Ok(corrupted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        storage::{compress, CompressionAlgorithm},
+        test::async_wrapper,
+    };
+    use chrono::Utc;
+
+    #[test]
+    fn record_checksums_when_storing_archives() {
+        async_wrapper(|env| async move {
+            env.async_fake_release()
+                .await
+                .name("foo")
+                .version("1.0.0")
+                .archive_storage(true)
+                .create_async()
+                .await?;
+
+            let storage = env.async_storage().await;
+            let mut conn = env.async_db().await.async_conn().await;
+            for archive_path in ["rustdoc/foo/1.0.0.zip", "sources/foo/1.0.0.zip"] {
+                let checksums = load_archive_checksums(&mut conn, archive_path)
+                    .await?
+                    .unwrap();
+                let zip = storage.get(archive_path, std::usize::MAX).await?;
+                assert_eq!(checksums.archive, Some(sha256(&zip.content)));
+            }
+
+            assert!(verify_archives(&mut conn, &storage, None).await?.is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn verify_corrupted_zip_archives() {
+        async_wrapper(|env| async move {
+            for name in ["corrupted", "missing"] {
+                env.async_fake_release()
+                    .await
+                    .name(name)
+                    .version("1.0.0")
+                    .archive_storage(true)
+                    .create_async()
+                    .await?;
+            }
+
+            let storage = env.async_storage().await;
+            let mut zip = storage
+                .get("rustdoc/corrupted/1.0.0.zip", std::usize::MAX)
+                .await?;
+            // overwrites the whole archive, the index stays the same
+            zip.content.fill(0);
+            storage.store_blobs(vec![zip]).await?;
+            storage.delete_prefix("sources/missing/1.0.0.zip").await?;
+
+            let mut conn = env.async_db().await.async_conn().await;
+            let corrupted = verify_archives(&mut conn, &storage, None).await?;
+            assert_eq!(corrupted.len(), 2);
+
+            assert_eq!(corrupted[0].name, "corrupted");
+            assert_eq!(
+                corrupted[0].problems[0],
+                (
+                    "rustdoc/corrupted/1.0.0.zip".into(),
+                    ArchiveProblem::ChecksumMismatch("rustdoc/corrupted/1.0.0.zip".into())
+                )
+            );
+            assert!(corrupted[0].problems.len() > 1);
+            assert!(corrupted[0].problems[1..]
+                .iter()
+                .all(|(_, problem)| matches!(problem, ArchiveProblem::BrokenFile { .. })));
+
+            assert_eq!(corrupted[1].name, "missing");
+            assert_eq!(
+                corrupted[1].problems,
+                vec![(
+                    "sources/missing/1.0.0.zip".into(),
+                    ArchiveProblem::NotInStorage
+                )]
+            );
+
+            // only the archives of the crate are verified
+            assert!(verify_archives(&mut conn, &storage, Some("other"))
+                .await?
+                .is_empty());
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn verify_corrupted_deduplicated_archive() {
+        async_wrapper(|env| async move {
+            env.override_config(|config| config.deduplicate_archives = true);
+            env.async_fake_release()
+                .await
+                .name("foo")
+                .version("1.0.0")
+                .archive_storage(true)
+                .source_file("src/lib.rs", b"pub fn foo() {}")
+                .create_async()
+                .await?;
+
+            let storage = env.async_storage().await;
+            let mut conn = env.async_db().await.async_conn().await;
+            assert!(verify_archives(&mut conn, &storage, Some("foo"))
+                .await?
+                .is_empty());
+
+            let hash = sha256(b"pub fn foo() {}");
+            storage
+                .store_blobs(vec![Blob {
+                    path: content_blob_path(&hash),
+                    mime: "text/rust".into(),
+                    content: compress(&b"pub fn bar() {}"[..], CompressionAlgorithm::Zstd)?,
+                    compression: Some(CompressionAlgorithm::Zstd),
+                    date_updated: Utc::now(),
+                }])
+                .await?;
+
+            let problems = storage
+                .verify_archive(
+                    "sources/foo/1.0.0.zip",
+                    load_archive_checksums(&mut conn, "sources/foo/1.0.0.zip")
+                        .await?
+                        .as_ref(),
+                )
+                .await?;
+            assert_eq!(
+                problems,
+                vec![ArchiveProblem::BrokenFile {
+                    path: "src/lib.rs".into(),
+                    reason: format!("content of blob {hash} doesn't match its hash"),
+                }]
+            );
+
+            Ok(())
+        })
+    }
+}
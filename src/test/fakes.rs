@@ -1,11 +1,12 @@
 use super::TestDatabase;
 
-use crate::db::types::BuildStatus;
-use crate::docbuilder::DocCoverage;
+use crate::db::types::{BuildFailureCategory, BuildStatus};
+use crate::docbuilder::{BuildFailure, DocCoverage, ResourceUsage, TargetBuildResult};
 use crate::error::Result;
 use crate::registry_api::{CrateData, CrateOwner, ReleaseData};
 use crate::storage::{
-    rustdoc_archive_path, source_archive_path, AsyncStorage, CompressionAlgorithms,
+    crate_storage_name, rustdoc_archive_path, source_archive_path, AsyncStorage,
+    CompressionAlgorithms,
 };
 use crate::utils::{Dependency, MetadataPackage, Target};
 use anyhow::{bail, Context};
@@ -14,6 +15,7 @@ use chrono::{DateTime, Utc};
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 use tracing::debug;
 
@@ -22,12 +24,14 @@ pub(crate) struct FakeRelease<'a> {
     db: &'a TestDatabase,
     storage: Arc<AsyncStorage>,
     runtime: Arc<Runtime>,
+    /// alternative registry the release comes from
+    registry: Option<String>,
     package: MetadataPackage,
     builds: Option<Vec<FakeBuild>>,
     /// name, content
     source_files: Vec<(&'a str, &'a [u8])>,
     /// name, content
-    rustdoc_files: Vec<(&'a str, &'a [u8])>,
+    rustdoc_files: Vec<(String, Vec<u8>)>,
     doc_targets: Vec<String>,
     default_target: Option<&'a str>,
     registry_crate_data: CrateData,
@@ -49,6 +53,8 @@ pub(crate) struct FakeBuild {
     rustc_version: String,
     docsrs_version: String,
     build_status: BuildStatus,
+    failure: Option<BuildFailure>,
+    target_results: Vec<TargetBuildResult>,
 }
 
 const DEFAULT_CONTENT: &[u8] =
@@ -64,6 +70,7 @@ impl<'a> FakeRelease<'a> {
             db,
             storage,
             runtime,
+            registry: None,
             package: MetadataPackage {
                 id: "fake-package-id".into(),
                 name: "fake-package".into(),
@@ -141,6 +148,11 @@ impl<'a> FakeRelease<'a> {
         self
     }
 
+    pub(crate) fn registry(mut self, registry: &str) -> Self {
+        self.registry = Some(registry.into());
+        self
+    }
+
     pub(crate) fn repo(mut self, repo: impl Into<String>) -> Self {
         self.package.repository = Some(repo.into());
         self
@@ -189,13 +201,14 @@ impl<'a> FakeRelease<'a> {
 
     /// Since we switched to LOL HTML, all data must have a valid <head> and <body>.
     /// To avoid duplicating them in every test, this just makes up some content.
-    pub(crate) fn rustdoc_file(mut self, path: &'a str) -> Self {
-        self.rustdoc_files.push((path, DEFAULT_CONTENT));
+    pub(crate) fn rustdoc_file(mut self, path: &str) -> Self {
+        self.rustdoc_files
+            .push((path.to_owned(), DEFAULT_CONTENT.to_vec()));
         self
     }
 
-    pub(crate) fn rustdoc_file_with(mut self, path: &'a str, data: &'a [u8]) -> Self {
-        self.rustdoc_files.push((path, data));
+    pub(crate) fn rustdoc_file_with(mut self, path: &str, data: Vec<u8>) -> Self {
+        self.rustdoc_files.push((path.to_owned(), data));
         self
     }
 
@@ -300,6 +313,7 @@ impl<'a> FakeRelease<'a> {
         use std::path::Path;
 
         let package = self.package;
+        let registry = self.registry;
         let db = self.db;
         let mut rustdoc_files = self.rustdoc_files;
         let storage = self.storage;
@@ -315,10 +329,7 @@ impl<'a> FakeRelease<'a> {
                     "<html><head></head><body>{}</body></html>",
                     std::str::from_utf8(data).expect("invalid utf8")
                 );
-                rustdoc_files.push((
-                    Box::leak(Box::new(updated)),
-                    Box::leak(source_html.into_bytes().into_boxed_slice()),
-                ));
+                rustdoc_files.push((updated, source_html.into_bytes()));
             }
         }
 
@@ -335,8 +346,12 @@ impl<'a> FakeRelease<'a> {
                 .unwrap()
         };
 
-        let store_files_into = |files: &[(&str, &[u8])], base_path: &Path| {
+        fn store_files_into(
+            files: &[(impl AsRef<str>, impl AsRef<[u8]>)],
+            base_path: &Path,
+        ) -> Result<()> {
             for (path, data) in files {
+                let path = path.as_ref();
                 if path.starts_with('/') {
                     anyhow::bail!("absolute paths not supported");
                 }
@@ -351,12 +366,14 @@ impl<'a> FakeRelease<'a> {
                 fs::write(file, data)?;
             }
             Ok(())
-        };
+        }
 
         async fn upload_files(
+            conn: &mut sqlx::PgConnection,
             kind: FileKind,
             source_directory: &Path,
             archive_storage: bool,
+            registry: Option<&str>,
             package: &MetadataPackage,
             storage: &AsyncStorage,
         ) -> Result<(Value, CompressionAlgorithms)> {
@@ -365,17 +382,19 @@ impl<'a> FakeRelease<'a> {
                 kind,
                 source_directory.display()
             );
+            let storage_name = crate_storage_name(registry, &package.name);
             if archive_storage {
                 let (archive, public) = match kind {
                     FileKind::Rustdoc => {
-                        (rustdoc_archive_path(&package.name, &package.version), true)
+                        (rustdoc_archive_path(&storage_name, &package.version), true)
                     }
                     FileKind::Sources => {
-                        (source_archive_path(&package.name, &package.version), false)
+                        (source_archive_path(&storage_name, &package.version), false)
                     }
                 };
                 debug!("store in archive: {:?}", archive);
                 let (files_list, new_alg) = crate::db::add_path_into_remote_archive(
+                    conn,
                     storage,
                     &archive,
                     source_directory,
@@ -392,7 +411,7 @@ impl<'a> FakeRelease<'a> {
                 };
                 crate::db::add_path_into_database(
                     storage,
-                    format!("{}/{}/{}/", prefix, package.name, package.version),
+                    format!("{}/{}/{}/", prefix, storage_name, package.version),
                     source_directory,
                 )
                 .await
@@ -420,10 +439,14 @@ impl<'a> FakeRelease<'a> {
             store_files_into(&[("Cargo.toml", content.as_bytes())], source_tmp.path())?;
         }
 
+        let mut async_conn = db.async_conn().await;
+
         let (source_meta, algs) = upload_files(
+            &mut async_conn,
             FileKind::Sources,
             source_tmp.path(),
             archive_storage,
+            registry.as_deref(),
             &package,
             &storage,
         )
@@ -436,7 +459,7 @@ impl<'a> FakeRelease<'a> {
         if builds.last().map(|b| b.build_status) == Some(BuildStatus::Success) {
             let index = [&package.name, "index.html"].join("/");
             if package.is_library() && !rustdoc_files.iter().any(|(path, _)| path == &index) {
-                rustdoc_files.push((&index, DEFAULT_CONTENT));
+                rustdoc_files.push((index, DEFAULT_CONTENT.to_vec()));
             }
 
             let rustdoc_tmp = create_temp_dir();
@@ -456,9 +479,11 @@ impl<'a> FakeRelease<'a> {
             }
 
             let (rustdoc_meta, _) = upload_files(
+                &mut async_conn,
                 FileKind::Rustdoc,
                 rustdoc_path,
                 archive_storage,
+                registry.as_deref(),
                 &package,
                 &storage,
             )
@@ -466,8 +491,6 @@ impl<'a> FakeRelease<'a> {
             debug!("uploaded rustdoc files: {}", rustdoc_meta);
         }
 
-        let mut async_conn = db.async_conn().await;
-
         let repository = match self.github_stats {
             Some(stats) => Some(stats.create(&mut async_conn).await?),
             None => None,
@@ -486,6 +509,7 @@ impl<'a> FakeRelease<'a> {
         let mut async_conn = db.async_conn().await;
         let release_id = crate::db::add_package_into_database(
             &mut async_conn,
+            registry.as_deref(),
             &package,
             crate_dir,
             default_target,
@@ -501,6 +525,7 @@ impl<'a> FakeRelease<'a> {
         .await?;
         crate::db::update_crate_data_in_database(
             &mut async_conn,
+            registry.as_deref(),
             &package.name,
             &self.registry_crate_data,
         )
@@ -605,6 +630,30 @@ impl FakeBuild {
         }
     }
 
+    pub(crate) fn failure(self, category: BuildFailureCategory, excerpt: Option<&str>) -> Self {
+        Self {
+            build_status: BuildStatus::Failure,
+            failure: Some(BuildFailure {
+                category,
+                excerpt: excerpt.map(Into::into),
+            }),
+            ..self
+        }
+    }
+
+    pub(crate) fn target_result(mut self, target: impl Into<String>, successful: bool) -> Self {
+        self.target_results.push(TargetBuildResult {
+            target: target.into(),
+            successful,
+            duration: Duration::from_secs(42),
+            resource_usage: ResourceUsage {
+                peak_memory: Some(512 * 1024 * 1024),
+                cpu_time: Some(Duration::from_secs(120)),
+            },
+        });
+        self
+    }
+
     async fn create(
         &self,
         conn: &mut sqlx::PgConnection,
@@ -631,6 +680,16 @@ impl FakeBuild {
             .await?;
         }
 
+        if let Some(failure) = self.failure.clone() {
+            crate::db::add_build_failure(&mut *conn, build_id, failure).await?;
+        }
+
+        for target_result in &self.target_results {
+            let log_path = format!("build-logs/{build_id}/{}.txt", target_result.target);
+            crate::db::add_target_build_result(&mut *conn, build_id, target_result, &log_path)
+                .await?;
+        }
+
         let prefix = format!("build-logs/{build_id}/");
 
         if let Some(s3_build_log) = self.s3_build_log.as_deref() {
@@ -659,6 +718,8 @@ impl Default for FakeBuild {
             rustc_version: "rustc 2.0.0-nightly (000000000 1970-01-01)".into(),
             docsrs_version: "docs.rs 1.0.0 (000000000 1970-01-01)".into(),
             build_status: BuildStatus::Success,
+            failure: None,
+            target_results: Vec::new(),
         }
     }
 }
@@ -346,6 +346,9 @@ impl TestEnvironment {
         config.local_archive_cache_path =
             std::env::temp_dir().join(format!("docsrs-test-index-{}", rand::random::<u64>()));
 
+        config.storage_filesystem_root =
+            std::env::temp_dir().join(format!("docsrs-test-storage-{}", rand::random::<u64>()));
+
         // set stale content serving so Cache::ForeverInCdn and Cache::ForeverInCdnAndStaleInBrowser
         // are actually different.
         config.cache_control_stale_while_revalidate = Some(86400);
@@ -372,6 +375,7 @@ impl TestEnvironment {
                     self.instance_metrics(),
                     self.config(),
                     self.storage(),
+                    self.runtime().block_on(self.async_storage()),
                     self.runtime(),
                 ))
             })
@@ -448,9 +452,21 @@ impl TestEnvironment {
     pub(crate) fn index(&self) -> Arc<Index> {
         self.index
             .get_or_init(|| {
+                let config = self.config();
+                let path = config.registry_index_path.clone();
                 Arc::new(
-                    Index::new(self.config().registry_index_path.clone())
-                        .expect("failed to initialize the index"),
+                    match config.registry_url.clone() {
+                        Some(registry_url) if Index::is_sparse_url(&registry_url) => {
+                            Index::from_sparse_url(
+                                path,
+                                registry_url,
+                                config.registry_changelog_url.clone(),
+                            )
+                        }
+                        Some(registry_url) => Index::from_url(path, registry_url),
+                        None => Index::new(path),
+                    }
+                    .expect("failed to initialize the index"),
                 )
             })
             .clone()
@@ -782,4 +798,10 @@ impl TestFrontend {
         debug!("getting {url} (no redirects)");
         self.client_no_redirect.request(Method::GET, url)
     }
+
+    pub(crate) fn post(&self, url: &str) -> RequestBuilder {
+        let url = self.build_url(url);
+        debug!("posting {url}");
+        self.client.request(Method::POST, url)
+    }
 }
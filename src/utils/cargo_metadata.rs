@@ -111,6 +111,14 @@ pub(crate) struct Target {
 }
 
 impl Target {
+    pub(crate) fn new(name: String, crate_types: Vec<String>, src_path: Option<String>) -> Self {
+        Target {
+            name,
+            crate_types,
+            src_path,
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn dummy_lib(name: String, src_path: Option<String>) -> Self {
         Target {
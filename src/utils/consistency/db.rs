@@ -15,12 +15,17 @@ pub(super) fn load(conn: &mut postgres::Client, config: &Config) -> Result<Crate
                  releases.yanked
              FROM crates
              INNER JOIN releases ON releases.crate_id = crates.id
+             -- alternative registries aren't in the index we compare with
+             WHERE crates.registry IS NULL
              UNION ALL 
              -- crates & releases that are already queued 
              -- don't have to be requeued.
              SELECT queue.name, queue.version, NULL as yanked
              FROM queue 
-             LEFT OUTER JOIN crates ON crates.name = queue.name 
+             LEFT OUTER JOIN crates ON (
+                 crates.name = queue.name AND
+                 crates.registry IS NULL
+             )
              LEFT OUTER JOIN releases ON (
                  releases.crate_id = crates.id AND 
                  releases.version = queue.version
@@ -1,12 +1,14 @@
 use crate::{db::delete, Context};
 use anyhow::{Context as _, Result};
 use itertools::Itertools;
+use std::collections::HashSet;
 use tracing::{info, warn};
 
 mod data;
 mod db;
 mod diff;
 mod index;
+mod storage;
 
 const BUILD_PRIORITY: i32 = 15;
 
@@ -67,6 +69,56 @@ pub fn run_check(ctx: &dyn Context, dry_run: bool) -> Result<()> {
     Ok(())
 }
 
+/// storage consistency check
+///
+/// will compare the releases and builds in our database with the archives and build logs in
+/// the storage, and fix the differences we find.
+///
+/// Differences that we check for, and the activities:
+/// * archive or archive index of a release not in the storage => queue a rebuild of the release.
+/// * archive in the storage, but no release in our DB => delete the archive from the storage.
+/// * build logs in the storage, but no build in our DB => delete the build logs from the storage.
+///
+/// Like [`run_check`], the command can just be re-run when activities fail.
+pub fn run_storage_check(ctx: &dyn Context, dry_run: bool) -> Result<()> {
+    info!("Comparing the storage with the database...");
+    let diff = ctx
+        .runtime()?
+        .block_on(async {
+            let storage = ctx.async_storage().await?;
+            let mut conn = ctx.pool()?.get_async().await?;
+            storage::find_differences(&mut conn, &storage, &ctx.config()?).await
+        })
+        .context("Comparing the storage with the database for consistency check")?;
+
+    let result = handle_storage_diff(ctx, diff.iter(), dry_run)?;
+
+    println!("============");
+    println!("SUMMARY");
+    println!("============");
+    println!("difference found:");
+    for (key, count) in diff.iter().counts_by(|el| match el {
+        storage::Difference::ArchiveNotInStorage(_, _) => "ArchiveNotInStorage",
+        storage::Difference::ArchiveIndexNotInStorage(_, _) => "ArchiveIndexNotInStorage",
+        storage::Difference::ArchiveNotInDb(_) => "ArchiveNotInDb",
+        storage::Difference::BuildLogsNotInDb(_) => "BuildLogsNotInDb",
+    }) {
+        println!("{key:24} => {count:4}");
+    }
+
+    println!("============");
+    if dry_run {
+        println!("activities that would have been triggered:");
+    } else {
+        println!("activities triggered:");
+    }
+    println!("builds queued:       {:4}", result.builds_queued);
+    println!("archives deleted:    {:4}", result.archives_deleted);
+    println!("build logs deleted:  {:4}", result.build_logs_deleted);
+
+    Ok(())
+}
+
 #[derive(Default)]
 struct HandleResult {
     builds_queued: u32,
@@ -92,7 +144,8 @@ where
         match difference {
             diff::Difference::CrateNotInIndex(name) => {
                 if !dry_run {
-                    if let Err(err) = delete::delete_crate(&mut conn, &storage, &config, name) {
+                    if let Err(err) = delete::delete_crate(&mut conn, &storage, &config, None, name)
+                    {
                         warn!("{:?}", err);
                     }
                 }
@@ -112,7 +165,8 @@ where
             diff::Difference::ReleaseNotInIndex(name, version) => {
                 if !dry_run {
                     if let Err(err) =
-                        delete::delete_version(&mut conn, &storage, &config, name, version)
+                        delete::delete_version(&mut conn, &storage, &config, None, name, version)
+                            .and_then(|_| build_queue.rebuild_crate_items(None, name))
                     {
                         warn!("{:?}", err);
                     }
@@ -129,7 +183,9 @@ where
             }
             diff::Difference::ReleaseYank(name, version, yanked) => {
                 if !dry_run {
-                    if let Err(err) = build_queue.set_yanked(&mut conn, name, version, *yanked) {
+                    if let Err(err) =
+                        build_queue.set_yanked(&mut conn, None, name, version, *yanked)
+                    {
                         warn!("{:?}", err);
                     }
                 }
@@ -141,6 +197,82 @@ where
     Ok(result)
 }
 
+#[derive(Default)]
+struct HandleStorageResult {
+    builds_queued: u32,
+    archives_deleted: u32,
+    build_logs_deleted: u32,
+}
+
+fn handle_storage_diff<'a, I>(
+    ctx: &dyn Context,
+    iter: I,
+    dry_run: bool,
+) -> Result<HandleStorageResult>
+where
+    I: Iterator<Item = &'a storage::Difference>,
+{
+    let mut result = HandleStorageResult::default();
+
+    let config = ctx.config()?;
+    let storage = ctx.storage()?;
+    let build_queue = ctx.build_queue()?;
+    // both archives of a release can be missing, one build restores them
+    let mut queued_releases = HashSet::new();
+
+    for difference in iter {
+        println!("{difference}");
+
+        match difference {
+            storage::Difference::ArchiveNotInStorage(release, _)
+            | storage::Difference::ArchiveIndexNotInStorage(release, _) => {
+                if !queued_releases.insert((&release.name, &release.registry, &release.version)) {
+                    continue;
+                }
+                if !dry_run {
+                    // the queue has the index URL of the registry, not its name
+                    let queued = release
+                        .registry
+                        .as_deref()
+                        .map(|registry| config.registry_index_url(registry))
+                        .transpose()
+                        .and_then(|registry_url| {
+                            build_queue.add_crate(
+                                &release.name,
+                                &release.version,
+                                BUILD_PRIORITY,
+                                registry_url,
+                            )
+                        });
+                    if let Err(err) = queued {
+                        warn!("{:?}", err);
+                    }
+                }
+                result.builds_queued += 1;
+            }
+            storage::Difference::ArchiveNotInDb(archive_path) => {
+                if !dry_run {
+                    // also deletes the indexes of the archive
+                    if let Err(err) = storage.delete_prefix(archive_path) {
+                        warn!("{:?}", err);
+                    }
+                }
+                result.archives_deleted += 1;
+            }
+            storage::Difference::BuildLogsNotInDb(build_id) => {
+                if !dry_run {
+                    if let Err(err) = storage.delete_prefix(&format!("build-logs/{build_id}/")) {
+                        warn!("{:?}", err);
+                    }
+                }
+                result.build_logs_deleted += 1;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
 #[cfg(test)]
 mod tests {
     use postgres_types::FromSql;
@@ -310,4 +442,57 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn test_storage_differences() {
+        wrapper(|env| {
+            let storage = env.storage();
+            storage.store_one("rustdoc/deleted/1.0.0.zip", b"zip".to_vec())?;
+            storage.store_one("rustdoc/deleted/1.0.0.zip.index", b"index".to_vec())?;
+            storage.store_one(
+                "build-logs/12345/x86_64-unknown-linux-gnu.txt",
+                b"log".to_vec(),
+            )?;
+
+            let release = storage::Release {
+                name: "krate".into(),
+                registry: None,
+                version: "0.1.1".into(),
+            };
+            let diff = [
+                storage::Difference::ArchiveNotInStorage(
+                    release.clone(),
+                    "rustdoc/krate/0.1.1.zip".into(),
+                ),
+                storage::Difference::ArchiveIndexNotInStorage(
+                    release,
+                    "sources/krate/0.1.1.zip".into(),
+                ),
+                storage::Difference::ArchiveNotInDb("rustdoc/deleted/1.0.0.zip".into()),
+                storage::Difference::BuildLogsNotInDb(12345),
+            ];
+
+            handle_storage_diff(env, diff.iter(), true)?;
+
+            assert!(env.build_queue().queued_crates()?.is_empty());
+            assert!(storage.exists("rustdoc/deleted/1.0.0.zip")?);
+            assert!(storage.exists("build-logs/12345/x86_64-unknown-linux-gnu.txt")?);
+
+            handle_storage_diff(env, diff.iter(), false)?;
+
+            assert_eq!(
+                env.build_queue()
+                    .queued_crates()?
+                    .iter()
+                    .map(|c| (c.name.as_str(), c.version.as_str(), c.priority))
+                    .collect::<Vec<_>>(),
+                vec![("krate", "0.1.1", 15)]
+            );
+            assert!(!storage.exists("rustdoc/deleted/1.0.0.zip")?);
+            assert!(!storage.exists("rustdoc/deleted/1.0.0.zip.index")?);
+            assert!(!storage.exists("build-logs/12345/x86_64-unknown-linux-gnu.txt")?);
+
+            Ok(())
+        })
+    }
 }
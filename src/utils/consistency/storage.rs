@@ -0,0 +1,375 @@
+use crate::{
+    storage::{crate_storage_name, rustdoc_archive_path, source_archive_path},
+    AsyncStorage, Config,
+};
+use anyhow::Result;
+use chrono::Utc;
+use futures_util::TryStreamExt;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt::Display,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Release {
+    pub(super) name: String,
+    pub(super) registry: Option<String>,
+    pub(super) version: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub(super) enum Difference {
+    /// The release should have the archive, but neither the archive nor its index are in
+    /// the storage.
+    ArchiveNotInStorage(Release, String),
+    /// The ZIP file of the archive is in the storage, but its index isn't.
+    ArchiveIndexNotInStorage(Release, String),
+    /// The archive is in the storage, but there's no release for it.
+    ArchiveNotInDb(String),
+    /// There are build logs in the storage for a build that doesn't exist.
+    BuildLogsNotInDb(i32),
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::ArchiveNotInStorage(release, archive_path) => {
+                write!(
+                    f,
+                    "Archive of release not in storage: {} {}, {archive_path}",
+                    release.name, release.version
+                )?;
+            }
+            Difference::ArchiveIndexNotInStorage(release, archive_path) => {
+                write!(
+                    f,
+                    "Archive index of release not in storage: {} {}, {archive_path}",
+                    release.name, release.version
+                )?;
+            }
+            Difference::ArchiveNotInDb(archive_path) => {
+                write!(f, "Archive in storage not in db: {archive_path}")?;
+            }
+            Difference::BuildLogsNotInDb(build_id) => {
+                write!(f, "Build logs in storage not in db: build-logs/{build_id}/")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveFile {
+    Zip,
+    ZipIndex,
+    BlobsIndex,
+}
+
+/// The files of an archive we found in the storage.
+#[derive(Debug, Default)]
+struct ArchiveFiles {
+    zip: bool,
+    zip_index: bool,
+    blobs_index: bool,
+}
+
+/// Splits the path of a file belonging to an archive into the path of the archive and the kind
+/// of the file. Returns `None` for all other files, like the ones of releases stored before we
+/// used archives.
+fn parse_archive_file(path: &str) -> Option<(&str, ArchiveFile)> {
+    let (archive_path, file) = [
+        (".zip", ArchiveFile::Zip),
+        (".zip.index", ArchiveFile::ZipIndex),
+        (".zip.blobs.index", ArchiveFile::BlobsIndex),
+    ]
+    .into_iter()
+    .find_map(|(suffix, file)| {
+        let rest = path.strip_suffix(suffix)?;
+        Some((&path[..rest.len() + ".zip".len()], file))
+    })?;
+
+    // `<rustdoc|sources>/<storage name>/<version>.zip`
+    let (top_level, rest) = archive_path.split_once('/')?;
+    if top_level != "rustdoc" && top_level != "sources" {
+        return None;
+    }
+    let (storage_name, version) = rest.strip_suffix(".zip")?.rsplit_once('/')?;
+    let valid_storage_name = match storage_name.split_once('/') {
+        None => !storage_name.is_empty(),
+        Some((registry, name)) => registry.starts_with('@') && !name.contains('/'),
+    };
+    if !valid_storage_name || semver::Version::parse(version).is_err() {
+        return None;
+    }
+
+    Some((archive_path, file))
+}
+
+fn parse_build_logs_path(path: &str) -> Option<i32> {
+    let (build_id, _) = path.strip_prefix("build-logs/")?.split_once('/')?;
+    build_id.parse().ok()
+}
+
+/// Compares the releases and builds in the database with the archives and build logs in the
+/// storage.
+///
+/// The storage is listed before the database is loaded, since builds add their files to the
+/// storage before they add the release and build to the database. Releases that are in the
+/// build queue are skipped for the same reason.
+pub(super) async fn find_differences(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+    config: &Config,
+) -> Result<Vec<Difference>> {
+    let listing_started = Utc::now();
+
+    let mut archives: BTreeMap<String, ArchiveFiles> = BTreeMap::new();
+    for prefix in ["rustdoc/", "sources/"] {
+        let mut paths = storage.list_prefix(prefix).await;
+        while let Some(path) = paths.try_next().await? {
+            let Some((archive_path, file)) = parse_archive_file(&path) else {
+                continue;
+            };
+            let files = archives.entry(archive_path.to_owned()).or_default();
+            match file {
+                ArchiveFile::Zip => files.zip = true,
+                ArchiveFile::ZipIndex => files.zip_index = true,
+                ArchiveFile::BlobsIndex => files.blobs_index = true,
+            }
+        }
+    }
+
+    let mut build_logs = BTreeSet::new();
+    let mut paths = storage.list_prefix("build-logs/").await;
+    while let Some(path) = paths.try_next().await? {
+        build_logs.extend(parse_build_logs_path(&path));
+    }
+
+    let releases = sqlx::query!(
+        r#"SELECT
+            crates.name,
+            crates.registry,
+            releases.version,
+            releases.rustdoc_status,
+            releases.archive_storage,
+            EXISTS (
+                SELECT 1 FROM builds
+                WHERE builds.rid = releases.id AND builds.build_time < $1
+            ) AS "built_before_listing!"
+         FROM releases
+         INNER JOIN crates ON crates.id = releases.crate_id
+         ORDER BY crates.name, releases.version"#,
+        listing_started,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let queued: HashSet<(String, String)> =
+        sqlx::query!("SELECT name, version, registry FROM queue")
+            .fetch_all(&mut *conn)
+            .await?
+            .into_iter()
+            .map(|row| {
+                // the queue has the index URL of the registry, crates.io might have one too
+                let registry = row
+                    .registry
+                    .as_deref()
+                    .and_then(|index_url| config.registry_for_index(index_url))
+                    .map(|registry| registry.name.as_str());
+                (
+                    crate_storage_name(registry, &row.name).into_owned(),
+                    row.version,
+                )
+            })
+            .collect();
+
+    let build_ids: HashSet<i32> = sqlx::query_scalar!("SELECT id FROM builds")
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .collect();
+
+    let mut differences = Vec::new();
+    let mut known_archives = HashSet::new();
+
+    for row in releases {
+        let storage_name = crate_storage_name(row.registry.as_deref(), &row.name);
+        let mut archive_paths = vec![source_archive_path(&storage_name, &row.version)];
+        if row.rustdoc_status {
+            archive_paths.push(rustdoc_archive_path(&storage_name, &row.version));
+        }
+        // releases without a rustdoc archive might still have one from an earlier build
+        known_archives.insert(rustdoc_archive_path(&storage_name, &row.version));
+        known_archives.insert(source_archive_path(&storage_name, &row.version));
+
+        // releases built while we listed the storage might not have been in the listing
+        if !row.archive_storage || !row.built_before_listing {
+            continue;
+        }
+
+        let release = Release {
+            name: row.name,
+            registry: row.registry,
+            version: row.version,
+        };
+        for archive_path in archive_paths {
+            match archives.get(&archive_path) {
+                Some(files) if files.blobs_index || (files.zip && files.zip_index) => {}
+                Some(files) if files.zip => differences.push(Difference::ArchiveIndexNotInStorage(
+                    release.clone(),
+                    archive_path,
+                )),
+                _ => differences.push(Difference::ArchiveNotInStorage(
+                    release.clone(),
+                    archive_path,
+                )),
+            }
+        }
+    }
+
+    for archive_path in archives.into_keys() {
+        if known_archives.contains(&archive_path) {
+            continue;
+        }
+        let is_queued = archive_path
+            .split_once('/')
+            .and_then(|(_, rest)| rest.strip_suffix(".zip"))
+            .and_then(|rest| rest.rsplit_once('/'))
+            .is_some_and(|(storage_name, version)| {
+                queued.contains(&(storage_name.to_owned(), version.to_owned()))
+            });
+        if !is_queued {
+            differences.push(Difference::ArchiveNotInDb(archive_path));
+        }
+    }
+
+    differences.extend(
+        build_logs
+            .into_iter()
+            .filter(|build_id| !build_ids.contains(build_id))
+            .map(Difference::BuildLogsNotInDb),
+    );
+
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::async_wrapper;
+
+    #[test]
+    fn test_parse_archive_file() {
+        assert_eq!(
+            parse_archive_file("rustdoc/foo/1.0.0.zip"),
+            Some(("rustdoc/foo/1.0.0.zip", ArchiveFile::Zip))
+        );
+        assert_eq!(
+            parse_archive_file("sources/@internal/foo/1.0.0-beta.1.zip.index"),
+            Some((
+                "sources/@internal/foo/1.0.0-beta.1.zip",
+                ArchiveFile::ZipIndex
+            ))
+        );
+        assert_eq!(
+            parse_archive_file("rustdoc/foo/1.0.0.zip.blobs.index"),
+            Some(("rustdoc/foo/1.0.0.zip", ArchiveFile::BlobsIndex))
+        );
+
+        for path in [
+            // files of releases stored before we used archives
+            "rustdoc/foo/1.0.0/foo/index.html",
+            "rustdoc/foo/1.0.0/foo/1.0.0.zip",
+            "sources/@internal/foo/1.0.0/1.0.0.zip",
+            "rustdoc/foo/not-a-version.zip",
+            "build-logs/1/foo.zip",
+        ] {
+            assert_eq!(parse_archive_file(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_find_differences() {
+        async_wrapper(|env| async move {
+            env.async_fake_release()
+                .await
+                .name("complete")
+                .version("1.0.0")
+                .archive_storage(true)
+                .create_async()
+                .await?;
+            env.async_fake_release()
+                .await
+                .name("missing-archive")
+                .version("1.0.0")
+                .archive_storage(true)
+                .create_async()
+                .await?;
+            env.async_fake_release()
+                .await
+                .name("missing-index")
+                .version("1.0.0")
+                .archive_storage(true)
+                .create_async()
+                .await?;
+
+            let storage = env.async_storage().await;
+            storage
+                .delete_prefix("rustdoc/missing-archive/1.0.0.zip")
+                .await?;
+            storage
+                .delete_prefix("sources/missing-index/1.0.0.zip.index")
+                .await?;
+            storage
+                .store_one("rustdoc/deleted/1.0.0.zip", b"zip".to_vec())
+                .await?;
+            storage
+                .store_one(
+                    "build-logs/12345/x86_64-unknown-linux-gnu.txt",
+                    b"log".to_vec(),
+                )
+                .await?;
+
+            // builds of the fake releases are added right now
+            let mut conn = env.async_db().await.async_conn().await;
+            sqlx::query!("UPDATE builds SET build_time = NOW() - INTERVAL '1 hour'")
+                .execute(&mut *conn)
+                .await?;
+
+            let release = |name: &str| Release {
+                name: name.into(),
+                registry: None,
+                version: "1.0.0".into(),
+            };
+            assert_eq!(
+                find_differences(&mut conn, &storage, &env.config()).await?,
+                vec![
+                    Difference::ArchiveNotInStorage(
+                        release("missing-archive"),
+                        "rustdoc/missing-archive/1.0.0.zip".into()
+                    ),
+                    Difference::ArchiveIndexNotInStorage(
+                        release("missing-index"),
+                        "sources/missing-index/1.0.0.zip".into()
+                    ),
+                    Difference::ArchiveNotInDb("rustdoc/deleted/1.0.0.zip".into()),
+                    Difference::BuildLogsNotInDb(12345),
+                ]
+            );
+
+            // the build of the deleted release might not be finished yet
+            sqlx::query!(
+                "INSERT INTO queue (name, version, priority) VALUES ('deleted', '1.0.0', 0)"
+            )
+            .execute(&mut *conn)
+            .await?;
+            assert!(!find_differences(&mut conn, &storage, &env.config())
+                .await?
+                .contains(&Difference::ArchiveNotInDb(
+                    "rustdoc/deleted/1.0.0.zip".into()
+                )));
+
+            Ok(())
+        })
+    }
+}
@@ -4,9 +4,9 @@
 
 use crate::{
     cdn,
-    utils::{queue_builder, report_error},
+    utils::{report_error, start_build_workers},
     web::start_web_server,
-    BuildQueue, Config, Context, Index, RustwideBuilder,
+    BuildQueue, Config, Context, Index,
 };
 use anyhow::{anyhow, Context as _, Error};
 use std::future::Future;
@@ -140,16 +140,7 @@ pub fn start_daemon<C: Context + Send + Sync + 'static>(
     }
 
     // build new crates every minute
-    let build_queue = context.build_queue()?;
-    let config = context.config()?;
-    let rustwide_builder = RustwideBuilder::init(&*context)?;
-    thread::Builder::new()
-        .name("build queue reader".to_string())
-        .spawn({
-            let context = context.clone();
-            move || queue_builder(&*context, rustwide_builder, build_queue, config).unwrap()
-        })
-        .unwrap();
+    start_build_workers(context.clone())?;
 
     start_background_repository_stats_updater(&*context)?;
     start_background_cdn_invalidator(&*context)?;
@@ -1,7 +1,30 @@
-use crate::web::page::TemplateData;
+use crate::{utils::spawn_blocking, web::page::TemplateData};
+use anyhow::Result;
+use async_stream::try_stream;
+use futures_util::Stream;
 use lol_html::element;
 use lol_html::errors::RewritingError;
+use std::{cell::RefCell, mem, sync::Arc};
 use tera::Context;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc,
+};
+use tracing::{Instrument as _, Span};
+
+/// How much of the page we read at once.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// How many chunks we read ahead of the rewriting.
+const READ_AHEAD_CHUNKS: usize = 4;
+
+/// The parts of the docs.rs page we put around the rustdoc content.
+struct RenderedTemplates {
+    head: String,
+    vendored_css: String,
+    body: String,
+    topbar: String,
+}
 
 /// Rewrite a rustdoc page to have the docs.rs topbar
 ///
@@ -9,20 +32,80 @@ use tera::Context;
 /// render the `rustdoc/` templates with the `html`.
 /// The output is an HTML page which has not yet been UTF-8 validated.
 /// In practice, the output should always be valid UTF-8.
-pub(crate) fn rewrite_lol(
-    html: &[u8],
+///
+/// The page is fed to the rewriter in chunks while it's read from `html`, and the rewritten
+/// chunks are returned as soon as they're ready, so we never hold the whole page in memory.
+/// Failures, including the [`RewritingError`] when the rewriting surpasses the memory limit, end
+/// the stream with an error, possibly after some rewritten chunks.
+pub(crate) async fn rewrite_lol(
+    mut html: impl AsyncRead + Unpin + Send + 'static,
     max_allowed_memory_usage: usize,
     ctx: Context,
-    templates: &TemplateData,
-) -> Result<Vec<u8>, RewritingError> {
+    templates: Arc<TemplateData>,
+) -> Result<impl Stream<Item = Result<Vec<u8>>> + Send + 'static> {
+    let templates = templates
+        .render_in_threadpool(move |templates| {
+            let templates = &templates.templates;
+            Ok(RenderedTemplates {
+                head: templates.render("rustdoc/head.html", &ctx)?,
+                vendored_css: templates.render("rustdoc/vendored.html", &ctx)?,
+                body: templates.render("rustdoc/body.html", &ctx)?,
+                topbar: templates.render("rustdoc/topbar.html", &ctx)?,
+            })
+        })
+        .await?;
+
+    // the rewriter isn't `Send`, so it runs on a blocking thread, waiting for the chunks we read
+    let (input_sender, input_receiver) = mpsc::channel::<Vec<u8>>(READ_AHEAD_CHUNKS);
+    let (output_sender, mut output_receiver) = mpsc::channel::<Vec<u8>>(READ_AHEAD_CHUNKS);
+    let rewriting = spawn_blocking(move || {
+        Ok(rewrite(
+            input_receiver,
+            output_sender,
+            max_allowed_memory_usage,
+            templates,
+        )?)
+    });
+    let reading = async move {
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = html.read(&mut buffer).await?;
+            // the rewriting only stops early when it failed, we get the error from it
+            if read == 0 || input_sender.send(buffer[..read].to_vec()).await.is_err() {
+                // dropping the sender tells the rewriter that the page is complete
+                return Ok(());
+            }
+        }
+    };
+    // Both stop when the returned stream is dropped, as their channels are closed then.
+    let pipeline = tokio::spawn(
+        async move { futures_util::try_join!(reading, rewriting).map(|_| ()) }
+            .instrument(Span::current()),
+    );
+
+    Ok(try_stream! {
+        while let Some(chunk) = output_receiver.recv().await {
+            yield chunk;
+        }
+        pipeline.await??;
+    })
+}
+
+fn rewrite(
+    mut input: mpsc::Receiver<Vec<u8>>,
+    output: mpsc::Sender<Vec<u8>>,
+    max_allowed_memory_usage: usize,
+    templates: RenderedTemplates,
+) -> Result<(), RewritingError> {
     use lol_html::html_content::{ContentType, Element};
     use lol_html::{HtmlRewriter, MemorySettings, Settings};
 
-    let templates = &templates.templates;
-    let tera_head = templates.render("rustdoc/head.html", &ctx).unwrap();
-    let tera_vendored_css = templates.render("rustdoc/vendored.html", &ctx).unwrap();
-    let tera_body = templates.render("rustdoc/body.html", &ctx).unwrap();
-    let tera_rustdoc_topbar = templates.render("rustdoc/topbar.html", &ctx).unwrap();
+    let RenderedTemplates {
+        head: tera_head,
+        vendored_css: tera_vendored_css,
+        body: tera_body,
+        topbar: tera_rustdoc_topbar,
+    } = templates;
 
     // Before: <body> ... rustdoc content ... </body>
     // After:
@@ -94,16 +177,26 @@ pub(crate) fn rewrite_lol(
     };
 
     // The input and output are always strings, we just use `&[u8]` so we only have to validate once.
-    let mut buffer = Vec::new();
-    // TODO: Make the rewriter persistent?
+    let buffer = RefCell::new(Vec::new());
     let mut writer = HtmlRewriter::new(settings, |bytes: &[u8]| {
-        buffer.extend_from_slice(bytes);
+        buffer.borrow_mut().extend_from_slice(bytes);
     });
+    // the rewriter calls the sink with tiny pieces, we send what it wrote for each input chunk
+    let flush = || {
+        let chunk = mem::take(&mut *buffer.borrow_mut());
+        // fails when nobody reads the page anymore
+        chunk.is_empty() || output.blocking_send(chunk).is_ok()
+    };
 
-    writer.write(html)?;
+    while let Some(chunk) = input.blocking_recv() {
+        writer.write(&chunk)?;
+        if !flush() {
+            return Ok(());
+        }
+    }
     writer.end()?;
-
-    Ok(buffer)
+    flush();
+    Ok(())
 }
 
 #[cfg(test)]
@@ -125,7 +218,7 @@ mod test {
                             <link rel="stylesheet" type="text/css" href="../../../main-20160728-1.12.0-nightly-54c0dcfd6.css">
                         </head>
                     </html>
-                "#)
+                "#.to_vec())
                 // A somewhat representative rustdoc html file from late 2022
                 .rustdoc_file_with("2022/index.html", br#"
                     <html>
@@ -139,7 +232,7 @@ mod test {
                             <noscript><link rel="stylesheet" href="/-/rustdoc.static/noscript-13285aec31fa243e.css"></noscript>
                         </head>
                     </html>
-                "#)
+                "#.to_vec())
                 .create()?;
 
             let output = env.frontend().get("/testing/0.1.0/2016/").send()?.text()?;
@@ -151,4 +244,83 @@ mod test {
             Ok(())
         });
     }
+
+    #[test]
+    fn rewriting_pages_larger_than_a_chunk() {
+        wrapper(|env| {
+            let item = r#"<div class="item">item</div>"#;
+            let page = format!(
+                "<html><head></head><body>{}<p>the end</p></body></html>",
+                item.repeat(10_000)
+            );
+            assert!(page.len() > 10 * super::CHUNK_SIZE);
+
+            env.fake_release()
+                .name("testing")
+                .version("0.1.0")
+                .rustdoc_file_with("big/index.html", page.into_bytes())
+                .create()?;
+
+            let output = env.frontend().get("/testing/0.1.0/big/").send()?.text()?;
+            assert!(output.contains("rustdoc_body_wrapper"));
+            assert_eq!(output.matches(item).count(), 10_000);
+            assert!(output.contains("<p>the end</p>"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn memory_limit_early_in_the_page() {
+        wrapper(|env| {
+            env.override_config(|config| config.max_parse_memory = 64 * 1024);
+
+            let page = format!(
+                r#"<html><head><link rel="stylesheet" href="rustdoc-{}.css"></head><body></body></html>"#,
+                "x".repeat(256 * 1024),
+            );
+            env.fake_release()
+                .name("testing")
+                .version("0.1.0")
+                .rustdoc_file_with("big/index.html", page.into_bytes())
+                .create()?;
+
+            let response = env.frontend().get("/testing/0.1.0/big/").send()?;
+            assert_eq!(
+                response.status(),
+                reqwest::StatusCode::INTERNAL_SERVER_ERROR
+            );
+            assert!(!response.text()?.contains("rustdoc_body_wrapper"));
+            assert_eq!(env.instance_metrics().html_rewrite_ooms.get(), 1);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn memory_limit_late_in_the_page() {
+        wrapper(|env| {
+            env.override_config(|config| config.max_parse_memory = 64 * 1024);
+
+            // the rewriting only runs out of memory at the huge stylesheet link, after many chunks
+            let page = format!(
+                r#"<html><head></head><body>{}<link rel="stylesheet" href="rustdoc-{}.css"></body></html>"#,
+                "<p>item</p>".repeat(20_000),
+                "x".repeat(256 * 1024),
+            );
+            env.fake_release()
+                .name("testing")
+                .version("0.1.0")
+                .rustdoc_file_with("big/index.html", page.into_bytes())
+                .create()?;
+
+            // we already started responding, so the response is aborted instead
+            let response = env.frontend().get("/testing/0.1.0/big/").send()?;
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+            assert!(response.text().is_err());
+            assert_eq!(env.instance_metrics().html_rewrite_ooms.get(), 1);
+
+            Ok(())
+        });
+    }
 }
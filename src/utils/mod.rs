@@ -1,6 +1,6 @@
 //! Various utilities for docs.rs
 
-pub(crate) use self::cargo_metadata::{CargoMetadata, Package as MetadataPackage};
+pub(crate) use self::cargo_metadata::{CargoMetadata, Package as MetadataPackage, Target};
 pub(crate) use self::copy::copy_dir_all;
 pub use self::daemon::{start_daemon, watch_registry};
 pub(crate) use self::html::rewrite_lol;
@@ -8,11 +8,11 @@ pub use self::queue::{
     get_crate_pattern_and_priority, get_crate_priority, list_crate_priorities,
     remove_crate_priority, set_crate_priority,
 };
-pub use self::queue_builder::queue_builder;
+pub use self::queue_builder::{queue_builder, start_build_workers, BuildWorker};
 pub(crate) use self::rustc_version::{get_correct_docsrs_style_file, parse_rustc_version};
 
 #[cfg(test)]
-pub(crate) use self::cargo_metadata::{Dependency, Target};
+pub(crate) use self::cargo_metadata::Dependency;
 
 mod cargo_metadata;
 #[cfg(feature = "consistency_check")]
@@ -23,6 +23,7 @@ mod html;
 mod queue;
 pub(crate) mod queue_builder;
 mod rustc_version;
+pub(crate) mod search_index;
 use anyhow::Result;
 use postgres::Client;
 use serde::de::DeserializeOwned;
@@ -54,6 +55,7 @@ pub(crate) fn report_error(err: &anyhow::Error) {
 pub enum ConfigName {
     RustcVersion,
     LastSeenIndexReference,
+    LastSeenSparseIndexState,
     QueueLocked,
     Toolchain,
 }
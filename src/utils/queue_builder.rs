@@ -2,25 +2,92 @@ use crate::Context;
 use crate::{docbuilder::RustwideBuilder, utils::report_error, BuildQueue, Config};
 use anyhow::{Context as _, Error};
 use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::Duration;
 use std::{fs, io, path::Path, thread};
-use tracing::{debug, error, warn};
+use tracing::{debug, error, info, warn};
+
+/// One of the build workers running on this server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildWorker {
+    /// Name the worker leases queue entries with.
+    pub(crate) name: String,
+    pub(crate) rustwide_workspace: PathBuf,
+    pub(crate) temp_dir: PathBuf,
+}
+
+impl BuildWorker {
+    /// All workers configured for this server.
+    ///
+    /// A single worker uses the configured workspace and temporary directory directly.
+    /// With more workers, each one gets its own subdirectories, since rustwide workspaces
+    /// can't be shared and every worker cleans up its temporary directory.
+    pub(crate) fn all(config: &Config) -> Vec<Self> {
+        if config.build_workers <= 1 {
+            return vec![Self {
+                name: config.build_worker_name.clone(),
+                rustwide_workspace: config.rustwide_workspace.clone(),
+                temp_dir: config.temp_dir.clone(),
+            }];
+        }
+
+        (0..config.build_workers)
+            .map(|i| Self {
+                name: format!("{}-{}", config.build_worker_name, i),
+                rustwide_workspace: config.rustwide_workspace.join(format!("worker-{i}")),
+                temp_dir: config.temp_dir.join(format!("worker-{i}")),
+            })
+            .collect()
+    }
+}
+
+/// Starts a thread running [`queue_builder`] for every configured build worker.
+pub fn start_build_workers<C: Context + Send + Sync + 'static>(
+    context: Arc<C>,
+) -> Result<Vec<JoinHandle<()>>, Error> {
+    let build_queue = context.build_queue()?;
+    let config = context.config()?;
+
+    let mut handles = Vec::new();
+    for worker in BuildWorker::all(&config) {
+        info!("starting build worker {}", worker.name);
+        let rustwide_builder = RustwideBuilder::init_for_worker(&*context, &worker)?;
+        let handle = thread::Builder::new()
+            .name(format!("build worker {}", worker.name))
+            .spawn({
+                let context = context.clone();
+                let build_queue = build_queue.clone();
+                move || queue_builder(&*context, rustwide_builder, build_queue, worker).unwrap()
+            })?;
+        handles.push(handle);
+    }
+
+    Ok(handles)
+}
 
 pub fn queue_builder(
     context: &dyn Context,
     mut builder: RustwideBuilder,
     build_queue: Arc<BuildQueue>,
-    config: Arc<Config>,
+    worker: BuildWorker,
 ) -> Result<(), Error> {
     loop {
-        if let Err(e) = remove_tempdirs(&config.temp_dir) {
+        if let Err(e) = remove_tempdirs(&worker.temp_dir) {
             report_error(&anyhow::anyhow!(e).context(format!(
                 "failed to clean temporary directory {:?}",
-                &config.temp_dir
+                &worker.temp_dir
             )));
         }
 
+        if let Err(err) = build_queue
+            .update_worker_status(&worker.name, None)
+            .context("could not update worker status")
+        {
+            report_error(&err);
+        }
+
         // check lock file
         match build_queue.is_locked().context("could not get queue lock") {
             Ok(true) => {
@@ -39,7 +106,7 @@ pub fn queue_builder(
         // If a panic occurs while building a crate, lock the queue until an admin has a chance to look at it.
         debug!("Checking build queue");
         let res = catch_unwind(AssertUnwindSafe(|| {
-            match build_queue.build_next_queue_package(context, &mut builder) {
+            match build_queue.build_next_queue_package(context, &mut builder, &worker.name) {
                 Ok(true) => {}
                 Ok(false) => {
                     debug!("Queue is empty, going back to sleep");
@@ -67,3 +134,52 @@ fn remove_tempdirs<P: AsRef<Path>>(path: P) -> Result<(), io::Error> {
     fs::create_dir_all(&path)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_worker_uses_configured_directories() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_workers = 1;
+                config.build_worker_name = "builder".into();
+            });
+            let config = env.config();
+
+            assert_eq!(
+                BuildWorker::all(&config),
+                vec![BuildWorker {
+                    name: "builder".into(),
+                    rustwide_workspace: config.rustwide_workspace.clone(),
+                    temp_dir: config.temp_dir.clone(),
+                }]
+            );
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn multiple_workers_get_separate_directories() {
+        crate::test::wrapper(|env| {
+            env.override_config(|config| {
+                config.build_workers = 2;
+                config.build_worker_name = "builder".into();
+            });
+            let config = env.config();
+
+            let workers = BuildWorker::all(&config);
+            assert_eq!(
+                workers.iter().map(|w| w.name.as_str()).collect::<Vec<_>>(),
+                ["builder-0", "builder-1"]
+            );
+            assert_eq!(
+                workers[1].rustwide_workspace,
+                config.rustwide_workspace.join("worker-1")
+            );
+            assert_eq!(workers[1].temp_dir, config.temp_dir.join("worker-1"));
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,721 @@
+//! Reading the search index rustdoc generates next to the documentation of a crate.
+//!
+//! Newer rustdoc versions keep the descriptions of the items in separate files, the description
+//! shards, see [`desc_shard_path`].
+
+use crate::{storage::PathNotFoundError, utils::spawn_blocking, AsyncStorage};
+use anyhow::{anyhow, bail, ensure, Context as _, Result};
+use base64::{engine::general_purpose::STANDARD as b64, Engine};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{HashSet, VecDeque},
+    fs, io,
+    path::Path,
+};
+
+/// The names of the item types, by the number rustdoc uses for them in the search index.
+const ITEM_TYPES: &[&str] = &[
+    "mod",
+    "externcrate",
+    "import",
+    "struct",
+    "enum",
+    "fn",
+    "type",
+    "static",
+    "trait",
+    "impl",
+    "tymethod",
+    "method",
+    "structfield",
+    "variant",
+    "macro",
+    "primitive",
+    "associatedtype",
+    "constant",
+    "associatedconstant",
+    "union",
+    "foreigntype",
+    "keyword",
+    "opaque",
+    "attr",
+    "derive",
+    "traitalias",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct SearchItem {
+    /// The full path of the item, like `foo::bar::Baz::new`.
+    pub(crate) path: String,
+    pub(crate) kind: &'static str,
+    pub(crate) description: Option<String>,
+    /// The page of the item, relative to the documentation of the target.
+    pub(crate) url: String,
+}
+
+impl SearchItem {
+    pub(crate) fn name(&self) -> &str {
+        self.path.rsplit("::").next().unwrap_or(&self.path)
+    }
+
+    /// Whether the item has its own page, rather than a section on the page of its parent.
+    pub(crate) fn has_own_page(&self) -> bool {
+        !self.url.contains('#')
+    }
+}
+
+fn item_type(value: &Value) -> Option<&'static str> {
+    let index = match value {
+        Value::Number(number) => number.as_u64()? as usize,
+        // newer versions encode the types of all items as a string, one letter per item
+        Value::String(letter) => (letter.chars().next()? as usize).checked_sub('A' as usize)?,
+        _ => return None,
+    };
+    ITEM_TYPES.get(index).copied()
+}
+
+/// Returns the JSON in the `JSON.parse('…')` call of a `search-index.js`.
+fn extract_json(js: &str) -> Result<String> {
+    let start = js
+        .find("JSON.parse('")
+        .ok_or_else(|| anyhow!("unsupported search index format"))?
+        + "JSON.parse('".len();
+
+    // undo the escaping of the javascript string literal. Older versions also split it into
+    // lines ending with a backslash.
+    let mut json = String::new();
+    let mut chars = js[start..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(escaped) => json.push(escaped),
+                None => break,
+            },
+            '\'' => return Ok(json),
+            c => json.push(c),
+        }
+    }
+    Err(anyhow!("unterminated search index"))
+}
+
+/// Decodes the numbers rustdoc writes as hex digits, where the last digit of each number is
+/// lowercase, with the sign in the lowest bit.
+fn decode_vlq_hex(encoded: &str) -> Result<Vec<i64>> {
+    let mut numbers = Vec::new();
+    // the last 16 numbers that were written out, most recent first
+    let mut recent = VecDeque::new();
+    let mut value: Option<i64> = None;
+    for c in encoded.chars() {
+        match (c, value) {
+            // backreferences to one of the recent numbers
+            ('0'..='?', None) => numbers.push(
+                *recent
+                    .get(c as usize - '0' as usize)
+                    .ok_or_else(|| anyhow!("invalid backreference in {encoded:?}"))?,
+            ),
+            // zero doesn't take part in the backreferences
+            ('`', None) => numbers.push(0),
+            ('@'..='O', _) => value = Some((value.unwrap_or(0) << 4) | (c as i64 - '@' as i64)),
+            ('`'..='o', _) => {
+                let zigzag = (value.take().unwrap_or(0) << 4) | (c as i64 - '`' as i64);
+                let number = if zigzag & 1 == 1 {
+                    -(zigzag >> 1)
+                } else {
+                    zigzag >> 1
+                };
+                numbers.push(number);
+                recent.push_front(number);
+                recent.truncate(16);
+            }
+            _ => bail!("invalid character {c:?} in {encoded:?}"),
+        }
+    }
+    ensure!(value.is_none(), "unterminated number in {encoded:?}");
+    Ok(numbers)
+}
+
+/// Decodes the base64 encoded roaring bitmaps in the search index, in the serialization format
+/// of the `roaring` crate.
+fn decode_bitmap(encoded: &str) -> Result<HashSet<u32>> {
+    const NO_RUN_COOKIE: u16 = 12346;
+    const RUN_COOKIE: u16 = 12347;
+
+    let bytes = b64.decode(encoded)?;
+    let mut position = 0;
+    let mut read = |len: usize| -> Result<&[u8]> {
+        let slice = bytes
+            .get(position..position + len)
+            .ok_or_else(|| anyhow!("truncated bitmap"))?;
+        position += len;
+        Ok(slice)
+    };
+    let read_u16 = |bytes: &[u8]| u16::from_le_bytes([bytes[0], bytes[1]]);
+
+    let cookie = read(4)?.to_vec();
+    let (containers, runs) = match read_u16(&cookie) {
+        NO_RUN_COOKIE => {
+            let count = read(4)?;
+            (
+                u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize,
+                Vec::new(),
+            )
+        }
+        RUN_COOKIE => {
+            let containers = read_u16(&cookie[2..]) as usize + 1;
+            (containers, read(containers.div_ceil(8))?.to_vec())
+        }
+        cookie => bail!("unknown bitmap cookie {cookie}"),
+    };
+
+    let mut headers = Vec::with_capacity(containers);
+    for _ in 0..containers {
+        let header = read(4)?;
+        let key = (read_u16(header) as u32) << 16;
+        let cardinality = read_u16(&header[2..]) as usize + 1;
+        headers.push((key, cardinality));
+    }
+    if runs.is_empty() || containers >= 4 {
+        // the offsets of the containers, they follow each other anyway
+        read(4 * containers)?;
+    }
+
+    let mut values = HashSet::new();
+    for (index, (key, cardinality)) in headers.into_iter().enumerate() {
+        if runs
+            .get(index / 8)
+            .is_some_and(|run| run & (1 << (index % 8)) != 0)
+        {
+            let count = read_u16(read(2)?) as usize;
+            for run in read(4 * count)?.chunks(4) {
+                let start = read_u16(run) as u32;
+                let len = read_u16(&run[2..]) as u32;
+                values.extend((start..=start + len).map(|value| key | value));
+            }
+        } else if cardinality >= 4096 {
+            for (word_index, word) in read(8192)?.chunks(8).enumerate() {
+                let word = u64::from_le_bytes(word.try_into()?);
+                values.extend(
+                    (0..64)
+                        .filter(|bit| word & (1 << bit) != 0)
+                        .map(|bit| key | (word_index as u32 * 64 + bit)),
+                );
+            }
+        } else {
+            values.extend(
+                read(2 * cardinality)?
+                    .chunks(2)
+                    .map(|value| key | read_u16(value) as u32),
+            );
+        }
+    }
+    Ok(values)
+}
+
+/// The descriptions of the crate and its items, by their position in the search index, where
+/// the crate comes first.
+///
+/// Older rustdoc versions have the descriptions in the index, newer ones in the description
+/// shards. Descriptions we don't have the shards for are missing.
+fn descriptions(index: &Value, desc_shards: &[String]) -> Result<Vec<Option<String>>> {
+    let non_empty = |description: &str| Some(description.to_owned()).filter(|d| !d.is_empty());
+
+    if let Some(descriptions) = index.get("d").and_then(Value::as_array) {
+        let crate_description = index.get("doc").and_then(Value::as_str).and_then(non_empty);
+        return Ok(std::iter::once(crate_description)
+            .chain(
+                descriptions
+                    .iter()
+                    .map(|description| description.as_str().and_then(non_empty)),
+            )
+            .collect());
+    }
+
+    let Some(empty) = index.get("e").and_then(Value::as_str) else {
+        return Ok(Vec::new());
+    };
+    let empty = decode_bitmap(empty).context("invalid empty descriptions")?;
+    let items = index.get("n").and_then(Value::as_array).map_or(0, Vec::len);
+    let mut lines = desc_shards.iter().flat_map(|shard| shard.split('\n'));
+    Ok((0..=items as u32)
+        .map(|position| {
+            if empty.contains(&position) {
+                None
+            } else {
+                lines.next().and_then(non_empty)
+            }
+        })
+        .collect())
+}
+
+/// Reads the items of one crate from the search index rustdoc generated.
+fn crate_items(
+    crate_name: &str,
+    index: &Value,
+    descriptions: &[Option<String>],
+) -> Vec<SearchItem> {
+    let names = index.get("n").and_then(Value::as_array);
+    let Some(names) = names else {
+        return Vec::new();
+    };
+
+    let types: Vec<Option<&'static str>> = match index.get("t") {
+        Some(Value::String(types)) => types
+            .chars()
+            .map(|c| item_type(&Value::String(c.into())))
+            .collect(),
+        Some(Value::Array(types)) => types.iter().map(item_type).collect(),
+        _ => return Vec::new(),
+    };
+
+    // the module paths of the items, which only change at the indexes listed here
+    let mut paths = vec![None; names.len()];
+    for (position, entry) in index
+        .get("q")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .enumerate()
+    {
+        match entry {
+            Value::Array(entry) => {
+                if let (Some(index), Some(path)) = (
+                    entry.first().and_then(Value::as_u64),
+                    entry.get(1).and_then(Value::as_str),
+                ) {
+                    if let Some(slot) = paths.get_mut(index as usize) {
+                        *slot = Some(path);
+                    }
+                }
+            }
+            // older versions list the path of every item, empty when it didn't change
+            Value::String(path) if !path.is_empty() => {
+                if let Some(slot) = paths.get_mut(position) {
+                    *slot = Some(path.as_str());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let description = |position: usize| descriptions.get(position).cloned().flatten();
+    let parent_indexes = index.get("i").and_then(Value::as_array);
+    let parents: Vec<Option<(&'static str, &str)>> = index
+        .get("p")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|parent| {
+            let parent = parent.as_array()?;
+            Some((item_type(parent.first()?)?, parent.get(1)?.as_str()?))
+        })
+        .collect();
+
+    let mut items = vec![SearchItem {
+        path: crate_name.to_owned(),
+        kind: "mod",
+        description: description(0),
+        url: format!("{crate_name}/index.html"),
+    }];
+
+    let mut module_path = crate_name;
+    for (position, name) in names.iter().enumerate() {
+        if let Some(path) = paths[position] {
+            module_path = path;
+        }
+        let (Some(name), Some(&Some(kind))) = (name.as_str(), types.get(position)) else {
+            continue;
+        };
+        if name.is_empty() || matches!(kind, "externcrate" | "import" | "impl") {
+            continue;
+        }
+
+        let parent = parent_indexes
+            .and_then(|indexes| indexes.get(position)?.as_u64())
+            .filter(|index| *index > 0)
+            .and_then(|index| parents.get(index as usize - 1).copied().flatten());
+
+        let directory = module_path.replace("::", "/");
+        let (path, url) = match (parent, kind) {
+            (Some((parent_kind, parent_name)), _) => (
+                format!("{module_path}::{parent_name}::{name}"),
+                format!("{directory}/{parent_kind}.{parent_name}.html#{kind}.{name}"),
+            ),
+            // members without a parent we know of don't have a page we could link to
+            (
+                None,
+                "tymethod" | "method" | "structfield" | "variant" | "associatedtype"
+                | "associatedconstant",
+            ) => continue,
+            (None, "mod") => (
+                format!("{module_path}::{name}"),
+                format!("{directory}/{name}/index.html"),
+            ),
+            (None, kind) => (
+                format!("{module_path}::{name}"),
+                format!("{directory}/{kind}.{name}.html"),
+            ),
+        };
+
+        items.push(SearchItem {
+            path,
+            kind,
+            description: description(position + 1),
+            url,
+        });
+    }
+
+    items
+}
+
+/// The search index of one crate.
+pub(crate) struct SearchIndex {
+    crate_name: String,
+    index: Value,
+}
+
+impl SearchIndex {
+    /// Reads the index of the crate called `crate_name` from a `search-index.js`, `None` when
+    /// the crate isn't in there.
+    pub(crate) fn parse(js: &str, crate_name: &str) -> Result<Option<Self>> {
+        let mut index: Value = serde_json::from_str(&extract_json(js)?)?;
+
+        // a map of the crates to their index, written as a list of pairs by newer versions.
+        let crate_index = match &mut index {
+            Value::Object(crates) => crates.remove(crate_name),
+            Value::Array(crates) => crates.iter_mut().find_map(|entry| {
+                let entry = entry.as_array_mut()?;
+                if entry.first()?.as_str()? != crate_name {
+                    return None;
+                }
+                Some(entry.get_mut(1)?.take())
+            }),
+            _ => None,
+        };
+
+        Ok(crate_index.map(|index| Self {
+            crate_name: crate_name.to_owned(),
+            index,
+        }))
+    }
+
+    /// The number of description shards, zero for older rustdoc versions.
+    pub(crate) fn desc_shards(&self) -> Result<usize> {
+        Ok(match self.index.get("D").and_then(Value::as_str) {
+            Some(lengths) => decode_vlq_hex(lengths)
+                .context("invalid description shards")?
+                .len(),
+            None => 0,
+        })
+    }
+
+    /// The items of the crate, with the descriptions in the content of the description shards,
+    /// see [`parse_desc_shard`].
+    pub(crate) fn items(&self, desc_shards: &[String]) -> Result<Vec<SearchItem>> {
+        let descriptions = descriptions(&self.index, desc_shards)?;
+        Ok(crate_items(&self.crate_name, &self.index, &descriptions))
+    }
+}
+
+/// The path of a description shard, relative to the documentation of the target.
+pub(crate) fn desc_shard_path(crate_name: &str, shard: usize, resource_suffix: &str) -> String {
+    format!("search.desc/{crate_name}/{crate_name}-desc-{shard}-{resource_suffix}.js")
+}
+
+/// Returns the descriptions in a description shard, one per line.
+pub(crate) fn parse_desc_shard(js: &str) -> Result<String> {
+    // `searchState.loadedDescShard("foo", 0, "…")`
+    let start = js
+        .find("loadedDescShard(")
+        .ok_or_else(|| anyhow!("unsupported description shard format"))?
+        + "loadedDescShard(".len();
+    let end = js
+        .rfind(')')
+        .filter(|end| *end >= start)
+        .ok_or_else(|| anyhow!("unterminated description shard"))?;
+    let arguments: Vec<Value> = serde_json::from_str(&format!("[{}]", &js[start..end]))?;
+    Ok(arguments
+        .get(2)
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("description shard without descriptions"))?
+        .to_owned())
+}
+
+/// Returns the value of the `data-resource-suffix` attribute rustdoc adds to its pages,
+/// which is part of the name of the search index.
+pub(crate) fn resource_suffix(html: &str) -> Option<&str> {
+    let start = html.find("data-resource-suffix=\"")? + "data-resource-suffix=\"".len();
+    let end = html[start..].find('"')? + start;
+    Some(&html[start..end])
+}
+
+/// Reads the items of the crate called `crate_name` from the search index in the rustdoc
+/// output at `doc_dir`. Returns `None` when there's no search index.
+pub(crate) fn read_search_index(
+    doc_dir: &Path,
+    crate_name: &str,
+) -> Result<Option<Vec<SearchItem>>> {
+    let crate_page = match fs::read_to_string(doc_dir.join(crate_name).join("index.html")) {
+        Ok(page) => page,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let suffix = resource_suffix(&crate_page).unwrap_or_default();
+    let js = match fs::read_to_string(doc_dir.join(format!("search-index{suffix}.js"))) {
+        Ok(js) => js,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context("failed to read the search index"),
+    };
+    let Some(index) = SearchIndex::parse(&js, crate_name)? else {
+        return Ok(Some(Vec::new()));
+    };
+
+    let mut desc_shards = Vec::new();
+    for shard in 0..index.desc_shards()? {
+        let js = fs::read_to_string(doc_dir.join(desc_shard_path(crate_name, shard, suffix)))
+            .context("failed to read a description shard")?;
+        desc_shards.push(parse_desc_shard(&js)?);
+    }
+    index.items(&desc_shards).map(Some)
+}
+
+/// Reads the items of the crate called `crate_name` from the search index of stored
+/// documentation, like [`read_search_index`] does for the output of a build. `doc_prefix` is
+/// the directory of the documentation of the target, empty for the default target.
+pub(crate) async fn fetch_search_index(
+    storage: &AsyncStorage,
+    storage_name: &str,
+    version: &str,
+    build_id: i32,
+    archive_storage: bool,
+    doc_prefix: &str,
+    crate_name: &str,
+) -> Result<Option<Vec<SearchItem>>> {
+    let fetch = |path: String| async move {
+        // boxed, the future of fetching from archives is too deeply nested for the compiler
+        // to compute the layout of the futures awaiting it
+        match Box::pin(storage.fetch_rustdoc_file(
+            storage_name,
+            version,
+            build_id,
+            &format!("{doc_prefix}{path}"),
+            archive_storage,
+            None,
+        ))
+        .await
+        {
+            Ok(blob) => Ok(Some(blob.content)),
+            Err(err) if err.is::<PathNotFoundError>() => Ok(None),
+            Err(err) => Err(err),
+        }
+    };
+
+    let Some(crate_page) = fetch(format!("{crate_name}/index.html")).await? else {
+        return Ok(None);
+    };
+    let suffix = resource_suffix(&String::from_utf8_lossy(&crate_page))
+        .unwrap_or_default()
+        .to_owned();
+    let Some(search_index) = fetch(format!("search-index{suffix}.js")).await? else {
+        return Ok(None);
+    };
+
+    let name = crate_name.to_owned();
+    let Some(index) = spawn_blocking(move || {
+        let js = String::from_utf8(search_index).context("search index isn't UTF-8")?;
+        SearchIndex::parse(&js, &name)
+    })
+    .await?
+    else {
+        return Ok(Some(Vec::new()));
+    };
+
+    let mut desc_shards = Vec::new();
+    for shard in 0..index.desc_shards()? {
+        let Some(js) = fetch(desc_shard_path(crate_name, shard, &suffix)).await? else {
+            // the items are still useful without descriptions
+            desc_shards.clear();
+            break;
+        };
+        desc_shards.push(parse_desc_shard(&String::from_utf8_lossy(&js))?);
+    }
+    spawn_blocking(move || index.items(&desc_shards))
+        .await
+        .map(Some)
+}
+
+/// A search index in the format of rustdoc 1.79, with its descriptions in [`TEST_DESC_SHARDS`].
+#[cfg(test)]
+pub(crate) const TEST_SEARCH_INDEX: &str = r#"var searchIndex = new Map(JSON.parse('[\
+["foo",{"t":"ADLLNIF","n":["bar","Baz","new","value","Qux","Trait","run"],"q":[[0,"foo"],[1,"foo::bar"]],"D":"fd","e":"OjAAAAEAAAAAAAIAEAAAAAQABgAHAA==","i":[0,0,1,1,2,0,0],"p":[[3,"Baz"],[4,"Kind"]]}]\
+]'));
+if (typeof exports !== 'undefined') exports.searchIndex = searchIndex;"#;
+
+#[cfg(test)]
+pub(crate) const TEST_DESC_SHARDS: [&str; 2] = [
+    r#"searchState.loadedDescShard("foo", 0, "The foo crate\nA module\nA struct")"#,
+    r#"searchState.loadedDescShard("foo", 1, "Creates a 'Baz'\nA variant")"#,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(path: &str, kind: &'static str, url: &str) -> SearchItem {
+        SearchItem {
+            path: path.into(),
+            kind,
+            description: None,
+            url: url.into(),
+        }
+    }
+
+    fn parse_search_index(js: &str, crate_name: &str, desc_shards: &[&str]) -> Vec<SearchItem> {
+        let desc_shards: Vec<_> = desc_shards
+            .iter()
+            .map(|shard| parse_desc_shard(shard).unwrap())
+            .collect();
+        SearchIndex::parse(js, crate_name)
+            .unwrap()
+            .map(|index| index.items(&desc_shards).unwrap())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn parse_index() {
+        let index = SearchIndex::parse(TEST_SEARCH_INDEX, "foo")
+            .unwrap()
+            .unwrap();
+        assert_eq!(index.desc_shards().unwrap(), 2);
+        let items = parse_search_index(TEST_SEARCH_INDEX, "foo", &TEST_DESC_SHARDS);
+        let paths: Vec<_> = items
+            .iter()
+            .map(|item| (item.path.as_str(), item.kind, item.url.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                ("foo", "mod", "foo/index.html"),
+                ("foo::bar", "mod", "foo/bar/index.html"),
+                ("foo::bar::Baz", "struct", "foo/bar/struct.Baz.html"),
+                (
+                    "foo::bar::Baz::new",
+                    "method",
+                    "foo/bar/struct.Baz.html#method.new"
+                ),
+                (
+                    "foo::bar::Baz::value",
+                    "method",
+                    "foo/bar/struct.Baz.html#method.value"
+                ),
+                (
+                    "foo::bar::Kind::Qux",
+                    "variant",
+                    "foo/bar/enum.Kind.html#variant.Qux"
+                ),
+                ("foo::bar::Trait", "trait", "foo/bar/trait.Trait.html"),
+                ("foo::bar::run", "fn", "foo/bar/fn.run.html"),
+            ]
+        );
+        let descriptions: Vec<_> = items
+            .iter()
+            .map(|item| item.description.as_deref())
+            .collect();
+        assert_eq!(
+            descriptions,
+            vec![
+                Some("The foo crate"),
+                Some("A module"),
+                Some("A struct"),
+                Some("Creates a 'Baz'"),
+                None,
+                Some("A variant"),
+                None,
+                None,
+            ]
+        );
+
+        // without the shards
+        let items = parse_search_index(TEST_SEARCH_INDEX, "foo", &[]);
+        assert_eq!(items.len(), 8);
+        assert!(items.iter().all(|item| item.description.is_none()));
+
+        assert!(SearchIndex::parse(TEST_SEARCH_INDEX, "other")
+            .unwrap()
+            .is_none());
+        assert!(SearchIndex::parse("var searchIndex = {};", "foo").is_err());
+    }
+
+    #[test]
+    fn parse_older_index() {
+        let js = r#"var searchIndex = JSON.parse('{\
+"foo":{"doc":"The foo crate","t":[3,11],"n":["Baz","new"],"q":["foo",""],"d":["",""],"i":[0,1],"p":[[3,"Baz"]]}\
+}');"#;
+        let items = parse_search_index(js, "foo", &[]);
+        assert_eq!(
+            items,
+            vec![
+                SearchItem {
+                    description: Some("The foo crate".into()),
+                    ..item("foo", "mod", "foo/index.html")
+                },
+                item("foo::Baz", "struct", "foo/struct.Baz.html"),
+                item("foo::Baz::new", "method", "foo/struct.Baz.html#method.new"),
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_shard_lengths() {
+        assert_eq!(decode_vlq_hex("fd").unwrap(), vec![3, 2]);
+        // 1000, -3 and 0, then a backreference to the first number
+        assert_eq!(decode_vlq_hex("GM`g`1").unwrap(), vec![1000, -3, 0, 1000]);
+        assert!(decode_vlq_hex("f!").is_err());
+        assert!(decode_vlq_hex("fG").is_err());
+    }
+
+    #[test]
+    fn decode_bitmaps() {
+        // an array container
+        assert_eq!(
+            decode_bitmap("OjAAAAEAAAAAAAIAEAAAAAQABgAHAA==").unwrap(),
+            HashSet::from([4, 6, 7])
+        );
+        // a run container, with 10 to 12 and 70000
+        let mut bytes = vec![0x3b, 0x30, 1, 0, 0b01];
+        bytes.extend([0, 0, 2, 0, 1, 0, 0, 0]);
+        bytes.extend([1, 0, 10, 0, 2, 0]);
+        bytes.extend([112, 17]);
+        assert_eq!(
+            decode_bitmap(&b64.encode(bytes)).unwrap(),
+            HashSet::from([10, 11, 12, 70000])
+        );
+        assert!(decode_bitmap("OjAAAAEAAAAAAAIAEAAAAAQA").is_err());
+    }
+
+    #[test]
+    fn read_index() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(read_search_index(dir.path(), "foo").unwrap().is_none());
+
+        fs::create_dir(dir.path().join("foo")).unwrap();
+        fs::write(
+            dir.path().join("foo/index.html"),
+            r#"<meta name="rustdoc-vars" data-resource-suffix="-1.79.0">"#,
+        )
+        .unwrap();
+        fs::write(dir.path().join("search-index-1.79.0.js"), TEST_SEARCH_INDEX).unwrap();
+        fs::create_dir_all(dir.path().join("search.desc/foo")).unwrap();
+        for (shard, content) in TEST_DESC_SHARDS.iter().enumerate() {
+            fs::write(
+                dir.path().join(desc_shard_path("foo", shard, "-1.79.0")),
+                content,
+            )
+            .unwrap();
+        }
+        let items = read_search_index(dir.path(), "foo").unwrap().unwrap();
+        assert_eq!(items.len(), 8);
+        assert_eq!(items[5].description.as_deref(), Some("A variant"));
+    }
+}
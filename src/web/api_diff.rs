@@ -0,0 +1,863 @@
+//! Comparison of the public API of two releases, based on their rustdoc JSON output.
+
+use crate::{
+    impl_axum_webpage,
+    storage::{is_size_limit_error, PathNotFoundError},
+    utils::spawn_blocking,
+    web::{
+        cache::CachePolicy,
+        crate_details::CrateDetails,
+        error::{AxumNope, AxumResult},
+        extractors::{DbConnection, Path},
+        item_cache::ItemCache,
+        match_version,
+        registries::CrateRegistry,
+        MetaData, ReqVersion,
+    },
+    AsyncStorage, RUSTDOC_JSON_PATH,
+};
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::Extension, http::header::ACCESS_CONTROL_ALLOW_ORIGIN, response::IntoResponse, Json,
+};
+use semver::Version;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// Keys which only contain identifiers, locations or documentation.
+/// They change between builds without the API itself changing.
+const IGNORED_KEYS: &[&str] = &["id", "crate_id", "span", "links", "docs", "attrs"];
+
+/// Keys which list member items that are compared on their own.
+const MEMBER_KEYS: &[&str] = &[
+    "items",
+    "impls",
+    "implementations",
+    "blanket_impls",
+    "synthetic_impls",
+];
+
+/// Keys which list member items that are part of the signature of their parent.
+const INLINED_MEMBER_KEYS: &[&str] = &["fields", "variants"];
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub(crate) struct ApiItem {
+    kind: String,
+    path: String,
+}
+
+/// All public items of a crate, with a normalized representation of their signature.
+pub(crate) type Api = BTreeMap<ApiItem, String>;
+
+/// The APIs of the releases compared last, by release and build id, so comparing a release
+/// again doesn't load and parse its rustdoc JSON again.
+pub(crate) type ApiCache = ItemCache<(i32, i32), Api>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+struct ApiDiff {
+    added: Vec<ApiItem>,
+    removed: Vec<ApiItem>,
+    changed: Vec<ApiItem>,
+}
+
+impl ApiDiff {
+    fn new(old: &Api, new: &Api) -> Self {
+        let mut diff = ApiDiff::default();
+
+        for (item, signature) in new {
+            match old.get(item) {
+                None => diff.added.push(item.clone()),
+                Some(old_signature) if old_signature != signature => {
+                    diff.changed.push(item.clone())
+                }
+                Some(_) => {}
+            }
+        }
+
+        diff.removed = old
+            .keys()
+            .filter(|item| !new.contains_key(item))
+            .cloned()
+            .collect();
+
+        diff
+    }
+}
+
+fn id_key(id: &Value) -> Option<String> {
+    // ids were strings in older format versions, and are integers in newer ones.
+    match id {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// Returns the kind and the kind-specific data of an item.
+fn item_inner(item: &Value) -> Option<(&str, &Value)> {
+    if let Some(kind) = item.get("kind").and_then(Value::as_str) {
+        // before format version 24, the kind was a separate field.
+        Some((kind, item.get("inner")?))
+    } else {
+        let (kind, inner) = item.get("inner")?.as_object()?.iter().next()?;
+        Some((kind, inner))
+    }
+}
+
+fn normalize(value: &Value, index: &Map<String, Value>) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .filter(|(key, _)| {
+                    !IGNORED_KEYS.contains(&key.as_str()) && !MEMBER_KEYS.contains(&key.as_str())
+                })
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::Array(members) if INLINED_MEMBER_KEYS.contains(&key.as_str()) => {
+                            Value::Array(
+                                members
+                                    .iter()
+                                    .filter_map(|id| index.get(&id_key(id)?))
+                                    .map(|member| normalize(member, index))
+                                    .collect(),
+                            )
+                        }
+                        value => normalize(value, index),
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.iter().map(|v| normalize(v, index)).collect()),
+        value => value.clone(),
+    }
+}
+
+fn signature(item: &Value, index: &Map<String, Value>) -> String {
+    normalize(
+        &serde_json::json!({
+            "visibility": item.get("visibility"),
+            "deprecation": item.get("deprecation"),
+            "inner": item.get("inner"),
+        }),
+        index,
+    )
+    .to_string()
+}
+
+/// Formats a type of the rustdoc JSON output roughly like it's written in Rust.
+///
+/// Types we don't know how to format are written as their JSON, so different types always
+/// have different names.
+fn type_name(ty: &Value) -> String {
+    let Some((kind, inner)) = ty.as_object().and_then(|ty| ty.iter().next()) else {
+        return ty.to_string();
+    };
+    let types = |types: &Value| {
+        types
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(type_name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    match (kind.as_str(), inner) {
+        ("primitive" | "generic", Value::String(name)) => name.clone(),
+        ("resolved_path", path) => format!(
+            "{}{}",
+            path.get("name").and_then(Value::as_str).unwrap_or_default(),
+            generic_args(path.get("args"))
+        ),
+        ("tuple", types_) => format!("({})", types(types_)),
+        ("slice", ty) => format!("[{}]", type_name(ty)),
+        ("array", array) => format!(
+            "[{}; {}]",
+            type_name(&array["type"]),
+            array.get("len").and_then(Value::as_str).unwrap_or_default()
+        ),
+        ("borrowed_ref", reference) => {
+            let lifetime = reference
+                .get("lifetime")
+                .and_then(Value::as_str)
+                .map(|lifetime| format!("{lifetime} "))
+                .unwrap_or_default();
+            // the field was renamed in format version 29
+            let mutable = reference
+                .get("is_mutable")
+                .or_else(|| reference.get("mutable"))
+                .and_then(Value::as_bool)
+                == Some(true);
+            format!(
+                "&{lifetime}{}{}",
+                if mutable { "mut " } else { "" },
+                type_name(&reference["type"])
+            )
+        }
+        _ => ty.to_string(),
+    }
+}
+
+/// Formats the generic arguments of a path, like `<u8, Item = T>`.
+fn generic_args(args: Option<&Value>) -> String {
+    let Some(args) = args.filter(|args| !args.is_null()) else {
+        return String::new();
+    };
+
+    if let Some(args) = args.get("angle_bracketed") {
+        let arguments = args
+            .get("args")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(
+                |arg| match arg.as_object().and_then(|arg| arg.iter().next()) {
+                    Some((kind, Value::String(lifetime))) if kind == "lifetime" => lifetime.clone(),
+                    Some((kind, ty)) if kind == "type" => type_name(ty),
+                    Some((kind, constant)) if kind == "const" => constant
+                        .get("expr")
+                        .and_then(Value::as_str)
+                        .map_or_else(|| constant.to_string(), str::to_owned),
+                    _ => arg.to_string(),
+                },
+            );
+        // the associated type bindings were renamed to constraints in format version 29
+        let constraints = args
+            .get("constraints")
+            .or_else(|| args.get("bindings"))
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(|constraint| {
+                let name = constraint
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default();
+                match constraint
+                    .get("binding")
+                    .and_then(|binding| binding.get("equality"))
+                {
+                    Some(term) => format!(
+                        "{name} = {}",
+                        term.get("type").map_or_else(|| term.to_string(), type_name)
+                    ),
+                    None => format!("{name}: {constraint}"),
+                }
+            });
+
+        let all: Vec<_> = arguments.chain(constraints).collect();
+        if all.is_empty() {
+            String::new()
+        } else {
+            format!("<{}>", all.join(", "))
+        }
+    } else if let Some(args) = args.get("parenthesized") {
+        // `Fn(A, B) -> C`
+        let inputs = args
+            .get("inputs")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(type_name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        match args.get("output").filter(|output| !output.is_null()) {
+            Some(output) => format!("({inputs}) -> {}", type_name(output)),
+            None => format!("({inputs})"),
+        }
+    } else {
+        args.to_string()
+    }
+}
+
+/// Collects the public items of the local crate from its rustdoc JSON output.
+///
+/// Next to the items that have a path, this includes the methods of inherent
+/// impls, and the trait implementations of each type.
+fn collect_api(doc: &Value) -> Api {
+    let mut api = Api::new();
+
+    let (Some(index), Some(paths)) = (
+        doc.get("index").and_then(Value::as_object),
+        doc.get("paths").and_then(Value::as_object),
+    ) else {
+        return api;
+    };
+
+    for (id, summary) in paths {
+        if summary.get("crate_id").and_then(Value::as_u64) != Some(0) {
+            continue;
+        }
+        // items with paths which are not in the index are private or stripped.
+        let Some(item) = index.get(id) else {
+            continue;
+        };
+        let (Some(kind), Some(path)) = (
+            summary.get("kind").and_then(Value::as_str),
+            summary.get("path").and_then(Value::as_array),
+        ) else {
+            continue;
+        };
+        let path = path
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join("::");
+
+        api.insert(
+            ApiItem {
+                kind: kind.into(),
+                path: path.clone(),
+            },
+            signature(item, index),
+        );
+
+        let Some(impls) = item_inner(item)
+            .and_then(|(_, inner)| inner.get("impls"))
+            .and_then(Value::as_array)
+        else {
+            continue;
+        };
+
+        for impl_ in impls
+            .iter()
+            .filter_map(|id| index.get(&id_key(id)?))
+            .filter_map(|impl_| item_inner(impl_).map(|(_, inner)| inner))
+        {
+            // auto-trait & blanket impls are generated by rustdoc and would only add noise
+            if impl_.get("synthetic").and_then(Value::as_bool) == Some(true)
+                || impl_.get("blanket_impl").map_or(false, |b| !b.is_null())
+            {
+                continue;
+            }
+
+            if let Some(trait_) = impl_.get("trait").filter(|t| !t.is_null()) {
+                let Some(trait_name) = trait_
+                    .get("name")
+                    .or_else(|| trait_.get("path"))
+                    .and_then(Value::as_str)
+                else {
+                    continue;
+                };
+                // impls of the same trait for the same type can differ in their generic
+                // arguments, like `From<u8>` and `From<u16>`.
+                let trait_args = generic_args(trait_.get("args"));
+                let for_ = match impl_.get("for") {
+                    Some(ty) if ty.get("resolved_path").is_some() => {
+                        format!("{path}{}", generic_args(ty["resolved_path"].get("args")))
+                    }
+                    Some(ty) if !ty.is_null() => type_name(ty),
+                    _ => path.clone(),
+                };
+                api.insert(
+                    ApiItem {
+                        kind: "impl".into(),
+                        path: format!("impl {trait_name}{trait_args} for {for_}"),
+                    },
+                    normalize(impl_, index).to_string(),
+                );
+            } else {
+                for member in impl_
+                    .get("items")
+                    .and_then(Value::as_array)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|id| index.get(&id_key(id)?))
+                {
+                    let (Some(name), Some((kind, _))) = (
+                        member.get("name").and_then(Value::as_str),
+                        item_inner(member),
+                    ) else {
+                        continue;
+                    };
+                    api.insert(
+                        ApiItem {
+                            kind: kind.into(),
+                            path: format!("{path}::{name}"),
+                        },
+                        signature(member, index),
+                    );
+                }
+            }
+        }
+    }
+
+    api
+}
+
+/// Loads the API of a release from its stored rustdoc JSON.
+/// Returns `None` when the release has no rustdoc JSON output, or when it's too big to load.
+async fn load_api(
+    storage: &AsyncStorage,
+    cache: &ApiCache,
+    krate: &CrateDetails,
+) -> Result<Option<Arc<Api>>> {
+    if !krate.rustdoc_status {
+        return Ok(None);
+    }
+
+    let build_id = krate
+        .latest_build_id
+        .ok_or_else(|| anyhow!("release with documentation but without a build"))?;
+    let key = (krate.release_id, build_id);
+    if let Some(api) = cache.get(&key) {
+        return Ok(Some(api));
+    }
+
+    let blob = match storage
+        .fetch_rustdoc_file(
+            &krate.storage_name(),
+            &krate.version.to_string(),
+            build_id,
+            RUSTDOC_JSON_PATH,
+            krate.archive_storage,
+            None,
+        )
+        .await
+    {
+        Ok(blob) => blob,
+        Err(err) if err.is::<PathNotFoundError>() || is_size_limit_error(&err) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let api = spawn_blocking(move || {
+        let doc: Value = serde_json::from_slice(&blob.content)?;
+        Ok(Arc::new(collect_api(&doc)))
+    })
+    .await?;
+
+    cache.insert(key, api.clone());
+    Ok(Some(api))
+}
+
+/// The two releases compared, and the difference of their APIs, if both have rustdoc JSON.
+struct LoadedApiDiff {
+    from: CrateDetails,
+    to: CrateDetails,
+    is_latest_url: bool,
+    diff: Option<ApiDiff>,
+}
+
+/// Matches both versions of the `:v1...:v2` path segment and computes the difference.
+///
+/// When one of the versions isn't canonical, a redirect to `/crate/:name/:v1...:v2/{page}`
+/// is returned.
+async fn load_api_diff(
+    conn: &mut sqlx::PgConnection,
+    storage: &AsyncStorage,
+    cache: &ApiCache,
+    registry: &CrateRegistry,
+    name: &str,
+    version_range: &str,
+    page: &str,
+) -> AxumResult<LoadedApiDiff> {
+    let (from_req, to_req) = version_range
+        .split_once("...")
+        .ok_or(AxumNope::ResourceNotFound)?;
+    let from_req: ReqVersion = from_req
+        .parse()
+        .map_err(|err: semver::Error| AxumNope::BadRequest(err.into()))?;
+    let to_req: ReqVersion = to_req
+        .parse()
+        .map_err(|err: semver::Error| AxumNope::BadRequest(err.into()))?;
+
+    let from = match_version(&mut *conn, registry.name(), name, &from_req)
+        .await?
+        .assume_exact_name()?
+        .into_canonical_req_version();
+    let to = match_version(&mut *conn, registry.name(), name, &to_req)
+        .await?
+        .assume_exact_name()?
+        .into_canonical_req_version();
+
+    if from.req_version != from_req || to.req_version != to_req {
+        return Err(AxumNope::Redirect(
+            format!(
+                "/crate/{name}/{}...{}/{page}",
+                from.req_version, to.req_version
+            ),
+            CachePolicy::ForeverInCdn,
+        ));
+    }
+
+    let from = CrateDetails::from_matched_release(&mut *conn, from).await?;
+    let to = CrateDetails::from_matched_release(&mut *conn, to).await?;
+
+    let diff = match (
+        load_api(storage, cache, &from).await?,
+        load_api(storage, cache, &to).await?,
+    ) {
+        (Some(old), Some(new)) => Some(ApiDiff::new(&old, &new)),
+        _ => None,
+    };
+
+    Ok(LoadedApiDiff {
+        from,
+        to,
+        is_latest_url: from_req.is_latest() || to_req.is_latest(),
+        diff,
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ApiDiffPage {
+    metadata: MetaData,
+    from_version: Version,
+    to_version: Version,
+    diff: Option<ApiDiff>,
+    is_latest_url: bool,
+    use_direct_platform_links: bool,
+}
+
+impl_axum_webpage! {
+    ApiDiffPage = "crate/api_diff.html",
+    cache_policy = |page| if page.is_latest_url {
+        CachePolicy::ForeverInCdn
+    } else {
+        CachePolicy::ForeverInCdnAndStaleInBrowser
+    },
+}
+
+pub(crate) async fn api_diff_handler(
+    Path((name, version_range)): Path<(String, String)>,
+    registry: CrateRegistry,
+    mut conn: DbConnection,
+    Extension(storage): Extension<Arc<AsyncStorage>>,
+    Extension(cache): Extension<Arc<ApiCache>>,
+) -> AxumResult<impl IntoResponse> {
+    let loaded = load_api_diff(
+        &mut conn,
+        &storage,
+        &cache,
+        &registry,
+        &name,
+        &version_range,
+        "api-diff",
+    )
+    .await?;
+
+    Ok(ApiDiffPage {
+        metadata: loaded.to.metadata,
+        from_version: loaded.from.version,
+        to_version: loaded.to.version,
+        diff: loaded.diff,
+        is_latest_url: loaded.is_latest_url,
+        use_direct_platform_links: true,
+    }
+    .into_response())
+}
+
+pub(crate) async fn api_diff_json_handler(
+    Path((name, version_range)): Path<(String, String)>,
+    registry: CrateRegistry,
+    mut conn: DbConnection,
+    Extension(storage): Extension<Arc<AsyncStorage>>,
+    Extension(cache): Extension<Arc<ApiCache>>,
+) -> AxumResult<impl IntoResponse> {
+    let loaded = load_api_diff(
+        &mut conn,
+        &storage,
+        &cache,
+        &registry,
+        &name,
+        &version_range,
+        "api-diff.json",
+    )
+    .await?;
+
+    let diff = loaded.diff.ok_or(AxumNope::ResourceNotFound)?;
+
+    Ok((
+        Extension(if loaded.is_latest_url {
+            CachePolicy::ForeverInCdn
+        } else {
+            CachePolicy::ForeverInCdnAndStaleInBrowser
+        }),
+        [(ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Json(serde_json::json!({
+            "name": name,
+            "from": loaded.from.version,
+            "to": loaded.to.version,
+            "added": diff.added,
+            "removed": diff.removed,
+            "changed": diff.changed,
+        })),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::{
+        assert_cache_control, assert_not_found, assert_redirect_cached, wrapper, TestEnvironment,
+    };
+    use kuchikiki::traits::TendrilSink;
+    use serde_json::json;
+
+    fn item(kind: &str, path: &str) -> ApiItem {
+        ApiItem {
+            kind: kind.into(),
+            path: path.into(),
+        }
+    }
+
+    /// A minimal rustdoc JSON document with a struct `krate::Foo` with a
+    /// method `new`, an implementation of `Clone`, and a function `krate::f`.
+    fn rustdoc_json(function_output: &str, with_clone: bool) -> Value {
+        let mut impls = vec![json!(10)];
+        if with_clone {
+            impls.push(json!(11));
+        }
+        json!({
+            "root": 0,
+            "format_version": 30,
+            "index": {
+                "0": {"name": "krate", "visibility": "public", "inner": {"module": {"items": [1, 2]}}},
+                "1": {"name": "Foo", "visibility": "public", "inner": {"struct": {"kind": "unit", "impls": impls}}},
+                "2": {"name": "f", "visibility": "public", "span": {"filename": "src/lib.rs"}, "inner": {"function": {"sig": {"output": {"primitive": function_output}}}}},
+                "10": {"name": null, "visibility": "default", "inner": {"impl": {"trait": null, "items": [12]}}},
+                "11": {"name": null, "visibility": "default", "inner": {"impl": {"trait": {"name": "Clone", "id": 99}, "items": []}}},
+                "12": {"name": "new", "visibility": "public", "inner": {"function": {"sig": {"output": {"generic": "Self"}}}}},
+            },
+            "paths": {
+                "0": {"crate_id": 0, "path": ["krate"], "kind": "module"},
+                "1": {"crate_id": 0, "path": ["krate", "Foo"], "kind": "struct"},
+                "2": {"crate_id": 0, "path": ["krate", "f"], "kind": "function"},
+                "99": {"crate_id": 1, "path": ["core", "clone", "Clone"], "kind": "trait"},
+            },
+        })
+    }
+
+    fn create_release(env: &TestEnvironment, version: &str, doc: Option<&Value>) -> Result<()> {
+        let release = env
+            .fake_release()
+            .name("krate")
+            .version(version)
+            .archive_storage(true);
+        let release = if let Some(doc) = doc {
+            release.rustdoc_file_with(RUSTDOC_JSON_PATH, doc.to_string().into_bytes())
+        } else {
+            release
+        };
+        release.create()?;
+        Ok(())
+    }
+
+    #[test]
+    fn collect_api_items() {
+        let api = collect_api(&rustdoc_json("u8", true));
+
+        assert_eq!(
+            api.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                item("function", "krate::Foo::new"),
+                item("function", "krate::f"),
+                item("impl", "impl Clone for krate::Foo"),
+                item("module", "krate"),
+                item("struct", "krate::Foo"),
+            ]
+        );
+    }
+
+    #[test]
+    fn collect_api_ignores_spans_and_ids() {
+        let mut changed = rustdoc_json("u8", true);
+        changed["index"]["2"]["span"] = json!({"filename": "src/other.rs"});
+        changed["index"]["11"]["inner"]["impl"]["trait"]["id"] = json!(100);
+
+        assert_eq!(
+            collect_api(&rustdoc_json("u8", true)),
+            collect_api(&changed)
+        );
+    }
+
+    #[test]
+    fn collect_api_trait_impls_with_generic_arguments() {
+        let mut doc = rustdoc_json("u8", true);
+        let from = |ty: &str| {
+            json!({"name": null, "visibility": "default", "inner": {"impl": {
+                "trait": {"name": "From", "id": 98, "args": {"angle_bracketed": {
+                    "args": [{"type": {"primitive": ty}}], "constraints": [],
+                }}},
+                "for": {"resolved_path": {"name": "Foo", "id": 1, "args": null}},
+                "items": [],
+            }}})
+        };
+        doc["index"]["13"] = from("u8");
+        doc["index"]["14"] = from("u16");
+        doc["index"]["1"]["inner"]["struct"]["impls"] = json!([10, 11, 13, 14]);
+
+        let api = collect_api(&doc);
+        assert!(api.contains_key(&item("impl", "impl From<u8> for krate::Foo")));
+        assert!(api.contains_key(&item("impl", "impl From<u16> for krate::Foo")));
+    }
+
+    #[test]
+    fn diff_added_removed_changed() {
+        let old = collect_api(&rustdoc_json("u8", true));
+        let new = collect_api(&rustdoc_json("u16", false));
+
+        assert_eq!(
+            ApiDiff::new(&old, &new),
+            ApiDiff {
+                added: vec![],
+                removed: vec![item("impl", "impl Clone for krate::Foo")],
+                changed: vec![item("function", "krate::f")],
+            }
+        );
+        assert_eq!(ApiDiff::new(&new, &new), ApiDiff::default());
+    }
+
+    #[test]
+    fn api_diff_page() {
+        wrapper(|env| {
+            create_release(env, "0.1.0", Some(&rustdoc_json("u8", true)))?;
+            create_release(env, "0.2.0", Some(&rustdoc_json("u16", false)))?;
+
+            let response = env
+                .frontend()
+                .get("/crate/krate/0.1.0...0.2.0/api-diff")
+                .send()?;
+            assert!(response.status().is_success());
+            assert_cache_control(
+                &response,
+                CachePolicy::ForeverInCdnAndStaleInBrowser,
+                &env.config(),
+            );
+
+            let page = kuchikiki::parse_html().one(response.text()?);
+            let items = |id: &str| {
+                page.select(&format!("#{id} code"))
+                    .unwrap()
+                    .map(|el| el.text_contents())
+                    .collect::<Vec<_>>()
+            };
+            assert!(items("added").is_empty());
+            assert_eq!(items("removed"), vec!["impl Clone for krate::Foo"]);
+            assert_eq!(items("changed"), vec!["krate::f"]);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_diff_json() {
+        wrapper(|env| {
+            create_release(env, "0.1.0", Some(&rustdoc_json("u8", false)))?;
+            create_release(env, "0.2.0", Some(&rustdoc_json("u8", true)))?;
+
+            let response = env
+                .frontend()
+                .get("/crate/krate/0.1.0...latest/api-diff.json")
+                .send()?;
+            assert!(response.status().is_success());
+            assert_cache_control(&response, CachePolicy::ForeverInCdn, &env.config());
+            assert_eq!(
+                response.json::<Value>()?,
+                json!({
+                    "name": "krate",
+                    "from": "0.1.0",
+                    "to": "0.2.0",
+                    "added": [{"kind": "impl", "path": "impl Clone for krate::Foo"}],
+                    "removed": [],
+                    "changed": [],
+                })
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_diff_from_cache() {
+        wrapper(|env| {
+            create_release(env, "0.1.0", Some(&rustdoc_json("u8", true)))?;
+            create_release(env, "0.2.0", Some(&rustdoc_json("u16", true)))?;
+
+            let diff = || -> Result<Value> {
+                Ok(env
+                    .frontend()
+                    .get("/crate/krate/0.1.0...0.2.0/api-diff.json")
+                    .send()?
+                    .error_for_status()?
+                    .json()?)
+            };
+            let first = diff()?;
+            assert_eq!(
+                first["changed"],
+                json!([{"kind": "function", "path": "krate::f"}])
+            );
+
+            // the parsed APIs are kept, the stored rustdoc JSON isn't read again
+            env.storage().delete_prefix("rustdoc/")?;
+            assert_eq!(diff()?, first);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_diff_of_too_big_rustdoc_json() {
+        wrapper(|env| {
+            env.override_config(|config| config.max_file_size = 100);
+            create_release(env, "0.1.0", Some(&rustdoc_json("u8", true)))?;
+            create_release(env, "0.2.0", Some(&rustdoc_json("u16", true)))?;
+
+            let web = env.frontend();
+
+            let response = web.get("/crate/krate/0.1.0...0.2.0/api-diff").send()?;
+            assert!(response.status().is_success());
+            let page = kuchikiki::parse_html().one(response.text()?);
+            assert!(page.select_first("#api-diff-unavailable").is_ok());
+
+            assert_not_found("/crate/krate/0.1.0...0.2.0/api-diff.json", web)?;
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_diff_semver_redirect() {
+        wrapper(|env| {
+            create_release(env, "0.1.0", Some(&rustdoc_json("u8", true)))?;
+            create_release(env, "0.2.0", Some(&rustdoc_json("u8", true)))?;
+
+            let web = env.frontend();
+            assert_redirect_cached(
+                "/crate/krate/0.1...0.2/api-diff",
+                "/crate/krate/0.1.0...0.2.0/api-diff",
+                CachePolicy::ForeverInCdn,
+                web,
+                &env.config(),
+            )?;
+            assert_redirect_cached(
+                "/crate/krate/0.1...*/api-diff.json",
+                "/crate/krate/0.1.0...latest/api-diff.json",
+                CachePolicy::ForeverInCdn,
+                web,
+                &env.config(),
+            )?;
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn api_diff_without_rustdoc_json() {
+        wrapper(|env| {
+            create_release(env, "0.1.0", None)?;
+            create_release(env, "0.2.0", Some(&rustdoc_json("u8", true)))?;
+
+            let web = env.frontend();
+
+            let response = web.get("/crate/krate/0.1.0...0.2.0/api-diff").send()?;
+            assert!(response.status().is_success());
+            let page = kuchikiki::parse_html().one(response.text()?);
+            assert!(page.select_first("#api-diff-unavailable").is_ok());
+
+            assert_not_found("/crate/krate/0.1.0...0.2.0/api-diff.json", web)?;
+            assert_not_found("/crate/krate/0.1.0/api-diff", web)?;
+            assert_not_found("/crate/krate/0.1.0...0.3.0/api-diff", web)?;
+            Ok(())
+        });
+    }
+}
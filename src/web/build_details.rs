@@ -1,10 +1,10 @@
 use crate::{
-    db::types::BuildStatus,
+    db::types::{BuildFailureCategory, BuildStatus},
     impl_axum_webpage,
     web::{
         error::{AxumNope, AxumResult},
         extractors::{DbConnection, Path},
-        file::File,
+        registries::CrateRegistry,
         MetaData,
     },
     AsyncStorage, Config,
@@ -24,13 +24,27 @@ pub(crate) struct BuildDetails {
     docsrs_version: String,
     build_status: BuildStatus,
     build_time: DateTime<Utc>,
+    failure_category: Option<BuildFailureCategory>,
+    error_excerpt: Option<String>,
     output: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct TargetResult {
+    target: String,
+    successful: bool,
+    duration_ms: i32,
+    peak_memory_bytes: Option<i64>,
+    cpu_time_ms: Option<i32>,
+    /// name of the build log of this target, relative to the build.
+    log_filename: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct BuildDetailsPage {
     metadata: MetaData,
     build_details: BuildDetails,
+    target_results: Vec<TargetResult>,
     use_direct_platform_links: bool,
     all_log_filenames: Vec<String>,
     current_filename: Option<String>,
@@ -50,6 +64,7 @@ pub(crate) struct BuildDetailsParams {
 
 pub(crate) async fn build_details_handler(
     Path(params): Path<BuildDetailsParams>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
     Extension(config): Extension<Arc<Config>>,
     Extension(storage): Extension<Arc<AsyncStorage>>,
@@ -62,15 +77,22 @@ pub(crate) async fn build_details_handler(
              builds.docsrs_version,
              builds.build_status as "build_status: BuildStatus",
              builds.build_time,
+             builds.failure_category as "failure_category: BuildFailureCategory",
+             builds.error_excerpt,
              builds.output,
              releases.default_target
          FROM builds
          INNER JOIN releases ON releases.id = builds.rid
          INNER JOIN crates ON releases.crate_id = crates.id
-         WHERE builds.id = $1 AND crates.name = $2 AND releases.version = $3"#,
+         WHERE
+            builds.id = $1 AND
+            crates.name = $2 AND
+            releases.version = $3 AND
+            crates.registry IS NOT DISTINCT FROM $4"#,
         id,
         params.name,
         params.version.to_string(),
+        registry.name(),
     )
     .fetch_optional(&mut *conn)
     .await?
@@ -86,9 +108,9 @@ pub(crate) async fn build_details_handler(
             .unwrap_or_else(|| format!("{}.txt", row.default_target));
 
         let path = format!("{prefix}{current_filename}");
-        let file = File::from_path(&storage, &path, &config).await?;
+        let file = storage.get(&path, config.max_file_size).await?;
         (
-            String::from_utf8(file.0.content).context("non utf8")?,
+            String::from_utf8(file.content).context("non utf8")?,
             storage
                 .list_prefix(&prefix) // the result from S3 is ordered by key
                 .await
@@ -103,16 +125,49 @@ pub(crate) async fn build_details_handler(
         )
     };
 
+    let log_prefix = format!("build-logs/{id}/");
+    let target_results = sqlx::query!(
+        "SELECT target, successful, duration_ms, log_path, peak_memory_bytes, cpu_time_ms
+         FROM build_target_results
+         WHERE build_id = $1
+         ORDER BY target",
+        id,
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| TargetResult {
+        log_filename: row
+            .log_path
+            .strip_prefix(&log_prefix)
+            .map(ToOwned::to_owned),
+        target: row.target,
+        successful: row.successful,
+        duration_ms: row.duration_ms,
+        peak_memory_bytes: row.peak_memory_bytes,
+        cpu_time_ms: row.cpu_time_ms,
+    })
+    .try_collect()
+    .await?;
+
     Ok(BuildDetailsPage {
-        metadata: MetaData::from_crate(&mut conn, &params.name, &params.version, None).await?,
+        metadata: MetaData::from_crate(
+            &mut conn,
+            registry.name(),
+            &params.name,
+            &params.version,
+            None,
+        )
+        .await?,
         build_details: BuildDetails {
             id,
             rustc_version: row.rustc_version,
             docsrs_version: row.docsrs_version,
             build_status: row.build_status,
             build_time: row.build_time,
+            failure_category: row.failure_category,
+            error_excerpt: row.error_excerpt,
             output,
         },
+        target_results,
         use_direct_platform_links: true,
         all_log_filenames,
         current_filename,
@@ -122,6 +177,7 @@ pub(crate) async fn build_details_handler(
 
 #[cfg(test)]
 mod tests {
+    use crate::db::types::BuildFailureCategory;
     use crate::test::{wrapper, FakeBuild};
     use kuchikiki::traits::TendrilSink;
     use test_case::test_case;
@@ -320,6 +376,140 @@ mod tests {
         });
     }
 
+    #[test]
+    fn build_failure_category() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![FakeBuild::default().failure(
+                    BuildFailureCategory::MissingNativeLibrary,
+                    Some("Package gtk+-3.0 was not found in the pkg-config search path."),
+                )])
+                .create()?;
+
+            let page = kuchikiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+
+            let node = page.select("ul > li a.release").unwrap().next().unwrap();
+            let attrs = node.attributes.borrow();
+            let url = attrs.get("href").unwrap();
+
+            let page = kuchikiki::parse_html().one(env.frontend().get(url).send()?.text()?);
+
+            let failure = page.select_first("#build-failure").unwrap();
+            let category = failure
+                .as_node()
+                .select_first(".failure-category")
+                .unwrap()
+                .text_contents();
+            assert_eq!(category.trim(), "Missing native library");
+            let excerpt = failure
+                .as_node()
+                .select_first("pre")
+                .unwrap()
+                .text_contents();
+            assert!(excerpt.contains("was not found in the pkg-config search path"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn target_results() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .builds(vec![FakeBuild::default()
+                    .target_result("x86_64-unknown-linux-gnu", true)
+                    .target_result("wasm32-unknown-unknown", false)])
+                .create()?;
+
+            let page = kuchikiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+
+            let node = page.select("ul > li a.release").unwrap().next().unwrap();
+            let attrs = node.attributes.borrow();
+            let build_url = attrs.get("href").unwrap();
+
+            let page = kuchikiki::parse_html().one(env.frontend().get(build_url).send()?.text()?);
+
+            let results: Vec<(String, String, String)> = page
+                .select("#target-results tbody tr")
+                .unwrap()
+                .map(|row| {
+                    let cells: Vec<_> = row
+                        .as_node()
+                        .select("td")
+                        .unwrap()
+                        .map(|cell| cell.text_contents().trim().to_owned())
+                        .collect();
+                    let log_link = row
+                        .as_node()
+                        .select_first("a")
+                        .unwrap()
+                        .attributes
+                        .borrow()
+                        .get("href")
+                        .unwrap()
+                        .to_owned();
+                    assert_eq!(cells[3], "2 minutes");
+                    (cells[0].clone(), cells[1].clone(), log_link)
+                })
+                .collect();
+
+            assert_eq!(
+                results,
+                vec![
+                    (
+                        "wasm32-unknown-unknown".into(),
+                        "failed".into(),
+                        format!("{build_url}/wasm32-unknown-unknown.txt")
+                    ),
+                    (
+                        "x86_64-unknown-linux-gnu".into(),
+                        "success".into(),
+                        format!("{build_url}/x86_64-unknown-linux-gnu.txt")
+                    ),
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn no_build_failure_for_successful_build() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let page = kuchikiki::parse_html().one(
+                env.frontend()
+                    .get("/crate/foo/0.1.0/builds")
+                    .send()?
+                    .text()?,
+            );
+
+            let node = page.select("ul > li a.release").unwrap().next().unwrap();
+            let attrs = node.attributes.borrow();
+            let url = attrs.get("href").unwrap();
+
+            let page = kuchikiki::parse_html().one(env.frontend().get(url).send()?.text()?);
+            assert!(page.select_first("#build-failure").is_err());
+
+            Ok(())
+        });
+    }
+
     #[test_case("42")]
     #[test_case("nan")]
     fn non_existing_build(build_id: &str) {
@@ -6,7 +6,9 @@ use crate::{
     web::{
         error::AxumResult,
         extractors::{DbConnection, Path},
-        match_version, MetaData, ReqVersion,
+        match_version,
+        registries::CrateRegistry,
+        MetaData, ReqVersion,
     },
     Config,
 };
@@ -43,10 +45,11 @@ impl_axum_webpage! {
 
 pub(crate) async fn build_list_handler(
     Path((name, req_version)): Path<(String, ReqVersion)>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
     Extension(config): Extension<Arc<Config>>,
 ) -> AxumResult<impl IntoResponse> {
-    let version = match_version(&mut conn, &name, &req_version)
+    let version = match_version(&mut conn, registry.name(), &name, &req_version)
         .await?
         .assume_exact_name()?
         .into_canonical_req_version_or_else(|version| {
@@ -58,10 +61,20 @@ pub(crate) async fn build_list_handler(
         .into_version();
 
     Ok(BuildsPage {
-        metadata: MetaData::from_crate(&mut conn, &name, &version, Some(req_version)).await?,
-        builds: get_builds(&mut conn, &name, &version).await?,
-        limits: Limits::for_crate(&config, &mut conn, &name).await?,
-        canonical_url: CanonicalUrl::from_path(format!("/crate/{name}/latest/builds")),
+        metadata: MetaData::from_crate(
+            &mut conn,
+            registry.name(),
+            &name,
+            &version,
+            Some(req_version),
+        )
+        .await?,
+        builds: get_builds(&mut conn, registry.name(), &name, &version).await?,
+        limits: Limits::for_crate(&config, &mut conn, registry.name(), &name).await?,
+        canonical_url: CanonicalUrl::from_path(format!(
+            "{}/crate/{name}/latest/builds",
+            registry.url_prefix()
+        )),
         use_direct_platform_links: true,
     }
     .into_response())
@@ -69,9 +82,10 @@ pub(crate) async fn build_list_handler(
 
 pub(crate) async fn build_list_json_handler(
     Path((name, req_version)): Path<(String, ReqVersion)>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
 ) -> AxumResult<impl IntoResponse> {
-    let version = match_version(&mut conn, &name, &req_version)
+    let version = match_version(&mut conn, registry.name(), &name, &req_version)
         .await?
         .assume_exact_name()?
         .into_canonical_req_version_or_else(|version| {
@@ -86,7 +100,7 @@ pub(crate) async fn build_list_json_handler(
         Extension(CachePolicy::NoStoreMustRevalidate),
         [(ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
         Json(
-            get_builds(&mut conn, &name, &version)
+            get_builds(&mut conn, registry.name(), &name, &version)
                 .await?
                 .iter()
                 .filter_map(|build| {
@@ -113,6 +127,7 @@ pub(crate) async fn build_list_json_handler(
 
 async fn get_builds(
     conn: &mut sqlx::PgConnection,
+    registry: Option<&str>,
     name: &str,
     version: &Version,
 ) -> Result<Vec<Build>> {
@@ -127,10 +142,14 @@ async fn get_builds(
          FROM builds
          INNER JOIN releases ON releases.id = builds.rid
          INNER JOIN crates ON releases.crate_id = crates.id
-         WHERE crates.name = $1 AND releases.version = $2
+         WHERE
+            crates.name = $1 AND
+            releases.version = $2 AND
+            crates.registry IS NOT DISTINCT FROM $3
          ORDER BY id DESC"#,
         name,
         version.to_string(),
+        registry,
     )
     .fetch_all(&mut *conn)
     .await?)
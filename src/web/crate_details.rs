@@ -4,12 +4,13 @@ use crate::web::rustdoc::RustdocHtmlParams;
 use crate::{
     db::types::BuildStatus,
     impl_axum_webpage,
-    storage::PathNotFoundError,
+    storage::{crate_storage_name, PathNotFoundError},
     web::{
         cache::CachePolicy,
         encode_url_path,
         error::{AxumNope, AxumResult},
         extractors::{DbConnection, Path},
+        registries::CrateRegistry,
         MatchedRelease, ReqVersion,
     },
     AsyncStorage,
@@ -26,13 +27,15 @@ use semver::Version;
 use serde::Deserialize;
 use serde::{ser::Serializer, Serialize};
 use serde_json::Value;
-use std::sync::Arc;
+use std::{borrow::Cow, sync::Arc};
 
 // TODO: Add target name and versions
 
 #[derive(Debug, Clone, PartialEq, Serialize)]
 pub(crate) struct CrateDetails {
     name: String,
+    /// alternative registry of the crate, `None` for crates.io
+    pub(crate) registry: Option<String>,
     pub version: Version,
     description: Option<String>,
     owners: Vec<(String, String)>,
@@ -116,6 +119,7 @@ impl CrateDetails {
     ) -> Result<Self> {
         Ok(Self::new(
             conn,
+            release.registry.as_deref(),
             &release.name,
             &release.release.version,
             Some(release.req_version),
@@ -127,6 +131,7 @@ impl CrateDetails {
 
     async fn new(
         conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
         name: &str,
         version: &Version,
         req_version: Option<ReqVersion>,
@@ -194,9 +199,13 @@ impl CrateDetails {
             INNER JOIN crates ON releases.crate_id = crates.id
             LEFT JOIN doc_coverage ON doc_coverage.release_id = releases.id
             LEFT JOIN repositories ON releases.repository_id = repositories.id
-            WHERE crates.name = $1 AND releases.version = $2;"#,
+            WHERE
+                crates.name = $1 AND
+                releases.version = $2 AND
+                crates.registry IS NOT DISTINCT FROM $3;"#,
             name,
             version.to_string(),
+            registry,
         )
         .fetch_optional(&mut *conn)
         .await?
@@ -231,6 +240,7 @@ impl CrateDetails {
 
         let mut crate_details = CrateDetails {
             name: krate.name,
+            registry: registry.map(str::to_owned),
             version: version.clone(),
             description: krate.description,
             owners: Vec::new(),
@@ -291,7 +301,7 @@ impl CrateDetails {
     async fn fetch_readme(&self, storage: &AsyncStorage) -> anyhow::Result<Option<String>> {
         let manifest = match storage
             .fetch_source_file(
-                &self.name,
+                &self.storage_name(),
                 &self.version.to_string(),
                 self.latest_build_id.unwrap_or(0),
                 "Cargo.toml",
@@ -320,7 +330,7 @@ impl CrateDetails {
         for path in &paths {
             match storage
                 .fetch_source_file(
-                    &self.name,
+                    &self.storage_name(),
                     &self.version.to_string(),
                     self.latest_build_id.unwrap_or(0),
                     path,
@@ -344,6 +354,11 @@ impl CrateDetails {
         Ok(None)
     }
 
+    /// Name of the crate in the storage, see [`crate_storage_name`].
+    pub(crate) fn storage_name(&self) -> Cow<'_, str> {
+        crate_storage_name(self.registry.as_deref(), &self.name)
+    }
+
     /// Returns the latest non-yanked, non-prerelease release of this crate (or latest
     /// yanked/prereleased if that is all that exist).
     pub fn latest_release(&self) -> Result<&Release> {
@@ -437,6 +452,7 @@ pub(crate) struct CrateDetailHandlerParams {
 pub(crate) async fn crate_details_handler(
     Path(params): Path<CrateDetailHandlerParams>,
     Extension(storage): Extension<Arc<AsyncStorage>>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
 ) -> AxumResult<AxumResponse> {
     let req_version = params.version.ok_or_else(|| {
@@ -446,7 +462,7 @@ pub(crate) async fn crate_details_handler(
         )
     })?;
 
-    let matched_release = match_version(&mut conn, &params.name, &req_version)
+    let matched_release = match_version(&mut conn, registry.name(), &params.name, &req_version)
         .await?
         .assume_exact_name()?
         .into_canonical_req_version_or_else(|version| {
@@ -490,12 +506,13 @@ impl_axum_webpage! {
 #[tracing::instrument]
 pub(crate) async fn get_all_releases(
     Path(params): Path<RustdocHtmlParams>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
 ) -> AxumResult<AxumResponse> {
     let req_path: String = params.path.clone().unwrap_or_default();
     let req_path: Vec<&str> = req_path.split('/').collect();
 
-    let matched_release = match_version(&mut conn, &params.name, &params.version)
+    let matched_release = match_version(&mut conn, registry.name(), &params.name, &params.version)
         .await?
         .into_canonical_req_version_or_else(|_| AxumNope::VersionNotFound)?;
 
@@ -508,7 +525,7 @@ pub(crate) async fn get_all_releases(
     )
     .fetch_optional(&mut *conn)
     .await?
-    .ok_or(AxumNope::CrateNotFound)?;
+    .ok_or_else(|| AxumNope::CrateNotFound(params.name.clone()))?;
 
     let doc_targets = MetaData::parse_doc_targets(row.doc_targets);
 
@@ -565,6 +582,8 @@ struct PlatformList {
     inner_path: String,
     use_direct_platform_links: bool,
     current_target: String,
+    /// targets where the documentation build failed in the build we're serving docs from
+    failed_targets: Vec<String>,
 }
 
 impl_axum_webpage! {
@@ -576,13 +595,14 @@ impl_axum_webpage! {
 #[tracing::instrument]
 pub(crate) async fn get_all_platforms_inner(
     Path(params): Path<RustdocHtmlParams>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
     is_crate_root: bool,
 ) -> AxumResult<AxumResponse> {
     let req_path: String = params.path.unwrap_or_default();
     let req_path: Vec<&str> = req_path.split('/').collect();
 
-    let matched_release = match_version(&mut conn, &params.name, &params.version)
+    let matched_release = match_version(&mut conn, registry.name(), &params.name, &params.version)
         .await?
         .into_exactly_named_or_else(|corrected_name, req_version| {
             AxumNope::Redirect(
@@ -617,10 +637,30 @@ pub(crate) async fn get_all_platforms_inner(
     )
     .fetch_optional(&mut *conn)
     .await?
-    .ok_or(AxumNope::CrateNotFound)?;
+    .ok_or_else(|| AxumNope::CrateNotFound(params.name.clone()))?;
 
     let doc_targets = MetaData::parse_doc_targets(krate.doc_targets);
 
+    let failed_targets = sqlx::query_scalar!(
+        "SELECT build_target_results.target
+         FROM build_target_results
+         WHERE
+            NOT build_target_results.successful AND
+            build_target_results.build_id = (
+                SELECT builds.id
+                FROM builds
+                WHERE
+                    builds.rid = $1 AND
+                    builds.build_status = 'success'
+                ORDER BY builds.build_time DESC
+                LIMIT 1
+            )
+         ORDER BY build_target_results.target",
+        matched_release.id(),
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+
     let latest_release = latest_release(&matched_release.all_releases)
         .expect("we couldn't end up here without releases");
 
@@ -669,23 +709,26 @@ pub(crate) async fn get_all_platforms_inner(
         inner_path,
         use_direct_platform_links: is_crate_root,
         current_target,
+        failed_targets,
     };
     Ok(res.into_response())
 }
 
 pub(crate) async fn get_all_platforms_root(
     Path(mut params): Path<RustdocHtmlParams>,
+    registry: CrateRegistry,
     conn: DbConnection,
 ) -> AxumResult<AxumResponse> {
     params.path = None;
-    get_all_platforms_inner(Path(params), conn, true).await
+    get_all_platforms_inner(Path(params), registry, conn, true).await
 }
 
 pub(crate) async fn get_all_platforms(
     params: Path<RustdocHtmlParams>,
+    registry: CrateRegistry,
     conn: DbConnection,
 ) -> AxumResult<AxumResponse> {
-    get_all_platforms_inner(params, conn, false).await
+    get_all_platforms_inner(params, registry, conn, false).await
 }
 
 #[cfg(test)]
@@ -745,6 +788,7 @@ mod tests {
 
         CrateDetails::new(
             &mut *conn,
+            None,
             name,
             &Version::parse(version).unwrap(),
             req_version,
@@ -1552,6 +1596,44 @@ mod tests {
         });
     }
 
+    #[test]
+    fn platform_menu_shows_failed_targets() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.4.0")
+                .rustdoc_file("dummy/index.html")
+                .default_target("x86_64-unknown-linux-gnu")
+                .builds(vec![FakeBuild::default()
+                    .target_result("x86_64-unknown-linux-gnu", true)
+                    .target_result("wasm32-unknown-unknown", false)])
+                .create()?;
+
+            let response = env
+                .frontend()
+                .get("/crate/dummy/0.4.0/menus/platforms")
+                .send()?;
+            assert!(response.status().is_success());
+            let page = kuchikiki::parse_html().one(response.text()?);
+
+            let links: Vec<String> = page
+                .select("li a")
+                .unwrap()
+                .map(|el| el.text_contents())
+                .collect();
+            assert_eq!(links, ["x86_64-unknown-linux-gnu"]);
+
+            let failed: Vec<String> = page
+                .select("li span.warn")
+                .unwrap()
+                .map(|el| el.text_contents().trim().to_owned())
+                .collect();
+            assert_eq!(failed, ["wasm32-unknown-unknown"]);
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn check_crate_name_in_redirect() {
         fn check_links(env: &TestEnvironment, url: &str, links: Vec<String>) {
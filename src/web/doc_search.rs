@@ -0,0 +1,415 @@
+//! Search within the documentation of a release, based on the search index rustdoc generated
+//! for it, so scripts and editors don't have to load and run `search-index.js` themselves.
+
+use crate::{
+    utils::search_index::{fetch_search_index, SearchItem},
+    web::{
+        axum_cached_redirect, axum_parse_uri_with_params,
+        cache::CachePolicy,
+        crate_details::CrateDetails,
+        encode_url_path,
+        error::{AxumNope, AxumResult},
+        extractors::{DbConnection, Path},
+        item_cache::ItemCache,
+        match_version,
+        registries::CrateRegistry,
+        ReqVersion,
+    },
+    AsyncStorage,
+};
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Extension, Query},
+    http::header::ACCESS_CONTROL_ALLOW_ORIGIN,
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const MAX_RESULTS: usize = 50;
+
+/// Returns the items matching `query`, best matches first.
+///
+/// Queries with a `::` are matched against the end of the item paths, all others against the
+/// item names.
+fn search<'a>(items: &'a [SearchItem], query: &str) -> Vec<&'a SearchItem> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let mut matches: Vec<(u8, &SearchItem)> = items
+        .iter()
+        .filter_map(|item| {
+            let rank = if query.contains("::") {
+                let path = item.path.to_lowercase();
+                if path == query || path.ends_with(&format!("::{query}")) {
+                    0
+                } else if path.contains(&query) {
+                    2
+                } else {
+                    return None;
+                }
+            } else {
+                let name = item.name().to_lowercase();
+                if name == query {
+                    0
+                } else if name.starts_with(&query) {
+                    1
+                } else if name.contains(&query) {
+                    2
+                } else {
+                    return None;
+                }
+            };
+            Some((rank, item))
+        })
+        .collect();
+
+    matches.sort_by(|(rank, item), (other_rank, other)| {
+        rank.cmp(other_rank)
+            .then_with(|| other.has_own_page().cmp(&item.has_own_page()))
+            .then_with(|| item.path.len().cmp(&other.path.len()))
+            .then_with(|| item.path.cmp(&other.path))
+    });
+    matches.truncate(MAX_RESULTS);
+    matches.into_iter().map(|(_, item)| item).collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    release_id: i32,
+    build_id: i32,
+    target: String,
+}
+
+/// The parsed search indexes of the releases searched last, so searching the same release
+/// again doesn't load and parse its search index again.
+pub(crate) type SearchIndexCache = ItemCache<CacheKey, Vec<SearchItem>>;
+
+/// Loads the search index of the documentation of `krate` for `target`.
+/// Returns `None` when there is no search index we can read.
+async fn load_search_index(
+    storage: &AsyncStorage,
+    cache: &SearchIndexCache,
+    krate: &CrateDetails,
+    target: &str,
+) -> Result<Option<Arc<Vec<SearchItem>>>> {
+    let build_id = krate
+        .latest_build_id
+        .ok_or_else(|| anyhow!("release with documentation but without a build"))?;
+    let key = CacheKey {
+        release_id: krate.release_id,
+        build_id,
+        target: target.to_owned(),
+    };
+    if let Some(items) = cache.get(&key) {
+        return Ok(Some(items));
+    }
+
+    // the documentation of the default target is at the root
+    let doc_prefix = if target == krate.metadata.default_target {
+        String::new()
+    } else {
+        format!("{target}/")
+    };
+    let Some(items) = fetch_search_index(
+        storage,
+        &krate.storage_name(),
+        &krate.version.to_string(),
+        build_id,
+        krate.archive_storage,
+        &doc_prefix,
+        &krate.target_name,
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let items = Arc::new(items);
+    cache.insert(key, items.clone());
+    Ok(Some(items))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct DocSearchParams {
+    q: String,
+    target: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct DocSearchResult<'a> {
+    path: &'a str,
+    kind: &'static str,
+    description: Option<&'a str>,
+    url: String,
+}
+
+/// Searches the items in the documentation of a release, for the default target unless
+/// another one is chosen with `target`.
+pub(crate) async fn search_json_handler(
+    Path((name, req_version)): Path<(String, ReqVersion)>,
+    Query(params): Query<DocSearchParams>,
+    registry: CrateRegistry,
+    mut conn: DbConnection,
+    Extension(storage): Extension<Arc<AsyncStorage>>,
+    Extension(cache): Extension<Arc<SearchIndexCache>>,
+) -> AxumResult<impl IntoResponse> {
+    let matched_release = match_version(&mut conn, registry.name(), &name, &req_version)
+        .await?
+        .assume_exact_name()?;
+    let original_req_version = matched_release.req_version.clone();
+    let matched_release = matched_release.into_canonical_req_version();
+    if matched_release.req_version != original_req_version {
+        // `AxumNope::Redirect` would encode the query as part of the path
+        let mut query = vec![("q", params.q.as_str())];
+        query.extend(params.target.as_deref().map(|target| ("target", target)));
+        return Ok(axum_cached_redirect(
+            axum_parse_uri_with_params(
+                &encode_url_path(&format!(
+                    "/crate/{name}/{}/search.json",
+                    matched_release.req_version
+                )),
+                query,
+            )?,
+            CachePolicy::ForeverInCdn,
+        )?
+        .into_response());
+    }
+    let req_version = matched_release.req_version.clone();
+
+    let krate = CrateDetails::from_matched_release(&mut conn, matched_release).await?;
+    if !krate.rustdoc_status {
+        return Err(AxumNope::ResourceNotFound);
+    }
+
+    let target = params
+        .target
+        .unwrap_or_else(|| krate.metadata.default_target.clone());
+    if target != krate.metadata.default_target && !krate.metadata.doc_targets.contains(&target) {
+        return Err(AxumNope::ResourceNotFound);
+    }
+
+    let items = load_search_index(&storage, &cache, &krate, &target)
+        .await?
+        .ok_or(AxumNope::ResourceNotFound)?;
+
+    let target_path = if target == krate.metadata.default_target {
+        String::new()
+    } else {
+        format!("{target}/")
+    };
+    let results: Vec<_> = search(&items, &params.q)
+        .into_iter()
+        .map(|item| DocSearchResult {
+            path: &item.path,
+            kind: item.kind,
+            description: item.description.as_deref(),
+            url: format!(
+                "{}/{name}/{req_version}/{target_path}{}",
+                registry.url_prefix(),
+                item.url
+            ),
+        })
+        .collect();
+
+    Ok((
+        Extension(if req_version.is_latest() {
+            CachePolicy::ForeverInCdn
+        } else {
+            CachePolicy::ForeverInCdnAndStaleInBrowser
+        }),
+        [(ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Json(serde_json::json!({
+            "name": name,
+            "version": krate.version.to_string(),
+            "target": target,
+            "query": params.q,
+            "results": results,
+        })),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test::*,
+        utils::search_index::{desc_shard_path, TEST_DESC_SHARDS, TEST_SEARCH_INDEX},
+    };
+    use reqwest::StatusCode;
+    use serde_json::Value;
+
+    fn item(path: &str, kind: &'static str, url: &str) -> SearchItem {
+        SearchItem {
+            path: path.into(),
+            kind,
+            description: None,
+            url: url.into(),
+        }
+    }
+
+    #[test]
+    fn ranking() {
+        let items = vec![
+            item(
+                "foo::Builder::new",
+                "method",
+                "foo/struct.Builder.html#method.new",
+            ),
+            item("foo::new", "fn", "foo/fn.new.html"),
+            item("foo::renew", "fn", "foo/fn.renew.html"),
+            item("foo::news::Feed", "struct", "foo/news/struct.Feed.html"),
+            item("foo::news", "mod", "foo/news/index.html"),
+        ];
+
+        let paths = |query| {
+            search(&items, query)
+                .into_iter()
+                .map(|item| item.path.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            paths("New"),
+            vec!["foo::new", "foo::Builder::new", "foo::news", "foo::renew"]
+        );
+        assert_eq!(paths("builder::new"), vec!["foo::Builder::new"]);
+        assert_eq!(paths("news::"), vec!["foo::news::Feed"]);
+        assert!(paths("  ").is_empty());
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let cache = SearchIndexCache::new(4);
+        let key = |release_id| CacheKey {
+            release_id,
+            build_id: 1,
+            target: "x86_64-unknown-linux-gnu".into(),
+        };
+        let items = |count| Arc::new(vec![item("foo::bar", "fn", "foo/fn.bar.html"); count]);
+
+        cache.insert(key(1), items(2));
+        cache.insert(key(2), items(1));
+        assert!(cache.get(&key(1)).is_some());
+        cache.insert(key(3), items(2));
+
+        assert!(cache.get(&key(1)).is_some());
+        assert!(cache.get(&key(2)).is_none());
+        assert!(cache.get(&key(3)).is_some());
+
+        // indexes bigger than the whole cache aren't cached
+        cache.insert(key(4), items(5));
+        assert!(cache.get(&key(4)).is_none());
+        assert!(cache.get(&key(1)).is_some());
+    }
+
+    #[test]
+    fn search_json() {
+        wrapper(|env| {
+            let crate_page = br#"<html><head><meta name="rustdoc-vars" data-root-path="../" data-current-crate="foo" data-resource-suffix="-20240401-1.79.0-nightly-abcdef"></head></html>"#;
+            env.fake_release()
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .rustdoc_file_with("foo/index.html", crate_page.to_vec())
+                .rustdoc_file_with(
+                    "search-index-20240401-1.79.0-nightly-abcdef.js",
+                    TEST_SEARCH_INDEX.as_bytes().to_vec(),
+                )
+                .rustdoc_file_with(
+                    &desc_shard_path("foo", 0, "-20240401-1.79.0-nightly-abcdef"),
+                    TEST_DESC_SHARDS[0].as_bytes().to_vec(),
+                )
+                .rustdoc_file_with(
+                    &desc_shard_path("foo", 1, "-20240401-1.79.0-nightly-abcdef"),
+                    TEST_DESC_SHARDS[1].as_bytes().to_vec(),
+                )
+                .create()?;
+
+            let web = env.frontend();
+            let response = web.get("/crate/foo/latest/search.json?q=baz").send()?;
+            assert!(response.status().is_success());
+            assert_eq!(response.headers()["access-control-allow-origin"], "*");
+            let json: Value = response.json()?;
+            assert_eq!(json["version"], "0.1.0");
+            assert_eq!(json["target"], "x86_64-unknown-linux-gnu");
+            assert_eq!(
+                json["results"][0],
+                serde_json::json!({
+                    "path": "foo::bar::Baz",
+                    "kind": "struct",
+                    "description": "A struct",
+                    "url": "/foo/latest/foo/bar/struct.Baz.html",
+                })
+            );
+
+            assert_redirect_unchecked(
+                "/crate/foo/0.1/search.json?q=baz",
+                "/crate/foo/0.1.0/search.json?q=baz",
+                web,
+            )?;
+
+            let response = web
+                .get("/crate/foo/0.1.0/search.json?q=baz&target=i686-pc-windows-msvc")
+                .send()?;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn search_json_of_alternative_registry() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.registries = vec![crate::registries::Registry {
+                    name: "internal".into(),
+                    index_url: "sparse+https://registry.example.com/index/".into(),
+                    api_host: "https://registry.example.com/".parse().unwrap(),
+                }]
+            });
+            let crate_page = br#"<html><head><meta name="rustdoc-vars" data-root-path="../" data-current-crate="foo" data-resource-suffix="-20240401-1.79.0-nightly-abcdef"></head></html>"#;
+            env.fake_release()
+                .registry("internal")
+                .name("foo")
+                .version("0.1.0")
+                .archive_storage(true)
+                .rustdoc_file_with("foo/index.html", crate_page.to_vec())
+                .rustdoc_file_with(
+                    "search-index-20240401-1.79.0-nightly-abcdef.js",
+                    TEST_SEARCH_INDEX.as_bytes().to_vec(),
+                )
+                .rustdoc_file_with(
+                    &desc_shard_path("foo", 0, "-20240401-1.79.0-nightly-abcdef"),
+                    TEST_DESC_SHARDS[0].as_bytes().to_vec(),
+                )
+                .rustdoc_file_with(
+                    &desc_shard_path("foo", 1, "-20240401-1.79.0-nightly-abcdef"),
+                    TEST_DESC_SHARDS[1].as_bytes().to_vec(),
+                )
+                .create()?;
+
+            let web = env.frontend();
+            assert_eq!(
+                web.get("/crate/foo/latest/search.json?q=baz")
+                    .send()?
+                    .status(),
+                StatusCode::NOT_FOUND
+            );
+
+            let json: Value = web
+                .get("/r/internal/crate/foo/latest/search.json?q=baz")
+                .send()?
+                .json()?;
+            assert_eq!(
+                json["results"][0]["url"],
+                "/r/internal/foo/latest/foo/bar/struct.Baz.html"
+            );
+
+            Ok(())
+        })
+    }
+}
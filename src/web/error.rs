@@ -1,7 +1,13 @@
 use crate::{
     db::PoolError,
     storage::PathNotFoundError,
-    web::{cache::CachePolicy, encode_url_path, releases::Search, AxumErrorPage},
+    web::{
+        cache::CachePolicy,
+        encode_url_path,
+        releases::Search,
+        suggestions::{CrateNotFoundPage, MissingCrate},
+        AxumErrorPage,
+    },
 };
 use anyhow::anyhow;
 use axum::{
@@ -17,7 +23,7 @@ pub enum AxumNope {
     #[error("Requested build not found")]
     BuildNotFound,
     #[error("Requested crate not found")]
-    CrateNotFound,
+    CrateNotFound(String),
     #[error("Requested owner not found")]
     OwnerNotFound,
     #[error("Requested crate does not have specified version")]
@@ -52,15 +58,12 @@ impl IntoResponse for AxumNope {
             }
             .into_response(),
 
-            AxumNope::CrateNotFound => {
-                // user tried to navigate to a crate that doesn't exist
-                // TODO: Display the attempted crate and a link to a search for said crate
-                AxumErrorPage {
-                    title: "The requested crate does not exist",
-                    message: "no such crate".into(),
-                    status: StatusCode::NOT_FOUND,
-                }
-                .into_response()
+            AxumNope::CrateNotFound(name) => {
+                // user tried to navigate to a crate that doesn't exist,
+                // `crate_suggestions_middleware` adds similarly named crates to the page.
+                let mut response = CrateNotFoundPage::new(name.clone(), Vec::new()).into_response();
+                response.extensions_mut().insert(MissingCrate(name));
+                response
             }
 
             AxumNope::OwnerNotFound => AxumErrorPage {
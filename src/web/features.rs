@@ -6,7 +6,9 @@ use crate::{
         error::{AxumNope, AxumResult},
         extractors::{DbConnection, Path},
         headers::CanonicalUrl,
-        match_version, MetaData, ReqVersion,
+        match_version,
+        registries::CrateRegistry,
+        MetaData, ReqVersion,
     },
 };
 use anyhow::anyhow;
@@ -37,9 +39,10 @@ impl_axum_webpage! {
 
 pub(crate) async fn build_features_handler(
     Path((name, req_version)): Path<(String, ReqVersion)>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
 ) -> AxumResult<impl IntoResponse> {
-    let version = match_version(&mut conn, &name, &req_version)
+    let version = match_version(&mut conn, registry.name(), &name, &req_version)
         .await?
         .assume_exact_name()?
         .into_canonical_req_version_or_else(|version| {
@@ -50,17 +53,27 @@ pub(crate) async fn build_features_handler(
         })?
         .into_version();
 
-    let metadata =
-        MetaData::from_crate(&mut conn, &name, &version, Some(req_version.clone())).await?;
+    let metadata = MetaData::from_crate(
+        &mut conn,
+        registry.name(),
+        &name,
+        &version,
+        Some(req_version.clone()),
+    )
+    .await?;
 
     let row = sqlx::query!(
         r#"
         SELECT releases.features as "features?: Vec<Feature>"
         FROM releases
         INNER JOIN crates ON crates.id = releases.crate_id
-        WHERE crates.name = $1 AND releases.version = $2"#,
+        WHERE
+            crates.name = $1 AND
+            releases.version = $2 AND
+            crates.registry IS NOT DISTINCT FROM $3"#,
         name,
         version.to_string(),
+        registry.name(),
     )
     .fetch_optional(&mut *conn)
     .await?
@@ -80,7 +93,11 @@ pub(crate) async fn build_features_handler(
         features,
         default_len,
         is_latest_url: req_version.is_latest(),
-        canonical_url: CanonicalUrl::from_path(format!("/crate/{}/latest/features", &name)),
+        canonical_url: CanonicalUrl::from_path(format!(
+            "{}/crate/{}/latest/features",
+            registry.url_prefix(),
+            &name
+        )),
         use_direct_platform_links: true,
     }
     .into_response())
@@ -3,29 +3,89 @@
 use super::cache::CachePolicy;
 use crate::{
     error::Result,
-    storage::{AsyncStorage, Blob},
+    storage::{AsyncStorage, Blob, CompressionAlgorithm, CompressionAlgorithms},
     Config,
 };
 
 use axum::{
-    extract::Extension,
+    async_trait,
+    extract::{Extension, FromRequestParts},
     http::{
-        header::{CONTENT_TYPE, LAST_MODIFIED},
-        StatusCode,
+        header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, LAST_MODIFIED, VARY},
+        request::Parts,
+        HeaderValue, StatusCode,
     },
     response::{IntoResponse, Response as AxumResponse},
 };
 use mime::Mime;
+use std::convert::Infallible;
+use strum::IntoEnumIterator;
+
+/// The name of the HTTP content-coding for files compressed with `alg`, if there is one.
+fn content_coding(alg: CompressionAlgorithm) -> Option<&'static str> {
+    match alg {
+        CompressionAlgorithm::Zstd => Some("zstd"),
+        CompressionAlgorithm::Bzip2 => None,
+    }
+}
+
+/// The compression algorithms of our stored files that the client accepts as `Content-Encoding`,
+/// from its `Accept-Encoding` header.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct AcceptEncoding(pub(crate) CompressionAlgorithms);
+
+impl AcceptEncoding {
+    fn from_header_value(value: &str) -> Self {
+        let mut accepted = CompressionAlgorithms::new();
+        for coding in value.split(',') {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            // `q=0` means the client doesn't accept the coding
+            let rejected = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            if rejected {
+                continue;
+            }
+            accepted.extend(CompressionAlgorithm::iter().filter(|&alg| {
+                content_coding(alg).is_some_and(|coding| coding.eq_ignore_ascii_case(name))
+            }));
+        }
+        Self(accepted)
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AcceptEncoding
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let mut accepted = CompressionAlgorithms::new();
+        for value in parts.headers.get_all(ACCEPT_ENCODING) {
+            if let Ok(value) = value.to_str() {
+                accepted.extend(Self::from_header_value(value).0);
+            }
+        }
+        Ok(Self(accepted))
+    }
+}
 
 #[derive(Debug)]
 pub(crate) struct File(pub(crate) Blob);
 
 impl File {
-    /// Gets file from database
+    /// Gets file from database, still compressed if the client accepts its compression.
     pub(super) async fn from_path(
         storage: &AsyncStorage,
         path: &str,
         config: &Config,
+        accepted: &AcceptEncoding,
     ) -> Result<File> {
         let max_size = if path.ends_with(".html") {
             config.max_file_size_html
@@ -33,7 +93,9 @@ impl File {
             config.max_file_size
         };
 
-        Ok(File(storage.get(path, max_size).await?))
+        Ok(File(
+            storage.get_for_client(path, max_size, &accepted.0).await?,
+        ))
     }
 }
 
@@ -45,7 +107,9 @@ impl IntoResponse for File {
             .parse::<Mime>()
             .unwrap_or(mime::APPLICATION_OCTET_STREAM);
 
-        (
+        let content_encoding = self.0.compression.and_then(content_coding);
+
+        let mut response = (
             StatusCode::OK,
             [
                 (CONTENT_TYPE, content_type.as_ref()),
@@ -57,7 +121,15 @@ impl IntoResponse for File {
             Extension(CachePolicy::ForeverInCdnAndBrowser),
             self.0.content,
         )
-            .into_response()
+            .into_response();
+
+        // we might send other clients the same file with another encoding
+        let headers = response.headers_mut();
+        headers.insert(VARY, HeaderValue::from(ACCEPT_ENCODING));
+        if let Some(coding) = content_encoding {
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static(coding));
+        }
+        response
     }
 }
 
@@ -81,6 +153,7 @@ mod tests {
                     &env.runtime().block_on(env.async_storage()),
                     "rustdoc/fake-package/1.0.0/fake-package/index.html",
                     &env.config(),
+                    &AcceptEncoding::default(),
                 ))
                 .unwrap();
             file.0.date_updated = now;
@@ -101,6 +174,35 @@ mod tests {
         });
     }
 
+    #[test]
+    fn parse_accept_encoding() {
+        let parse = |value| AcceptEncoding::from_header_value(value).0;
+        let zstd = CompressionAlgorithms::from([CompressionAlgorithm::Zstd]);
+
+        assert_eq!(parse(""), CompressionAlgorithms::new());
+        assert_eq!(parse("gzip, deflate, br"), CompressionAlgorithms::new());
+        assert_eq!(parse("gzip, deflate, br, zstd"), zstd);
+        assert_eq!(parse("ZSTD;q=0.5"), zstd);
+        assert_eq!(parse("zstd;q=0, gzip"), CompressionAlgorithms::new());
+        // bzip2 isn't a content-coding browsers understand
+        assert_eq!(parse("bzip2"), CompressionAlgorithms::new());
+    }
+
+    #[test]
+    fn file_with_content_encoding() {
+        let response = File(Blob {
+            path: "some.js".into(),
+            mime: "application/javascript".into(),
+            date_updated: Utc::now(),
+            content: b"compressed".to_vec(),
+            compression: Some(CompressionAlgorithm::Zstd),
+        })
+        .into_response();
+
+        assert_eq!(response.headers().get(CONTENT_ENCODING).unwrap(), "zstd");
+        assert_eq!(response.headers().get(VARY).unwrap(), "accept-encoding");
+    }
+
     #[test]
     fn test_max_size() {
         const MAX_SIZE: usize = 1024;
@@ -115,12 +217,12 @@ mod tests {
             env.fake_release()
                 .name("dummy")
                 .version("0.1.0")
-                .rustdoc_file_with("small.html", &[b'A'; MAX_HTML_SIZE / 2] as &[u8])
-                .rustdoc_file_with("exact.html", &[b'A'; MAX_HTML_SIZE] as &[u8])
-                .rustdoc_file_with("big.html", &[b'A'; MAX_HTML_SIZE * 2] as &[u8])
-                .rustdoc_file_with("small.js", &[b'A'; MAX_SIZE / 2] as &[u8])
-                .rustdoc_file_with("exact.js", &[b'A'; MAX_SIZE] as &[u8])
-                .rustdoc_file_with("big.js", &[b'A'; MAX_SIZE * 2] as &[u8])
+                .rustdoc_file_with("small.html", vec![b'A'; MAX_HTML_SIZE / 2])
+                .rustdoc_file_with("exact.html", vec![b'A'; MAX_HTML_SIZE])
+                .rustdoc_file_with("big.html", vec![b'A'; MAX_HTML_SIZE * 2])
+                .rustdoc_file_with("small.js", vec![b'A'; MAX_SIZE / 2])
+                .rustdoc_file_with("exact.js", vec![b'A'; MAX_SIZE])
+                .rustdoc_file_with("big.js", vec![b'A'; MAX_SIZE * 2])
                 .create()?;
 
             let file = |path| {
@@ -128,6 +230,7 @@ mod tests {
                     &env.runtime().block_on(env.async_storage()),
                     &format!("rustdoc/dummy/0.1.0/{path}"),
                     &env.config(),
+                    &AcceptEncoding::default(),
                 ))
             };
             let assert_len = |len, path| {
@@ -0,0 +1,104 @@
+//! In-memory cache for data parsed from the stored documentation of releases, like their
+//! search indexes, so requests for the same release don't load and parse it again.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+/// Values which are made of a number of items, the size of the cache is counted in those.
+pub(crate) trait Items {
+    fn item_count(&self) -> usize;
+}
+
+impl<T> Items for Vec<T> {
+    fn item_count(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<K, V> Items for BTreeMap<K, V> {
+    fn item_count(&self) -> usize {
+        self.len()
+    }
+}
+
+struct CacheState<K, V> {
+    /// The values, and the position of the entry in `lru`.
+    entries: HashMap<K, (Arc<V>, u64)>,
+    /// The keys of the entries, least recently used first.
+    lru: BTreeMap<u64, K>,
+    next_tick: u64,
+    total_items: usize,
+}
+
+impl<K, V> Default for CacheState<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            next_tick: 0,
+            total_items: 0,
+        }
+    }
+}
+
+impl<K: Hash + Eq, V: Items> CacheState<K, V> {
+    fn remove(&mut self, key: &K) {
+        if let Some((value, tick)) = self.entries.remove(key) {
+            self.lru.remove(&tick);
+            self.total_items -= value.item_count();
+        }
+    }
+}
+
+/// The values used last, holding at most `max_items` items over all of them.
+///
+/// Keys should contain the build id of the release, rebuilds get a new one so their values
+/// aren't mixed up with the ones of the previous build.
+pub(crate) struct ItemCache<K, V> {
+    max_items: usize,
+    state: Mutex<CacheState<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V: Items> ItemCache<K, V> {
+    pub(crate) fn new(max_items: usize) -> Self {
+        Self {
+            max_items,
+            state: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &K) -> Option<Arc<V>> {
+        let mut state = self.state.lock().unwrap();
+        let tick = state.next_tick;
+        let (value, previous_tick) = state.entries.get_mut(key)?;
+        let value = value.clone();
+        let previous_tick = std::mem::replace(previous_tick, tick);
+        state.next_tick += 1;
+        state.lru.remove(&previous_tick);
+        state.lru.insert(tick, key.clone());
+        Some(value)
+    }
+
+    pub(crate) fn insert(&self, key: K, value: Arc<V>) {
+        if value.item_count() > self.max_items {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+        while state.total_items + value.item_count() > self.max_items {
+            let Some((_, oldest)) = state.lru.pop_first() else {
+                break;
+            };
+            state.remove(&oldest);
+        }
+
+        let tick = state.next_tick;
+        state.next_tick += 1;
+        state.total_items += value.item_count();
+        state.lru.insert(tick, key.clone());
+        state.entries.insert(key, (value, tick));
+    }
+}
@@ -9,19 +9,23 @@ use axum_extra::middleware::option_layer;
 use serde_json::Value;
 use tracing::{info, instrument};
 
+mod api_diff;
 mod build_details;
 mod builds;
 pub(crate) mod cache;
 pub(crate) mod crate_details;
 mod csp;
+mod doc_search;
 pub(crate) mod error;
 mod extractors;
 mod features;
 mod file;
 mod headers;
 mod highlight;
+mod item_cache;
 mod markdown;
 pub(crate) mod metrics;
+mod registries;
 mod releases;
 mod routes;
 mod rustdoc;
@@ -29,6 +33,10 @@ mod sitemap;
 mod source;
 mod statics;
 mod status;
+mod suggestions;
+mod webhook;
+
+pub(crate) use self::releases::CrateSearchBackend;
 
 use crate::{impl_axum_webpage, Context};
 use anyhow::Error;
@@ -54,7 +62,7 @@ use std::{
     str::FromStr,
     sync::Arc,
 };
-use tower::ServiceBuilder;
+use tower::{Layer as _, ServiceBuilder};
 use tower_http::{catch_panic::CatchPanicLayer, timeout::TimeoutLayer, trace::TraceLayer};
 use url::form_urlencoded;
 
@@ -115,6 +123,9 @@ pub(crate) struct MatchedRelease {
     /// crate name
     pub name: String,
 
+    /// alternative registry the crate comes from, `None` for crates.io
+    pub(crate) registry: Option<String>,
+
     /// The crate name that was found when attempting to load a crate release.
     /// `match_version` will attempt to match a provided crate name against similar crate names with
     /// dashes (`-`) replaced with underscores (`_`) and vice versa.
@@ -135,7 +146,7 @@ impl MatchedRelease {
         if self.corrected_name.is_none() {
             Ok(self)
         } else {
-            Err(AxumNope::CrateNotFound)
+            Err(AxumNope::CrateNotFound(self.name))
         }
     }
 
@@ -235,9 +246,12 @@ impl MatchedRelease {
 /// This function will also check for crates where dashes in the name (`-`) have been replaced with
 /// underscores (`_`) and vice-versa. The return value will indicate whether the crate name has
 /// been matched exactly, or if there has been a "correction" in the name that matched instead.
+///
+/// Only crates of the given alternative `registry` are considered, or crates.io crates for `None`.
 #[instrument(skip(conn))]
 async fn match_version(
     conn: &mut sqlx::PgConnection,
+    registry: Option<&str>,
     name: &str,
     input_version: &ReqVersion,
 ) -> Result<MatchedRelease, AxumNope> {
@@ -245,13 +259,16 @@ async fn match_version(
         let row = sqlx::query!(
             "SELECT id, name
              FROM crates
-             WHERE normalize_crate_name(name) = normalize_crate_name($1)",
+             WHERE
+                normalize_crate_name(name) = normalize_crate_name($1) AND
+                registry IS NOT DISTINCT FROM $2",
             name,
+            registry,
         )
         .fetch_optional(&mut *conn)
         .await
         .context("error fetching crate")?
-        .ok_or(AxumNope::CrateNotFound)?;
+        .ok_or_else(|| AxumNope::CrateNotFound(name.to_owned()))?;
 
         if row.name != name {
             (row.id, Some(row.name))
@@ -267,7 +284,7 @@ async fn match_version(
         .context("error fetching releases for crate")?;
 
     if releases.is_empty() {
-        return Err(AxumNope::CrateNotFound);
+        return Err(AxumNope::CrateNotFound(name.to_owned()));
     }
 
     let req_semver: VersionReq = match input_version {
@@ -278,6 +295,7 @@ async fn match_version(
             {
                 return Ok(MatchedRelease {
                     name: name.to_owned(),
+                    registry: registry.map(str::to_owned),
                     corrected_name,
                     req_version: input_version.clone(),
                     release: release.clone(),
@@ -307,6 +325,7 @@ async fn match_version(
     {
         return Ok(MatchedRelease {
             name: name.to_owned(),
+            registry: registry.map(str::to_owned),
             corrected_name,
             req_version: input_version.clone(),
             release: release.clone(),
@@ -323,6 +342,7 @@ async fn match_version(
             .cloned()
             .map(|release| MatchedRelease {
                 name: name.to_owned(),
+                registry: registry.map(str::to_owned),
                 corrected_name: corrected_name.clone(),
                 req_version: input_version.clone(),
                 release,
@@ -396,11 +416,29 @@ fn apply_middleware(
             .layer(Extension(context.config()?))
             .layer(Extension(context.storage()?))
             .layer(Extension(async_storage))
+            .layer(Extension(Arc::new(doc_search::SearchIndexCache::new(
+                config.search_index_cache_items,
+            ))))
+            .layer(Extension(Arc::new(api_diff::ApiCache::new(
+                config.api_diff_cache_items,
+            ))))
+            .layer(Extension(
+                Arc::new(suggestions::SuggestionsCache::default()),
+            ))
+            // opening the index is expensive, and it's only needed for the webhook.
+            .layer(option_layer(if config.index_webhook_secret.is_some() {
+                Some(Extension(context.index()?))
+            } else {
+                None
+            }))
             .layer(option_layer(template_data.map(Extension)))
             .layer(middleware::from_fn(csp::csp_middleware))
             .layer(option_layer(has_templates.then_some(middleware::from_fn(
                 page::web_page::render_templates_middleware,
             ))))
+            .layer(middleware::from_fn(
+                suggestions::crate_suggestions_middleware,
+            ))
             .layer(middleware::from_fn(cache::cache_middleware)),
     ))
 }
@@ -409,7 +447,17 @@ pub(crate) fn build_axum_app(
     context: &dyn Context,
     template_data: Arc<TemplateData>,
 ) -> Result<AxumRouter, Error> {
-    apply_middleware(routes::build_axum_routes(), context, Some(template_data))
+    let app = apply_middleware(routes::build_axum_routes(), context, Some(template_data))?;
+
+    // the namespaces of alternative registries are resolved before routing,
+    // so they can't be a layer of the router itself.
+    Ok(AxumRouter::new().fallback_service(
+        middleware::from_fn_with_state(
+            context.config()?,
+            registries::registry_namespace_middleware,
+        )
+        .layer(app),
+    ))
 }
 
 pub(crate) fn build_metrics_axum_app(context: &dyn Context) -> Result<AxumRouter, Error> {
@@ -631,6 +679,7 @@ impl MetaData {
     #[fn_error_context::context("getting metadata for {name} {version}")]
     async fn from_crate(
         conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
         name: &str,
         version: &Version,
         req_version: Option<ReqVersion>,
@@ -654,9 +703,13 @@ impl MetaData {
                 ORDER BY builds.build_time
                 DESC LIMIT 1
             ) AS builds ON true
-            WHERE crates.name = $1 AND releases.version = $2"#,
+            WHERE
+                crates.name = $1 AND
+                releases.version = $2 AND
+                crates.registry IS NOT DISTINCT FROM $3"#,
             name,
             version.to_string(),
+            registry,
         )
         .fetch_one(&mut *conn)
         .await
@@ -733,6 +786,7 @@ mod test {
         let mut conn = db.async_conn().await;
         let version = match_version(
             &mut conn,
+            None,
             "foo",
             &ReqVersion::from_str(v.unwrap_or_default()).unwrap(),
         )
@@ -1150,6 +1204,7 @@ mod test {
             let mut conn = env.async_db().await.async_conn().await;
             let metadata = MetaData::from_crate(
                 &mut conn,
+                None,
                 "foo",
                 &"0.1.0".parse().unwrap(),
                 Some(ReqVersion::Latest),
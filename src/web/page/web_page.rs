@@ -1,5 +1,5 @@
 use super::TemplateData;
-use crate::web::{csp::Csp, error::AxumNope};
+use crate::web::{csp::Csp, error::AxumNope, registries::CrateRegistry};
 use anyhow::Error;
 use axum::{
     body::Body,
@@ -111,8 +111,8 @@ macro_rules! impl_axum_webpage {
 }
 
 /// adding this to the axum response extensions will lead
-/// to the template being rendered, adding the csp_nonce to
-/// the context.
+/// to the template being rendered, adding the csp_nonce and
+/// the registry_prefix to the context.
 #[derive(Clone)]
 pub(crate) struct DelayedTemplateRender {
     pub template: String,
@@ -124,6 +124,7 @@ fn render_response(
     mut response: AxumResponse,
     templates: Arc<TemplateData>,
     csp_nonce: String,
+    registry_prefix: String,
 ) -> BoxFuture<'static, AxumResponse> {
     async move {
         if let Some(render) = response.extensions_mut().remove::<DelayedTemplateRender>() {
@@ -133,6 +134,7 @@ fn render_response(
                 cpu_intensive_rendering,
             } = render;
             context.insert("csp_nonce", &csp_nonce);
+            context.insert("registry_prefix", &registry_prefix);
 
             let rendered = if cpu_intensive_rendering {
                 templates
@@ -161,6 +163,7 @@ fn render_response(
                             AxumNope::InternalError(err).into_response(),
                             templates,
                             csp_nonce,
+                            registry_prefix,
                         )
                         .await;
                     }
@@ -193,7 +196,14 @@ pub(crate) async fn render_templates_middleware(req: AxumRequest, next: Next) ->
         .nonce()
         .to_owned();
 
+    // links to crates of an alternative registry have to stay in its URL namespace
+    let registry_prefix = req
+        .extensions()
+        .get::<CrateRegistry>()
+        .map(CrateRegistry::url_prefix)
+        .unwrap_or_default();
+
     let response = next.run(req).await;
 
-    render_response(response, templates, csp_nonce).await
+    render_response(response, templates, csp_nonce, registry_prefix).await
 }
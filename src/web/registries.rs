@@ -0,0 +1,212 @@
+//! URL namespaces of the alternative registries.
+//!
+//! `/r/<registry>/<path>` serves the same pages as `/<path>`, for the crates of that registry.
+//! The prefix is removed before routing and added back to redirects, handlers find out which
+//! registry a request is about with the [`CrateRegistry`] extractor.
+
+use crate::Config;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request as AxumRequest, State},
+    http::{header::LOCATION, request::Parts, HeaderValue, Uri},
+    middleware::Next,
+    response::Response as AxumResponse,
+};
+use std::{convert::Infallible, sync::Arc};
+
+/// The alternative registry whose crates a request is about, `None` for crates.io.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CrateRegistry(Option<String>);
+
+impl CrateRegistry {
+    pub(crate) fn new(name: Option<String>) -> Self {
+        Self(name)
+    }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+
+    /// Prefix of the URLs of the crates in the registry, empty for crates.io.
+    pub(crate) fn url_prefix(&self) -> String {
+        self.0
+            .as_ref()
+            .map(|name| format!("/r/{name}"))
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CrateRegistry
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<CrateRegistry>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// Splits `/r/<registry>/<path>` into the registry and `/<path>`.
+fn split_namespace(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix("/r/")?;
+    Some(match rest.find('/') {
+        Some(idx) => rest.split_at(idx),
+        None => (rest, "/"),
+    })
+}
+
+/// Has to run before routing, so it wraps the whole app instead of being a layer of the router.
+///
+/// Paths starting with `/r/` with a registry that isn't configured are left alone,
+/// they could be the docs of the `r` crate.
+pub(crate) async fn registry_namespace_middleware(
+    State(config): State<Arc<Config>>,
+    mut request: AxumRequest,
+    next: Next,
+) -> AxumResponse {
+    let Some((registry, path)) = split_namespace(request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let Some(registry) = config.registry(registry) else {
+        return next.run(request).await;
+    };
+
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_owned(),
+    };
+    let mut uri = request.uri().clone().into_parts();
+    uri.path_and_query = path_and_query.parse().ok();
+    let Ok(uri) = Uri::from_parts(uri) else {
+        return next.run(request).await;
+    };
+
+    let prefix = registry.url_prefix();
+    *request.uri_mut() = uri;
+    request
+        .extensions_mut()
+        .insert(CrateRegistry(Some(registry.name.clone())));
+
+    let mut response = next.run(request).await;
+
+    // keep redirects in the namespace of the registry
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|location| location.to_str().ok())
+        .filter(|location| location.starts_with('/') && !location.starts_with("//"))
+        .and_then(|location| HeaderValue::from_str(&format!("{prefix}{location}")).ok());
+    if let Some(location) = location {
+        response.headers_mut().insert(LOCATION, location);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        registries::Registry,
+        test::{assert_redirect, wrapper},
+    };
+    use reqwest::StatusCode;
+    use test_case::test_case;
+
+    #[test_case("/r/internal/foo/1.0.0/", Some(("internal", "/foo/1.0.0/")))]
+    #[test_case("/r/internal", Some(("internal", "/")))]
+    #[test_case("/r/1.0.0/r/", Some(("1.0.0", "/r/")))]
+    #[test_case("/crate/r/latest", None)]
+    fn split(path: &str, expected: Option<(&str, &str)>) {
+        assert_eq!(split_namespace(path), expected);
+    }
+
+    fn internal_registry() -> Registry {
+        Registry {
+            name: "internal".into(),
+            index_url: "sparse+https://registry.example.com/index/".into(),
+            api_host: "https://registry.example.com/".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn crates_with_the_same_name() {
+        wrapper(|env| {
+            env.override_config(|config| config.registries = vec![internal_registry()]);
+
+            env.fake_release()
+                .name("foo")
+                .version("1.0.0")
+                .description("from crates.io")
+                .create()?;
+            env.fake_release()
+                .registry("internal")
+                .name("foo")
+                .version("2.0.0")
+                .description("from the internal registry")
+                .archive_storage(true)
+                .rustdoc_file("foo/index.html")
+                .create()?;
+
+            let web = env.frontend();
+
+            let crates_io = web.get("/crate/foo/latest").send()?;
+            assert!(crates_io.status().is_success());
+            let crates_io = crates_io.text()?;
+            assert!(crates_io.contains("from crates.io"));
+            assert!(!crates_io.contains("from the internal registry"));
+
+            let internal = web.get("/r/internal/crate/foo/latest").send()?;
+            assert!(internal.status().is_success());
+            let internal = internal.text()?;
+            assert!(internal.contains("from the internal registry"));
+            assert!(internal.contains(r#"href="/r/internal/crate/foo/latest/source/""#));
+
+            assert_eq!(
+                web.get("/crate/foo/2.0.0").send()?.status(),
+                StatusCode::NOT_FOUND
+            );
+            assert_eq!(
+                web.get("/r/internal/crate/foo/1.0.0").send()?.status(),
+                StatusCode::NOT_FOUND
+            );
+
+            assert!(web
+                .get("/r/internal/foo/2.0.0/foo/")
+                .send()?
+                .status()
+                .is_success());
+            assert_redirect("/r/internal/foo", "/r/internal/foo/latest/foo/", web)?;
+
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn unknown_registry() {
+        wrapper(|env| {
+            env.override_config(|config| config.registries = vec![internal_registry()]);
+            env.fake_release()
+                .registry("internal")
+                .name("foo")
+                .version("1.0.0")
+                .create()?;
+
+            // handled like the docs of the `r` crate, with an invalid version
+            assert_eq!(
+                env.frontend()
+                    .get("/r/other/crate/foo/1.0.0")
+                    .send()?
+                    .status(),
+                StatusCode::BAD_REQUEST
+            );
+            Ok(())
+        })
+    }
+}
@@ -1,23 +1,27 @@
 //! Releases web handlers
 
 use crate::{
-    build_queue::QueuedCrate,
+    build_queue::{QueuedCrate, WorkerStatus},
     cdn,
-    db::Pool,
+    db::{types::BuildFailureCategory, Pool},
     impl_axum_webpage,
     utils::{report_error, retry_async, spawn_blocking},
     web::{
         axum_parse_uri_with_params, axum_redirect, encode_url_path,
         error::{AxumNope, AxumResult},
         extractors::{DbConnection, Path},
-        match_version, ReqVersion,
+        match_version,
+        registries::CrateRegistry,
+        ReqVersion,
     },
     BuildQueue, Config, InstanceMetrics,
 };
 use anyhow::{anyhow, bail, Context as _, Result};
 use axum::{
     extract::{Extension, Query},
+    http::header::ACCESS_CONTROL_ALLOW_ORIGIN,
     response::{IntoResponse, Response as AxumResponse},
+    Json,
 };
 use base64::{engine::general_purpose::STANDARD as b64, Engine};
 use chrono::{DateTime, Utc};
@@ -26,7 +30,7 @@ use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use sqlx::Row;
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::str;
+use std::str::{self, FromStr};
 use std::sync::Arc;
 use tracing::{debug, warn};
 use url::form_urlencoded;
@@ -50,13 +54,16 @@ pub struct Release {
     pub(crate) build_time: DateTime<Utc>,
     stars: i32,
     has_unyanked_releases: Option<bool>,
+    /// see [`CrateRegistry::url_prefix`]
+    registry_prefix: String,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub(crate) enum Order {
     ReleaseTime, // this is default order
     GithubStars,
-    RecentFailures,
+    /// Failed releases, optionally only those whose latest build failed for the given reason
+    RecentFailures(Option<BuildFailureCategory>),
     FailuresByGithubStars,
 }
 
@@ -79,10 +86,15 @@ pub(crate) async fn get_releases(
     let (ordering, filter_failed): (&'static str, _) = match order {
         Order::ReleaseTime => ("release_build_status.last_build_time", false),
         Order::GithubStars => ("repositories.stars", false),
-        Order::RecentFailures => ("release_build_status.last_build_time", true),
+        Order::RecentFailures(_) => ("release_build_status.last_build_time", true),
         Order::FailuresByGithubStars => ("repositories.stars", true),
     };
 
+    let failure_category = match order {
+        Order::RecentFailures(category) => category,
+        _ => None,
+    };
+
     let query = format!(
         "SELECT crates.name,
             releases.version,
@@ -90,7 +102,8 @@ pub(crate) async fn get_releases(
             releases.target_name,
             releases.rustdoc_status,
             release_build_status.last_build_time AS build_time,
-            repositories.stars
+            repositories.stars,
+            crates.registry
         FROM crates
         {1}
         INNER JOIN release_build_status ON releases.id = release_build_status.rid
@@ -98,6 +111,13 @@ pub(crate) async fn get_releases(
         WHERE
             ((NOT $3) OR (release_build_status.build_status = 'failure' AND releases.is_library = TRUE))
             AND {0} IS NOT NULL
+            AND ($4::build_failure_category IS NULL OR $4 = (
+                SELECT builds.failure_category
+                FROM builds
+                WHERE builds.rid = releases.id
+                ORDER BY builds.id DESC
+                LIMIT 1
+            ))
 
         ORDER BY {0} DESC
         LIMIT $1 OFFSET $2",
@@ -113,6 +133,7 @@ pub(crate) async fn get_releases(
         .bind(limit)
         .bind(offset)
         .bind(filter_failed)
+        .bind(failure_category)
         .fetch(conn)
         .map_ok(|row| Release {
             name: row.get(0),
@@ -123,6 +144,7 @@ pub(crate) async fn get_releases(
             build_time: row.get(5),
             stars: row.get::<Option<i32>, _>(6).unwrap_or(0),
             has_unyanked_releases: None,
+            registry_prefix: CrateRegistry::new(row.get(7)).url_prefix(),
         })
         .try_collect()
         .await?)
@@ -135,10 +157,38 @@ struct SearchResult {
     pub next_page: Option<String>,
 }
 
+/// Where crate searches are answered, see [`Config::crate_search_backend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString)]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub(crate) enum CrateSearchBackend {
+    /// The crates.io search API, for the crates.io crates docs.rs has built.
+    CratesIo,
+    /// Full-text and trigram search over the crates in our database, see
+    /// [`get_local_search_results`].
+    Local,
+}
+
+/// Get the search results for a crate search query from the configured backend.
+///
+/// `query_params` are the query args of the search, starting with `?`. They are passed on
+/// as-is, and the args of the previous and next pages are returned the same way.
+async fn get_search_results(
+    conn: &mut sqlx::PgConnection,
+    config: &Config,
+    query_params: &str,
+) -> Result<SearchResult, anyhow::Error> {
+    match config.crate_search_backend {
+        CrateSearchBackend::CratesIo => {
+            get_crates_io_search_results(conn, config, query_params).await
+        }
+        CrateSearchBackend::Local => get_local_search_results(conn, query_params).await,
+    }
+}
+
 /// Get the search results for a crate search query
 ///
 /// This delegates to the crates.io search API.
-async fn get_search_results(
+async fn get_crates_io_search_results(
     conn: &mut sqlx::PgConnection,
     config: &Config,
     query_params: &str,
@@ -256,7 +306,7 @@ async fn get_search_results(
            INNER JOIN builds ON releases.id = builds.rid
            LEFT JOIN repositories ON releases.repository_id = repositories.id
 
-           WHERE crates.name = ANY($1)"#,
+           WHERE crates.name = ANY($1) AND crates.registry IS NULL"#,
         &names[..],
     )
     .fetch(&mut *conn)
@@ -272,6 +322,7 @@ async fn get_search_results(
                 rustdoc_status: row.rustdoc_status,
                 stars: row.stars.unwrap_or(0),
                 has_unyanked_releases: row.has_unyanked_releases,
+                registry_prefix: String::new(),
             },
         )
     })
@@ -293,6 +344,134 @@ async fn get_search_results(
     })
 }
 
+/// Searches the latest releases of all crates in the database, matching the query against the
+/// crate names with trigrams and against the keywords and descriptions with full-text search.
+///
+/// Understands the `q`, `sort`, `page` and `per_page` args of the crates.io API, so the search
+/// page and its pagination links work the same with both backends.
+async fn get_local_search_results(
+    conn: &mut sqlx::PgConnection,
+    query_params: &str,
+) -> Result<SearchResult> {
+    let mut query = String::new();
+    let mut sort_by = "relevance".to_owned();
+    let mut page: i64 = 1;
+    let mut per_page = RELEASES_IN_RELEASES;
+    for (key, value) in form_urlencoded::parse(query_params.trim_start_matches('?').as_bytes()) {
+        match &*key {
+            "q" => query = value.trim().to_owned(),
+            "sort" => sort_by = value.into_owned(),
+            "page" => page = value.parse().unwrap_or(1).max(1),
+            "per_page" => per_page = value.parse().unwrap_or(RELEASES_IN_RELEASES).clamp(1, 100),
+            _ => {}
+        }
+    }
+
+    if query.is_empty() {
+        return Ok(SearchResult {
+            results: Vec::new(),
+            executed_query: Some(query),
+            prev_page: None,
+            next_page: None,
+        });
+    }
+
+    // WARNING: it is _crucial_ that this always be hard-coded and NEVER be user input
+    let ordering: &'static str = match sort_by.as_str() {
+        "downloads" => {
+            "(SELECT SUM(all_releases.downloads)
+              FROM releases AS all_releases
+              WHERE all_releases.crate_id = crates.id) DESC"
+        }
+        "recent-downloads" => "releases.downloads DESC",
+        "recent-updates" => "releases.release_time DESC NULLS LAST",
+        "new" => {
+            "(SELECT MIN(all_releases.release_time)
+              FROM releases AS all_releases
+              WHERE all_releases.crate_id = crates.id) DESC NULLS LAST"
+        }
+        // exact name matches first, then weigh how well the crate matches by its popularity
+        _ => {
+            "normalize_crate_name(crates.name) = normalize_crate_name($1) DESC,
+             (
+                 similarity(crates.name, $1) +
+                 ts_rank(release_search_document(releases.description, releases.keywords), query)
+             ) * LOG(10 + COALESCE(repositories.stars, 0)) DESC"
+        }
+    };
+
+    let sql = format!(
+        "SELECT crates.name,
+            releases.version,
+            releases.description,
+            release_build_status.last_build_time AS build_time,
+            releases.target_name,
+            releases.rustdoc_status,
+            repositories.stars,
+            EXISTS (
+                SELECT 1
+                FROM releases AS all_releases
+                WHERE
+                    all_releases.crate_id = crates.id AND
+                    all_releases.yanked = false
+            ) AS has_unyanked_releases,
+            crates.registry
+        FROM crates
+        INNER JOIN releases ON crates.latest_version_id = releases.id
+        INNER JOIN release_build_status ON releases.id = release_build_status.rid
+        LEFT JOIN repositories ON releases.repository_id = repositories.id
+        CROSS JOIN websearch_to_tsquery('english', $1) AS query
+        WHERE
+            release_build_status.last_build_time IS NOT NULL AND (
+                crates.name % $1 OR
+                starts_with(normalize_crate_name(crates.name), normalize_crate_name($1)) OR
+                release_search_document(releases.description, releases.keywords) @@ query
+            )
+        ORDER BY {ordering}, crates.name
+        LIMIT $2 OFFSET $3"
+    );
+
+    // fetch one more to know if there is a next page
+    let mut results: Vec<Release> = sqlx::query(sql.as_str())
+        .bind(&query)
+        .bind(per_page + 1)
+        .bind((page - 1) * per_page)
+        .fetch(conn)
+        .map_ok(|row| Release {
+            name: row.get(0),
+            version: row.get(1),
+            description: row.get(2),
+            build_time: row.get(3),
+            target_name: row.get(4),
+            rustdoc_status: row.get(5),
+            stars: row.get::<Option<i32>, _>(6).unwrap_or(0),
+            has_unyanked_releases: row.get(7),
+            registry_prefix: CrateRegistry::new(row.get(8)).url_prefix(),
+        })
+        .try_collect()
+        .await?;
+
+    let has_next_page = results.len() > per_page as usize;
+    results.truncate(per_page as usize);
+
+    let page_params = |page: i64| {
+        let params = form_urlencoded::Serializer::new(String::new())
+            .append_pair("q", &query)
+            .append_pair("sort", &sort_by)
+            .append_pair("per_page", &per_page.to_string())
+            .append_pair("page", &page.to_string())
+            .finish();
+        format!("?{params}")
+    };
+
+    Ok(SearchResult {
+        results,
+        prev_page: (page > 1).then(|| page_params(page - 1)),
+        next_page: has_next_page.then(|| page_params(page + 1)),
+        executed_query: Some(query),
+    })
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 struct HomePage {
     recent_releases: Vec<Release>,
@@ -335,6 +514,9 @@ struct ViewReleases {
     show_previous_page: bool,
     page_number: i64,
     owner: Option<String>,
+    failure_category: Option<BuildFailureCategory>,
+    /// query string appended to the pagination links
+    query: Option<String>,
 }
 
 impl_axum_webpage! {
@@ -355,6 +537,7 @@ pub(crate) async fn releases_handler(
     conn: &mut sqlx::PgConnection,
     page: Option<i64>,
     release_type: ReleaseType,
+    failure_category: Option<BuildFailureCategory>,
 ) -> AxumResult<impl IntoResponse> {
     let page_number = page.unwrap_or(1);
 
@@ -363,7 +546,7 @@ pub(crate) async fn releases_handler(
         ReleaseType::Stars => ("Crates with most stars", Order::GithubStars, true),
         ReleaseType::RecentFailures => (
             "Recent crates failed to build",
-            Order::RecentFailures,
+            Order::RecentFailures(failure_category),
             false,
         ),
         ReleaseType::Failures => (
@@ -400,6 +583,8 @@ pub(crate) async fn releases_handler(
         show_previous_page,
         page_number,
         owner: None,
+        failure_category,
+        query: failure_category.map(|category| format!("?category={category}")),
     })
 }
 
@@ -407,28 +592,50 @@ pub(crate) async fn recent_releases_handler(
     page: Option<Path<i64>>,
     mut conn: DbConnection,
 ) -> AxumResult<impl IntoResponse> {
-    releases_handler(&mut conn, page.map(|p| p.0), ReleaseType::Recent).await
+    releases_handler(&mut conn, page.map(|p| p.0), ReleaseType::Recent, None).await
 }
 
 pub(crate) async fn releases_by_stars_handler(
     page: Option<Path<i64>>,
     mut conn: DbConnection,
 ) -> AxumResult<impl IntoResponse> {
-    releases_handler(&mut conn, page.map(|p| p.0), ReleaseType::Stars).await
+    releases_handler(&mut conn, page.map(|p| p.0), ReleaseType::Stars, None).await
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct RecentFailuresParams {
+    category: Option<String>,
 }
 
 pub(crate) async fn releases_recent_failures_handler(
     page: Option<Path<i64>>,
+    Query(params): Query<RecentFailuresParams>,
     mut conn: DbConnection,
 ) -> AxumResult<impl IntoResponse> {
-    releases_handler(&mut conn, page.map(|p| p.0), ReleaseType::RecentFailures).await
+    // the filter form submits an empty category for "all failures"
+    let failure_category = params
+        .category
+        .filter(|category| !category.is_empty())
+        .map(|category| {
+            BuildFailureCategory::from_str(&category)
+                .map_err(|_| AxumNope::BadRequest(anyhow!("unknown failure category: {category}")))
+        })
+        .transpose()?;
+
+    releases_handler(
+        &mut conn,
+        page.map(|p| p.0),
+        ReleaseType::RecentFailures,
+        failure_category,
+    )
+    .await
 }
 
 pub(crate) async fn releases_failures_by_stars_handler(
     page: Option<Path<i64>>,
     mut conn: DbConnection,
 ) -> AxumResult<impl IntoResponse> {
-    releases_handler(&mut conn, page.map(|p| p.0), ReleaseType::Failures).await
+    releases_handler(&mut conn, page.map(|p| p.0), ReleaseType::Failures, None).await
 }
 
 pub(crate) async fn owner_handler(Path(owner): Path<String>) -> AxumResult<impl IntoResponse> {
@@ -499,7 +706,8 @@ async fn redirect_to_random_crate(
         INNER JOIN repositories ON releases.repository_id = repositories.id
         WHERE
             releases.rustdoc_status = TRUE AND
-            repositories.stars >= 100
+            repositories.stars >= 100 AND
+            crates.registry IS NULL
         LIMIT 1",
         config.random_crate_search_view_size as i32,
     )
@@ -562,7 +770,7 @@ pub(crate) async fn search_handler(
         // since we never pass a version into `match_version` here, we'll never get
         // `MatchVersion::Exact`, so the distinction between `Exact` and `Semver` doesn't
         // matter
-        if let Ok(matchver) = match_version(&mut conn, krate, &ReqVersion::Latest)
+        if let Ok(matchver) = match_version(&mut conn, None, krate, &ReqVersion::Latest)
             .await
             .map(|matched_release| matched_release.into_exactly_named())
         {
@@ -587,6 +795,12 @@ pub(crate) async fn search_handler(
 
             return Ok(super::axum_redirect(uri)?.into_response());
         }
+
+        // not an item of a crate we know, so look for items with this path in all crates
+        if query.contains("::") {
+            let uri = axum_parse_uri_with_params("/releases/items", [("q", query.as_str())])?;
+            return Ok(super::axum_redirect(uri)?.into_response());
+        }
     }
 
     let search_result = if let Some(paginate) = params.get("paginate") {
@@ -661,6 +875,193 @@ pub(crate) async fn search_handler(
     .into_response())
 }
 
+/// Items on a page of the search for items across all crates.
+const ITEMS_PER_PAGE: i64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct CrateItem {
+    crate_name: String,
+    version: String,
+    path: String,
+    kind: String,
+    description: Option<String>,
+    /// The page of the item in the documentation of the release.
+    url: String,
+    stars: i32,
+    downloads: i32,
+}
+
+/// Escapes the wildcards of `LIKE` patterns, which includes the `_` common in item names.
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Searches the items in the documentation of the latest releases of all crates. A query with a
+/// `::` finds the items whose path ends with it, all others the items whose name starts with it.
+///
+/// Items named exactly like the query come first, then the items of the most popular crates.
+/// Returns a page of items and whether there are more.
+async fn search_crate_items(
+    conn: &mut sqlx::PgConnection,
+    query: &str,
+    page: i64,
+) -> Result<(Vec<CrateItem>, bool)> {
+    let query = query.trim().trim_start_matches("::").to_lowercase();
+    let (name_pattern, exact_name, path_pattern) = match query.rsplit_once("::") {
+        Some((_, name)) => (
+            escape_like(name),
+            name.to_owned(),
+            Some(format!("%::{}", escape_like(&query))),
+        ),
+        None => (format!("{}%", escape_like(&query)), query.clone(), None),
+    };
+
+    let mut items: Vec<CrateItem> = sqlx::query!(
+        r#"SELECT
+            crates.name,
+            crates.registry,
+            releases.version,
+            crate_items.path,
+            crate_items.kind,
+            crate_items.url,
+            crate_items.description,
+            COALESCE(repositories.stars, 0) AS "stars!",
+            releases.downloads
+         FROM crate_items
+         INNER JOIN crates ON crates.id = crate_items.crate_id
+         INNER JOIN releases ON releases.id = crate_items.release_id
+         LEFT JOIN repositories ON repositories.id = releases.repository_id
+         WHERE
+            LOWER(crate_items.name) LIKE $1 AND
+            ($2::TEXT IS NULL OR LOWER(crate_items.path) = $3 OR LOWER(crate_items.path) LIKE $2)
+         ORDER BY
+            LOWER(crate_items.name) = $4 DESC,
+            COALESCE(repositories.stars, 0) DESC,
+            releases.downloads DESC,
+            crate_items.path
+         LIMIT $5 OFFSET $6"#,
+        name_pattern,
+        path_pattern,
+        query,
+        exact_name,
+        ITEMS_PER_PAGE + 1,
+        (page - 1) * ITEMS_PER_PAGE,
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| CrateItem {
+        url: format!(
+            "{}/{}/{}/{}",
+            CrateRegistry::new(row.registry).url_prefix(),
+            row.name,
+            row.version,
+            row.url
+        ),
+        crate_name: row.name,
+        version: row.version,
+        path: row.path,
+        kind: row.kind,
+        description: row.description,
+        stars: row.stars,
+        downloads: row.downloads,
+    })
+    .try_collect()
+    .await?;
+
+    let has_more = items.len() > ITEMS_PER_PAGE as usize;
+    items.truncate(ITEMS_PER_PAGE as usize);
+    Ok((items, has_more))
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ItemSearchParams {
+    #[serde(default)]
+    q: String,
+    page: Option<i64>,
+}
+
+impl ItemSearchParams {
+    fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    fn page_link(&self, path: &str, page: i64) -> Result<String> {
+        let page = page.to_string();
+        Ok(
+            axum_parse_uri_with_params(path, [("q", self.q.as_str()), ("page", &page)])?
+                .to_string(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct ItemSearch {
+    title: String,
+    search_query: String,
+    items: Vec<CrateItem>,
+    previous_page_link: Option<String>,
+    next_page_link: Option<String>,
+}
+
+impl_axum_webpage! {
+    ItemSearch = "releases/items.html",
+}
+
+pub(crate) async fn items_search_handler(
+    mut conn: DbConnection,
+    Query(params): Query<ItemSearchParams>,
+) -> AxumResult<impl IntoResponse> {
+    if params.q.trim().is_empty() {
+        return Err(AxumNope::NoResults);
+    }
+
+    let page = params.page();
+    let (items, has_more) = search_crate_items(&mut conn, &params.q, page).await?;
+
+    let title = if items.is_empty() {
+        format!("No items found for '{}'", params.q)
+    } else {
+        format!("Items matching '{}'", params.q)
+    };
+
+    Ok(ItemSearch {
+        title,
+        previous_page_link: (page > 1)
+            .then(|| params.page_link("/releases/items", page - 1))
+            .transpose()?,
+        next_page_link: has_more
+            .then(|| params.page_link("/releases/items", page + 1))
+            .transpose()?,
+        search_query: params.q,
+        items,
+    })
+}
+
+pub(crate) async fn items_search_json_handler(
+    mut conn: DbConnection,
+    Query(params): Query<ItemSearchParams>,
+) -> AxumResult<impl IntoResponse> {
+    if params.q.trim().is_empty() {
+        return Err(AxumNope::BadRequest(anyhow!("missing search query")));
+    }
+
+    let page = params.page();
+    let (items, has_more) = search_crate_items(&mut conn, &params.q, page).await?;
+
+    Ok((
+        [(ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Json(serde_json::json!({
+            "query": params.q,
+            "items": items,
+            "next_page": has_more
+                .then(|| params.page_link("/releases/items.json", page + 1))
+                .transpose()?,
+        })),
+    ))
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize)]
 struct ReleaseActivity {
     description: &'static str,
@@ -731,6 +1132,7 @@ pub(crate) async fn activity_handler(mut conn: DbConnection) -> AxumResult<impl
 struct BuildQueuePage {
     description: &'static str,
     queue: Vec<QueuedCrate>,
+    workers: Vec<WorkerStatus>,
     active_deployments: Vec<String>,
 }
 
@@ -742,7 +1144,7 @@ pub(crate) async fn build_queue_handler(
     Extension(build_queue): Extension<Arc<BuildQueue>>,
     Extension(pool): Extension<Pool>,
 ) -> AxumResult<impl IntoResponse> {
-    let (queue, active_deployments) = spawn_blocking(move || {
+    let (queue, workers, active_deployments) = spawn_blocking(move || {
         let mut queue = build_queue.queued_crates()?;
         for krate in queue.iter_mut() {
             // The priority here is inverted: in the database if a crate has a higher priority it
@@ -750,6 +1152,7 @@ pub(crate) async fn build_queue_handler(
             // familiar with docs.rs's inner workings.
             krate.priority = -krate.priority;
         }
+        let workers = build_queue.worker_statuses()?;
 
         let mut conn = pool.get()?;
         let mut active_deployments: Vec<_> = cdn::queued_or_active_crate_invalidations(&mut *conn)?
@@ -764,13 +1167,14 @@ pub(crate) async fn build_queue_handler(
         // reverse the list, so the oldest comes first
         active_deployments.reverse();
 
-        Ok((queue, active_deployments))
+        Ok((queue, workers, active_deployments))
     })
     .await?;
 
     Ok(BuildQueuePage {
         description: "crate documentation scheduled to build & deploy",
         queue,
+        workers,
         active_deployments,
     })
 }
@@ -778,10 +1182,12 @@ pub(crate) async fn build_queue_handler(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::registry_api::CrateOwner;
     use crate::test::{
         assert_cache_control, assert_redirect, assert_redirect_unchecked, assert_success, wrapper,
-        TestFrontend,
+        FakeBuild, TestFrontend,
+    };
+    use crate::{
+        db::update_crate_items, registry_api::CrateOwner, utils::search_index::SearchItem,
     };
     use anyhow::Error;
     use chrono::{Duration, TimeZone};
@@ -1304,6 +1710,81 @@ mod tests {
         })
     }
 
+    #[test]
+    fn local_search() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.crate_search_backend = CrateSearchBackend::Local;
+                // crates.io must not be asked
+                config.registry_api_host = "http://127.0.0.1:1".parse().unwrap();
+            });
+
+            env.fake_release()
+                .name("serde_json")
+                .version("1.0.0")
+                .description("A JSON serialization file format")
+                .github_stats("serde-rs/json", 4000, 10, 10)
+                .create()?;
+            env.fake_release()
+                .name("serde")
+                .version("1.0.0")
+                .description("A generic serialization and deserialization framework")
+                .github_stats("serde-rs/serde", 8000, 10, 10)
+                .create()?;
+            env.fake_release()
+                .name("toml")
+                .version("0.8.0")
+                .keywords(vec!["encoding".into(), "serde".into()])
+                .create()?;
+            env.fake_release()
+                .name("unrelated")
+                .version("0.1.0")
+                .description("Something else entirely")
+                .create()?;
+
+            let web = env.frontend();
+            let links = get_release_links("/releases/search?query=serde", web)?;
+            assert_eq!(
+                links,
+                vec![
+                    "/serde/latest/serde/",
+                    "/serde_json/latest/serde_json/",
+                    "/toml/latest/toml/",
+                ]
+            );
+
+            // the description matches with full-text search
+            let links = get_release_links("/releases/search?query=serializations", web)?;
+            assert_eq!(links.len(), 2);
+            assert!(!links.contains(&"/toml/latest/toml/".to_string()));
+
+            let links = get_release_links("/releases/search?query=serde&sort=new", web)?;
+            assert_eq!(links[0], "/toml/latest/toml/");
+
+            // pagination uses the same encoded query args as crates.io
+            let page = kuchikiki::parse_html().one(
+                web.get(&format!(
+                    "/releases/search?paginate={}",
+                    b64.encode("?q=serde&sort=relevance&per_page=1")
+                ))
+                .send()?
+                .text()?,
+            );
+            let next_page = page
+                .select_first("a[href^='/releases/search?paginate=']")
+                .expect("missing next page link")
+                .attributes
+                .borrow()
+                .get("href")
+                .unwrap()
+                .to_owned();
+            let links = get_release_links(&next_page, web)?;
+            assert_eq!(links, vec!["/serde_json/latest/serde_json/"]);
+
+            Ok(())
+        })
+    }
+
     fn get_release_links(path: &str, web: &TestFrontend) -> Result<Vec<String>, Error> {
         let response = web.get(path).send()?;
         assert!(response.status().is_success());
@@ -1320,6 +1801,81 @@ mod tests {
             .collect())
     }
 
+    #[test]
+    fn search_items_across_crates() {
+        wrapper(|env| {
+            let popular = env
+                .fake_release()
+                .name("popular")
+                .version("1.0.0")
+                .github_stats("some/popular", 1000, 10, 10)
+                .create()?;
+            let other = env.fake_release().name("other").version("0.1.0").create()?;
+
+            let item = |path: &str, url: &str| SearchItem {
+                path: path.into(),
+                kind: "trait",
+                description: None,
+                url: url.into(),
+            };
+            env.runtime().block_on(async {
+                let mut conn = env.async_db().await.async_conn().await;
+                update_crate_items(
+                    &mut conn,
+                    other,
+                    &[
+                        item("other::io::AsyncRead", "other/io/trait.AsyncRead.html"),
+                        item("other::AsyncReadExt", "other/trait.AsyncReadExt.html"),
+                        item("other::ioxutil::Read", "other/ioxutil/trait.Read.html"),
+                    ],
+                )
+                .await?;
+                update_crate_items(
+                    &mut conn,
+                    popular,
+                    &[item("popular::AsyncRead", "popular/trait.AsyncRead.html")],
+                )
+                .await
+            })?;
+
+            let web = env.frontend();
+            let paths = |query: &str| -> Result<Vec<String>, Error> {
+                let response = web.get(&format!("/releases/items.json?q={query}")).send()?;
+                assert!(response.status().is_success());
+                let json: serde_json::Value = response.json()?;
+                Ok(json["items"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|item| item["path"].as_str().unwrap().to_owned())
+                    .collect())
+            };
+            assert_eq!(
+                paths("asyncread")?,
+                vec![
+                    "popular::AsyncRead",
+                    "other::io::AsyncRead",
+                    "other::AsyncReadExt"
+                ]
+            );
+            assert_eq!(paths("io::AsyncRead")?, vec!["other::io::AsyncRead"]);
+            // `_` isn't a wildcard
+            assert!(paths("io_util::Read")?.is_empty());
+
+            assert_eq!(
+                get_release_links("/releases/items?q=AsyncRead", web)?[0],
+                "/popular/1.0.0/popular/trait.AsyncRead.html"
+            );
+            assert_redirect(
+                "/releases/search?query=::AsyncRead",
+                "/releases/items?q=%3A%3AAsyncRead",
+                web,
+            )?;
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn releases_by_stars() {
         wrapper(|env| {
@@ -1438,6 +1994,50 @@ mod tests {
         })
     }
 
+    #[test]
+    fn releases_failed_by_category() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("crate_that_timed_out")
+                .version("0.1.0")
+                .release_time(Utc.with_ymd_and_hms(2020, 5, 16, 4, 33, 50).unwrap())
+                .builds(vec![
+                    FakeBuild::default().failure(BuildFailureCategory::Timeout, None)
+                ])
+                .create()?;
+            env.fake_release()
+                .name("crate_that_failed")
+                .version("0.1.0")
+                .release_time(Utc.with_ymd_and_hms(2020, 6, 16, 4, 33, 50).unwrap())
+                .builds(vec![FakeBuild::default().failure(
+                    BuildFailureCategory::CompileError,
+                    Some("error[E0425]: cannot find value `x` in this scope"),
+                )])
+                .create()?;
+
+            let links = get_release_links("/releases/recent-failures", env.frontend())?;
+            assert_eq!(links.len(), 2);
+
+            let links =
+                get_release_links("/releases/recent-failures?category=timeout", env.frontend())?;
+            assert_eq!(links.len(), 1);
+            assert!(links[0].contains("crate_that_timed_out"));
+
+            let links = get_release_links("/releases/recent-failures?category=", env.frontend())?;
+            assert_eq!(links.len(), 2);
+
+            assert_eq!(
+                env.frontend()
+                    .get("/releases/recent-failures?category=unknown")
+                    .send()?
+                    .status(),
+                400
+            );
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn releases_homepage_and_recent() {
         wrapper(|env| {
@@ -1633,6 +2233,42 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_releases_queue_workers() {
+        wrapper(|env| {
+            let queue = env.build_queue();
+            let web = env.frontend();
+
+            let empty = kuchikiki::parse_html().one(web.get("/releases/queue").send()?.text()?);
+            assert!(empty.select_first("#build-workers").is_err());
+
+            queue.update_worker_status("builder-0", None)?;
+            queue.update_worker_status("builder-1", None)?;
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            env.db().conn().execute(
+                "UPDATE build_workers
+                 SET crate_name = 'foo', crate_version = '1.0.0', build_started_at = NOW()
+                 WHERE name = 'builder-1'",
+                &[],
+            )?;
+
+            let page = kuchikiki::parse_html().one(web.get("/releases/queue").send()?.text()?);
+            let workers = page
+                .select("#build-workers > li")
+                .expect("missing worker list")
+                .map(|li| li.text_contents())
+                .collect::<Vec<_>>();
+            assert_eq!(workers.len(), 2);
+            assert!(workers[0].contains("builder-0") && workers[0].contains("idle"));
+            assert!(workers[1].contains("builder-1") && workers[1].contains("foo 1.0.0"));
+
+            // the pending list is unchanged
+            assert_eq!(page.select(".queue-list > li").unwrap().count(), 1);
+
+            Ok(())
+        });
+    }
+
     #[test]
     fn home_page_links() {
         wrapper(|env| {
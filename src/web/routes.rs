@@ -6,14 +6,14 @@ use axum::{
     handler::Handler as AxumHandler,
     middleware::{self, Next},
     response::{IntoResponse, Redirect},
-    routing::{get, MethodRouter},
+    routing::{get, post, MethodRouter},
     Router as AxumRouter,
 };
 use axum_extra::routing::RouterExt;
 use std::convert::Infallible;
 use tracing::{debug, instrument};
 
-const INTERNAL_PREFIXES: &[&str] = &["-", "about", "crate", "releases", "sitemap.xml"];
+const INTERNAL_PREFIXES: &[&str] = &["-", "_", "about", "crate", "releases", "sitemap.xml"];
 
 #[instrument(skip_all)]
 pub(crate) fn get_static<H, T, S>(handler: H) -> MethodRouter<S, Infallible>
@@ -39,6 +39,18 @@ where
     }))
 }
 
+#[instrument(skip_all)]
+fn post_internal<H, T, S>(handler: H) -> MethodRouter<S, Infallible>
+where
+    H: AxumHandler<T, S>,
+    T: 'static,
+    S: Clone + Send + Sync + 'static,
+{
+    post(handler).route_layer(middleware::from_fn(|request, next| async {
+        request_recorder(request, next, None).await
+    }))
+}
+
 #[instrument(skip_all)]
 fn get_rustdoc<H, T, S>(handler: H) -> MethodRouter<S, Infallible>
 where
@@ -53,20 +65,23 @@ where
         .layer(middleware::from_fn(block_blacklisted_prefixes_middleware))
 }
 
+/// Whether `name` is the first component of the paths of our own pages, which can't be crates.
+pub(crate) fn is_internal_prefix(name: &str) -> bool {
+    INTERNAL_PREFIXES.binary_search(&name).is_ok()
+}
+
 async fn block_blacklisted_prefixes_middleware(
     request: AxumHttpRequest,
     next: Next,
 ) -> impl IntoResponse {
     if let Some(first_component) = request.uri().path().trim_matches('/').split('/').next() {
-        if !first_component.is_empty()
-            && (INTERNAL_PREFIXES.binary_search(&first_component).is_ok())
-        {
+        if !first_component.is_empty() && is_internal_prefix(first_component) {
             debug!(
                 first_component = first_component,
                 uri = ?request.uri(),
                 "blocking blacklisted prefix"
             );
-            return AxumNope::CrateNotFound.into_response();
+            return AxumNope::CrateNotFound(first_component.to_owned()).into_response();
         }
     }
 
@@ -200,6 +215,18 @@ pub(super) fn build_axum_routes() -> AxumRouter {
             "/releases/search",
             get_internal(super::releases::search_handler),
         )
+        .route_with_tsr(
+            "/releases/items",
+            get_internal(super::releases::items_search_handler),
+        )
+        .route(
+            "/releases/items.json",
+            get_internal(super::releases::items_search_json_handler),
+        )
+        .route(
+            "/releases/suggestions.json",
+            get_internal(super::suggestions::suggestions_json_handler),
+        )
         .route_with_tsr(
             "/releases/queue",
             get_internal(super::releases::build_queue_handler),
@@ -216,6 +243,14 @@ pub(super) fn build_axum_routes() -> AxumRouter {
             "/crate/:name/:version/status.json",
             get_internal(super::status::status_handler),
         )
+        .route(
+            "/_/index-webhook",
+            post_internal(super::webhook::index_webhook_handler),
+        )
+        .route(
+            "/crate/:name/:version/queue.json",
+            get_internal(super::status::queue_status_handler),
+        )
         .route_with_tsr(
             "/crate/:name/:version/builds/:id",
             get_internal(super::build_details::build_details_handler),
@@ -224,6 +259,14 @@ pub(super) fn build_axum_routes() -> AxumRouter {
             "/crate/:name/:version/builds/:id/:filename",
             get_internal(super::build_details::build_details_handler),
         )
+        .route_with_tsr(
+            "/crate/:name/:version/api-diff",
+            get_internal(super::api_diff::api_diff_handler),
+        )
+        .route(
+            "/crate/:name/:version/api-diff.json",
+            get_internal(super::api_diff::api_diff_json_handler),
+        )
         .route_with_tsr(
             "/crate/:name/:version/features",
             get_internal(super::features::build_features_handler),
@@ -284,6 +327,14 @@ pub(super) fn build_axum_routes() -> AxumRouter {
             "/crate/:name/:version/download",
             get_internal(super::rustdoc::download_handler),
         )
+        .route(
+            "/crate/:name/:version/json",
+            get_internal(super::rustdoc::json_download_handler),
+        )
+        .route(
+            "/crate/:name/:version/search.json",
+            get_internal(super::doc_search::search_json_handler),
+        )
         .route(
             "/crate/:name/:version/target-redirect/*path",
             get_internal(super::rustdoc::target_redirect_handler),
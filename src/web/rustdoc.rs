@@ -2,7 +2,7 @@
 
 use crate::{
     db::Pool,
-    storage::rustdoc_archive_path,
+    storage::{crate_storage_name, rustdoc_archive_path, StreamingBlob},
     utils,
     web::{
         axum_cached_redirect, axum_parse_uri_with_params,
@@ -12,19 +12,25 @@ use crate::{
         encode_url_path,
         error::{AxumNope, AxumResult},
         extractors::{DbConnection, Path},
-        file::File,
+        file::{AcceptEncoding, File},
         match_version,
         page::TemplateData,
+        registries::CrateRegistry,
         MetaData, ReqVersion,
     },
-    AsyncStorage, Config, InstanceMetrics, RUSTDOC_STATIC_STORAGE_PREFIX,
+    AsyncStorage, Config, InstanceMetrics, RUSTDOC_JSON_PATH, RUSTDOC_STATIC_STORAGE_PREFIX,
 };
 use anyhow::{anyhow, Context as _};
 use axum::{
+    body::Body,
     extract::{Extension, Query},
     http::{StatusCode, Uri},
     response::{Html, IntoResponse, Response as AxumResponse},
 };
+use futures_util::{
+    stream::{self, StreamExt},
+    TryStreamExt,
+};
 use lol_html::errors::RewritingError;
 use once_cell::sync::Lazy;
 use semver::Version;
@@ -35,6 +41,9 @@ use std::{
 };
 use tracing::{debug, error, info_span, instrument, trace, Instrument};
 
+/// How much of a rewritten rustdoc page we collect before we start responding.
+const HELD_BACK_PAGE_SIZE: usize = 64 * 1024;
+
 static DOC_RUST_LANG_ORG_REDIRECTS: Lazy<HashMap<&str, &str>> = Lazy::new(|| {
     HashMap::from([
         ("alloc", "stable/alloc"),
@@ -68,6 +77,7 @@ pub(crate) struct RustdocRedirectorParams {
 async fn try_serve_legacy_toolchain_asset(
     storage: Arc<AsyncStorage>,
     config: Arc<Config>,
+    accept_encoding: &AcceptEncoding,
     path: impl AsRef<str>,
 ) -> AxumResult<AxumResponse> {
     let path = path.as_ref().to_owned();
@@ -79,18 +89,21 @@ async fn try_serve_legacy_toolchain_asset(
     // since new nightly versions will always put their
     // toolchain specific resources into the new folder,
     // which is reached via the new handler.
-    Ok(File::from_path(&storage, &path, &config)
+    Ok(File::from_path(&storage, &path, &config, accept_encoding)
         .await
         .map(IntoResponse::into_response)?)
 }
 
 /// Handler called for `/:crate` and `/:crate/:version` URLs. Automatically redirects to the docs
 /// or crate details page based on whether the given crate version was successfully built.
+#[allow(clippy::too_many_arguments)]
 #[instrument(skip(storage, config, conn))]
 pub(crate) async fn rustdoc_redirector_handler(
     Path(params): Path<RustdocRedirectorParams>,
     Extension(storage): Extension<Arc<AsyncStorage>>,
     Extension(config): Extension<Arc<Config>>,
+    registry: CrateRegistry,
+    accept_encoding: AcceptEncoding,
     mut conn: DbConnection,
     Query(query_pairs): Query<HashMap<String, String>>,
     uri: Uri,
@@ -121,9 +134,14 @@ pub(crate) async fn rustdoc_redirector_handler(
             .binary_search(&extension)
             .is_ok()
         {
-            return try_serve_legacy_toolchain_asset(storage, config, params.name)
-                .instrument(info_span!("serve static asset"))
-                .await;
+            return try_serve_legacy_toolchain_asset(
+                storage,
+                config,
+                &accept_encoding,
+                params.name,
+            )
+            .instrument(info_span!("serve static asset"))
+            .await;
         }
     }
 
@@ -159,6 +177,7 @@ pub(crate) async fn rustdoc_redirector_handler(
     // anyway
     let matched_release = match_version(
         &mut conn,
+        registry.name(),
         &crate_name,
         &params.version.clone().unwrap_or_default(),
     )
@@ -176,11 +195,12 @@ pub(crate) async fn rustdoc_redirector_handler(
 
                 match storage
                     .fetch_rustdoc_file(
-                        &crate_name,
+                        &krate.storage_name(),
                         &krate.version.to_string(),
                         krate.latest_build_id.unwrap_or(0),
                         target,
                         krate.archive_storage,
+                        Some(&accept_encoding.0),
                     )
                     .await
                 {
@@ -200,7 +220,13 @@ pub(crate) async fn rustdoc_redirector_handler(
                         // docs that were affected by this bug.
                         // https://github.com/rust-lang/docs.rs/issues/1979
                         if target.starts_with("search-") || target.starts_with("settings-") {
-                            try_serve_legacy_toolchain_asset(storage, config, target).await
+                            try_serve_legacy_toolchain_asset(
+                                storage,
+                                config,
+                                &accept_encoding,
+                                target,
+                            )
+                            .await
                         } else {
                             Err(err.into())
                         }
@@ -258,6 +284,7 @@ pub(crate) async fn rustdoc_redirector_handler(
 
 #[derive(Debug, Clone, Serialize)]
 struct RustdocPage {
+    registry_prefix: String,
     latest_path: String,
     permalink_path: String,
     latest_version: String,
@@ -275,12 +302,11 @@ struct RustdocPage {
 }
 
 impl RustdocPage {
-    fn into_response(
+    async fn into_response(
         self,
-        rustdoc_html: &[u8],
-        max_parse_memory: usize,
-        templates: &TemplateData,
-        metrics: &InstanceMetrics,
+        rustdoc_html: StreamingBlob,
+        templates: Arc<TemplateData>,
+        metrics: Arc<InstanceMetrics>,
         config: &Config,
         file_path: &str,
     ) -> AxumResult<AxumResponse> {
@@ -292,19 +318,37 @@ impl RustdocPage {
 
         // Extract the head and body of the rustdoc file so that we can insert it into our own html
         // while logging OOM errors from html rewriting
-        let html = match utils::rewrite_lol(rustdoc_html, max_parse_memory, ctx, templates) {
-            Err(RewritingError::MemoryLimitExceeded(..)) => {
-                metrics.html_rewrite_ooms.inc();
-
-                return Err(AxumNope::InternalError(
-                    anyhow!(
-                        "Failed to serve the rustdoc file '{}' because rewriting it surpassed the memory limit of {} bytes",
-                        file_path, config.max_parse_memory,
-                    )
-                ));
+        let max_parse_memory = config.max_parse_memory;
+        let file_path = file_path.to_owned();
+        let mut html = Box::pin(
+            utils::rewrite_lol(rustdoc_html.content, max_parse_memory, ctx, templates)
+                .await?
+                .map_err(move |err| {
+                    if matches!(
+                        err.downcast_ref(),
+                        Some(RewritingError::MemoryLimitExceeded(..))
+                    ) {
+                        metrics.html_rewrite_ooms.inc();
+                        anyhow!(
+                            "Failed to serve the rustdoc file '{}' because rewriting it surpassed the memory limit of {} bytes",
+                            file_path, max_parse_memory,
+                        )
+                    } else {
+                        err.context("error rewriting HTML")
+                    }
+                }),
+        );
+
+        // We hold back the start of the page, so failures there still get an error page. Later
+        // failures abort the response, which keeps the CDN from caching the truncated page.
+        let mut start = Vec::new();
+        while start.len() < HELD_BACK_PAGE_SIZE {
+            match html.next().await {
+                Some(chunk) => start.extend(chunk?),
+                None => break,
             }
-            result => result.context("error rewriting HTML")?,
-        };
+        }
+        let html = stream::once(async { Ok(start) }).chain(html.inspect_err(utils::report_error));
 
         Ok((
             StatusCode::OK,
@@ -314,7 +358,7 @@ impl RustdocPage {
             } else {
                 CachePolicy::ForeverInCdnAndStaleInBrowser
             }),
-            Html(html),
+            Html(Body::from_stream(html)),
         )
             .into_response())
     }
@@ -345,6 +389,8 @@ pub(crate) async fn rustdoc_html_server_handler(
     Extension(storage): Extension<Arc<AsyncStorage>>,
     Extension(config): Extension<Arc<Config>>,
     Extension(csp): Extension<Arc<Csp>>,
+    registry: CrateRegistry,
+    accept_encoding: AcceptEncoding,
     uri: Uri,
 ) -> AxumResult<AxumResponse> {
     // since we directly use the Uri-path and not the extracted params from the router,
@@ -386,7 +432,7 @@ pub(crate) async fn rustdoc_html_server_handler(
     // * If both the name and the version are an exact match, return the version of the crate.
     // * If there is an exact match, but the requested crate name was corrected (dashes vs. underscores), redirect to the corrected name.
     // * If there is a semver (but not exact) match, redirect to the exact version.
-    let matched_release = match_version(&mut conn, &params.name, &params.version)
+    let matched_release = match_version(&mut conn, registry.name(), &params.name, &params.version)
         .await?
         .into_exactly_named_or_else(|corrected_name, req_version| {
             AxumNope::Redirect(
@@ -444,17 +490,43 @@ pub(crate) async fn rustdoc_html_server_handler(
 
     trace!(?storage_path, ?req_path, "try fetching from storage");
 
-    // Attempt to load the file from the database
-    let blob = match storage
-        .fetch_rustdoc_file(
-            &params.name,
-            &krate.version.to_string(),
-            krate.latest_build_id.unwrap_or(0),
-            &storage_path,
-            krate.archive_storage,
-        )
-        .await
-    {
+    // Attempt to load the file from the database. HTML files are rewritten while we read them.
+    let blob = if storage_path.ends_with(".html") {
+        storage
+            .stream_rustdoc_file(
+                &krate.storage_name(),
+                &krate.version.to_string(),
+                krate.latest_build_id.unwrap_or(0),
+                &storage_path,
+                krate.archive_storage,
+            )
+            .await
+    } else {
+        match storage
+            .fetch_rustdoc_file(
+                &krate.storage_name(),
+                &krate.version.to_string(),
+                krate.latest_build_id.unwrap_or(0),
+                &storage_path,
+                krate.archive_storage,
+                Some(&accept_encoding.0),
+            )
+            .await
+        {
+            // Serve non-html files directly
+            Ok(blob) => {
+                trace!(?storage_path, "serve asset");
+
+                // default asset caching behaviour is `Cache::ForeverInCdnAndBrowser`.
+                // This is an edge-case when we serve invocation specific static assets under `/latest/`:
+                // https://github.com/rust-lang/docs.rs/issues/1593
+                return Ok(File(blob).into_response());
+            }
+            Err(err) => Err(err),
+        }
+    };
+
+    let blob = match blob {
         Ok(file) => file,
         Err(err) => {
             if !matches!(err.downcast_ref(), Some(AxumNope::ResourceNotFound))
@@ -468,7 +540,7 @@ pub(crate) async fn rustdoc_html_server_handler(
 
             return if storage
                 .rustdoc_file_exists(
-                    &params.name,
+                    &krate.storage_name(),
                     &krate.version.to_string(),
                     krate.latest_build_id.unwrap_or(0),
                     &storage_path,
@@ -512,16 +584,6 @@ pub(crate) async fn rustdoc_html_server_handler(
         }
     };
 
-    // Serve non-html files directly
-    if !storage_path.ends_with(".html") {
-        trace!(?storage_path, "serve asset");
-
-        // default asset caching behaviour is `Cache::ForeverInCdnAndBrowser`.
-        // This is an edge-case when we serve invocation specific static assets under `/latest/`:
-        // https://github.com/rust-lang/docs.rs/issues/1593
-        return Ok(File(blob).into_response());
-    }
-
     let latest_release = krate.latest_release()?;
 
     // Get the latest version of the crate
@@ -569,13 +631,15 @@ pub(crate) async fn rustdoc_html_server_handler(
         "".to_string()
     };
 
+    let registry_prefix = registry.url_prefix();
+
     let permalink_path = format!(
-        "/{}/{}/{}{}",
+        "{registry_prefix}/{}/{}/{}{}",
         params.name, latest_version, inner_path, query_string
     );
 
     let latest_path = format!(
-        "/crate/{}/latest{}{}",
+        "{registry_prefix}/crate/{}/latest{}{}",
         params.name, target_redirect, query_string
     );
 
@@ -590,36 +654,24 @@ pub(crate) async fn rustdoc_html_server_handler(
     };
 
     // Build the page of documentation,
-    templates
-        .render_in_threadpool({
-            let metrics = metrics.clone();
-            move |templates| {
-                let metadata = krate.metadata.clone();
-                Ok(RustdocPage {
-                    latest_path,
-                    permalink_path,
-                    latest_version: latest_version.to_string(),
-                    target,
-                    inner_path,
-                    is_latest_version,
-                    is_latest_url: params.version.is_latest(),
-                    is_prerelease,
-                    metadata,
-                    krate,
-                    current_target,
-                }
-                .into_response(
-                    &blob.content,
-                    config.max_parse_memory,
-                    templates,
-                    &metrics,
-                    &config,
-                    &storage_path,
-                ))
-            }
-        })
-        .instrument(info_span!("rewrite html"))
-        .await?
+    let metadata = krate.metadata.clone();
+    RustdocPage {
+        registry_prefix,
+        latest_path,
+        permalink_path,
+        latest_version: latest_version.to_string(),
+        target,
+        inner_path,
+        is_latest_version,
+        is_latest_url: params.version.is_latest(),
+        is_prerelease,
+        metadata,
+        krate,
+        current_target,
+    }
+    .into_response(blob, templates, metrics, &config, &storage_path)
+    .instrument(info_span!("rewrite html"))
+    .await
 }
 
 /// Checks whether the given path exists.
@@ -690,10 +742,11 @@ fn path_for_version(
 #[instrument(skip_all)]
 pub(crate) async fn target_redirect_handler(
     Path((name, req_version, req_path)): Path<(String, ReqVersion, String)>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
     Extension(storage): Extension<Arc<AsyncStorage>>,
 ) -> AxumResult<impl IntoResponse> {
-    let matched_release = match_version(&mut conn, &name, &req_version)
+    let matched_release = match_version(&mut conn, registry.name(), &name, &req_version)
         .await?
         .into_canonical_req_version_or_else(|_| AxumNope::VersionNotFound)?;
 
@@ -724,7 +777,7 @@ pub(crate) async fn target_redirect_handler(
 
     let (redirect_path, query_args) = if storage
         .rustdoc_file_exists(
-            &name,
+            &crate_details.storage_name(),
             &crate_details.version.to_string(),
             crate_details.latest_build_id.unwrap_or(0),
             &storage_location_for_path,
@@ -778,16 +831,20 @@ pub(crate) async fn badge_handler(
 #[instrument(skip_all)]
 pub(crate) async fn download_handler(
     Path((name, req_version)): Path<(String, ReqVersion)>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
     Extension(storage): Extension<Arc<AsyncStorage>>,
     Extension(config): Extension<Arc<Config>>,
 ) -> AxumResult<impl IntoResponse> {
-    let version = match_version(&mut conn, &name, &req_version)
+    let version = match_version(&mut conn, registry.name(), &name, &req_version)
         .await?
         .assume_exact_name()?
         .into_version();
 
-    let archive_path = rustdoc_archive_path(&name, &version.to_string());
+    let archive_path = rustdoc_archive_path(
+        &crate_storage_name(registry.name(), &name),
+        &version.to_string(),
+    );
 
     // not all archives are set for public access yet, so we check if
     // the access is set and fix it if needed.
@@ -798,11 +855,21 @@ pub(crate) async fn download_handler(
     {
         Ok(is_public) => is_public,
         Err(err) => {
-            if matches!(err.downcast_ref(), Some(crate::storage::PathNotFoundError)) {
-                return Err(AxumNope::ResourceNotFound);
-            } else {
+            if !matches!(err.downcast_ref(), Some(crate::storage::PathNotFoundError)) {
                 return Err(AxumNope::InternalError(err));
             }
+
+            // deduplicated archives don't have a ZIP file until someone wants to download it
+            match storage.restore_archive_zip(&archive_path).await {
+                Ok(()) => false,
+                Err(err) => {
+                    if matches!(err.downcast_ref(), Some(crate::storage::PathNotFoundError)) {
+                        return Err(AxumNope::ResourceNotFound);
+                    } else {
+                        return Err(AxumNope::InternalError(err));
+                    }
+                }
+            }
         }
     };
 
@@ -816,6 +883,56 @@ pub(crate) async fn download_handler(
     )?)
 }
 
+/// Serves the rustdoc JSON output for the default target of a release.
+#[instrument(skip_all)]
+pub(crate) async fn json_download_handler(
+    Path((name, req_version)): Path<(String, ReqVersion)>,
+    registry: CrateRegistry,
+    accept_encoding: AcceptEncoding,
+    mut conn: DbConnection,
+    Extension(storage): Extension<Arc<AsyncStorage>>,
+) -> AxumResult<impl IntoResponse> {
+    let matched_release = match_version(&mut conn, registry.name(), &name, &req_version)
+        .await?
+        .assume_exact_name()?
+        .into_canonical_req_version_or_else(|version| {
+            AxumNope::Redirect(
+                format!("/crate/{name}/{version}/json"),
+                CachePolicy::ForeverInCdn,
+            )
+        })?;
+
+    let krate = CrateDetails::from_matched_release(&mut conn, matched_release).await?;
+
+    if !krate.rustdoc_status {
+        return Err(AxumNope::ResourceNotFound);
+    }
+
+    let build_id = krate
+        .latest_build_id
+        .ok_or_else(|| anyhow!("release with documentation but without a build"))?;
+
+    let blob = storage
+        .fetch_rustdoc_file(
+            &krate.storage_name(),
+            &krate.version.to_string(),
+            build_id,
+            RUSTDOC_JSON_PATH,
+            krate.archive_storage,
+            Some(&accept_encoding.0),
+        )
+        .await?;
+
+    Ok((
+        Extension(if req_version.is_latest() {
+            CachePolicy::ForeverInCdn
+        } else {
+            CachePolicy::ForeverInCdnAndStaleInBrowser
+        }),
+        File(blob),
+    ))
+}
+
 /// Serves shared resources used by rustdoc-generated documentation.
 ///
 /// This serves files from S3, and is pointed to by the `--static-root-path` flag to rustdoc.
@@ -824,10 +941,11 @@ pub(crate) async fn static_asset_handler(
     Path(path): Path<String>,
     Extension(storage): Extension<Arc<AsyncStorage>>,
     Extension(config): Extension<Arc<Config>>,
+    accept_encoding: AcceptEncoding,
 ) -> AxumResult<impl IntoResponse> {
     let storage_path = format!("{RUSTDOC_STATIC_STORAGE_PREFIX}{path}");
 
-    Ok(File::from_path(&storage, &storage_path, &config).await?)
+    Ok(File::from_path(&storage, &storage_path, &config, &accept_encoding).await?)
 }
 
 #[cfg(test)]
@@ -2465,6 +2583,30 @@ mod test {
         });
     }
 
+    #[test]
+    fn download_deduplicated_archive() {
+        wrapper(|env| {
+            env.override_config(|config| config.deduplicate_archives = true);
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .archive_storage(true)
+                .create()?;
+
+            assert!(!env.storage().exists("rustdoc/dummy/0.1.0.zip")?);
+
+            assert_redirect_cached_unchecked(
+                "/crate/dummy/0.1.0/download",
+                "https://static.docs.rs/rustdoc/dummy/0.1.0.zip",
+                CachePolicy::ForeverInCdn,
+                env.frontend(),
+                &env.config(),
+            )?;
+            assert!(env.storage().get_public_access("rustdoc/dummy/0.1.0.zip")?);
+            Ok(())
+        });
+    }
+
     #[test]
     fn download_specific_version() {
         wrapper(|env| {
@@ -2521,6 +2663,92 @@ mod test {
         });
     }
 
+    #[test_case(true)]
+    #[test_case(false)]
+    fn json_specific_version(archive_storage: bool) {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .archive_storage(archive_storage)
+                .rustdoc_file_with("rustdoc.json", br#"{"format_version":28}"#.to_vec())
+                .create()?;
+
+            let web = env.frontend();
+
+            let response = web.get("/crate/dummy/0.1.0/json").send()?;
+            assert!(response.status().is_success());
+            assert_cache_control(
+                &response,
+                CachePolicy::ForeverInCdnAndStaleInBrowser,
+                &env.config(),
+            );
+            assert_eq!(
+                response.headers().get("content-type").unwrap(),
+                "application/json"
+            );
+            assert_eq!(response.text()?, r#"{"format_version":28}"#);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn json_semver_and_latest() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .archive_storage(true)
+                .rustdoc_file_with("rustdoc.json", br#"{"version":"0.1.0"}"#.to_vec())
+                .create()?;
+            env.fake_release()
+                .name("dummy")
+                .version("0.2.0")
+                .archive_storage(true)
+                .rustdoc_file_with("rustdoc.json", br#"{"version":"0.2.0"}"#.to_vec())
+                .create()?;
+
+            let web = env.frontend();
+
+            assert_redirect_cached(
+                "/crate/dummy/0.1/json",
+                "/crate/dummy/0.1.0/json",
+                CachePolicy::ForeverInCdn,
+                web,
+                &env.config(),
+            )?;
+
+            let response = web.get("/crate/dummy/latest/json").send()?;
+            assert!(response.status().is_success());
+            assert_cache_control(&response, CachePolicy::ForeverInCdn, &env.config());
+            assert_eq!(response.text()?, r#"{"version":"0.2.0"}"#);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn json_missing_404() {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .archive_storage(true)
+                .create()?;
+            env.fake_release()
+                .name("failed")
+                .version("0.1.0")
+                .build_result_failed()
+                .create()?;
+
+            let web = env.frontend();
+
+            assert_not_found("/crate/dummy/0.1.0/json", web)?;
+            assert_not_found("/crate/failed/0.1.0/json", web)?;
+            assert_not_found("/crate/unknown/0.1.0/json", web)?;
+            Ok(())
+        });
+    }
+
     #[test_case("search-1234.js")]
     #[test_case("settings-1234.js")]
     fn fallback_to_root_storage_for_some_js_assets(path: &str) {
@@ -2552,6 +2780,56 @@ mod test {
         })
     }
 
+    #[test_case(true)]
+    #[test_case(false)]
+    fn pass_zstd_compressed_assets_through(archive_storage: bool) {
+        wrapper(|env| {
+            env.fake_release()
+                .name("dummy")
+                .version("0.1.0")
+                .archive_storage(archive_storage)
+                .rustdoc_file_with("dummy/some.js", b"var content = 1;".to_vec())
+                .create()?;
+
+            let web = env.frontend();
+            let metrics = env.instance_metrics();
+
+            let response = web
+                .get("/dummy/0.1.0/dummy/some.js")
+                .header("accept-encoding", "gzip, zstd")
+                .send()?;
+            assert!(response.status().is_success());
+            assert_eq!(response.headers().get("content-encoding").unwrap(), "zstd");
+            assert_eq!(response.headers().get("vary").unwrap(), "accept-encoding");
+            assert_eq!(
+                zstd::decode_all(response.bytes()?.as_ref())?,
+                b"var content = 1;"
+            );
+            assert!(metrics.compressed_bytes_passed_through_total.get() > 0);
+            assert_eq!(metrics.compressed_bytes_decompressed_total.get(), 0);
+
+            // clients that don't accept zstd get the decompressed file
+            let response = web
+                .get("/dummy/0.1.0/dummy/some.js")
+                .header("accept-encoding", "gzip, zstd;q=0")
+                .send()?;
+            assert!(response.status().is_success());
+            assert!(response.headers().get("content-encoding").is_none());
+            assert_eq!(response.text()?, "var content = 1;");
+            assert!(metrics.compressed_bytes_decompressed_total.get() > 0);
+
+            // HTML pages are rewritten, so we have to decompress them
+            let response = web
+                .get("/dummy/0.1.0/dummy/index.html")
+                .header("accept-encoding", "zstd")
+                .send()?;
+            assert!(response.status().is_success());
+            assert!(response.headers().get("content-encoding").is_none());
+
+            Ok(())
+        })
+    }
+
     #[test]
     fn redirect_with_encoded_chars_in_path() {
         wrapper(|env| {
@@ -71,7 +71,8 @@ pub(crate) async fn sitemap_handler(
          INNER JOIN releases ON releases.crate_id = crates.id
          WHERE
             rustdoc_status = true AND
-            crates.name ILIKE $1
+            crates.name ILIKE $1 AND
+            crates.registry IS NULL
          GROUP BY crates.name, releases.target_name
          "#,
         format!("{letter}%"),
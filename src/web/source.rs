@@ -2,10 +2,10 @@ use super::{error::AxumResult, match_version};
 use crate::{
     db::Pool,
     impl_axum_webpage,
-    storage::PathNotFoundError,
+    storage::{crate_storage_name, PathNotFoundError},
     web::{
         cache::CachePolicy, error::AxumNope, extractors::Path, file::File as DbFile,
-        headers::CanonicalUrl, MetaData, ReqVersion,
+        headers::CanonicalUrl, registries::CrateRegistry, MetaData, ReqVersion,
     },
     AsyncStorage,
 };
@@ -69,6 +69,7 @@ impl FileList {
     #[instrument(skip(conn))]
     async fn from_path(
         conn: &mut sqlx::PgConnection,
+        registry: Option<&str>,
         name: &str,
         version: &Version,
         req_version: Option<ReqVersion>,
@@ -78,9 +79,13 @@ impl FileList {
             "SELECT releases.files
             FROM releases
             INNER JOIN crates ON crates.id = releases.crate_id
-            WHERE crates.name = $1 AND releases.version = $2",
+            WHERE
+                crates.name = $1 AND
+                releases.version = $2 AND
+                crates.registry IS NOT DISTINCT FROM $3",
             name,
             version.to_string(),
+            registry,
         )
         .fetch_optional(&mut *conn)
         .await?
@@ -137,7 +142,7 @@ impl FileList {
             });
 
             Ok(Some(FileList {
-                metadata: MetaData::from_crate(conn, name, version, req_version).await?,
+                metadata: MetaData::from_crate(conn, registry, name, version, req_version).await?,
                 files: file_list,
             }))
         } else {
@@ -181,10 +186,11 @@ pub(crate) async fn source_browser_handler(
     Path(params): Path<SourceBrowserHandlerParams>,
     Extension(storage): Extension<Arc<AsyncStorage>>,
     Extension(pool): Extension<Pool>,
+    registry: CrateRegistry,
 ) -> AxumResult<impl IntoResponse> {
     let mut conn = pool.get_async().await?;
 
-    let version = match_version(&mut conn, &params.name, &params.version)
+    let version = match_version(&mut conn, registry.name(), &params.name, &params.version)
         .await?
         .into_exactly_named_or_else(|corrected_name, req_version| {
             AxumNope::Redirect(
@@ -217,9 +223,11 @@ pub(crate) async fn source_browser_handler(
          INNER JOIN crates ON releases.crate_id = crates.id
          WHERE
              name = $1 AND
-             version = $2",
+             version = $2 AND
+             registry IS NOT DISTINCT FROM $3",
         params.name,
-        version.to_string()
+        version.to_string(),
+        registry.name(),
     )
     .fetch_one(&mut *conn)
     .await?;
@@ -229,7 +237,7 @@ pub(crate) async fn source_browser_handler(
     let blob = if !params.path.ends_with('/') {
         match storage
             .fetch_source_file(
-                &params.name,
+                &crate_storage_name(registry.name(), &params.name),
                 &version.to_string(),
                 row.latest_build_id.unwrap_or(0),
                 &params.path,
@@ -252,8 +260,10 @@ pub(crate) async fn source_browser_handler(
     };
 
     let canonical_url = CanonicalUrl::from_path(format!(
-        "/crate/{}/latest/source/{}",
-        params.name, params.path
+        "{}/crate/{}/latest/source/{}",
+        registry.url_prefix(),
+        params.name,
+        params.path
     ));
 
     let (file, file_content) = if let Some(blob) = blob {
@@ -291,6 +301,7 @@ pub(crate) async fn source_browser_handler(
 
     let file_list = FileList::from_path(
         &mut conn,
+        registry.name(),
         &params.name,
         &version,
         Some(params.version.clone()),
@@ -1,15 +1,29 @@
 use super::{cache::CachePolicy, error::AxumNope};
-use crate::web::{
-    error::AxumResult,
-    extractors::{DbConnection, Path},
-    match_version, ReqVersion,
+use crate::{
+    utils::spawn_blocking,
+    web::{
+        error::AxumResult,
+        extractors::{DbConnection, Path},
+        match_version,
+        registries::CrateRegistry,
+        ReqVersion,
+    },
+    BuildQueue, Config,
 };
+use anyhow::Context as _;
 use axum::{
     extract::Extension, http::header::ACCESS_CONTROL_ALLOW_ORIGIN, response::IntoResponse, Json,
 };
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Number of recent builds the average time between builds is calculated from.
+const RECENT_BUILDS: i64 = 100;
 
 pub(crate) async fn status_handler(
     Path((name, req_version)): Path<(String, ReqVersion)>,
+    registry: CrateRegistry,
     mut conn: DbConnection,
 ) -> impl IntoResponse {
     (
@@ -18,7 +32,7 @@ pub(crate) async fn status_handler(
         // We use an async block to emulate a try block so that we can apply the above CORS header
         // and cache policy to both successful and failed responses
         async move {
-            let matched_release = match_version(&mut conn, &name, &req_version)
+            let matched_release = match_version(&mut conn, registry.name(), &name, &req_version)
                 .await?
                 .assume_exact_name()?;
 
@@ -44,13 +58,165 @@ pub(crate) async fn status_handler(
     )
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct QueueStatus {
+    version: String,
+    queued: bool,
+    building: bool,
+    /// number of queued releases that will be built before this one.
+    position: Option<usize>,
+    priority: Option<i32>,
+    attempt: Option<i32>,
+    estimated_start: Option<DateTime<Utc>>,
+}
+
+/// Counts the releases that will be built before a release with `priority`, from the number of
+/// buildable releases by priority. The counts don't tell the order within a priority, so all
+/// releases with the same priority are counted, which errs on the late side.
+fn releases_ahead(priority: i32, buildable_by_priority: &HashMap<i32, usize>) -> usize {
+    buildable_by_priority
+        .iter()
+        .filter(|(&other, _)| other <= priority)
+        .map(|(_, count)| count)
+        .sum()
+}
+
+/// Estimates how long it takes until the builds of `ahead` other releases are started,
+/// when all build servers together finish a build every `build_interval`.
+fn estimate_wait(ahead: usize, build_interval: Duration) -> Duration {
+    build_interval.mul_f64(ahead as f64)
+}
+
+pub(crate) async fn queue_status_handler(
+    Path((name, req_version)): Path<(String, ReqVersion)>,
+    Extension(build_queue): Extension<Arc<BuildQueue>>,
+    Extension(config): Extension<Arc<Config>>,
+    registry: CrateRegistry,
+    mut conn: DbConnection,
+) -> impl IntoResponse {
+    (
+        Extension(CachePolicy::NoStoreMustRevalidate),
+        [(ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        async move {
+            // Releases only show up in the database after their first build, so exact versions
+            // are looked up in the queue directly.
+            let version = match req_version {
+                ReqVersion::Exact(version) => version.to_string(),
+                req_version => {
+                    let version = match_version(&mut conn, registry.name(), &name, &req_version)
+                        .await?
+                        .assume_exact_name()?
+                        .into_version();
+                    return Err(AxumNope::Redirect(
+                        format!("/crate/{name}/{version}/queue.json"),
+                        CachePolicy::NoCaching,
+                    ));
+                }
+            };
+
+            let (queue, buildable_by_priority) = spawn_blocking(move || {
+                Ok((
+                    build_queue.queued_crates()?,
+                    build_queue.buildable_count_by_priority()?,
+                ))
+            })
+            .await?;
+
+            // the queue knows registries by their index, crates of registries that aren't
+            // configured are built like crates.io crates.
+            let Some(krate) = queue.iter().find(|krate| {
+                let queued_registry = krate
+                    .registry
+                    .as_deref()
+                    .and_then(|index_url| config.registry_for_index(index_url))
+                    .map(|registry| registry.name.as_str());
+                krate.name == name && krate.version == version && queued_registry == registry.name()
+            }) else {
+                return AxumResult::Ok(
+                    Json(QueueStatus {
+                        version,
+                        queued: false,
+                        building: false,
+                        position: None,
+                        priority: None,
+                        attempt: None,
+                        estimated_start: None,
+                    })
+                    .into_response(),
+                );
+            };
+
+            let (position, estimated_start) = if krate.building {
+                (None, None)
+            } else {
+                // Releases waiting for their next attempt are left out, they are picked up
+                // whenever their delay is over. That includes this one.
+                let next_attempt_at = krate.next_attempt_at.filter(|at| *at > Utc::now());
+                let mut ahead = releases_ahead(krate.priority, &buildable_by_priority);
+                if next_attempt_at.is_none() {
+                    ahead = ahead.saturating_sub(1);
+                }
+
+                // The time between the recent builds of all servers already includes how many
+                // builds run at the same time. Times without anything to build make it longer,
+                // so the estimate errs on the late side.
+                let build_interval_seconds = sqlx::query_scalar!(
+                    r#"SELECT
+                        (
+                            EXTRACT(EPOCH FROM MAX(recent_builds.build_time) - MIN(recent_builds.build_time)) /
+                            NULLIF(COUNT(*) - 1, 0)
+                        )::FLOAT8 AS "interval_seconds"
+                     FROM (
+                         SELECT build_time
+                         FROM builds
+                         WHERE build_status != 'in_progress'
+                         ORDER BY build_time DESC
+                         LIMIT $1
+                     ) AS recent_builds"#,
+                    RECENT_BUILDS,
+                )
+                .fetch_one(&mut *conn)
+                .await
+                .context("error fetching recent build times")?;
+
+                let estimated_start = build_interval_seconds.map(|build_interval_seconds| {
+                    let wait =
+                        estimate_wait(ahead, Duration::from_secs_f64(build_interval_seconds));
+                    let start = Utc::now()
+                        + chrono::Duration::from_std(wait).unwrap_or_else(|_| chrono::Duration::zero());
+                    next_attempt_at.map_or(start, |at| start.max(at))
+                });
+
+                (Some(ahead), estimated_start)
+            };
+
+            AxumResult::Ok(
+                Json(QueueStatus {
+                    version,
+                    queued: true,
+                    building: krate.building,
+                    position,
+                    priority: Some(krate.priority),
+                    attempt: Some(krate.attempt),
+                    estimated_start,
+                })
+                .into_response(),
+            )
+        }
+        .await,
+    )
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{estimate_wait, releases_ahead};
     use crate::{
         test::{assert_cache_control, assert_redirect, wrapper},
         web::cache::CachePolicy,
     };
+    use chrono::{DateTime, Utc};
     use reqwest::StatusCode;
+    use std::time::Duration;
     use test_case::test_case;
 
     #[test_case("latest")]
@@ -174,4 +340,188 @@ mod tests {
             Ok(())
         });
     }
+
+    #[test]
+    fn queue_status_not_queued() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let response = env.frontend().get("/crate/foo/0.1.0/queue.json").send()?;
+            assert_cache_control(&response, CachePolicy::NoStoreMustRevalidate, &env.config());
+            assert_eq!(response.headers()["access-control-allow-origin"], "*");
+            assert_eq!(response.status(), StatusCode::OK);
+            let value: serde_json::Value = serde_json::from_str(&response.text()?)?;
+
+            assert_eq!(
+                value,
+                serde_json::json!({
+                    "version": "0.1.0",
+                    "queued": false,
+                    "building": false,
+                    "position": null,
+                    "priority": null,
+                    "attempt": null,
+                    "estimated_start": null,
+                })
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn queue_status_position() {
+        wrapper(|env| {
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            queue.add_crate("bar", "0.1.0", -10, None)?;
+            queue.add_crate("baz", "0.0.1", 10, None)?;
+            queue.add_crate("qux", "0.0.1", -10, None)?;
+
+            // without recent builds there is nothing to base an estimate on
+            let response = env.frontend().get("/crate/foo/1.0.0/queue.json").send()?;
+            assert_eq!(response.status(), StatusCode::OK);
+            let value: serde_json::Value = serde_json::from_str(&response.text()?)?;
+            assert_eq!(
+                value,
+                serde_json::json!({
+                    "version": "1.0.0",
+                    "queued": true,
+                    "building": false,
+                    "position": 2,
+                    "priority": 0,
+                    "attempt": 0,
+                    "estimated_start": null,
+                })
+            );
+
+            // the estimate needs at least two builds
+            env.fake_release().name("other").version("0.1.0").create()?;
+            env.fake_release().name("other").version("0.2.0").create()?;
+            env.db().conn().execute(
+                "UPDATE builds
+                 SET build_time = NOW() - INTERVAL '10 minutes'
+                 WHERE id = (SELECT MIN(id) FROM builds)",
+                &[],
+            )?;
+
+            // a crate that is being built doesn't count against the position
+            env.db().conn().execute(
+                "UPDATE queue
+                 SET leased_by = 'builder', lease_expires_at = NOW() + INTERVAL '5 minutes'
+                 WHERE name = 'bar'",
+                &[],
+            )?;
+
+            let value: serde_json::Value = serde_json::from_str(
+                &env.frontend()
+                    .get("/crate/baz/0.0.1/queue.json")
+                    .send()?
+                    .text()?,
+            )?;
+            assert_eq!(value["position"], 2);
+            assert_eq!(value["priority"], 10);
+            let estimated_start: DateTime<Utc> =
+                value["estimated_start"].as_str().unwrap().parse()?;
+            assert!(estimated_start > Utc::now());
+
+            let value: serde_json::Value = serde_json::from_str(
+                &env.frontend()
+                    .get("/crate/bar/0.1.0/queue.json")
+                    .send()?
+                    .text()?,
+            )?;
+            assert_eq!(value["queued"], true);
+            assert_eq!(value["building"], true);
+            assert_eq!(value["position"], serde_json::Value::Null);
+            assert_eq!(value["estimated_start"], serde_json::Value::Null);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn queue_status_waiting_for_next_attempt() {
+        wrapper(|env| {
+            env.override_config(|config| {
+                config.delay_between_build_attempts = Duration::from_secs(3600);
+            });
+            let queue = env.build_queue();
+            queue.add_crate("foo", "1.0.0", 0, None)?;
+            queue.add_crate("bar", "0.1.0", -10, None)?;
+            queue.add_crate("baz", "0.0.1", 10, None)?;
+            env.db().conn().execute(
+                "UPDATE queue
+                 SET attempt = 1, last_attempt = NOW()
+                 WHERE name = 'bar'",
+                &[],
+            )?;
+
+            env.fake_release().name("other").version("0.1.0").create()?;
+            env.fake_release().name("other").version("0.2.0").create()?;
+            env.db().conn().execute(
+                "UPDATE builds
+                 SET build_time = NOW() - INTERVAL '10 minutes'
+                 WHERE id = (SELECT MIN(id) FROM builds)",
+                &[],
+            )?;
+
+            // a release waiting for its next attempt doesn't count against the position
+            let value: serde_json::Value = serde_json::from_str(
+                &env.frontend()
+                    .get("/crate/baz/0.0.1/queue.json")
+                    .send()?
+                    .text()?,
+            )?;
+            assert_eq!(value["position"], 1);
+
+            // and can't start before the delay is over
+            let value: serde_json::Value = serde_json::from_str(
+                &env.frontend()
+                    .get("/crate/bar/0.1.0/queue.json")
+                    .send()?
+                    .text()?,
+            )?;
+            assert_eq!(value["position"], 0);
+            assert_eq!(value["attempt"], 1);
+            let estimated_start: DateTime<Utc> =
+                value["estimated_start"].as_str().unwrap().parse()?;
+            assert!(estimated_start > Utc::now() + chrono::Duration::minutes(59));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn queue_status_redirect() {
+        wrapper(|env| {
+            env.fake_release().name("foo").version("0.1.0").create()?;
+
+            let redirect = assert_redirect(
+                "/crate/foo/0.1/queue.json",
+                "/crate/foo/0.1.0/queue.json",
+                env.frontend(),
+            )?;
+            assert_cache_control(&redirect, CachePolicy::NoStoreMustRevalidate, &env.config());
+            assert_eq!(redirect.headers()["access-control-allow-origin"], "*");
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn estimate_wait_from_build_interval() {
+        let interval = Duration::from_secs(60);
+        assert_eq!(estimate_wait(0, interval), Duration::ZERO);
+        assert_eq!(estimate_wait(3, interval), Duration::from_secs(180));
+    }
+
+    #[test]
+    fn releases_ahead_by_priority() {
+        let buildable = [(-10, 2), (0, 3), (10, 4)].into_iter().collect();
+        assert_eq!(releases_ahead(-20, &buildable), 0);
+        assert_eq!(releases_ahead(0, &buildable), 5);
+        assert_eq!(releases_ahead(5, &buildable), 5);
+        assert_eq!(releases_ahead(10, &buildable), 9);
+    }
 }
@@ -0,0 +1,372 @@
+//! "Did you mean" suggestions for crates that don't exist.
+
+use crate::{
+    db::Pool,
+    impl_axum_webpage,
+    utils::report_error,
+    web::{
+        cache::CachePolicy,
+        error::{AxumNope, AxumResult},
+        extractors::DbConnection,
+        registries::CrateRegistry,
+        routes::is_internal_prefix,
+    },
+};
+use anyhow::{anyhow, Result};
+use axum::{
+    extract::{Extension, Query, Request as AxumRequest},
+    http::{header::ACCESS_CONTROL_ALLOW_ORIGIN, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response as AxumResponse},
+    Json,
+};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How many similarly named crates we suggest at most.
+const MAX_SUGGESTIONS: i64 = 5;
+
+/// Crate names are at most 64 characters long, longer names aren't worth comparing.
+const MAX_NAME_LENGTH: usize = 64;
+
+/// How long we reuse the suggestions for a name.
+const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How many names we keep the suggestions for at most.
+const MAX_CACHED_NAMES: usize = 10_000;
+
+/// The registry and the normalized name suggestions were looked for.
+type CacheKey = (Option<String>, String);
+
+/// The suggestions for the names requested last, so clients requesting the same missing crate
+/// over and over don't run the query every time.
+#[derive(Default)]
+pub(crate) struct SuggestionsCache {
+    entries: Mutex<HashMap<CacheKey, (Instant, Vec<String>)>>,
+}
+
+impl SuggestionsCache {
+    fn get(&self, key: &CacheKey) -> Option<Vec<String>> {
+        let entries = self.entries.lock().unwrap();
+        let (created, suggestions) = entries.get(key)?;
+        (created.elapsed() < CACHE_TTL).then(|| suggestions.clone())
+    }
+
+    fn insert(&self, key: CacheKey, suggestions: Vec<String>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHED_NAMES {
+            entries.retain(|_, (created, _)| created.elapsed() < CACHE_TTL);
+            if entries.len() >= MAX_CACHED_NAMES {
+                entries.clear();
+            }
+        }
+        entries.insert(key, (Instant::now(), suggestions));
+    }
+}
+
+/// [`suggest_crates`], reusing the suggestions found for the same name recently.
+async fn cached_suggest_crates(
+    cache: &SuggestionsCache,
+    conn: &mut sqlx::PgConnection,
+    registry: Option<&str>,
+    name: &str,
+) -> Result<Vec<String>> {
+    let key = (
+        registry.map(str::to_owned),
+        name.to_lowercase().replace('-', "_"),
+    );
+    if let Some(suggestions) = cache.get(&key) {
+        return Ok(suggestions);
+    }
+
+    let suggestions = suggest_crates(conn, registry, name).await?;
+    cache.insert(key, suggestions.clone());
+    Ok(suggestions)
+}
+
+/// Finds the crates of the registry whose names are closest to `name`, ignoring the case and the
+/// difference between `-` and `_` like crate names do.
+///
+/// Only names sharing enough trigrams with `name` are considered, so the trigram index can find
+/// them. Of those, names within a few typos of `name` come first, then the names sharing the most
+/// trigrams with it, the crates with more stars first.
+pub(crate) async fn suggest_crates(
+    conn: &mut sqlx::PgConnection,
+    registry: Option<&str>,
+    name: &str,
+) -> Result<Vec<String>> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Ok(Vec::new());
+    }
+
+    // one typo in short names, up to three in long ones
+    let max_distance = (name.len() / 4).clamp(1, 3) as i32;
+
+    Ok(sqlx::query_scalar!(
+        r#"SELECT candidates.name as "name!"
+           FROM (
+               SELECT
+                   crates.name,
+                   crates.latest_version_id,
+                   levenshtein_less_equal(
+                       normalize_crate_name(crates.name),
+                       normalize_crate_name($1),
+                       $3
+                   ) AS distance,
+                   similarity(crates.name, $1) AS similarity
+               FROM crates
+               WHERE
+                   crates.name % $1 AND
+                   crates.registry IS NOT DISTINCT FROM $2
+           ) AS candidates
+           INNER JOIN releases ON candidates.latest_version_id = releases.id
+           LEFT JOIN repositories ON releases.repository_id = repositories.id
+           ORDER BY
+               LEAST(candidates.distance, $3 + 1),
+               candidates.similarity DESC,
+               repositories.stars DESC NULLS LAST,
+               candidates.name
+           LIMIT $4"#,
+        name,
+        registry,
+        max_distance,
+        MAX_SUGGESTIONS,
+    )
+    .fetch(&mut *conn)
+    .try_collect()
+    .await?)
+}
+
+/// Marks the response to a request for a crate that doesn't exist, so
+/// [`crate_suggestions_middleware`] can look for crates with similar names.
+#[derive(Debug, Clone)]
+pub(crate) struct MissingCrate(pub(crate) String);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct CrateNotFoundPage {
+    title: &'static str,
+    /// the name of the crate that was requested
+    name: String,
+    suggestions: Vec<String>,
+}
+
+impl CrateNotFoundPage {
+    pub(crate) fn new(name: String, suggestions: Vec<String>) -> Self {
+        Self {
+            title: "The requested crate does not exist",
+            name,
+            suggestions,
+        }
+    }
+}
+
+impl_axum_webpage! {
+    CrateNotFoundPage = "crate/not_found.html",
+    status = |_| StatusCode::NOT_FOUND,
+}
+
+/// Adds the crates with names similar to the requested one to the "crate not found" pages.
+///
+/// Has to run inside of `render_templates_middleware`, which renders the page it returns.
+pub(crate) async fn crate_suggestions_middleware(req: AxumRequest, next: Next) -> AxumResponse {
+    let pool = req.extensions().get::<Pool>().cloned();
+    let cache = req.extensions().get::<Arc<SuggestionsCache>>().cloned();
+    let registry = req
+        .extensions()
+        .get::<CrateRegistry>()
+        .cloned()
+        .unwrap_or_default();
+
+    let response = next.run(req).await;
+
+    let missing_crate = response.extensions().get::<MissingCrate>().cloned();
+    let (Some(MissingCrate(name)), Some(pool), Some(cache)) = (missing_crate, pool, cache) else {
+        return response;
+    };
+    // the blocked prefixes of our own pages aren't typos of crate names
+    if is_internal_prefix(&name) {
+        return response;
+    }
+
+    let suggestions = async {
+        let mut conn = pool.get_async().await?;
+        cached_suggest_crates(&cache, &mut conn, registry.name(), &name).await
+    }
+    .await;
+
+    match suggestions {
+        Ok(suggestions) if !suggestions.is_empty() => {
+            CrateNotFoundPage::new(name, suggestions).into_response()
+        }
+        Ok(_) => response,
+        Err(err) => {
+            report_error(&err.context("error fetching crate suggestions"));
+            response
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct SuggestionParams {
+    #[serde(default)]
+    q: String,
+}
+
+/// The suggestions of [`suggest_crates`] for tools, as
+/// `{"query": .., "suggestions": [{"name": .., "url": ..}]}`.
+pub(crate) async fn suggestions_json_handler(
+    mut conn: DbConnection,
+    registry: CrateRegistry,
+    Extension(cache): Extension<Arc<SuggestionsCache>>,
+    Query(params): Query<SuggestionParams>,
+) -> AxumResult<impl IntoResponse> {
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Err(AxumNope::BadRequest(anyhow!("missing crate name")));
+    }
+
+    let suggestions: Vec<_> = cached_suggest_crates(&cache, &mut conn, registry.name(), query)
+        .await?
+        .into_iter()
+        .map(|name| {
+            serde_json::json!({
+                "url": format!("{}/crate/{name}/latest", registry.url_prefix()),
+                "name": name,
+            })
+        })
+        .collect();
+
+    Ok((
+        Extension(CachePolicy::ShortInCdnAndBrowser),
+        [(ACCESS_CONTROL_ALLOW_ORIGIN, "*")],
+        Json(serde_json::json!({
+            "query": query,
+            "suggestions": suggestions,
+        })),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use kuchikiki::traits::TendrilSink;
+    use serde_json::Value;
+
+    #[test]
+    fn suggest_similar_names() {
+        wrapper(|env| {
+            for (name, stars) in [
+                ("serde", 8000),
+                ("serde_json", 4000),
+                ("serde-yaml", 1000),
+                ("tokio", 20000),
+            ] {
+                env.fake_release()
+                    .name(name)
+                    .version("1.0.0")
+                    .github_stats(format!("owner/{name}"), stars, 10, 10)
+                    .create()?;
+            }
+
+            env.runtime().block_on(async {
+                let mut conn = env.async_db().await.async_conn().await;
+
+                // typos come first, `-` and `_` are the same in crate names
+                let suggestions = suggest_crates(&mut conn, None, "serde-jsno").await?;
+                assert_eq!(suggestions[0], "serde_json");
+                assert!(!suggestions.contains(&"tokio".to_owned()));
+
+                let suggestions = suggest_crates(&mut conn, None, "tokyo").await?;
+                assert_eq!(suggestions, vec!["tokio"]);
+
+                // crates of other registries aren't suggested
+                assert!(suggest_crates(&mut conn, Some("internal"), "tokyo")
+                    .await?
+                    .is_empty());
+
+                assert!(suggest_crates(&mut conn, None, "completely-different")
+                    .await?
+                    .is_empty());
+
+                Ok::<_, anyhow::Error>(())
+            })?;
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn suggestions_on_404_page() {
+        wrapper(|env| {
+            env.fake_release().name("regex").version("1.0.0").create()?;
+
+            let web = env.frontend();
+            for path in [
+                "/regez",
+                "/crate/regez/latest",
+                "/crate/regez/latest/target-redirect/x86_64-unknown-linux-gnu/regez/",
+            ] {
+                let response = web.get(path).send()?;
+                assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+
+                let page = kuchikiki::parse_html().one(response.text()?);
+                let links: Vec<_> = page
+                    .select(".suggestions a")
+                    .unwrap()
+                    .map(|el| el.attributes.borrow().get("href").unwrap().to_owned())
+                    .collect();
+                assert_eq!(links, vec!["/crate/regex/latest"], "{path}");
+            }
+
+            let page = kuchikiki::parse_html().one(web.get("/nothing-like-it").send()?.text()?);
+            assert_eq!(page.select(".suggestions a").unwrap().count(), 0);
+
+            // the prefixes of our own pages aren't crates
+            env.fake_release()
+                .name("sitemap")
+                .version("1.0.0")
+                .create()?;
+            let response = web.get("/sitemap.xml/1.0.0/sitemap/").send()?;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            let page = kuchikiki::parse_html().one(response.text()?);
+            assert_eq!(page.select(".suggestions a").unwrap().count(), 0);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn suggestions_json() {
+        wrapper(|env| {
+            env.fake_release().name("regex").version("1.0.0").create()?;
+
+            let web = env.frontend();
+            let response = web.get("/releases/suggestions.json?q=regez").send()?;
+            assert!(response.status().is_success());
+            assert_eq!(
+                response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+                "*"
+            );
+            assert_eq!(
+                response.json::<Value>()?,
+                serde_json::json!({
+                    "query": "regez",
+                    "suggestions": [{"name": "regex", "url": "/crate/regex/latest"}],
+                })
+            );
+
+            assert_eq!(
+                web.get("/releases/suggestions.json").send()?.status(),
+                StatusCode::BAD_REQUEST
+            );
+
+            Ok(())
+        });
+    }
+}
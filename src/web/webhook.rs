@@ -0,0 +1,217 @@
+//! Webhook the registry index calls when it changes, so new releases are queued right away
+//! instead of on the next run of the registry watcher.
+
+use crate::{
+    utils::report_error,
+    web::error::{AxumNope, AxumResult},
+    BuildQueue, Config, Index,
+};
+use anyhow::{anyhow, Context as _};
+use axum::{
+    body::Bytes,
+    extract::Extension,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Header with the signature of the payload, in the format GitHub uses:
+/// `sha256=<hex encoded HMAC of the body>`.
+const SIGNATURE_HEADER: &str = "x-hub-signature-256";
+
+fn verify_signature(secret: &str, signature: Option<&str>, body: &[u8]) -> anyhow::Result<()> {
+    let signature = signature
+        .context("missing signature")?
+        .strip_prefix("sha256=")
+        .context("unsupported signature algorithm")?;
+    let signature = hex::decode(signature).context("invalid signature")?;
+
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.verify_slice(&signature)
+        .map_err(|_| anyhow!("signature doesn't match"))
+}
+
+/// Starts an update of the build queue from the registry index.
+///
+/// The payload is ignored, we only need to know that something changed. Webhooks arriving
+/// while the queue is being updated are merged into a single update after the running one.
+pub(crate) async fn index_webhook_handler(
+    Extension(config): Extension<Arc<Config>>,
+    Extension(build_queue): Extension<Arc<BuildQueue>>,
+    index: Option<Extension<Arc<Index>>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> AxumResult<impl IntoResponse> {
+    let (Some(secret), Some(Extension(index))) = (&config.index_webhook_secret, index) else {
+        return Err(AxumNope::ResourceNotFound);
+    };
+
+    let signature = headers
+        .get(SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok());
+    if let Err(err) = verify_signature(secret, signature, &body) {
+        warn!("rejected index webhook: {}", err);
+        return Err(AxumNope::BadRequest(err));
+    }
+
+    // Updating the queue can take a while, so we don't let the webhook wait for it.
+    tokio::task::spawn_blocking(move || {
+        let result = build_queue.is_locked().and_then(|locked| {
+            if locked {
+                debug!("Queue is locked, skipping index update from webhook");
+                return Ok(());
+            }
+            let added = build_queue.get_new_crates(&index)?;
+            debug!("{} crates added to queue by index webhook", added);
+            Ok(())
+        });
+        if let Err(err) = result.context("failed to update queue from index webhook") {
+            report_error(&err);
+        }
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::wrapper;
+    use std::path::Path;
+    use std::process::Command;
+    use std::time::{Duration, Instant};
+
+    const SECRET: &str = "webhook-secret";
+
+    fn sign(body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn git(repo: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed: {output:?}");
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    #[test]
+    fn signature_verification() {
+        let body = br#"{"ref": "refs/heads/master"}"#;
+
+        assert!(verify_signature(SECRET, Some(&sign(body)), body).is_ok());
+        assert!(verify_signature(SECRET, None, body).is_err());
+        assert!(verify_signature(SECRET, Some(&sign(b"other body")), body).is_err());
+        assert!(verify_signature("other secret", Some(&sign(body)), body).is_err());
+        assert!(verify_signature(SECRET, Some("sha1=1234"), body).is_err());
+        assert!(verify_signature(SECRET, Some("sha256=not hex"), body).is_err());
+    }
+
+    #[test]
+    fn disabled_without_secret() {
+        wrapper(|env| {
+            let response = env.frontend().post("/_/index-webhook").send()?;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn queue_releases_from_webhook() {
+        wrapper(|env| {
+            // a local registry index, with an initial commit the watcher already saw
+            let upstream = tempfile::tempdir()?;
+            git(upstream.path(), &["init", "--initial-branch=master"]);
+            git(upstream.path(), &["config", "user.name", "docs.rs"]);
+            git(
+                upstream.path(),
+                &["config", "user.email", "docs@example.com"],
+            );
+            std::fs::write(upstream.path().join("config.json"), "{}")?;
+            git(upstream.path(), &["add", "."]);
+            git(upstream.path(), &["commit", "-m", "initial"]);
+            let initial = git(upstream.path(), &["rev-parse", "HEAD"]);
+
+            let local = tempfile::tempdir()?;
+            env.override_config(|config| {
+                config.index_webhook_secret = Some(SECRET.into());
+                config.registry_url = Some(upstream.path().display().to_string());
+                config.registry_index_path = local.path().join("index");
+            });
+
+            let queue = env.build_queue();
+            queue.set_last_seen_reference(crates_index_diff::gix::ObjectId::from_hex(
+                initial.as_bytes(),
+            )?)?;
+
+            // publish a new release
+            std::fs::create_dir_all(upstream.path().join("3/f"))?;
+            std::fs::write(
+                upstream.path().join("3/f/foo"),
+                format!(
+                    "{}\n",
+                    serde_json::json!({
+                        "name": "foo",
+                        "vers": "1.0.0",
+                        "deps": [],
+                        "cksum": "0".repeat(64),
+                        "features": {},
+                        "yanked": false,
+                    })
+                ),
+            )?;
+            git(upstream.path(), &["add", "."]);
+            git(upstream.path(), &["commit", "-m", "publish foo 1.0.0"]);
+
+            let body = br#"{"ref": "refs/heads/master"}"#;
+            let rejected = env
+                .frontend()
+                .post("/_/index-webhook")
+                .header(SIGNATURE_HEADER, sign(b"something else"))
+                .body(body.to_vec())
+                .send()?;
+            assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+
+            let response = env
+                .frontend()
+                .post("/_/index-webhook")
+                .header(SIGNATURE_HEADER, sign(body))
+                .body(body.to_vec())
+                .send()?;
+            assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+            let start = Instant::now();
+            while queue.last_seen_reference()?.map(|oid| oid.to_string()) == Some(initial.clone()) {
+                assert!(
+                    start.elapsed() < Duration::from_secs(30),
+                    "index wasn't updated"
+                );
+                std::thread::sleep(Duration::from_millis(100));
+            }
+
+            let queued = queue.queued_crates()?;
+            assert_eq!(queued.len(), 1);
+            assert_eq!(queued[0].name, "foo");
+            assert_eq!(queued[0].version, "1.0.0");
+
+            // processing the same changes again doesn't queue the release twice
+            queue.set_last_seen_reference(crates_index_diff::gix::ObjectId::from_hex(
+                initial.as_bytes(),
+            )?)?;
+            assert_eq!(queue.get_new_crates(&env.index())?, 0);
+            assert_eq!(queue.queued_crates()?.len(), 1);
+
+            Ok(())
+        })
+    }
+}